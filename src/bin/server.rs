@@ -2,7 +2,7 @@ extern crate rustredis;
 // #[macro_use]
 // extern crate log;
 
-use log::{info, warn};
+use log::{error, info, warn};
 use rustredis::config::Config;
 use rustredis::server::Server;
 
@@ -11,9 +11,14 @@ fn main() {
     // Builder::new()
     //     .parse_env(&env::var("LOG").unwrap_or_default())
     //     .init();
-    let conf = Config{ addr: "127.0.0.1:6379".to_string(), };
-    let mut server = Server::from_config(&conf).unwrap();
+    let conf = Config {
+        addr: "127.0.0.1:6379".to_string(),
+        ..Default::default()
+    };
+    let server = Server::from_config(&conf).unwrap();
     warn!("warnning");
     info!("run server now");
-    server.run();
+    if let Err(err) = server.run() {
+        error!("server exits for error: {:?}", err);
+    }
 }
\ No newline at end of file
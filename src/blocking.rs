@@ -0,0 +1,280 @@
+//! Clients blocked by commands like BLPOP, waiting for their keys to be ready.
+//!
+//! As in redis, a client which can not be served right away is registered on each of
+//! its keys, in the order clients have blocked. Commands creating a value which may
+//! serve blocked clients signal its key as ready, and once the command is done the
+//! clients blocked on the ready keys are served first come first served, before any
+//! other command can see the value. The reply is sent to the task of the client, which
+//! waits for it without holding the databases.
+
+use std::collections::{HashMap, VecDeque};
+
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use crate::{command::BlockingCommand, db::Db, error::Result, protocol::Resp3Piece};
+
+/// Where the reply of a blocked command is sent once it is served.
+pub type ReplySender = oneshot::Sender<Result<Resp3Piece>>;
+pub type ReplyReceiver = oneshot::Receiver<Result<Resp3Piece>>;
+
+#[derive(Debug)]
+struct BlockedClient {
+    /// Database selected by the client when it blocked.
+    db: usize,
+    cmd: BlockingCommand,
+    reply: ReplySender,
+}
+
+#[derive(Debug, Default)]
+pub struct BlockedClients {
+    /// Ids of the clients blocked on each key of each database, in the order they
+    /// blocked.
+    keys: HashMap<(usize, Bytes), VecDeque<u64>>,
+    clients: HashMap<u64, BlockedClient>,
+}
+
+impl BlockedClients {
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Blocks the client on the keys of the command, returning where its reply will be
+    /// received once it is served.
+    pub fn block(&mut self, id: u64, db: usize, cmd: BlockingCommand) -> ReplyReceiver {
+        let (reply, rx) = oneshot::channel();
+        for key in cmd.keys() {
+            let queue = self.keys.entry((db, key)).or_default();
+            // a key given twice does not make the client wait twice on it.
+            if !queue.contains(&id) {
+                queue.push_back(id);
+            }
+        }
+        self.clients.insert(id, BlockedClient { db, cmd, reply });
+        rx
+    }
+
+    /// Unblocks the client, which is not served anymore. Returns whether it was
+    /// blocked.
+    pub fn unblock(&mut self, id: u64) -> bool {
+        self.remove(id).is_some()
+    }
+
+    fn remove(&mut self, id: u64) -> Option<BlockedClient> {
+        let client = self.clients.remove(&id)?;
+        for key in client.cmd.keys() {
+            let key = (client.db, key);
+            if let Some(queue) = self.keys.get_mut(&key) {
+                queue.retain(|&other| other != id);
+                if queue.is_empty() {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+        Some(client)
+    }
+
+    /// Keys of the database clients are blocked on.
+    pub fn keys_of(&self, index: usize) -> impl Iterator<Item = &Bytes> {
+        self.keys
+            .keys()
+            .filter(move |(db, _)| *db == index)
+            .map(|(_, key)| key)
+    }
+
+    /// Serves the clients blocked on a key of the database which has been signaled as
+    /// ready, in the order they blocked, until one of them can not be served anymore.
+    /// Clients waiting for another type of value than the one of the key are skipped.
+    pub fn serve(&mut self, index: usize, db: &mut Db, key: Bytes) {
+        let key = (index, key);
+        let queue: Vec<u64> = match self.keys.get(&key) {
+            Some(queue) => queue.iter().copied().collect(),
+            None => return,
+        };
+        for id in queue {
+            let client = &self.clients[&id];
+            // the client has gone, the value must not be consumed for nothing.
+            if client.reply.is_closed() {
+                self.unblock(id);
+                continue;
+            }
+            match db.lookup(&key.1) {
+                Some(value) if client.cmd.waits_for(value) => {}
+                Some(_) => continue,
+                None => break,
+            }
+            // a stream may still serve the clients reading it through other groups.
+            let reply = match client.cmd.try_serve(db, &key.1).transpose() {
+                Some(reply) => reply,
+                None => continue,
+            };
+            let client = self.remove(id).unwrap();
+            let _ = client.reply.send(reply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        command::{tests::exec_on, Command},
+        db::Databases,
+        protocol::Resp3Piece,
+    };
+
+    use super::ReplyReceiver;
+
+    fn block(dbs: &mut Databases, id: u64, selected: usize, args: &[&str]) -> ReplyReceiver {
+        let args = args.iter().map(|arg| Bytes::copy_from_slice(arg.as_bytes())).collect();
+        match Command::from_args(args).unwrap() {
+            Command::Blocking(cmd) => dbs.block(id, selected, cmd),
+            cmd => panic!("{:?} does not block", cmd),
+        }
+    }
+
+    fn served(rx: &mut ReplyReceiver) -> Option<Resp3Piece> {
+        rx.try_recv().ok().map(|reply| reply.unwrap())
+    }
+
+    fn pair(key: &str, element: &str) -> Resp3Piece {
+        Resp3Piece::Array(vec![Resp3Piece::bulk(key.to_string()), Resp3Piece::bulk(element.to_string())])
+    }
+
+    #[test]
+    fn first_come_first_served() {
+        let mut dbs = Databases::new(2);
+        let mut rx1 = block(&mut dbs, 1, 0, &["blpop", "a", "b", "0"]);
+        let mut rx2 = block(&mut dbs, 2, 0, &["brpop", "b", "0"]);
+        let mut rx3 = block(&mut dbs, 3, 1, &["blpop", "b", "0"]);
+        exec_on(&mut dbs, &mut 0, &["rpush", "b", "x", "y"]).unwrap();
+        dbs.serve_blocked();
+        assert_eq!(served(&mut rx1), Some(pair("b", "x")));
+        assert_eq!(served(&mut rx2), Some(pair("b", "y")));
+        assert_eq!(served(&mut rx3), None);
+        assert_eq!(exec_on(&mut dbs, &mut 0, &["exists", "b"]).unwrap(), Resp3Piece::Integer(0));
+        // the first client is not blocked on `a` anymore.
+        let mut rx4 = block(&mut dbs, 4, 0, &["blpop", "a", "0"]);
+        exec_on(&mut dbs, &mut 0, &["lpush", "a", "z"]).unwrap();
+        dbs.serve_blocked();
+        assert_eq!(served(&mut rx4), Some(pair("a", "z")));
+        assert!(dbs.unblock(3));
+        assert!(!dbs.unblock(3));
+    }
+
+    #[test]
+    fn served_in_chain() {
+        let mut dbs = Databases::new(2);
+        let mut rx1 = block(&mut dbs, 1, 0, &["blmove", "a", "b", "left", "right", "0"]);
+        let mut rx2 = block(&mut dbs, 2, 0, &["blmpop", "0", "1", "b", "left", "count", "2"]);
+        let rx3 = block(&mut dbs, 3, 0, &["blpop", "c", "0"]);
+        drop(rx3);
+        exec_on(&mut dbs, &mut 1, &["rpush", "a", "v"]).unwrap();
+        exec_on(&mut dbs, &mut 1, &["swapdb", "0", "1"]).unwrap();
+        exec_on(&mut dbs, &mut 0, &["rpush", "c", "w"]).unwrap();
+        dbs.serve_blocked();
+        assert_eq!(served(&mut rx1), Some(Resp3Piece::bulk("v")));
+        assert_eq!(
+            served(&mut rx2),
+            Some(Resp3Piece::Array(vec![
+                Resp3Piece::bulk("b"),
+                Resp3Piece::Array(vec![Resp3Piece::bulk("v")])
+            ]))
+        );
+        // the client which has gone did not consume the element.
+        assert!(!dbs.unblock(3));
+        assert_eq!(exec_on(&mut dbs, &mut 0, &["llen", "c"]).unwrap(), Resp3Piece::Integer(1));
+    }
+
+    #[test]
+    fn served_from_the_ready_key() {
+        let mut dbs = Databases::new(1);
+        let mut rx1 = block(&mut dbs, 1, 0, &["blpop", "a", "b", "0"]);
+        exec_on(&mut dbs, &mut 0, &["set", "a", "x"]).unwrap();
+        exec_on(&mut dbs, &mut 0, &["rpush", "b", "v"]).unwrap();
+        dbs.serve_blocked();
+        // `a` now holds a string, which must not fail the client woken up by `b`.
+        assert_eq!(served(&mut rx1), Some(pair("b", "v")));
+
+        exec_on(&mut dbs, &mut 0, &["del", "a"]).unwrap();
+        let mut rx2 = block(&mut dbs, 2, 0, &["bzpopmin", "a", "b", "0"]);
+        exec_on(&mut dbs, &mut 0, &["zadd", "b", "1", "m"]).unwrap();
+        exec_on(&mut dbs, &mut 0, &["zadd", "a", "2", "n"]).unwrap();
+        dbs.serve_blocked();
+        // `b` got ready first, so the client is served from it though `a` comes first.
+        assert_eq!(
+            served(&mut rx2),
+            Some(Resp3Piece::Array(vec![
+                Resp3Piece::bulk("b"),
+                Resp3Piece::bulk("m"),
+                Resp3Piece::Double(1.0)
+            ]))
+        );
+        assert_eq!(exec_on(&mut dbs, &mut 0, &["zcard", "a"]).unwrap(), Resp3Piece::Integer(1));
+    }
+
+    #[test]
+    fn sorted_sets_first_come_first_served() {
+        let mut dbs = Databases::new(1);
+        let mut rx1 = block(&mut dbs, 1, 0, &["bzpopmin", "q", "0"]);
+        let mut rx2 = block(&mut dbs, 2, 0, &["blpop", "q", "0"]);
+        let mut rx3 = block(&mut dbs, 3, 0, &["bzmpop", "0", "1", "q", "max", "count", "5"]);
+        let mut rx4 = block(&mut dbs, 4, 0, &["bzpopmax", "q", "0"]);
+        exec_on(&mut dbs, &mut 0, &["zadd", "q", "1", "low", "5", "high", "3", "mid"]).unwrap();
+        dbs.serve_blocked();
+        let element = |member: &str, score: f64| {
+            Resp3Piece::Array(vec![Resp3Piece::bulk(member.to_string()), Resp3Piece::Double(score)])
+        };
+        assert_eq!(
+            served(&mut rx1),
+            Some(Resp3Piece::Array(vec![
+                Resp3Piece::bulk("q"),
+                Resp3Piece::bulk("low"),
+                Resp3Piece::Double(1.0)
+            ]))
+        );
+        // the client waiting for a list is left blocked.
+        assert_eq!(served(&mut rx2), None);
+        assert_eq!(
+            served(&mut rx3),
+            Some(Resp3Piece::Array(vec![
+                Resp3Piece::bulk("q"),
+                Resp3Piece::Array(vec![element("high", 5.0), element("mid", 3.0)])
+            ]))
+        );
+        assert_eq!(served(&mut rx4), None);
+        exec_on(&mut dbs, &mut 0, &["rpush", "q", "v"]).unwrap();
+        dbs.serve_blocked();
+        assert_eq!(served(&mut rx2), Some(pair("q", "v")));
+        assert_eq!(served(&mut rx4), None);
+        assert!(dbs.unblock(4));
+    }
+
+    #[test]
+    fn stream_groups_served_in_turn() {
+        let mut dbs = Databases::new(1);
+        exec_on(&mut dbs, &mut 0, &["xgroup", "create", "s", "one", "$", "mkstream"]).unwrap();
+        exec_on(&mut dbs, &mut 0, &["xgroup", "create", "s", "two", "$"]).unwrap();
+        let read = |group, consumer| {
+            ["xreadgroup", "group", group, consumer, "block", "0", "streams", "s", ">"]
+        };
+        let mut rx1 = block(&mut dbs, 1, 0, &read("one", "a"));
+        let mut rx2 = block(&mut dbs, 2, 0, &read("one", "b"));
+        let mut rx3 = block(&mut dbs, 3, 0, &read("two", "c"));
+        exec_on(&mut dbs, &mut 0, &["xadd", "s", "1-1", "f", "v"]).unwrap();
+        dbs.serve_blocked();
+        let entries = Resp3Piece::Array(vec![Resp3Piece::Array(vec![
+            Resp3Piece::bulk("s"),
+            Resp3Piece::Array(vec![Resp3Piece::Array(vec![
+                Resp3Piece::bulk("1-1"),
+                Resp3Piece::Array(vec![Resp3Piece::bulk("f"), Resp3Piece::bulk("v")]),
+            ])]),
+        ])]);
+        assert_eq!(served(&mut rx1), Some(entries.clone()));
+        // the entry has been delivered to the group of the second client already.
+        assert_eq!(served(&mut rx2), None);
+        assert_eq!(served(&mut rx3), Some(entries));
+        assert!(dbs.unblock(2));
+    }
+}
@@ -1,66 +1,334 @@
-use std::io::BufRead;
+use std::{future, sync::Arc};
 
-use bytes::{Buf, BytesMut};
-use log::{debug, info, warn};
+use bytes::{Bytes, BytesMut};
+use log::{debug, warn};
 use tokio::{
-    io::{AsyncReadExt, BufReader},
-    net::TcpStream,
+    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::Mutex,
+    time::{self, Instant},
 };
 
-use crate::command::Command;
+use crate::{
+    blocking::ReplyReceiver,
+    command::{BlockingCommand, Command},
+    db::Databases,
+    error::{CommandError, Error, Result},
+    protocol::{Limits, Protocol, ProtocolVersion, RawPiece, RequestDecoder, Resp3Piece},
+    server::REDIS_VERSION,
+    util::parse_i64,
+};
+
+const READ_BUF_SIZE: usize = 16 * 1024;
+const WRITE_BUF_SIZE: usize = 64 * 1024;
 
 pub struct Client {
     id: u64,
-    stream: BufReader<TcpStream>,
-    flags: u32,
-    // rs: ReadHalf<TcpStream>,
-    // ws: WriteHalf<TcpStream>,
+    rs: OwnedReadHalf,
+    ws: BufWriter<OwnedWriteHalf>,
+    /// Bytes read from the peer which have not been decoded yet.
+    read_buf: BytesMut,
+    decoder: RequestDecoder,
+    protocol: ProtocolVersion,
+    name: Option<Bytes>,
+    dbs: Arc<Mutex<Databases>>,
+    /// Index of the database selected with SELECT.
+    selected_db: usize,
+    /// Commands queued since MULTI, until EXEC or DISCARD.
+    multi: Option<Vec<Command>>,
+    /// Whether a command could not be queued, which makes EXEC abort.
+    multi_error: bool,
 }
 
 impl Client {
-    pub fn new(id: u64, stream: TcpStream) -> Self {
-        // let (rs, ws) = tio::split(stream);
+    pub fn new(id: u64, stream: TcpStream, limits: Limits, dbs: Arc<Mutex<Databases>>) -> Self {
+        let (rs, ws) = stream.into_split();
         Self {
             id,
-            stream: BufReader::new(stream),
-            flags: 0,
+            rs,
+            ws: BufWriter::with_capacity(WRITE_BUF_SIZE, ws),
+            read_buf: BytesMut::with_capacity(READ_BUF_SIZE),
+            decoder: RequestDecoder::new(limits),
+            protocol: ProtocolVersion::Resp2,
+            name: None,
+            dbs,
+            selected_db: 0,
+            multi: None,
+            multi_error: false,
+        }
+    }
+
+    /// Serves the requests of the client until it disconnects or breaks the protocol.
+    pub async fn serve(&mut self) {
+        match self.process().await {
+            Err(Error::EOF) => debug!("client({}) closed the connection", self.id),
+            Err(Error::IO(err)) => debug!("client({}) io error: {:?}", self.id, err),
+            Err(err) => warn!("client({}) is closed for error: {:?}", self.id, err),
+            Ok(()) => {}
         }
     }
 
-    pub async fn read_command(&mut self) -> Option<Command> {
+    /// Executes every complete request in the read buffer in order, and only reads from
+    /// the socket once the buffer is drained. The replies of pipelined requests are
+    /// written into the write buffer, and flushed together when there is nothing more
+    /// to execute, so a whole pipeline costs one read and as few writes as possible.
+    async fn process(&mut self) -> Result<()> {
         loop {
-            match Command::from_resp2(&mut self.stream).await {
-                Ok(cmd) => {return Some(cmd)},
+            let result = match self.decoder.decode(&mut self.read_buf) {
+                Ok(Some(args)) => self.execute_request(args).await,
+                Ok(None) => {
+                    self.ws.flush().await?;
+                    self.fill_read_buf().await?;
+                    continue;
+                }
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(reply) => self.write_reply(reply).await?,
                 Err(err) => {
-                    match err {
-                        crate::error::Error::EOF => {
-                            return None
-                        },
-                        _ => {
-                            warn!("error on reading command: {:?}", err)
-                        }
+                    self.write_reply(Self::error_reply(&err)).await?;
+                    if err.is_fatal() {
+                        // the rest of the stream can not be trusted after a protocol error.
+                        self.ws.flush().await?;
+                        return Err(err);
                     }
-                },
+                }
+            }
+        }
+    }
+
+    /// Parses and dispatches a request. A request which is not a valid command makes
+    /// the transaction in progress, if any, abort on EXEC.
+    async fn execute_request(&mut self, args: Vec<Bytes>) -> Result<Resp3Piece> {
+        let cmd = match Command::from_args(args) {
+            Ok(cmd) => cmd,
+            Err(err) => {
+                if self.multi.is_some() {
+                    self.multi_error = true;
+                }
+                return Err(err);
+            }
+        };
+        debug!("Got cmd by client({:?}): {:?}", self.id, cmd);
+        self.dispatch(cmd).await
+    }
+
+    async fn fill_read_buf(&mut self) -> Result<()> {
+        if self.read_buf.capacity() - self.read_buf.len() < READ_BUF_SIZE / 4 {
+            self.read_buf.reserve(READ_BUF_SIZE);
+        }
+        if self.rs.read_buf(&mut self.read_buf).await? == 0 {
+            return Err(Error::EOF);
+        }
+        Ok(())
+    }
+
+    /// Executes a command, or queues it inside MULTI. Errors of the command are
+    /// replied, only errors of the connection are returned.
+    async fn dispatch(&mut self, cmd: Command) -> Result<Resp3Piece> {
+        let reply = match cmd {
+            Command::Multi if self.multi.is_some() => {
+                Resp3Piece::from(&CommandError::Err("MULTI calls can not be nested".into()))
+            }
+            Command::Multi => {
+                self.multi = Some(vec![]);
+                Resp3Piece::ok()
+            }
+            Command::Exec => self.exec().await,
+            Command::Discard => match self.multi.take() {
+                Some(_) => {
+                    self.multi_error = false;
+                    Resp3Piece::ok()
+                }
+                None => Resp3Piece::from(&CommandError::Err("DISCARD without MULTI".into())),
+            },
+            cmd if self.multi.is_some() => {
+                self.multi.as_mut().unwrap().push(cmd);
+                Resp3Piece::simple("QUEUED")
+            }
+            Command::Blocking(cmd) => self.block(cmd).await?,
+            cmd => self.execute_command(cmd).await,
+        };
+        Ok(reply)
+    }
+
+    pub async fn execute_command(&mut self, cmd: Command) -> Resp3Piece {
+        let result = match cmd {
+            Command::Hello {
+                protover,
+                auth,
+                setname,
+            } => self.hello(protover, auth, setname),
+            cmd => {
+                let mut dbs = self.dbs.lock().await;
+                let result = cmd.execute(&mut dbs, &mut self.selected_db);
+                dbs.serve_blocked();
+                result
             }
+        };
+        match result {
+            Ok(reply) => reply,
+            Err(err) => Self::error_reply(&err),
         }
-        // let cmd = Command::from_resp2(&mut self.stream).await;
-
-        // loop {
-        //     info!("read_command now");
-        //     if let Some(cmd) = self.parse_command() {
-        //         return Some(cmd);
-        //     }
-        //     if 0 == self.stream.read_buf(&mut self.buffer).await.unwrap() {
-        //         if self.buffer.is_empty() {
-        //             return None;
-        //         } else {
-        //             return Some(Command::Quit);
-        //         }
-        //     }
-        // }
     }
 
-    pub fn execute_command(&self, cmd: Command) {}
+    /// Executes the commands queued since MULTI at once, with the databases held all
+    /// along, so that no other client sees them half done. Blocking commands do not
+    /// block inside a transaction.
+    async fn exec(&mut self) -> Resp3Piece {
+        let cmds = match self.multi.take() {
+            Some(cmds) => cmds,
+            None => return Resp3Piece::from(&CommandError::Err("EXEC without MULTI".into())),
+        };
+        if std::mem::take(&mut self.multi_error) {
+            return Resp3Piece::from(&CommandError::ExecAbort);
+        }
+        let dbs = self.dbs.clone();
+        let mut dbs = dbs.lock().await;
+        let mut replies = Vec::with_capacity(cmds.len());
+        for cmd in cmds {
+            let result = match cmd {
+                Command::Hello {
+                    protover,
+                    auth,
+                    setname,
+                } => self.hello(protover, auth, setname),
+                cmd => cmd.execute(&mut dbs, &mut self.selected_db),
+            };
+            replies.push(result.unwrap_or_else(|err| Self::error_reply(&err)));
+        }
+        dbs.serve_blocked();
+        Resp3Piece::Array(replies)
+    }
 
+    /// Executes a blocking command. When none of its keys can serve it, the client
+    /// blocks until another client makes one of them ready, the timeout elapses or the
+    /// connection is closed. The databases are not held while waiting.
+    async fn block(&mut self, cmd: BlockingCommand) -> Result<Resp3Piece> {
+        let deadline = cmd.timeout().map(|timeout| Instant::now() + timeout);
+        let timeout_reply = cmd.timeout_reply();
+        let mut rx = {
+            let mut dbs = self.dbs.lock().await;
+            match cmd.try_execute(dbs.get_mut(self.selected_db)) {
+                Ok(Some(reply)) => {
+                    dbs.serve_blocked();
+                    return Ok(reply);
+                }
+                Ok(None) => dbs.block(self.id, self.selected_db, cmd),
+                Err(err) => return Ok(Self::error_reply(&err)),
+            }
+        };
+        let served = self.wait_served(&mut rx, deadline).await;
+        if !matches!(served, Ok(Some(_))) {
+            self.dbs.lock().await.unblock(self.id);
+        }
+        let reply = match served? {
+            Some(reply) => reply,
+            // the client may have been served right before it was unblocked.
+            None => rx.try_recv().unwrap_or(Ok(timeout_reply)),
+        };
+        Ok(reply.unwrap_or_else(|err| Self::error_reply(&err)))
+    }
 
+    /// Waits for the reply of a blocked command, `None` meaning that the deadline has
+    /// passed. The connection is read meanwhile, so that a client which disconnects is
+    /// noticed, and the requests it sends are kept until it is unblocked.
+    async fn wait_served(
+        &mut self,
+        rx: &mut ReplyReceiver,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Result<Resp3Piece>>> {
+        // the replies of the requests before are not held back by the wait.
+        self.ws.flush().await?;
+        let timeout = async {
+            match deadline {
+                Some(deadline) => time::sleep_until(deadline).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::pin!(timeout);
+        loop {
+            tokio::select! {
+                reply = &mut *rx => return Ok(reply.ok()),
+                _ = &mut timeout => return Ok(None),
+                read = self.fill_read_buf() => read?,
+            }
+        }
+    }
+
+    fn hello(
+        &mut self,
+        protover: Option<Bytes>,
+        auth: Option<(Bytes, Bytes)>,
+        setname: Option<Bytes>,
+    ) -> Result<Resp3Piece> {
+        let protocol = match protover {
+            None => self.protocol,
+            Some(ver) => match parse_i64(&ver) {
+                Some(2) => ProtocolVersion::Resp2,
+                Some(3) => ProtocolVersion::Resp3,
+                Some(_) => return Err(CommandError::NoProto.into()),
+                None => {
+                    return Err(CommandError::Err(
+                        "Protocol version is not an integer or out of range".into(),
+                    )
+                    .into())
+                }
+            },
+        };
+        // there is no ACL yet, the default user accepts any password.
+        if let Some((user, _)) = auth {
+            if user != "default" {
+                return Err(CommandError::WrongPass.into());
+            }
+        }
+        if let Some(name) = setname {
+            if name.iter().any(|&c| !(b'!'..=b'~').contains(&c)) {
+                return Err(CommandError::Err(
+                    "Client names cannot contain spaces, newlines or special characters."
+                        .into(),
+                )
+                .into());
+            }
+            self.name = if name.is_empty() { None } else { Some(name) };
+        }
+        self.protocol = protocol;
+        let proto = match protocol {
+            ProtocolVersion::Resp2 => 2,
+            ProtocolVersion::Resp3 => 3,
+        };
+        let field = |name: &'static str| Resp3Piece::bulk(name.as_bytes());
+        Ok(Resp3Piece::Map(vec![
+            (field("server"), field("redis")),
+            (field("version"), field(REDIS_VERSION)),
+            (field("proto"), Resp3Piece::Integer(proto)),
+            (field("id"), Resp3Piece::Integer(self.id as i64)),
+            (field("mode"), field("standalone")),
+            (field("role"), field("master")),
+            (field("modules"), Resp3Piece::Array(vec![])),
+        ]))
+    }
+
+    /// Writes the reply in the protocol of the connection. The reply stays in the write
+    /// buffer until it is full or flushed.
+    async fn write_reply(&mut self, reply: Resp3Piece) -> Result<()> {
+        match self.protocol {
+            ProtocolVersion::Resp2 => RawPiece::from(reply).marshal(&mut self.ws).await?,
+            ProtocolVersion::Resp3 => reply.marshal(&mut self.ws).await?,
+        };
+        Ok(())
+    }
+
+    fn error_reply(err: &Error) -> Resp3Piece {
+        let err = match err {
+            Error::Command(err) => return Resp3Piece::from(err),
+            Error::BrokenProtocol(msg) => CommandError::Err(format!("Protocol error: {}", msg)),
+            Error::Unsupported(msg) | Error::Encode(msg) => CommandError::Err(msg.clone()),
+            other => CommandError::Err(format!("{}", other)),
+        };
+        Resp3Piece::from(&err)
+    }
 }
@@ -0,0 +1,913 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Error, Result},
+    object::{RedisString, Value},
+    protocol::Resp3Piece,
+    util::parse_i64,
+};
+
+use super::{lower_bytes, not_integer_error, string::STRING_MAX_SIZE, syntax_error, Args};
+
+/// Operations of BITOP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+    /// Bits of the first key which are set in none of the others.
+    Diff,
+    /// Bits set in any of the other keys but not in the first one.
+    Diff1,
+    /// Bits of the first key which are also set in at least one of the others.
+    AndOr,
+    /// Bits set in exactly one of the keys.
+    One,
+}
+
+impl BitOp {
+    fn parse(op: &[u8]) -> Option<Self> {
+        Some(match op {
+            b"and" => Self::And,
+            b"or" => Self::Or,
+            b"xor" => Self::Xor,
+            b"not" => Self::Not,
+            b"diff" => Self::Diff,
+            b"diff1" => Self::Diff1,
+            b"andor" => Self::AndOr,
+            b"one" => Self::One,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::And => "AND",
+            Self::Or => "OR",
+            Self::Xor => "XOR",
+            Self::Not => "NOT",
+            Self::Diff => "DIFF",
+            Self::Diff1 => "DIFF1",
+            Self::AndOr => "ANDOR",
+            Self::One => "ONE",
+        }
+    }
+
+    /// Computes the operation on the sources, which are all as long as the result.
+    fn apply(&self, srcs: &[Vec<u8>], len: usize) -> Vec<u8> {
+        let mut result = vec![0u8; len];
+        match self {
+            Self::Not => {
+                for (r, s) in result.iter_mut().zip(&srcs[0]) {
+                    *r = !s;
+                }
+            }
+            Self::And | Self::Or | Self::Xor => {
+                result.copy_from_slice(&srcs[0]);
+                for src in &srcs[1..] {
+                    for (r, s) in result.iter_mut().zip(src) {
+                        match self {
+                            Self::And => *r &= s,
+                            Self::Or => *r |= s,
+                            _ => *r ^= s,
+                        }
+                    }
+                }
+            }
+            Self::Diff | Self::Diff1 | Self::AndOr => {
+                // the union of all the keys but the first one.
+                for src in &srcs[1..] {
+                    for (r, s) in result.iter_mut().zip(src) {
+                        *r |= s;
+                    }
+                }
+                for (r, s) in result.iter_mut().zip(&srcs[0]) {
+                    *r = match self {
+                        Self::Diff => s & !*r,
+                        Self::Diff1 => !s & *r,
+                        _ => s & *r,
+                    };
+                }
+            }
+            Self::One => {
+                let mut twice = vec![0u8; len];
+                for src in srcs {
+                    for ((r, t), s) in result.iter_mut().zip(twice.iter_mut()).zip(src) {
+                        *t |= *r & s;
+                        *r |= s;
+                    }
+                }
+                for (r, t) in result.iter_mut().zip(twice) {
+                    *r &= !t;
+                }
+            }
+        }
+        result
+    }
+}
+
+/// What BITFIELD does when an integer overflows on SET or INCRBY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wrap around, with the semantics of two's complement for signed integers.
+    Wrap,
+    /// Saturate to the min or max value of the integer.
+    Sat,
+    /// Do nothing, and reply a null.
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitfieldAction {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+/// A subcommand of BITFIELD, working on an integer of `bits` bits at a bit offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitfieldOp {
+    action: BitfieldAction,
+    signed: bool,
+    bits: u32,
+    offset: u64,
+    overflow: Overflow,
+}
+
+impl BitfieldOp {
+    /// Parses a type like `i16` or `u8`. Unsigned integers are at most 63 bits long, so
+    /// that they can be replied as signed integers.
+    fn parse_type(arg: &[u8]) -> Result<(bool, u32)> {
+        let signed = match arg.first() {
+            Some(b'i' | b'I') => true,
+            Some(b'u' | b'U') => false,
+            _ => return Err(Self::type_error()),
+        };
+        let bits = parse_i64(&arg[1..]).ok_or_else(Self::type_error)?;
+        if bits < 1 || (signed && bits > 64) || (!signed && bits > 63) {
+            return Err(Self::type_error());
+        }
+        Ok((signed, bits as u32))
+    }
+
+    fn type_error() -> Error {
+        CommandError::Err(
+            "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.".into(),
+        )
+        .into()
+    }
+
+    /// Parses an offset, which is multiplied by the size of the integer when it is
+    /// prefixed by `#`.
+    fn parse_offset(arg: &[u8], bits: u32) -> Result<u64> {
+        let (multiply, arg) = match arg {
+            [b'#', rest @ ..] => (true, rest),
+            _ => (false, arg),
+        };
+        let offset = parse_i64(arg)
+            .filter(|&offset| offset >= 0)
+            .and_then(|offset| {
+                if multiply {
+                    (offset as u64).checked_mul(bits as u64)
+                } else {
+                    Some(offset as u64)
+                }
+            })
+            .filter(|&offset| offset + (bits as u64) <= STRING_MAX_SIZE * 8);
+        offset.ok_or_else(|| {
+            CommandError::Err("bit offset is not an integer or out of range".into()).into()
+        })
+    }
+
+    fn min(&self) -> i128 {
+        if self.signed {
+            -(1i128 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed {
+            (1i128 << (self.bits - 1)) - 1
+        } else {
+            (1i128 << self.bits) - 1
+        }
+    }
+
+    /// Fits a value into the integer as the overflow behavior says, or returns `None`
+    /// when it overflows and it should fail.
+    fn fit(&self, value: i128) -> Option<i64> {
+        if value >= self.min() && value <= self.max() {
+            return Some(value as i64);
+        }
+        match self.overflow {
+            Overflow::Fail => None,
+            Overflow::Sat if value > self.max() => Some(self.max() as i64),
+            Overflow::Sat => Some(self.min() as i64),
+            Overflow::Wrap => Some(self.decode(value as u64 & self.mask())),
+        }
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits)
+    }
+
+    /// Interprets the raw bits of the integer, sign extending them if it is signed.
+    fn decode(&self, raw: u64) -> i64 {
+        if self.signed && self.bits < 64 && raw & (1 << (self.bits - 1)) != 0 {
+            (raw | !self.mask()) as i64
+        } else {
+            raw as i64
+        }
+    }
+
+    /// Offset of the byte after the last one of the integer.
+    fn end_byte(&self) -> usize {
+        ((self.offset + self.bits as u64 - 1) / 8 + 1) as usize
+    }
+
+    /// Executes the subcommand, returning its reply and the new value of the integer
+    /// if it must be written.
+    fn execute(&self, data: &[u8]) -> (Resp3Piece, Option<i64>) {
+        let old = self.decode(get_bits(data, self.offset, self.bits));
+        let (new, reply) = match self.action {
+            BitfieldAction::Get => return (Resp3Piece::Integer(old), None),
+            // unsigned integers overflow for negative values, which are huge values.
+            BitfieldAction::Set(value) if !self.signed && value < 0 => {
+                (self.fit(value as u64 as i128), old)
+            }
+            BitfieldAction::Set(value) => (self.fit(value as i128), old),
+            BitfieldAction::IncrBy(by) => {
+                let new = self.fit(old as i128 + by as i128);
+                (new, new.unwrap_or_default())
+            }
+        };
+        match new {
+            Some(new) => (Resp3Piece::Integer(reply), Some(new)),
+            None => (Resp3Piece::Null, None),
+        }
+    }
+}
+
+/// Reads the bits of the integer at the bit offset, as an unsigned integer. Bits beyond
+/// the end of the string are zeros.
+fn get_bits(data: &[u8], offset: u64, bits: u32) -> u64 {
+    let mut value = 0;
+    for i in offset..offset + bits as u64 {
+        let bit = match data.get((i >> 3) as usize) {
+            Some(byte) => (byte >> (7 - (i & 7))) & 1,
+            None => 0,
+        };
+        value = (value << 1) | bit as u64;
+    }
+    value
+}
+
+/// Writes the lowest `bits` bits of the value at the bit offset, the string being long
+/// enough.
+fn set_bits(data: &mut [u8], offset: u64, bits: u32, value: u64) {
+    for (j, i) in (offset..offset + bits as u64).enumerate() {
+        let bit = (value >> (bits as usize - 1 - j)) & 1;
+        let byte = &mut data[(i >> 3) as usize];
+        let mask = 1 << (7 - (i & 7));
+        if bit == 1 {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+}
+
+/// Commands working on strings as arrays of bits, the first bit being the most
+/// significant bit of the first byte.
+#[derive(Debug)]
+pub enum BitmapCommand {
+    SetBit {
+        key: Bytes,
+        offset: u64,
+        on: bool,
+    },
+    GetBit {
+        key: Bytes,
+        offset: u64,
+    },
+    /// `BITCOUNT key [start end [BYTE | BIT]]`
+    BitCount {
+        key: Bytes,
+        range: Option<(i64, i64, bool)>,
+    },
+    /// `BITPOS key bit [start [end [BYTE | BIT]]]`
+    BitPos {
+        key: Bytes,
+        bit: bool,
+        start: i64,
+        end: Option<i64>,
+        is_bit: bool,
+    },
+    BitOp {
+        op: BitOp,
+        dest: Bytes,
+        keys: Vec<Bytes>,
+    },
+    /// `BITFIELD` and `BITFIELD_RO`, the latter only accepting GET.
+    BitField {
+        key: Bytes,
+        ops: Vec<BitfieldOp>,
+    },
+}
+
+impl BitmapCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "setbit" => {
+                let key = args.next_arg()?;
+                let offset = parse_bit_offset(&args.next_arg()?)?;
+                let on = match args.next_arg()?.as_ref() {
+                    b"0" => false,
+                    b"1" => true,
+                    _ => {
+                        return Err(CommandError::Err(
+                            "bit is not an integer or out of range".into(),
+                        )
+                        .into())
+                    }
+                };
+                Self::SetBit { key, offset, on }
+            }
+            "getbit" => Self::GetBit {
+                key: args.next_arg()?,
+                offset: parse_bit_offset(&args.next_arg()?)?,
+            },
+            "bitcount" => {
+                let key = args.next_arg()?;
+                let range = match args.remaining() {
+                    0 => None,
+                    1 => return Err(syntax_error()),
+                    _ => {
+                        let start = args.next_i64()?;
+                        let end = args.next_i64()?;
+                        Some((start, end, Self::parse_unit(args)?))
+                    }
+                };
+                Self::BitCount { key, range }
+            }
+            "bitpos" => {
+                let key = args.next_arg()?;
+                let bit = match parse_i64(&args.next_arg()?) {
+                    Some(0) => false,
+                    Some(1) => true,
+                    Some(_) => {
+                        return Err(CommandError::Err(
+                            "The bit argument must be 1 or 0.".into(),
+                        )
+                        .into())
+                    }
+                    None => return Err(not_integer_error()),
+                };
+                let start = if args.remaining() > 0 { args.next_i64()? } else { 0 };
+                let end = if args.remaining() > 0 {
+                    Some(args.next_i64()?)
+                } else {
+                    None
+                };
+                Self::BitPos {
+                    key,
+                    bit,
+                    start,
+                    end,
+                    is_bit: Self::parse_unit(args)?,
+                }
+            }
+            "bitfield" | "bitfield_ro" => Self::parse_bitfield(args, name == "bitfield_ro")?,
+            "bitop" => {
+                let op = args.next_arg()?;
+                let op = BitOp::parse(&lower_bytes(&op)).ok_or_else(syntax_error)?;
+                let dest = args.next_arg()?;
+                let keys = args.rest()?;
+                match op {
+                    BitOp::Not if keys.len() != 1 => {
+                        return Err(CommandError::Err(
+                            "BITOP NOT must be called with a single source key.".into(),
+                        )
+                        .into())
+                    }
+                    BitOp::Diff | BitOp::Diff1 | BitOp::AndOr if keys.len() < 2 => {
+                        return Err(CommandError::Err(format!(
+                            "BITOP {} must be called with at least two source keys.",
+                            op.name()
+                        ))
+                        .into())
+                    }
+                    _ => {}
+                }
+                Self::BitOp { op, dest, keys }
+            }
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    fn parse_bitfield(args: &mut Args, read_only: bool) -> Result<Self> {
+        let key = args.next_arg()?;
+        let mut ops = vec![];
+        let mut overflow = Overflow::Wrap;
+        while let Some(sub) = args.next_lower() {
+            if sub == b"overflow" {
+                let kind = args.next_lower().ok_or_else(syntax_error)?;
+                overflow = match kind.as_slice() {
+                    b"wrap" => Overflow::Wrap,
+                    b"sat" => Overflow::Sat,
+                    b"fail" => Overflow::Fail,
+                    _ => {
+                        return Err(
+                            CommandError::Err("Invalid OVERFLOW type specified".into()).into()
+                        )
+                    }
+                };
+                continue;
+            }
+            if !matches!(sub.as_slice(), b"get" | b"set" | b"incrby") {
+                return Err(syntax_error());
+            }
+            let typ = args.next_opt().ok_or_else(syntax_error)?;
+            let offset = args.next_opt().ok_or_else(syntax_error)?;
+            let (signed, bits) = BitfieldOp::parse_type(&typ)?;
+            let offset = BitfieldOp::parse_offset(&offset, bits)?;
+            let action = match sub.as_slice() {
+                b"get" => BitfieldAction::Get,
+                _ if read_only => {
+                    return Err(CommandError::Err(
+                        "BITFIELD_RO only supports the GET subcommand".into(),
+                    )
+                    .into())
+                }
+                sub => {
+                    let value = args.next_opt().ok_or_else(syntax_error)?;
+                    let value = parse_i64(&value).ok_or_else(not_integer_error)?;
+                    if sub == b"set" {
+                        BitfieldAction::Set(value)
+                    } else {
+                        BitfieldAction::IncrBy(value)
+                    }
+                }
+            };
+            ops.push(BitfieldOp {
+                action,
+                signed,
+                bits,
+                offset,
+                overflow,
+            });
+        }
+        Ok(Self::BitField { key, ops })
+    }
+
+    /// Parses the optional `BYTE | BIT` unit ending ranges, telling whether they are in
+    /// bits.
+    fn parse_unit(args: &mut Args) -> Result<bool> {
+        let is_bit = match args.next_lower().as_deref() {
+            None | Some(b"byte") => false,
+            Some(b"bit") => true,
+            Some(_) => return Err(syntax_error()),
+        };
+        if args.remaining() > 0 {
+            return Err(syntax_error());
+        }
+        Ok(is_bit)
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::SetBit { key, offset, on } => {
+                let byte = (offset >> 3) as usize;
+                let mask = 1u8 << (7 - (offset & 7));
+                let value = match db.lookup_mut(&key) {
+                    Some(value) => value.as_string_mut()?,
+                    None => {
+                        let mut data = vec![0; byte + 1];
+                        if on {
+                            data[byte] |= mask;
+                        }
+                        db.set(key, Value::String(RedisString::Raw(data.into())), false);
+                        return Ok(Resp3Piece::Integer(0));
+                    }
+                };
+                let old = value.modify(|data| {
+                    if data.len() <= byte {
+                        data.resize(byte + 1, 0);
+                    }
+                    let old = data[byte] & mask != 0;
+                    if on {
+                        data[byte] |= mask;
+                    } else {
+                        data[byte] &= !mask;
+                    }
+                    old
+                });
+                Ok(Resp3Piece::Integer(old as i64))
+            }
+            Self::GetBit { key, offset } => {
+                let data = match db.lookup(&key) {
+                    Some(value) => value.as_string()?.to_bytes(),
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let bit = match data.get((offset >> 3) as usize) {
+                    Some(byte) => byte & (1 << (7 - (offset & 7))) != 0,
+                    None => false,
+                };
+                Ok(Resp3Piece::Integer(bit as i64))
+            }
+            Self::BitCount { key, range } => {
+                let data = match db.lookup(&key) {
+                    Some(value) => value.as_string()?.to_bytes(),
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let (start, end, is_bit) = range.unwrap_or((0, -1, false));
+                let range = match BitRange::new(data.len(), start, end, is_bit) {
+                    Some(range) => range,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let bytes = &data[range.start..=range.end];
+                let mut count = popcount(bytes);
+                count -= (bytes[0] & range.first_mask).count_ones() as u64;
+                count -= (bytes[bytes.len() - 1] & range.last_mask).count_ones() as u64;
+                Ok(Resp3Piece::Integer(count as i64))
+            }
+            Self::BitPos {
+                key,
+                bit,
+                start,
+                end,
+                is_bit,
+            } => {
+                let data = match db.lookup(&key) {
+                    Some(value) => value.as_string()?.to_bytes(),
+                    // a missing key is an empty string, which is all clear bits.
+                    None => return Ok(Resp3Piece::Integer(if bit { -1 } else { 0 })),
+                };
+                let range = match BitRange::new(data.len(), start, end.unwrap_or(-1), is_bit) {
+                    Some(range) => range,
+                    None => return Ok(Resp3Piece::Integer(-1)),
+                };
+                let bytes = &data[range.start..=range.end];
+                let found = if range.first_mask == 0 && range.last_mask == 0 {
+                    bitpos(bytes, bit)
+                } else {
+                    // bits out of the range are made to never match.
+                    let mut bytes = bytes.to_vec();
+                    let last = bytes.len() - 1;
+                    if bit {
+                        bytes[0] &= !range.first_mask;
+                        bytes[last] &= !range.last_mask;
+                    } else {
+                        bytes[0] |= range.first_mask;
+                        bytes[last] |= range.last_mask;
+                    }
+                    bitpos(&bytes, bit)
+                };
+                let pos = match found {
+                    Some(pos) => pos,
+                    None => return Ok(Resp3Piece::Integer(-1)),
+                };
+                // looking for a clear bit, the string is considered padded with zeros on
+                // the right, unless the end of the range was given.
+                if !bit && end.is_some() && pos == bytes.len() as u64 * 8 {
+                    return Ok(Resp3Piece::Integer(-1));
+                }
+                Ok(Resp3Piece::Integer((pos + range.start as u64 * 8) as i64))
+            }
+            Self::BitOp { op, dest, keys } => {
+                let mut srcs = Vec::with_capacity(keys.len());
+                for key in &keys {
+                    srcs.push(match db.lookup(key) {
+                        Some(value) => value.as_string()?.to_bytes(),
+                        None => Bytes::new(),
+                    });
+                }
+                let len = srcs.iter().map(Bytes::len).max().unwrap_or(0);
+                if len == 0 {
+                    db.remove(&dest);
+                    return Ok(Resp3Piece::Integer(0));
+                }
+                // shorter strings are padded with zeros.
+                let srcs: Vec<Vec<u8>> = srcs
+                    .into_iter()
+                    .map(|src| {
+                        let mut src = src.to_vec();
+                        src.resize(len, 0);
+                        src
+                    })
+                    .collect();
+                let result = op.apply(&srcs, len);
+                db.set(dest, Value::String(RedisString::Raw(result.into())), false);
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::BitField { key, ops } => {
+                let write_end = ops
+                    .iter()
+                    .filter(|op| op.action != BitfieldAction::Get)
+                    .map(BitfieldOp::end_byte)
+                    .max();
+                let write_end = match write_end {
+                    Some(end) => end,
+                    None => {
+                        // only GETs, the key is not created.
+                        let data = match db.lookup(&key) {
+                            Some(value) => value.as_string()?.to_bytes(),
+                            None => Bytes::new(),
+                        };
+                        let replies = ops.iter().map(|op| op.execute(&data).0).collect();
+                        return Ok(Resp3Piece::Array(replies));
+                    }
+                };
+                if !db.exists(&key) {
+                    db.set(key.clone(), Value::String(RedisString::Raw(Bytes::new())), false);
+                }
+                let value = db.lookup_mut(&key).unwrap().as_string_mut()?;
+                let replies = value.modify(|data| {
+                    // the string grows to fit every write, even those which fail.
+                    if data.len() < write_end {
+                        data.resize(write_end, 0);
+                    }
+                    ops.iter()
+                        .map(|op| {
+                            let (reply, new) = op.execute(data);
+                            if let Some(new) = new {
+                                set_bits(data, op.offset, op.bits, new as u64);
+                            }
+                            reply
+                        })
+                        .collect()
+                });
+                Ok(Resp3Piece::Array(replies))
+            }
+        }
+    }
+}
+
+/// A range of a string given in bytes or in bits. The bytes of the range are inclusive,
+/// and the masks tell the bits of the first and the last byte which are out of the
+/// range.
+struct BitRange {
+    start: usize,
+    end: usize,
+    first_mask: u8,
+    last_mask: u8,
+}
+
+impl BitRange {
+    /// Resolves the inclusive range of BITCOUNT and BITPOS, whose offsets count from the
+    /// end when they are negative. `None` is returned when the range is empty.
+    fn new(len: usize, start: i64, end: i64, is_bit: bool) -> Option<Self> {
+        if start < 0 && end < 0 && start > end {
+            return None;
+        }
+        let total = if is_bit { len as i64 * 8 } else { len as i64 };
+        let start = if start < 0 { total + start } else { start }.max(0);
+        let end = if end < 0 { total + end } else { end }.max(0).min(total - 1);
+        if start > end {
+            return None;
+        }
+        if !is_bit {
+            return Some(Self {
+                start: start as usize,
+                end: end as usize,
+                first_mask: 0,
+                last_mask: 0,
+            });
+        }
+        Some(Self {
+            start: (start >> 3) as usize,
+            end: (end >> 3) as usize,
+            first_mask: !(0xffu8 >> (start & 7)),
+            last_mask: (1u16 << (7 - (end & 7))) as u8 - 1,
+        })
+    }
+}
+
+/// Counts the set bits, a word at a time.
+fn popcount(bytes: &[u8]) -> u64 {
+    let mut chunks = bytes.chunks_exact(8);
+    let mut count: u64 = chunks
+        .by_ref()
+        .map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()).count_ones() as u64)
+        .sum();
+    count += chunks
+        .remainder()
+        .iter()
+        .map(|b| b.count_ones() as u64)
+        .sum::<u64>();
+    count
+}
+
+/// Finds the position of the first bit set to `bit`. Looking for a clear bit in bytes
+/// which have none, the position right after them is returned, as if the bytes were
+/// followed by zeros.
+fn bitpos(bytes: &[u8], bit: bool) -> Option<u64> {
+    let skip = if bit { 0u8 } else { 0xff };
+    // whole words which can not match are skipped first.
+    let words = bytes
+        .chunks_exact(8)
+        .take_while(|chunk| chunk.iter().all(|&b| b == skip))
+        .count();
+    let start = words * 8;
+    for (i, &byte) in bytes[start..].iter().enumerate() {
+        let byte = if bit { byte } else { !byte };
+        if byte != 0 {
+            return Some(((start + i) * 8) as u64 + byte.leading_zeros() as u64);
+        }
+    }
+    if bit {
+        None
+    } else {
+        Some(bytes.len() as u64 * 8)
+    }
+}
+
+fn parse_bit_offset(arg: &[u8]) -> Result<u64> {
+    match parse_i64(arg) {
+        Some(offset) if offset >= 0 && (offset as u64) < STRING_MAX_SIZE * 8 => Ok(offset as u64),
+        _ => Err(Error::from(CommandError::Err(
+            "bit offset is not an integer or out of range".into(),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+    };
+
+    fn int(db: &mut Db, args: &[&str]) -> i64 {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Integer(n) => n,
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn setbit_and_getbit() {
+        let mut db = Db::new();
+        assert_eq!(int(&mut db, &["setbit", "k", "7", "1"]), 0);
+        assert_eq!(int(&mut db, &["setbit", "k", "7", "1"]), 1);
+        assert_eq!(exec(&mut db, &["get", "k"]).unwrap(), bulk("\u{1}"));
+        assert_eq!(int(&mut db, &["setbit", "k", "17", "1"]), 0);
+        assert_eq!(int(&mut db, &["strlen", "k"]), 3);
+        assert_eq!(int(&mut db, &["getbit", "k", "17"]), 1);
+        assert_eq!(int(&mut db, &["getbit", "k", "16"]), 0);
+        assert_eq!(int(&mut db, &["getbit", "k", "1000"]), 0);
+        assert_eq!(int(&mut db, &["setbit", "k", "7", "0"]), 1);
+        assert!(exec(&mut db, &["setbit", "k", "1", "2"]).is_err());
+        assert!(exec(&mut db, &["setbit", "k", "-1", "1"]).is_err());
+        assert!(exec(&mut db, &["setbit", "k", "4294967296", "1"]).is_err());
+        exec(&mut db, &["set", "n", "1"]).unwrap();
+        assert_eq!(int(&mut db, &["getbit", "n", "2"]), 1);
+    }
+
+    #[test]
+    fn bitcount() {
+        let mut db = Db::new();
+        exec(&mut db, &["set", "k", "foobar"]).unwrap();
+        assert_eq!(int(&mut db, &["bitcount", "k"]), 26);
+        assert_eq!(int(&mut db, &["bitcount", "k", "0", "0"]), 4);
+        assert_eq!(int(&mut db, &["bitcount", "k", "1", "1"]), 6);
+        assert_eq!(int(&mut db, &["bitcount", "k", "1", "1", "byte"]), 6);
+        assert_eq!(int(&mut db, &["bitcount", "k", "5", "30", "bit"]), 17);
+        assert_eq!(int(&mut db, &["bitcount", "k", "-2", "-1"]), 7);
+        assert_eq!(int(&mut db, &["bitcount", "k", "-1", "-2"]), 0);
+        assert_eq!(int(&mut db, &["bitcount", "nokey"]), 0);
+        assert!(exec(&mut db, &["bitcount", "k", "0"]).is_err());
+        assert!(exec(&mut db, &["bitcount", "k", "0", "1", "word"]).is_err());
+        let long = "x".repeat(1000);
+        exec(&mut db, &["set", "long", &long]).unwrap();
+        assert_eq!(int(&mut db, &["bitcount", "long", "3", "-4"]), 994 * 4);
+    }
+
+    #[test]
+    fn bitpos() {
+        let mut db = Db::new();
+        for (i, byte) in ["255", "240", "0"].iter().enumerate() {
+            let byte: u8 = byte.parse().unwrap();
+            for bit in 0..8 {
+                let on = (byte >> (7 - bit)) & 1;
+                exec(&mut db, &["setbit", "k", &(i * 8 + bit).to_string(), &on.to_string()])
+                    .unwrap();
+            }
+        }
+        assert_eq!(int(&mut db, &["bitpos", "k", "0"]), 12);
+        assert_eq!(int(&mut db, &["bitpos", "k", "1", "2"]), -1);
+        assert_eq!(int(&mut db, &["bitpos", "k", "1", "1"]), 8);
+        assert_eq!(int(&mut db, &["bitpos", "k", "1", "7", "15", "bit"]), 7);
+        assert_eq!(int(&mut db, &["bitpos", "k", "0", "0", "0"]), -1);
+        assert_eq!(int(&mut db, &["bitpos", "k", "0", "2", "-1", "bit"]), 12);
+        exec(&mut db, &["set", "ones", "\u{7f}"]).unwrap();
+        exec(&mut db, &["setbit", "ones", "0", "1"]).unwrap();
+        assert_eq!(int(&mut db, &["bitpos", "ones", "0"]), 8);
+        assert_eq!(int(&mut db, &["bitpos", "ones", "0", "0", "-1"]), -1);
+        assert_eq!(int(&mut db, &["bitpos", "nokey", "0"]), 0);
+        assert_eq!(int(&mut db, &["bitpos", "nokey", "1"]), -1);
+        assert!(exec(&mut db, &["bitpos", "k", "2"]).is_err());
+    }
+
+    fn ints(db: &mut Db, args: &[&str]) -> Vec<Option<i64>> {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    Resp3Piece::Integer(n) => Some(n),
+                    Resp3Piece::Null => None,
+                    other => panic!("unexpected reply {:?}", other),
+                })
+                .collect(),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bitfield() {
+        let mut db = Db::new();
+        assert_eq!(ints(&mut db, &["bitfield", "k", "get", "u8", "0"]), [Some(0)]);
+        assert!(!db.exists(b"k"));
+        assert_eq!(
+            ints(&mut db, &["bitfield", "k", "set", "i8", "#1", "-100", "get", "u4", "8", "get", "i8", "8"]),
+            [Some(0), Some(9), Some(-100)]
+        );
+        assert_eq!(exec(&mut db, &["strlen", "k"]).unwrap(), Resp3Piece::Integer(2));
+        assert_eq!(
+            ints(&mut db, &["bitfield", "k", "incrby", "i8", "8", "-100", "incrby", "i8", "8", "1"]),
+            [Some(56), Some(57)]
+        );
+        assert_eq!(
+            ints(&mut db, &[
+                "bitfield", "k", "overflow", "sat", "incrby", "i8", "8", "100", "overflow", "fail",
+                "incrby", "i8", "8", "1", "overflow", "wrap", "incrby", "i8", "8", "1",
+            ]),
+            [Some(127), None, Some(-128)]
+        );
+        assert_eq!(
+            ints(&mut db, &[
+                "bitfield", "c", "set", "u2", "0", "5", "overflow", "sat", "set", "u2", "2", "-1",
+                "incrby", "u2", "2", "-5", "overflow", "fail", "set", "u2", "4", "4", "get", "u6",
+                "0",
+            ]),
+            [Some(0), Some(0), Some(0), None, Some(16)]
+        );
+        assert_eq!(
+            ints(&mut db, &["bitfield", "big", "set", "i64", "0", "-1", "get", "u63", "0", "incrby",
+                "i64", "0", "-9223372036854775808"]),
+            [Some(0), Some(i64::MAX), Some(i64::MAX)]
+        );
+        assert_eq!(
+            ints(&mut db, &["bitfield_ro", "big", "get", "i64", "0", "get", "u1", "100000"]),
+            [Some(i64::MAX), Some(0)]
+        );
+        for args in [
+            &["bitfield", "k", "get", "u64", "0"][..],
+            &["bitfield", "k", "get", "i65", "0"],
+            &["bitfield", "k", "get", "x8", "0"],
+            &["bitfield", "k", "get", "u8", "-1"],
+            &["bitfield", "k", "get", "u8", "#4294967296"],
+            &["bitfield", "k", "set", "u8", "0"],
+            &["bitfield", "k", "set", "u8", "0", "a"],
+            &["bitfield", "k", "overflow", "oops"],
+            &["bitfield", "k", "unknown"],
+            &["bitfield_ro", "k", "set", "u8", "0", "1"],
+        ] {
+            assert!(exec(&mut db, args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn bitop() {
+        let mut db = Db::new();
+        exec(&mut db, &["set", "a", "\u{3}"]).unwrap();
+        exec(&mut db, &["set", "b", "\u{5}\u{1}"]).unwrap();
+        exec(&mut db, &["set", "c", "\u{6}"]).unwrap();
+        for (op, expected) in [
+            ("and", "\u{0}\u{0}"),
+            ("or", "\u{7}\u{1}"),
+            ("xor", "\u{0}\u{1}"),
+            ("diff", "\u{0}\u{0}"),
+            ("diff1", "\u{4}\u{1}"),
+            ("andor", "\u{3}\u{0}"),
+            ("one", "\u{0}\u{1}"),
+        ] {
+            assert_eq!(int(&mut db, &["bitop", op, "d", "a", "b", "c"]), 2, "{}", op);
+            assert_eq!(exec(&mut db, &["get", "d"]).unwrap(), bulk(expected), "{}", op);
+        }
+        assert_eq!(int(&mut db, &["bitop", "not", "d", "a"]), 1);
+        assert_eq!(exec(&mut db, &["getbit", "d", "0"]).unwrap(), Resp3Piece::Integer(1));
+        assert_eq!(int(&mut db, &["bitop", "or", "d", "x", "y"]), 0);
+        assert!(!db.exists(b"d"));
+        assert!(exec(&mut db, &["bitop", "not", "d", "a", "b"]).is_err());
+        assert!(exec(&mut db, &["bitop", "diff", "d", "a"]).is_err());
+        assert!(exec(&mut db, &["bitop", "nand", "d", "a"]).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Result},
+    object::Value,
+    protocol::Resp3Piece,
+    util::parse_f64,
+};
+
+use super::{
+    list::{self, parse_mpop, End},
+    zset, Args, ReadGroup,
+};
+
+/// Operations of the blocking commands, which are the non blocking commands they are
+/// named after.
+#[derive(Debug)]
+pub enum BlockingOp {
+    /// `BLPOP key [key ...] timeout` and `BRPOP`.
+    Pop { keys: Vec<Bytes>, end: End },
+    /// `BLMOVE source destination LEFT | RIGHT LEFT | RIGHT timeout`, and `BRPOPLPUSH`.
+    Move {
+        src: Bytes,
+        dst: Bytes,
+        from: End,
+        to: End,
+    },
+    /// `BLMPOP timeout numkeys key [key ...] LEFT | RIGHT [COUNT count]`
+    MPop {
+        keys: Vec<Bytes>,
+        end: End,
+        count: usize,
+    },
+    /// `BZPOPMIN key [key ...] timeout` and `BZPOPMAX`.
+    ZPop { keys: Vec<Bytes>, max: bool },
+    /// `BZMPOP timeout numkeys key [key ...] MIN | MAX [COUNT count]`
+    ZMPop {
+        keys: Vec<Bytes>,
+        max: bool,
+        count: usize,
+    },
+    /// XREADGROUP with BLOCK.
+    ReadGroup(ReadGroup),
+}
+
+/// A command which blocks the client until one of its keys can serve it, or until the
+/// timeout elapses.
+#[derive(Debug)]
+pub struct BlockingCommand {
+    pub op: BlockingOp,
+    /// Timeout in milliseconds, zero to block forever.
+    pub timeout: u64,
+}
+
+impl BlockingCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let (op, timeout) = match name {
+            "blpop" | "brpop" | "bzpopmin" | "bzpopmax" => {
+                let mut keys = args.rest()?;
+                let timeout = keys.pop().unwrap();
+                if keys.is_empty() {
+                    return Err(args.arity_error());
+                }
+                let op = match name {
+                    "blpop" => BlockingOp::Pop { keys, end: End::Left },
+                    "brpop" => BlockingOp::Pop { keys, end: End::Right },
+                    _ => BlockingOp::ZPop {
+                        keys,
+                        max: name == "bzpopmax",
+                    },
+                };
+                (op, timeout)
+            }
+            "blmove" => {
+                let op = BlockingOp::Move {
+                    src: args.next_arg()?,
+                    dst: args.next_arg()?,
+                    from: End::parse(&args.next_arg()?)?,
+                    to: End::parse(&args.next_arg()?)?,
+                };
+                (op, args.next_arg()?)
+            }
+            "brpoplpush" => {
+                let op = BlockingOp::Move {
+                    src: args.next_arg()?,
+                    dst: args.next_arg()?,
+                    from: End::Right,
+                    to: End::Left,
+                };
+                (op, args.next_arg()?)
+            }
+            "blmpop" => {
+                let timeout = args.next_arg()?;
+                let (keys, end, count) = parse_mpop(args)?;
+                (BlockingOp::MPop { keys, end, count }, timeout)
+            }
+            "bzmpop" => {
+                let timeout = args.next_arg()?;
+                let (keys, max, count) = zset::parse_mpop(args)?;
+                (BlockingOp::ZMPop { keys, max, count }, timeout)
+            }
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(Self {
+            op,
+            timeout: parse_timeout(&timeout)?,
+        }))
+    }
+
+    /// Keys the command waits for.
+    pub fn keys(&self) -> Vec<Bytes> {
+        match &self.op {
+            BlockingOp::Pop { keys, .. }
+            | BlockingOp::MPop { keys, .. }
+            | BlockingOp::ZPop { keys, .. }
+            | BlockingOp::ZMPop { keys, .. } => keys.clone(),
+            BlockingOp::Move { src, .. } => vec![src.clone()],
+            BlockingOp::ReadGroup(read) => read.keys(),
+        }
+    }
+
+    /// Whether the value is of the type the command waits for. Clients waiting for
+    /// another type stay blocked when the key gets the value, as in redis.
+    pub fn waits_for(&self, value: &Value) -> bool {
+        match self.op {
+            BlockingOp::Pop { .. } | BlockingOp::Move { .. } | BlockingOp::MPop { .. } => {
+                matches!(value, Value::List(_))
+            }
+            BlockingOp::ZPop { .. } | BlockingOp::ZMPop { .. } => {
+                matches!(value, Value::ZSet(_))
+            }
+            BlockingOp::ReadGroup(_) => matches!(value, Value::Stream(_)),
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.timeout {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Executes the command if one of its keys can serve it, `None` meaning that the
+    /// client has to wait.
+    pub fn try_execute(&self, db: &mut Db) -> Result<Option<Resp3Piece>> {
+        self.execute_on(db, None)
+    }
+
+    /// Serves the client from the key signaled as ready, which is one of its keys. Its
+    /// other keys are left alone even when they come first, as `serveClientBlockedOnList`
+    /// of redis does, so a key which got a value of another type meanwhile does not fail
+    /// the command.
+    pub fn try_serve(&self, db: &mut Db, key: &Bytes) -> Result<Option<Resp3Piece>> {
+        self.execute_on(db, Some(key))
+    }
+
+    /// Executes the command on its keys, or only on `ready` if given.
+    fn execute_on(&self, db: &mut Db, ready: Option<&Bytes>) -> Result<Option<Resp3Piece>> {
+        let ready = ready.map(std::slice::from_ref);
+        let reply = match &self.op {
+            BlockingOp::Pop { keys, end } => {
+                list::mpop(db, ready.unwrap_or(keys), *end, 1)?.map(|(key, mut elements)| {
+                    let element = Resp3Piece::bulk(elements.remove(0));
+                    Resp3Piece::Array(vec![Resp3Piece::bulk(key), element])
+                })
+            }
+            BlockingOp::Move { src, dst, from, to } => {
+                list::lmove(db, src, dst, *from, *to)?.map(Resp3Piece::bulk)
+            }
+            BlockingOp::MPop { keys, end, count } => {
+                list::mpop(db, ready.unwrap_or(keys), *end, *count)?.map(|(key, elements)| {
+                    let elements = elements.into_iter().map(Resp3Piece::bulk).collect();
+                    Resp3Piece::Array(vec![Resp3Piece::bulk(key), Resp3Piece::Array(elements)])
+                })
+            }
+            BlockingOp::ZPop { keys, max } => {
+                zset::mpop(db, ready.unwrap_or(keys), *max, 1)?.map(|(key, mut elements)| {
+                    let (member, score) = elements.remove(0);
+                    Resp3Piece::Array(vec![
+                        Resp3Piece::bulk(key),
+                        Resp3Piece::bulk(member),
+                        Resp3Piece::Double(score),
+                    ])
+                })
+            }
+            BlockingOp::ZMPop { keys, max, count } => {
+                zset::mpop(db, ready.unwrap_or(keys), *max, *count)?
+                    .map(|(key, elements)| zset::mpop_reply(key, elements))
+            }
+            BlockingOp::ReadGroup(read) => read.read(db, ready)?,
+        };
+        Ok(reply)
+    }
+
+    /// Reply of a command which times out.
+    pub fn timeout_reply(&self) -> Resp3Piece {
+        match self.op {
+            BlockingOp::Move { .. } => Resp3Piece::Null,
+            _ => Resp3Piece::NullArray,
+        }
+    }
+
+    /// Executes the command without blocking, as inside MULTI, where a command which
+    /// can not be served times out right away.
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        Ok(self.try_execute(db)?.unwrap_or_else(|| self.timeout_reply()))
+    }
+}
+
+/// Parses a timeout in seconds, which may have a fractional part, into milliseconds.
+fn parse_timeout(arg: &[u8]) -> Result<u64> {
+    let err = |msg: &str| CommandError::Err(msg.to_string()).into();
+    let secs = parse_f64(arg).ok_or_else(|| err("timeout is not a float or out of range"))?;
+    if secs < 0.0 {
+        return Err(err("timeout is negative"));
+    }
+    let ms = (secs * 1000.0).ceil();
+    if ms > i64::MAX as f64 {
+        return Err(err("timeout is out of range"));
+    }
+    Ok(ms as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+    };
+
+    #[test]
+    fn parse_timeouts() {
+        let mut db = Db::new();
+        assert!(exec(&mut db, &["blpop", "0"]).is_err());
+        assert!(exec(&mut db, &["blpop", "l", "-1"]).is_err());
+        assert!(exec(&mut db, &["blpop", "l", "one"]).is_err());
+        assert!(exec(&mut db, &["blpop", "l", "inf"]).is_err());
+        assert!(exec(&mut db, &["blmpop", "0.1", "0", "l", "left"]).is_err());
+        assert!(exec(&mut db, &["blmove", "a", "b", "left", "up", "1"]).is_err());
+        assert_eq!(exec(&mut db, &["brpop", "l", "0.001"]).unwrap(), Resp3Piece::NullArray);
+    }
+
+    #[test]
+    fn served_without_blocking() {
+        let mut db = Db::new();
+        exec(&mut db, &["rpush", "b", "1", "2", "3"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["blpop", "a", "b", "0"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("b"), bulk("1")])
+        );
+        assert_eq!(exec(&mut db, &["brpoplpush", "b", "a", "0"]).unwrap(), bulk("3"));
+        assert_eq!(exec(&mut db, &["blmove", "x", "a", "left", "left", "0"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(
+            exec(&mut db, &["blmpop", "0", "2", "x", "b", "right", "count", "3"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("b"), Resp3Piece::Array(vec![bulk("2")])])
+        );
+        exec(&mut db, &["set", "s", "v"]).unwrap();
+        assert!(exec(&mut db, &["blpop", "x", "s", "0"]).is_err());
+    }
+
+    #[test]
+    fn sorted_sets_served_without_blocking() {
+        let mut db = Db::new();
+        exec(&mut db, &["zadd", "z", "1", "a", "2", "b", "3", "c"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["bzpopmax", "x", "z", "0"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("z"), bulk("c"), Resp3Piece::Double(3.0)])
+        );
+        assert_eq!(
+            exec(&mut db, &["bzmpop", "1", "2", "x", "z", "min", "count", "5"]).unwrap(),
+            Resp3Piece::Array(vec![
+                bulk("z"),
+                Resp3Piece::Array(vec![
+                    Resp3Piece::Array(vec![bulk("a"), Resp3Piece::Double(1.0)]),
+                    Resp3Piece::Array(vec![bulk("b"), Resp3Piece::Double(2.0)]),
+                ]),
+            ])
+        );
+        assert_eq!(exec(&mut db, &["bzpopmin", "z", "0.01"]).unwrap(), Resp3Piece::NullArray);
+        assert!(exec(&mut db, &["bzpopmin", "0"]).is_err());
+        assert!(exec(&mut db, &["bzmpop", "0", "1", "z", "left"]).is_err());
+        exec(&mut db, &["rpush", "l", "v"]).unwrap();
+        assert!(exec(&mut db, &["bzpopmin", "l", "0"]).is_err());
+    }
+}
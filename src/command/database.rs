@@ -0,0 +1,178 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Databases,
+    error::{CommandError, Error, Result},
+    protocol::Resp3Piece,
+    util::parse_i64,
+};
+
+use super::{not_integer_error, syntax_error, Args};
+
+/// Commands working on whole databases rather than on keys of the selected one.
+#[derive(Debug)]
+pub enum DbCommand {
+    Select { index: Bytes },
+    Move { key: Bytes, index: Bytes },
+    SwapDb { first: Bytes, second: Bytes },
+    FlushDb { lazy: bool },
+    FlushAll { lazy: bool },
+    DbSize,
+}
+
+impl DbCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "select" => Self::Select {
+                index: args.next_arg()?,
+            },
+            "move" => Self::Move {
+                key: args.next_arg()?,
+                index: args.next_arg()?,
+            },
+            "swapdb" => Self::SwapDb {
+                first: args.next_arg()?,
+                second: args.next_arg()?,
+            },
+            "flushdb" => Self::FlushDb {
+                lazy: Self::parse_flush_mode(args)?,
+            },
+            "flushall" => Self::FlushAll {
+                lazy: Self::parse_flush_mode(args)?,
+            },
+            "dbsize" => Self::DbSize,
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    /// Parses the optional `ASYNC | SYNC` of FLUSHDB and FLUSHALL.
+    fn parse_flush_mode(args: &mut Args) -> Result<bool> {
+        let lazy = match args.next_lower().as_deref() {
+            None | Some(b"sync") => false,
+            Some(b"async") => true,
+            Some(_) => return Err(syntax_error()),
+        };
+        Ok(lazy)
+    }
+
+    /// Executes the command on the databases, `selected` being the database selected by
+    /// the client, which SELECT changes.
+    pub fn execute(self, dbs: &mut Databases, selected: &mut usize) -> Result<Resp3Piece> {
+        match self {
+            Self::Select { index } => {
+                *selected = db_index(dbs, &index).ok_or_else(not_integer_error)??;
+                Ok(Resp3Piece::ok())
+            }
+            Self::Move { key, index } => {
+                let index = db_index(dbs, &index).ok_or_else(not_integer_error)??;
+                if index == *selected {
+                    return Err(CommandError::Err(
+                        "source and destination objects are the same".into(),
+                    )
+                    .into());
+                }
+                let (src, dst) = dbs.get_pair_mut(*selected, index);
+                if !src.exists(&key) || dst.exists(&key) {
+                    return Ok(Resp3Piece::Integer(0));
+                }
+                let (key, value, when) = src.take_entry(&key).unwrap();
+                dst.insert_entry(key, value, when);
+                Ok(Resp3Piece::Integer(1))
+            }
+            Self::SwapDb { first, second } => {
+                let invalid = |which: &str| {
+                    Error::from(CommandError::Err(format!("invalid {} DB index", which)))
+                };
+                let first = db_index(dbs, &first).ok_or_else(|| invalid("first"))??;
+                let second = db_index(dbs, &second).ok_or_else(|| invalid("second"))??;
+                dbs.swap(first, second);
+                Ok(Resp3Piece::ok())
+            }
+            Self::FlushDb { lazy } => {
+                let old = dbs.get_mut(*selected).flush();
+                free(old, lazy);
+                Ok(Resp3Piece::ok())
+            }
+            Self::FlushAll { lazy } => {
+                let old = dbs.flush_all();
+                free(old, lazy);
+                Ok(Resp3Piece::ok())
+            }
+            Self::DbSize => Ok(Resp3Piece::Integer(dbs.get_mut(*selected).len() as i64)),
+        }
+    }
+}
+
+/// Parses a database index. `None` is returned when it is not an integer, so that
+/// commands can reply their own error, and an error when it is out of range.
+fn db_index(dbs: &Databases, index: &[u8]) -> Option<Result<usize>> {
+    let index = parse_i64(index)?;
+    if index < 0 || index as usize >= dbs.len() {
+        return Some(Err(CommandError::Err("DB index is out of range".into()).into()));
+    }
+    Some(Ok(index as usize))
+}
+
+/// Drops flushed databases, in a thread of its own for the ASYNC flavors, so that a big
+/// keyspace does not stall the server while it is freed.
+fn free<T: Send + 'static>(old: T, lazy: bool) {
+    if lazy {
+        std::thread::spawn(move || drop(old));
+    } else {
+        drop(old);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec_on},
+        db::Databases,
+        protocol::Resp3Piece,
+    };
+
+    #[test]
+    fn select_and_move() {
+        let mut dbs = Databases::new(4);
+        let mut selected = 0;
+        let mut exec = |args: &[&str]| exec_on(&mut dbs, &mut selected, args);
+        exec(&["set", "k", "v", "ex", "100"]).unwrap();
+        assert_eq!(exec(&["move", "k", "1"]).unwrap(), Resp3Piece::Integer(1));
+        assert_eq!(exec(&["move", "k", "1"]).unwrap(), Resp3Piece::Integer(0));
+        assert_eq!(exec(&["dbsize"]).unwrap(), Resp3Piece::Integer(0));
+        assert!(exec(&["select", "4"]).is_err());
+        assert!(exec(&["select", "one"]).is_err());
+        assert_eq!(exec(&["select", "1"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(exec(&["get", "k"]).unwrap(), bulk("v"));
+        assert!(exec(&["ttl", "k"]).unwrap() != Resp3Piece::Integer(-1));
+        assert!(exec(&["move", "k", "1"]).is_err());
+        exec(&["set", "k2", "v"]).unwrap();
+        assert_eq!(exec(&["dbsize"]).unwrap(), Resp3Piece::Integer(2));
+    }
+
+    #[test]
+    fn swap_and_flush() {
+        let mut dbs = Databases::new(3);
+        let mut selected = 0;
+        let mut exec = |args: &[&str]| exec_on(&mut dbs, &mut selected, args);
+        exec(&["set", "a", "0"]).unwrap();
+        assert_eq!(exec(&["swapdb", "0", "2"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(exec(&["get", "a"]).unwrap(), Resp3Piece::Null);
+        assert!(exec(&["swapdb", "0", "x"]).is_err());
+        assert!(exec(&["swapdb", "0", "3"]).is_err());
+        assert_eq!(exec(&["swapdb", "2", "0"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(exec(&["get", "a"]).unwrap(), bulk("0"));
+        assert_eq!(exec(&["flushdb", "async"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(exec(&["dbsize"]).unwrap(), Resp3Piece::Integer(0));
+        exec(&["set", "a", "0"]).unwrap();
+        exec(&["select", "1"]).unwrap();
+        exec(&["set", "b", "1"]).unwrap();
+        assert!(exec(&["flushall", "now"]).is_err());
+        assert_eq!(exec(&["flushall", "sync"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(exec(&["dbsize"]).unwrap(), Resp3Piece::Integer(0));
+        exec(&["select", "0"]).unwrap();
+        assert_eq!(exec(&["dbsize"]).unwrap(), Resp3Piece::Integer(0));
+    }
+}
@@ -0,0 +1,927 @@
+use std::{collections::HashSet, hash::Hash};
+
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Result},
+    object::{RedisHash, Value},
+    protocol::Resp3Piece,
+    util::{format_f64, now_ms, parse_f64, parse_i64, random},
+};
+
+use super::{
+    keyspace::{invalid_expire_error, ExpireCondition},
+    list::parse_positive,
+    not_float_error, syntax_error, Args, Expiry,
+};
+
+/// Latest time fields may expire at, in milliseconds, as redis keeps it on 48 bits.
+const HASH_FIELD_MAX_EXPIRE: u64 = (1 << 48) - 1;
+
+/// Commands working on hashes.
+#[derive(Debug)]
+pub enum HashCommand {
+    /// `HSET key field value [field value ...]`, and `HMSET` which replies OK.
+    Set {
+        key: Bytes,
+        pairs: Vec<(Bytes, Bytes)>,
+        reply_ok: bool,
+    },
+    SetNx {
+        key: Bytes,
+        field: Bytes,
+        value: Bytes,
+    },
+    Get {
+        key: Bytes,
+        field: Bytes,
+    },
+    MGet {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    Del {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    Exists {
+        key: Bytes,
+        field: Bytes,
+    },
+    Len {
+        key: Bytes,
+    },
+    /// `HKEYS`, `HVALS` and `HGETALL`.
+    GetAll {
+        key: Bytes,
+        fields: bool,
+        values: bool,
+    },
+    IncrBy {
+        key: Bytes,
+        field: Bytes,
+        by: i64,
+    },
+    IncrByFloat {
+        key: Bytes,
+        field: Bytes,
+        by: f64,
+    },
+    StrLen {
+        key: Bytes,
+        field: Bytes,
+    },
+    /// `HRANDFIELD key [count [WITHVALUES]]`
+    RandField {
+        key: Bytes,
+        count: Option<i64>,
+        with_values: bool,
+    },
+    /// `HEXPIRE key seconds [NX | XX | GT | LT] FIELDS numfields field [field ...]`, and
+    /// `HPEXPIRE`, `HEXPIREAT` and `HPEXPIREAT`.
+    Expire {
+        key: Bytes,
+        expiry: Expiry,
+        condition: ExpireCondition,
+        fields: Vec<Bytes>,
+    },
+    /// `HTTL key FIELDS numfields field [field ...]`, and `HPTTL`, `HEXPIRETIME` and
+    /// `HPEXPIRETIME`.
+    Ttl {
+        key: Bytes,
+        fields: Vec<Bytes>,
+        millis: bool,
+        absolute: bool,
+    },
+    /// `HPERSIST key FIELDS numfields field [field ...]`
+    Persist {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    /// `HGETEX key [EX seconds | PX ms | EXAT time | PXAT time | PERSIST]
+    /// FIELDS numfields field [field ...]`
+    GetEx {
+        key: Bytes,
+        expiry: Option<Expiry>,
+        persist: bool,
+        fields: Vec<Bytes>,
+    },
+    /// `HSETEX key [FNX | FXX] [EX seconds | PX ms | EXAT time | PXAT time | KEEPTTL]
+    /// FIELDS numfields field value [field value ...]`
+    SetEx {
+        key: Bytes,
+        only_new: bool,
+        only_existing: bool,
+        expiry: Option<Expiry>,
+        keep_ttl: bool,
+        pairs: Vec<(Bytes, Bytes)>,
+    },
+}
+
+impl HashCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "hset" | "hmset" => Self::Set {
+                key: args.next_arg()?,
+                pairs: args.pairs()?,
+                reply_ok: name == "hmset",
+            },
+            "hsetnx" => Self::SetNx {
+                key: args.next_arg()?,
+                field: args.next_arg()?,
+                value: args.next_arg()?,
+            },
+            "hget" => Self::Get {
+                key: args.next_arg()?,
+                field: args.next_arg()?,
+            },
+            "hmget" => Self::MGet {
+                key: args.next_arg()?,
+                fields: args.rest()?,
+            },
+            "hdel" => Self::Del {
+                key: args.next_arg()?,
+                fields: args.rest()?,
+            },
+            "hexists" => Self::Exists {
+                key: args.next_arg()?,
+                field: args.next_arg()?,
+            },
+            "hlen" => Self::Len { key: args.next_arg()? },
+            "hkeys" | "hvals" | "hgetall" => Self::GetAll {
+                key: args.next_arg()?,
+                fields: name != "hvals",
+                values: name != "hkeys",
+            },
+            "hincrby" => Self::IncrBy {
+                key: args.next_arg()?,
+                field: args.next_arg()?,
+                by: args.next_i64()?,
+            },
+            "hincrbyfloat" => Self::IncrByFloat {
+                key: args.next_arg()?,
+                field: args.next_arg()?,
+                by: parse_f64(&args.next_arg()?).ok_or_else(not_float_error)?,
+            },
+            "hstrlen" => Self::StrLen {
+                key: args.next_arg()?,
+                field: args.next_arg()?,
+            },
+            "hrandfield" => {
+                let key = args.next_arg()?;
+                let count = match args.next_opt() {
+                    Some(count) => Some(parse_i64(&count).ok_or_else(super::not_integer_error)?),
+                    None => None,
+                };
+                let with_values = match args.next_lower().as_deref() {
+                    None => false,
+                    Some(b"withvalues") if args.remaining() == 0 => true,
+                    Some(_) => return Err(syntax_error()),
+                };
+                Self::RandField {
+                    key,
+                    count,
+                    with_values,
+                }
+            }
+            "hexpire" | "hpexpire" | "hexpireat" | "hpexpireat" => {
+                let key = args.next_arg()?;
+                let unit = match name {
+                    "hexpire" => b"ex".as_slice(),
+                    "hpexpire" => b"px",
+                    "hexpireat" => b"exat",
+                    _ => b"pxat",
+                };
+                let expiry = Expiry::parse(unit, &args.next_arg()?)?;
+                let mut opt = args.next_lower();
+                let condition = match opt.as_deref().and_then(ExpireCondition::single) {
+                    Some(condition) => {
+                        opt = args.next_lower();
+                        condition
+                    }
+                    None => ExpireCondition::default(),
+                };
+                parse_numfields(opt, args, 1)?;
+                Self::Expire {
+                    key,
+                    expiry,
+                    condition,
+                    fields: args.rest()?,
+                }
+            }
+            "httl" | "hpttl" | "hexpiretime" | "hpexpiretime" => {
+                let key = args.next_arg()?;
+                parse_numfields(args.next_lower(), args, 1)?;
+                Self::Ttl {
+                    key,
+                    fields: args.rest()?,
+                    millis: name.starts_with("hp"),
+                    absolute: name.ends_with("time"),
+                }
+            }
+            "hpersist" => {
+                let key = args.next_arg()?;
+                parse_numfields(args.next_lower(), args, 1)?;
+                Self::Persist {
+                    key,
+                    fields: args.rest()?,
+                }
+            }
+            "hgetex" => Self::parse_getex(args)?,
+            "hsetex" => Self::parse_setex(args)?,
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    fn parse_getex(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let mut expiry = None;
+        let mut persist = false;
+        let opt = loop {
+            let opt = args.next_lower();
+            match opt.as_deref() {
+                Some(b"persist") if expiry.is_none() && !persist => persist = true,
+                Some(unit) if Expiry::is_expire_option(unit) && expiry.is_none() && !persist => {
+                    let value = args.next_opt().ok_or_else(syntax_error)?;
+                    expiry = Some(Expiry::parse(unit, &value)?);
+                }
+                Some(b"fields") | None => break opt,
+                Some(_) => return Err(syntax_error()),
+            }
+        };
+        parse_numfields(opt, args, 1)?;
+        Ok(Self::GetEx {
+            key,
+            expiry,
+            persist,
+            fields: args.rest()?,
+        })
+    }
+
+    fn parse_setex(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let (mut only_new, mut only_existing) = (false, false);
+        let mut expiry = None;
+        let mut keep_ttl = false;
+        let opt = loop {
+            let opt = args.next_lower();
+            match opt.as_deref() {
+                Some(b"fnx") if !only_new && !only_existing => only_new = true,
+                Some(b"fxx") if !only_new && !only_existing => only_existing = true,
+                Some(b"keepttl") if expiry.is_none() && !keep_ttl => keep_ttl = true,
+                Some(unit) if Expiry::is_expire_option(unit) && expiry.is_none() && !keep_ttl => {
+                    let value = args.next_opt().ok_or_else(syntax_error)?;
+                    expiry = Some(Expiry::parse(unit, &value)?);
+                }
+                Some(b"fields") | None => break opt,
+                Some(_) => return Err(syntax_error()),
+            }
+        };
+        parse_numfields(opt, args, 2)?;
+        Ok(Self::SetEx {
+            key,
+            only_new,
+            only_existing,
+            expiry,
+            keep_ttl,
+            pairs: args.pairs()?,
+        })
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::Set {
+                key,
+                pairs,
+                reply_ok,
+            } => {
+                let limits = *db.limits();
+                let hash = hash_or_create(db, key)?;
+                let added = pairs
+                    .iter()
+                    .filter(|(field, value)| hash.set(field, value, false, &limits))
+                    .count();
+                Ok(if reply_ok {
+                    Resp3Piece::ok()
+                } else {
+                    Resp3Piece::Integer(added as i64)
+                })
+            }
+            Self::SetNx { key, field, value } => {
+                let limits = *db.limits();
+                let hash = hash_or_create(db, key)?;
+                if hash.contains(&field) {
+                    return Ok(Resp3Piece::Integer(0));
+                }
+                hash.set(&field, &value, false, &limits);
+                Ok(Resp3Piece::Integer(1))
+            }
+            Self::Get { key, field } => {
+                let value = match db.lookup(&key) {
+                    Some(value) => value.as_hash()?.get(&field),
+                    None => None,
+                };
+                Ok(bulk_or_null(value))
+            }
+            Self::MGet { key, fields } => {
+                let hash = match db.lookup(&key) {
+                    Some(value) => Some(value.as_hash()?),
+                    None => None,
+                };
+                let values = fields
+                    .iter()
+                    .map(|field| bulk_or_null(hash.and_then(|hash| hash.get(field))))
+                    .collect();
+                Ok(Resp3Piece::Array(values))
+            }
+            Self::Del { key, fields } => {
+                let hash = match db.lookup_mut(&key) {
+                    Some(value) => value.as_hash_mut()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let deleted = fields.iter().filter(|field| hash.remove(field)).count();
+                if hash.is_empty() {
+                    db.remove(&key);
+                }
+                Ok(Resp3Piece::Integer(deleted as i64))
+            }
+            Self::Exists { key, field } => {
+                let exists = match db.lookup(&key) {
+                    Some(value) => value.as_hash()?.contains(&field),
+                    None => false,
+                };
+                Ok(Resp3Piece::Integer(exists as i64))
+            }
+            Self::Len { key } => {
+                let len = match db.lookup(&key) {
+                    Some(value) => value.as_hash()?.len(),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::GetAll {
+                key,
+                fields,
+                values,
+            } => {
+                let hash = match db.lookup(&key) {
+                    Some(value) => value.as_hash()?,
+                    None if fields && values => return Ok(Resp3Piece::Map(vec![])),
+                    None => return Ok(Resp3Piece::Array(vec![])),
+                };
+                let bulk = |data: &[u8]| Resp3Piece::bulk(Bytes::copy_from_slice(data));
+                Ok(match (fields, values) {
+                    (true, true) => {
+                        Resp3Piece::Map(hash.iter().map(|(f, v)| (bulk(f), bulk(v))).collect())
+                    }
+                    (true, false) => Resp3Piece::Array(hash.iter().map(|(f, _)| bulk(f)).collect()),
+                    _ => Resp3Piece::Array(hash.iter().map(|(_, v)| bulk(v)).collect()),
+                })
+            }
+            Self::IncrBy { key, field, by } => {
+                let limits = *db.limits();
+                let hash = hash_or_create(db, key)?;
+                let current = match hash.get(&field) {
+                    Some(value) => parse_i64(value).ok_or_else(|| {
+                        CommandError::Err("hash value is not an integer".into())
+                    })?,
+                    None => 0,
+                };
+                let n = current.checked_add(by).ok_or_else(|| {
+                    CommandError::Err("increment or decrement would overflow".into())
+                })?;
+                hash.set(&field, n.to_string().as_bytes(), true, &limits);
+                Ok(Resp3Piece::Integer(n))
+            }
+            Self::IncrByFloat { key, field, by } => {
+                let limits = *db.limits();
+                let hash = hash_or_create(db, key)?;
+                let current = match hash.get(&field) {
+                    Some(value) => parse_f64(value)
+                        .ok_or_else(|| CommandError::Err("hash value is not a float".into()))?,
+                    None => 0.0,
+                };
+                let n = current + by;
+                if !n.is_finite() {
+                    return Err(CommandError::Err(
+                        "increment would produce NaN or Infinity".into(),
+                    )
+                    .into());
+                }
+                let data = Bytes::from(format_f64(n));
+                hash.set(&field, &data, true, &limits);
+                Ok(Resp3Piece::bulk(data))
+            }
+            Self::StrLen { key, field } => {
+                let len = match db.lookup(&key) {
+                    Some(value) => value.as_hash()?.get(&field).map_or(0, |value| value.len()),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::RandField {
+                key,
+                count,
+                with_values,
+            } => {
+                let hash = match db.lookup(&key) {
+                    Some(value) => value.as_hash()?,
+                    None if count.is_some() => return Ok(Resp3Piece::Array(vec![])),
+                    None => return Ok(Resp3Piece::Null),
+                };
+                let mut pick = hash.random_picker();
+                let count = match count {
+                    Some(count) => count,
+                    None => return Ok(Resp3Piece::bulk(Bytes::copy_from_slice(pick().0))),
+                };
+                if count < -i64::MAX || (with_values && count < -i64::MAX / 2) {
+                    return Err(CommandError::Err("value is out of range".into()).into());
+                }
+                let entries = random_picks(hash.len(), count, pick, || hash.iter().collect());
+                let bulk = |data: &[u8]| Resp3Piece::bulk(Bytes::copy_from_slice(data));
+                Ok(if with_values {
+                    Resp3Piece::Pairs(entries.map(|(f, v)| (bulk(f), bulk(v))).collect())
+                } else {
+                    Resp3Piece::Array(entries.map(|(f, _)| bulk(f)).collect())
+                })
+            }
+            Self::Expire {
+                key,
+                expiry,
+                condition,
+                fields,
+            } => {
+                let cmd = match expiry {
+                    Expiry::Ex(_) => "hexpire",
+                    Expiry::Px(_) => "hpexpire",
+                    Expiry::ExAt(_) => "hexpireat",
+                    Expiry::PxAt(_) => "hpexpireat",
+                };
+                let when = resolve_field_expiry(&expiry, cmd)?;
+                let hash = match db.lookup_mut(&key) {
+                    Some(value) => value.as_hash_mut()?,
+                    None => return Ok(integers(fields.iter().map(|_| -2))),
+                };
+                let now = now_ms();
+                let replies: Vec<i64> = fields
+                    .iter()
+                    .map(|field| {
+                        if !hash.contains(field) {
+                            -2
+                        } else if !condition.allows(hash.get_expire(field), when as i64) {
+                            0
+                        } else if when <= now {
+                            hash.remove(field);
+                            2
+                        } else {
+                            hash.set_expire(field, when);
+                            1
+                        }
+                    })
+                    .collect();
+                fields_changed(db, &key);
+                Ok(integers(replies))
+            }
+            Self::Ttl {
+                key,
+                fields,
+                millis,
+                absolute,
+            } => {
+                let hash = match db.lookup(&key) {
+                    Some(value) => value.as_hash()?,
+                    None => return Ok(integers(fields.iter().map(|_| -2))),
+                };
+                let now = now_ms();
+                Ok(integers(fields.iter().map(|field| {
+                    match hash.get_expire(field) {
+                        _ if !hash.contains(field) => -2,
+                        None => -1,
+                        Some(when) if absolute && millis => when as i64,
+                        Some(when) if absolute => (when / 1000) as i64,
+                        Some(when) if millis => when.saturating_sub(now) as i64,
+                        Some(when) => when.saturating_sub(now).div_ceil(1000) as i64,
+                    }
+                })))
+            }
+            Self::Persist { key, fields } => {
+                let hash = match db.lookup_mut(&key) {
+                    Some(value) => value.as_hash_mut()?,
+                    None => return Ok(integers(fields.iter().map(|_| -2))),
+                };
+                let replies: Vec<i64> = fields
+                    .iter()
+                    .map(|field| match hash.contains(field) {
+                        false => -2,
+                        true if hash.persist(field) => 1,
+                        true => -1,
+                    })
+                    .collect();
+                fields_changed(db, &key);
+                Ok(integers(replies))
+            }
+            Self::GetEx {
+                key,
+                expiry,
+                persist,
+                fields,
+            } => {
+                let when = match expiry {
+                    Some(expiry) => Some(resolve_field_expiry(&expiry, "hgetex")?),
+                    None => None,
+                };
+                let hash = match db.lookup_mut(&key) {
+                    Some(value) => value.as_hash_mut()?,
+                    None => return Ok(Resp3Piece::Array(vec![Resp3Piece::Null; fields.len()])),
+                };
+                let now = now_ms();
+                let mut values = Vec::with_capacity(fields.len());
+                for field in &fields {
+                    values.push(bulk_or_null(hash.get(field)));
+                    if !hash.contains(field) {
+                        continue;
+                    }
+                    match when {
+                        Some(when) if when <= now => {
+                            hash.remove(field);
+                        }
+                        Some(when) => hash.set_expire(field, when),
+                        None if persist => {
+                            hash.persist(field);
+                        }
+                        None => {}
+                    }
+                }
+                fields_changed(db, &key);
+                Ok(Resp3Piece::Array(values))
+            }
+            Self::SetEx {
+                key,
+                only_new,
+                only_existing,
+                expiry,
+                keep_ttl,
+                pairs,
+            } => {
+                let when = match expiry {
+                    Some(expiry) => Some(resolve_field_expiry(&expiry, "hsetex")?),
+                    None => None,
+                };
+                let existing = match db.lookup(&key) {
+                    Some(value) => {
+                        let hash = value.as_hash()?;
+                        pairs.iter().filter(|(field, _)| hash.contains(field)).count()
+                    }
+                    None => 0,
+                };
+                if (only_new && existing > 0) || (only_existing && existing < pairs.len()) {
+                    return Ok(Resp3Piece::Integer(0));
+                }
+                let limits = *db.limits();
+                let now = now_ms();
+                let hash = hash_or_create(db, key.clone())?;
+                for (field, value) in &pairs {
+                    hash.set(field, value, keep_ttl, &limits);
+                    match when {
+                        Some(when) if when <= now => {
+                            hash.remove(field);
+                        }
+                        Some(when) => hash.set_expire(field, when),
+                        None => {}
+                    }
+                }
+                fields_changed(db, &key);
+                Ok(Resp3Piece::Integer(1))
+            }
+        }
+    }
+}
+
+/// Parses the `FIELDS numfields field [field ...]` arguments ending the commands on
+/// expiring fields, `opt` being the argument where FIELDS is expected. Each field is
+/// followed by `width - 1` more arguments, its value for HSETEX.
+fn parse_numfields(opt: Option<Vec<u8>>, args: &mut Args, width: usize) -> Result<()> {
+    let err = |msg: &str| CommandError::Err(msg.to_string()).into();
+    if opt.as_deref() != Some(b"fields") {
+        return Err(err("Mandatory argument FIELDS is missing or not at the right position"));
+    }
+    let msg = "Parameter `numFields` should be greater than 0";
+    let numfields = parse_positive(&args.next_arg()?, msg, 1)?;
+    if numfields.checked_mul(width) != Some(args.remaining()) {
+        return Err(err("The `numfields` parameter must match the number of arguments"));
+    }
+    Ok(())
+}
+
+/// Resolves the unix time in milliseconds at which fields expire. Times in the past
+/// delete the fields, but unlike for keys they may not be negative, nor later than
+/// [`HASH_FIELD_MAX_EXPIRE`].
+fn resolve_field_expiry(expiry: &Expiry, cmd: &str) -> Result<u64> {
+    let (Expiry::Ex(value) | Expiry::Px(value) | Expiry::ExAt(value) | Expiry::PxAt(value)) =
+        *expiry;
+    if value < 0 {
+        return Err(CommandError::Err("invalid expire time, must be >= 0".into()).into());
+    }
+    match expiry.resolve_any(cmd)? as u64 {
+        when if when > HASH_FIELD_MAX_EXPIRE => Err(invalid_expire_error(cmd)),
+        when => Ok(when),
+    }
+}
+
+/// Updates the keyspace once fields of the hash have been given expire times, or have
+/// been removed for expiring right away. The hash is deleted when no field is left.
+fn fields_changed(db: &mut Db, key: &[u8]) {
+    if matches!(db.lookup(key), Some(Value::Hash(hash)) if hash.is_empty()) {
+        db.remove(key);
+    } else {
+        db.sync_field_expires(key);
+    }
+}
+
+fn integers(replies: impl IntoIterator<Item = i64>) -> Resp3Piece {
+    Resp3Piece::Array(replies.into_iter().map(Resp3Piece::Integer).collect())
+}
+
+/// Returns the hash of the key, which is created when it does not exist.
+fn hash_or_create(db: &mut Db, key: Bytes) -> Result<&mut RedisHash> {
+    if !db.exists(&key) {
+        db.set(key.clone(), Value::Hash(RedisHash::new()), false);
+    }
+    db.lookup_mut(&key).unwrap().as_hash_mut()
+}
+
+fn bulk_or_null(value: Option<&[u8]>) -> Resp3Piece {
+    match value {
+        Some(value) => Resp3Piece::bulk(Bytes::copy_from_slice(value)),
+        None => Resp3Piece::Null,
+    }
+}
+
+/// Picks random items of a collection of `len` items as HRANDFIELD, SRANDMEMBER and
+/// ZRANDMEMBER do with a count: exactly `-count` items which may repeat when it is
+/// negative, or else distinct items up to `count`. `pick` returns a random item, and
+/// `all` all of them.
+///
+/// Repeated items are picked one at a time as the reply is built, like redis writes
+/// them, so the collection is never copied and the reply grows with the picks.
+pub(super) fn random_picks<'a, T: Hash + Eq + 'a>(
+    len: usize,
+    count: i64,
+    mut pick: impl FnMut() -> T + 'a,
+    all: impl FnOnce() -> Vec<T>,
+) -> Box<dyn Iterator<Item = T> + 'a> {
+    if count < 0 {
+        let mut left = count.unsigned_abs();
+        // no size hint, so that the reply is not allocated for all the picks up front.
+        return Box::new(std::iter::from_fn(move || {
+            left = left.checked_sub(1)?;
+            Some(pick())
+        }));
+    }
+    let count = count as usize;
+    if count >= len {
+        return Box::new(all().into_iter());
+    }
+    if count * 3 > len {
+        // most items are picked, the others are removed at random.
+        let mut items = all();
+        while items.len() > count {
+            items.swap_remove(random() as usize % items.len());
+        }
+        return Box::new(items.into_iter());
+    }
+    let mut picked = HashSet::with_capacity(count);
+    while picked.len() < count {
+        picked.insert(pick());
+    }
+    Box::new(picked.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+    };
+
+    fn int(db: &mut Db, args: &[&str]) -> i64 {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Integer(n) => n,
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    fn len(reply: Resp3Piece) -> usize {
+        match reply {
+            Resp3Piece::Array(items) => items.len(),
+            Resp3Piece::Map(pairs) | Resp3Piece::Pairs(pairs) => pairs.len(),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_get_and_delete() {
+        let mut db = Db::new();
+        assert_eq!(int(&mut db, &["hset", "h", "a", "1", "b", "2"]), 2);
+        assert_eq!(int(&mut db, &["hset", "h", "a", "3", "c", "4"]), 1);
+        assert!(exec(&mut db, &["hset", "h", "a"]).is_err());
+        assert_eq!(exec(&mut db, &["hmset", "h", "d", "5"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(int(&mut db, &["hsetnx", "h", "a", "0"]), 0);
+        assert_eq!(int(&mut db, &["hsetnx", "h", "e", "6"]), 1);
+        assert_eq!(exec(&mut db, &["hget", "h", "a"]).unwrap(), bulk("3"));
+        assert_eq!(exec(&mut db, &["hget", "h", "x"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(
+            exec(&mut db, &["hmget", "h", "b", "x"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("2"), Resp3Piece::Null])
+        );
+        assert_eq!(int(&mut db, &["hexists", "h", "c"]), 1);
+        assert_eq!(int(&mut db, &["hstrlen", "h", "c"]), 1);
+        assert_eq!(int(&mut db, &["hlen", "h"]), 5);
+        assert_eq!(
+            exec(&mut db, &["hgetall", "h"]).unwrap(),
+            Resp3Piece::Map(vec![
+                (bulk("a"), bulk("3")),
+                (bulk("b"), bulk("2")),
+                (bulk("c"), bulk("4")),
+                (bulk("d"), bulk("5")),
+                (bulk("e"), bulk("6")),
+            ])
+        );
+        assert_eq!(len(exec(&mut db, &["hkeys", "h"]).unwrap()), 5);
+        assert_eq!(exec(&mut db, &["hvals", "nokey"]).unwrap(), Resp3Piece::Array(vec![]));
+        assert_eq!(int(&mut db, &["hdel", "h", "a", "b", "x"]), 2);
+        assert_eq!(int(&mut db, &["hdel", "h", "c", "d", "e"]), 3);
+        assert_eq!(int(&mut db, &["exists", "h"]), 0);
+        exec(&mut db, &["set", "s", "v"]).unwrap();
+        assert!(exec(&mut db, &["hget", "s", "a"]).is_err());
+        assert!(exec(&mut db, &["hset", "s", "a", "1"]).is_err());
+    }
+
+    #[test]
+    fn increments() {
+        let mut db = Db::new();
+        assert_eq!(int(&mut db, &["hincrby", "h", "n", "5"]), 5);
+        assert_eq!(int(&mut db, &["hincrby", "h", "n", "-7"]), -2);
+        assert!(exec(&mut db, &["hincrby", "h", "n", "9223372036854775807"]).is_ok());
+        assert!(exec(&mut db, &["hincrby", "h", "n", "3"]).is_err());
+        exec(&mut db, &["hset", "h", "s", "abc", "f", "1.5"]).unwrap();
+        assert!(exec(&mut db, &["hincrby", "h", "s", "1"]).is_err());
+        assert_eq!(exec(&mut db, &["hincrbyfloat", "h", "f", "0.25"]).unwrap(), bulk("1.75"));
+        assert_eq!(exec(&mut db, &["hincrbyfloat", "h", "g", "-3"]).unwrap(), bulk("-3"));
+        assert!(exec(&mut db, &["hincrbyfloat", "h", "s", "1"]).is_err());
+        assert!(exec(&mut db, &["hincrbyfloat", "h", "f", "inf"]).is_err());
+        assert!(exec(&mut db, &["hincrbyfloat", "h", "f", "x"]).is_err());
+        exec(&mut db, &["hset", "h", "k", "0.1"]).unwrap();
+        assert_eq!(exec(&mut db, &["hincrbyfloat", "h", "k", "0.2"]).unwrap(), bulk("0.3"));
+        assert_eq!(exec(&mut db, &["hincrbyfloat", "h", "t", "1e-20"]).unwrap(), bulk("0"));
+    }
+
+    #[test]
+    fn random_fields() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["hrandfield", "h"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["hrandfield", "h", "3"]).unwrap(), Resp3Piece::Array(vec![]));
+        exec(&mut db, &["hset", "h", "a", "1", "b", "2", "c", "3"]).unwrap();
+        assert!(matches!(exec(&mut db, &["hrandfield", "h"]).unwrap(), Resp3Piece::BulkString { .. }));
+        assert_eq!(len(exec(&mut db, &["hrandfield", "h", "2"]).unwrap()), 2);
+        assert_eq!(len(exec(&mut db, &["hrandfield", "h", "10", "withvalues"]).unwrap()), 3);
+        assert_eq!(len(exec(&mut db, &["hrandfield", "h", "-10"]).unwrap()), 10);
+        assert_eq!(len(exec(&mut db, &["hrandfield", "h", "0"]).unwrap()), 0);
+        match exec(&mut db, &["hrandfield", "h", "3"]).unwrap() {
+            Resp3Piece::Array(mut fields) => {
+                fields.sort_by_key(|field| format!("{:?}", field));
+                assert_eq!(fields, vec![bulk("a"), bulk("b"), bulk("c")]);
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(exec(&mut db, &["hrandfield", "h", "1", "values"]).is_err());
+        assert!(exec(&mut db, &["hrandfield", "h", "-9223372036854775807", "withvalues"]).is_err());
+        assert!(exec(&mut db, &["hrandfield", "h", "-9223372036854775808"]).is_err());
+        assert_eq!(len(exec(&mut db, &["hrandfield", "h", "-100000"]).unwrap()), 100000);
+        // a hash table, with distinct fields picked among all, or at random.
+        for i in 0..200 {
+            let field = format!("f{}", i);
+            exec(&mut db, &["hset", "t", &field, &field]).unwrap();
+        }
+        for count in ["150", "10", "-300"] {
+            let pairs = match exec(&mut db, &["hrandfield", "t", count, "withvalues"]).unwrap() {
+                Resp3Piece::Pairs(pairs) => pairs,
+                other => panic!("unexpected reply {:?}", other),
+            };
+            assert_eq!(pairs.len() as i64, count.parse::<i64>().unwrap().abs());
+            assert!(pairs.iter().all(|(field, value)| field == value));
+            let distinct: HashSet<_> =
+                pairs.iter().map(|(field, _)| format!("{:?}", field)).collect();
+            assert!(count.starts_with('-') || distinct.len() == pairs.len());
+        }
+    }
+
+    #[test]
+    fn encoding_conversion() {
+        let mut db = Db::new();
+        exec(&mut db, &["hset", "h", "a", "1"]).unwrap();
+        assert_eq!(exec(&mut db, &["object", "encoding", "h"]).unwrap(), bulk("listpack"));
+        let long = "x".repeat(65);
+        exec(&mut db, &["hset", "h", "b", &long]).unwrap();
+        assert_eq!(exec(&mut db, &["object", "encoding", "h"]).unwrap(), bulk("hashtable"));
+        for i in 0..129 {
+            exec(&mut db, &["hset", "big", &i.to_string(), "v"]).unwrap();
+            let encoding = if i < 128 { "listpack" } else { "hashtable" };
+            assert_eq!(exec(&mut db, &["object", "encoding", "big"]).unwrap(), bulk(encoding));
+        }
+        assert_eq!(exec(&mut db, &["object", "encoding", "nokey"]).unwrap(), Resp3Piece::Null);
+        assert!(exec(&mut db, &["object", "refcount", "h"]).is_err());
+    }
+
+    fn ints(db: &mut Db, args: &[&str]) -> Vec<i64> {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    Resp3Piece::Integer(n) => n,
+                    other => panic!("unexpected reply {:?}", other),
+                })
+                .collect(),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn field_expiration() {
+        let mut db = Db::new();
+        exec(&mut db, &["hset", "h", "a", "1", "b", "2", "c", "3"]).unwrap();
+        let args = ["hexpire", "h", "100", "fields", "2", "a", "x"];
+        assert_eq!(ints(&mut db, &args), vec![1, -2]);
+        assert_eq!(ints(&mut db, &["hexpire", "h", "200", "nx", "fields", "1", "a"]), vec![0]);
+        assert_eq!(ints(&mut db, &["hexpire", "h", "50", "gt", "fields", "1", "a"]), vec![0]);
+        assert_eq!(ints(&mut db, &["hexpire", "h", "200", "xx", "fields", "1", "a"]), vec![1]);
+        assert_eq!(ints(&mut db, &["hexpire", "h", "50", "xx", "fields", "1", "b"]), vec![0]);
+        assert_eq!(ints(&mut db, &["httl", "h", "fields", "3", "a", "b", "x"]), vec![200, -1, -2]);
+        let ms = ints(&mut db, &["hpttl", "h", "fields", "1", "a"])[0];
+        assert!(ms > 199_000 && ms <= 200_000);
+        let at = ints(&mut db, &["hpexpiretime", "h", "fields", "1", "a"])[0];
+        assert_eq!(ints(&mut db, &["hexpiretime", "h", "fields", "1", "a"]), vec![at / 1000]);
+        assert_eq!(exec(&mut db, &["object", "encoding", "h"]).unwrap(), bulk("listpackex"));
+        let args = ["hpersist", "h", "fields", "3", "a", "b", "x"];
+        assert_eq!(ints(&mut db, &args), vec![1, -1, -2]);
+        assert_eq!(ints(&mut db, &["httl", "nokey", "fields", "1", "a"]), vec![-2]);
+
+        assert_eq!(ints(&mut db, &["hpexpireat", "h", "1", "fields", "2", "b", "c"]), vec![2, 2]);
+        assert_eq!(int(&mut db, &["hlen", "h"]), 1);
+        assert_eq!(ints(&mut db, &["hexpire", "h", "0", "fields", "1", "a"]), vec![2]);
+        assert_eq!(int(&mut db, &["exists", "h"]), 0);
+
+        exec(&mut db, &["hset", "h", "a", "1"]).unwrap();
+        assert!(exec(&mut db, &["hexpire", "h", "-1", "fields", "1", "a"]).is_err());
+        assert!(exec(&mut db, &["hexpireat", "h", "281474976711", "fields", "1", "a"]).is_err());
+        assert!(exec(&mut db, &["hexpire", "h", "10", "a"]).is_err());
+        assert!(exec(&mut db, &["hexpire", "h", "10", "fields", "0"]).is_err());
+        assert!(exec(&mut db, &["hexpire", "h", "10", "fields", "2", "a"]).is_err());
+        assert!(exec(&mut db, &["hexpire", "h", "10", "nx", "xx", "fields", "1", "a"]).is_err());
+        exec(&mut db, &["set", "s", "v"]).unwrap();
+        assert!(exec(&mut db, &["httl", "s", "fields", "1", "a"]).is_err());
+    }
+
+    #[test]
+    fn get_and_set_with_expiration() {
+        let mut db = Db::new();
+        exec(&mut db, &["hset", "h", "a", "1", "b", "2"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["hgetex", "h", "ex", "100", "fields", "2", "a", "x"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("1"), Resp3Piece::Null])
+        );
+        assert_eq!(ints(&mut db, &["httl", "h", "fields", "2", "a", "b"]), vec![100, -1]);
+        exec(&mut db, &["hgetex", "h", "persist", "fields", "1", "a"]).unwrap();
+        assert_eq!(ints(&mut db, &["httl", "h", "fields", "1", "a"]), vec![-1]);
+        assert_eq!(
+            exec(&mut db, &["hgetex", "h", "pxat", "1", "fields", "1", "b"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("2")])
+        );
+        assert_eq!(int(&mut db, &["hexists", "h", "b"]), 0);
+        assert!(exec(&mut db, &["hgetex", "h", "ex", "1", "persist", "fields", "1", "a"]).is_err());
+
+        let args = ["hsetex", "h", "fnx", "ex", "100", "fields", "2", "a", "3", "c", "4"];
+        assert_eq!(int(&mut db, &args), 0);
+        assert_eq!(int(&mut db, &["hsetex", "h", "fxx", "fields", "1", "c", "4"]), 0);
+        assert_eq!(int(&mut db, &["hsetex", "h", "fnx", "px", "5000", "fields", "1", "c", "4"]), 1);
+        assert_eq!(int(&mut db, &["hsetex", "h", "fxx", "keepttl", "fields", "1", "c", "5"]), 1);
+        assert_eq!(ints(&mut db, &["httl", "h", "fields", "1", "c"]), vec![5]);
+        assert_eq!(int(&mut db, &["hsetex", "h", "fields", "1", "c", "6"]), 1);
+        assert_eq!(ints(&mut db, &["httl", "h", "fields", "1", "c"]), vec![-1]);
+        assert_eq!(exec(&mut db, &["hget", "h", "c"]).unwrap(), bulk("6"));
+        assert!(exec(&mut db, &["hsetex", "h", "fields", "1", "c"]).is_err());
+        assert!(exec(&mut db, &["hsetex", "h", "fnx", "fxx", "fields", "1", "c", "1"]).is_err());
+        let args = ["hsetex", "h", "keepttl", "ex", "1", "fields", "1", "c", "1"];
+        assert!(exec(&mut db, &args).is_err());
+
+        let args = ["hsetex", "n", "exat", "1", "fields", "1", "a", "1"];
+        assert_eq!(int(&mut db, &args), 1);
+        assert_eq!(int(&mut db, &["exists", "n"]), 0);
+    }
+}
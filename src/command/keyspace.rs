@@ -0,0 +1,338 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Error, Result},
+    protocol::Resp3Piece,
+    util::{now_ms, parse_i64},
+};
+
+use super::{lower_bytes, not_integer_error, Args};
+
+/// Expire time given by options like `EX seconds`, kept as given until the command is
+/// executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    Ex(i64),
+    Px(i64),
+    ExAt(i64),
+    PxAt(i64),
+}
+
+impl Expiry {
+    /// Parses the value of an expire option. `opt` is the option in lowercase, and is
+    /// known to be one of `ex`, `px`, `exat` and `pxat`.
+    pub fn parse(opt: &[u8], value: &[u8]) -> Result<Self> {
+        let value = parse_i64(value).ok_or_else(not_integer_error)?;
+        Ok(match opt {
+            b"ex" => Self::Ex(value),
+            b"px" => Self::Px(value),
+            b"exat" => Self::ExAt(value),
+            _ => Self::PxAt(value),
+        })
+    }
+
+    pub fn is_expire_option(opt: &[u8]) -> bool {
+        matches!(opt, b"ex" | b"px" | b"exat" | b"pxat")
+    }
+
+    /// Resolves the unix time in milliseconds at which the key expires. Times which are
+    /// not positive or overflow are rejected, as redis does.
+    pub fn resolve(&self, cmd: &str) -> Result<u64> {
+        let value = match *self {
+            Self::Ex(v) | Self::Px(v) | Self::ExAt(v) | Self::PxAt(v) => v,
+        };
+        if value <= 0 {
+            return Err(invalid_expire_error(cmd));
+        }
+        self.resolve_any(cmd).map(|when| when as u64)
+    }
+
+    /// Like [`Expiry::resolve`], but times in the past are accepted, and may even be
+    /// negative. EXPIRE and friends delete the key for such times.
+    pub fn resolve_any(&self, cmd: &str) -> Result<i64> {
+        let (value, relative) = match *self {
+            Self::Ex(v) => (v.checked_mul(1000), true),
+            Self::Px(v) => (Some(v), true),
+            Self::ExAt(v) => (v.checked_mul(1000), false),
+            Self::PxAt(v) => (Some(v), false),
+        };
+        let value = value.ok_or_else(|| invalid_expire_error(cmd))?;
+        if relative {
+            value
+                .checked_add(now_ms() as i64)
+                .ok_or_else(|| invalid_expire_error(cmd))
+        } else {
+            Ok(value)
+        }
+    }
+}
+
+pub(super) fn invalid_expire_error(cmd: &str) -> Error {
+    CommandError::Err(format!("invalid expire time in '{}' command", cmd)).into()
+}
+
+/// Conditions of EXPIRE and friends on the current expire time of the key. XX may be
+/// combined with GT or LT.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpireCondition {
+    /// Only set the expire time if the key has none.
+    nx: bool,
+    /// Only set the expire time if the key already has one.
+    xx: bool,
+    /// Only set the expire time if it is later than the current one. A key without
+    /// expire time never expires, so it is never set.
+    gt: bool,
+    /// Only set the expire time if it is earlier than the current one, or if the key
+    /// has none.
+    lt: bool,
+}
+
+impl ExpireCondition {
+    fn parse(args: &mut Args) -> Result<Self> {
+        let mut cond = Self::default();
+        while let Some(opt) = args.next_lower() {
+            match opt.as_slice() {
+                b"nx" => cond.nx = true,
+                b"xx" => cond.xx = true,
+                b"gt" => cond.gt = true,
+                b"lt" => cond.lt = true,
+                _ => {
+                    return Err(CommandError::Err(format!(
+                        "Unsupported option {}",
+                        String::from_utf8_lossy(&opt)
+                    ))
+                    .into())
+                }
+            }
+        }
+        if cond.nx && (cond.xx || cond.gt || cond.lt) {
+            return Err(CommandError::Err(
+                "NX and XX, GT or LT options at the same time are not compatible".into(),
+            )
+            .into());
+        }
+        if cond.gt && cond.lt {
+            return Err(CommandError::Err(
+                "GT and LT options at the same time are not compatible".into(),
+            )
+            .into());
+        }
+        Ok(cond)
+    }
+
+    /// Parses the single condition taken by HEXPIRE and friends, `opt` being in
+    /// lowercase.
+    pub(super) fn single(opt: &[u8]) -> Option<Self> {
+        let mut cond = Self::default();
+        match opt {
+            b"nx" => cond.nx = true,
+            b"xx" => cond.xx = true,
+            b"gt" => cond.gt = true,
+            b"lt" => cond.lt = true,
+            _ => return None,
+        }
+        Some(cond)
+    }
+
+    /// Tells whether the expire time `when` may replace the current one.
+    pub(super) fn allows(&self, current: Option<u64>, when: i64) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(current) => {
+                let current = current as i64;
+                !self.nx && (!self.gt || when > current) && (!self.lt || when < current)
+            }
+        }
+    }
+}
+
+/// Commands working on keys of any type.
+#[derive(Debug)]
+pub enum KeyCommand {
+    Del { keys: Vec<Bytes> },
+    Exists { keys: Vec<Bytes> },
+    Type { key: Bytes },
+    /// `EXPIRE`, `PEXPIRE`, `EXPIREAT` and `PEXPIREAT`, which only differ in the unit of
+    /// the time and whether it is relative.
+    Expire {
+        key: Bytes,
+        expiry: Expiry,
+        condition: ExpireCondition,
+    },
+    /// `TTL`, `PTTL`, `EXPIRETIME` and `PEXPIRETIME`.
+    Ttl {
+        key: Bytes,
+        millis: bool,
+        absolute: bool,
+    },
+    Persist { key: Bytes },
+    /// `OBJECT ENCODING key`
+    ObjectEncoding { key: Bytes },
+}
+
+impl KeyCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "del" | "unlink" => Self::Del { keys: args.rest()? },
+            "exists" => Self::Exists { keys: args.rest()? },
+            "type" => Self::Type { key: args.next_arg()? },
+            "expire" | "pexpire" | "expireat" | "pexpireat" => {
+                let key = args.next_arg()?;
+                let value = args.next_arg()?;
+                let unit = match name {
+                    "expire" => b"ex".as_slice(),
+                    "pexpire" => b"px",
+                    "expireat" => b"exat",
+                    _ => b"pxat",
+                };
+                Self::Expire {
+                    key,
+                    expiry: Expiry::parse(unit, &value)?,
+                    condition: ExpireCondition::parse(args)?,
+                }
+            }
+            "ttl" | "pttl" | "expiretime" | "pexpiretime" => Self::Ttl {
+                key: args.next_arg()?,
+                millis: name.starts_with('p'),
+                absolute: name.ends_with("time"),
+            },
+            "persist" => Self::Persist { key: args.next_arg()? },
+            "object" => {
+                let sub = args.next_arg()?;
+                match lower_bytes(&sub).as_slice() {
+                    b"encoding" => Self::ObjectEncoding { key: args.next_arg()? },
+                    _ => {
+                        return Err(CommandError::Err(format!(
+                            "unknown subcommand '{}'. Try OBJECT HELP.",
+                            String::from_utf8_lossy(&sub)
+                        ))
+                        .into())
+                    }
+                }
+            }
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::Del { keys } => {
+                let deleted = keys.iter().filter(|key| db.delete(key)).count();
+                Ok(Resp3Piece::Integer(deleted as i64))
+            }
+            Self::Exists { keys } => {
+                // a key given several times is counted several times.
+                let found = keys.iter().filter(|key| db.exists(key)).count();
+                Ok(Resp3Piece::Integer(found as i64))
+            }
+            Self::Type { key } => {
+                let typ = db.lookup(&key).map(|v| v.type_name()).unwrap_or("none");
+                Ok(Resp3Piece::simple(typ))
+            }
+            Self::Expire {
+                key,
+                expiry,
+                condition,
+            } => {
+                let cmd = match expiry {
+                    Expiry::Ex(_) => "expire",
+                    Expiry::Px(_) => "pexpire",
+                    Expiry::ExAt(_) => "expireat",
+                    Expiry::PxAt(_) => "pexpireat",
+                };
+                let when = expiry.resolve_any(cmd)?;
+                if !db.exists(&key) || !condition.allows(db.get_expire(&key), when) {
+                    return Ok(Resp3Piece::Integer(0));
+                }
+                // a time in the past deletes the key.
+                db.set_expire(&key, when.max(0) as u64);
+                Ok(Resp3Piece::Integer(1))
+            }
+            Self::Ttl {
+                key,
+                millis,
+                absolute,
+            } => {
+                if !db.exists(&key) {
+                    return Ok(Resp3Piece::Integer(-2));
+                }
+                let when = match db.get_expire(&key) {
+                    Some(when) => when as i64,
+                    None => return Ok(Resp3Piece::Integer(-1)),
+                };
+                let ttl = if absolute {
+                    when
+                } else {
+                    (when - now_ms() as i64).max(0)
+                };
+                Ok(Resp3Piece::Integer(if millis { ttl } else { (ttl + 500) / 1000 }))
+            }
+            Self::Persist { key } => {
+                let persisted = db.exists(&key) && db.persist(&key);
+                Ok(Resp3Piece::Integer(persisted as i64))
+            }
+            Self::ObjectEncoding { key } => Ok(match db.lookup(&key) {
+                Some(value) => Resp3Piece::bulk(value.encoding()),
+                None => Resp3Piece::Null,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{command::tests::exec, db::Db, protocol::Resp3Piece, util::now_ms};
+
+    fn int(db: &mut Db, args: &[&str]) -> i64 {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Integer(n) => n,
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn expire_conditions() {
+        let mut db = Db::new();
+        assert_eq!(int(&mut db, &["expire", "k", "100"]), 0);
+        exec(&mut db, &["set", "k", "v"]).unwrap();
+        assert_eq!(int(&mut db, &["expire", "k", "100", "xx"]), 0);
+        assert_eq!(int(&mut db, &["expire", "k", "100", "gt"]), 0);
+        assert_eq!(int(&mut db, &["expire", "k", "100", "xx", "lt"]), 0);
+        assert_eq!(int(&mut db, &["expire", "k", "100", "nx"]), 1);
+        assert_eq!(int(&mut db, &["expire", "k", "200", "nx"]), 0);
+        assert_eq!(int(&mut db, &["expire", "k", "50", "gt"]), 0);
+        assert_eq!(int(&mut db, &["expire", "k", "200", "gt"]), 1);
+        assert_eq!(int(&mut db, &["expire", "k", "300", "lt"]), 0);
+        assert_eq!(int(&mut db, &["pexpire", "k", "1000", "xx", "lt"]), 1);
+        let ttl = int(&mut db, &["pttl", "k"]);
+        assert!(ttl > 0 && ttl <= 1000);
+        assert!(exec(&mut db, &["expire", "k", "10", "nx", "xx"]).is_err());
+        assert!(exec(&mut db, &["expire", "k", "10", "gt", "lt"]).is_err());
+        assert!(exec(&mut db, &["expire", "k", "10", "foo"]).is_err());
+        assert!(exec(&mut db, &["expire", "k", "9223372036854775807"]).is_err());
+        assert_eq!(int(&mut db, &["expire", "k", "-1"]), 1);
+        assert_eq!(int(&mut db, &["exists", "k"]), 0);
+    }
+
+    #[test]
+    fn ttl_and_persist() {
+        let mut db = Db::new();
+        assert_eq!(int(&mut db, &["ttl", "k"]), -2);
+        assert_eq!(int(&mut db, &["pexpiretime", "k"]), -2);
+        exec(&mut db, &["set", "k", "v"]).unwrap();
+        assert_eq!(int(&mut db, &["pttl", "k"]), -1);
+        assert_eq!(int(&mut db, &["expiretime", "k"]), -1);
+        assert_eq!(int(&mut db, &["persist", "k"]), 0);
+        let at = (now_ms() / 1000 + 100) as i64;
+        assert_eq!(int(&mut db, &["expireat", "k", &at.to_string()]), 1);
+        assert_eq!(int(&mut db, &["expiretime", "k"]), at);
+        assert_eq!(int(&mut db, &["pexpiretime", "k"]), at * 1000);
+        let ttl = int(&mut db, &["ttl", "k"]);
+        assert!((99..=100).contains(&ttl));
+        assert_eq!(int(&mut db, &["persist", "k"]), 1);
+        assert_eq!(int(&mut db, &["ttl", "k"]), -1);
+    }
+}
@@ -0,0 +1,686 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Error, Result},
+    object::{QuickList, Value},
+    protocol::Resp3Piece,
+    util::parse_i64,
+};
+
+use super::{lower_bytes, syntax_error, Args};
+
+/// End of a list, `LEFT` being the head.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    pub(super) fn parse(arg: &[u8]) -> Result<Self> {
+        match lower_bytes(arg).as_slice() {
+            b"left" => Ok(Self::Left),
+            b"right" => Ok(Self::Right),
+            _ => Err(syntax_error()),
+        }
+    }
+}
+
+/// Commands working on lists.
+#[derive(Debug)]
+pub enum ListCommand {
+    /// `LPUSH`, `RPUSH`, and `LPUSHX`, `RPUSHX` which only push to existing lists.
+    Push {
+        key: Bytes,
+        elements: Vec<Bytes>,
+        end: End,
+        only_existing: bool,
+    },
+    /// `LPOP key [count]` and `RPOP key [count]`.
+    Pop {
+        key: Bytes,
+        end: End,
+        count: Option<usize>,
+    },
+    Range {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    Index {
+        key: Bytes,
+        index: i64,
+    },
+    Set {
+        key: Bytes,
+        index: i64,
+        element: Bytes,
+    },
+    /// `LINSERT key BEFORE | AFTER pivot element`
+    Insert {
+        key: Bytes,
+        before: bool,
+        pivot: Bytes,
+        element: Bytes,
+    },
+    Rem {
+        key: Bytes,
+        count: i64,
+        element: Bytes,
+    },
+    Trim {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    Len {
+        key: Bytes,
+    },
+    /// `LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]`
+    Pos {
+        key: Bytes,
+        element: Bytes,
+        rank: i64,
+        count: Option<usize>,
+        max_len: usize,
+    },
+    /// `LMOVE source destination LEFT | RIGHT LEFT | RIGHT`, and `RPOPLPUSH`.
+    Move {
+        src: Bytes,
+        dst: Bytes,
+        from: End,
+        to: End,
+    },
+    /// `LMPOP numkeys key [key ...] LEFT | RIGHT [COUNT count]`
+    MPop {
+        keys: Vec<Bytes>,
+        end: End,
+        count: usize,
+    },
+}
+
+impl ListCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "lpush" | "rpush" | "lpushx" | "rpushx" => Self::Push {
+                key: args.next_arg()?,
+                elements: args.rest()?,
+                end: if name.starts_with('l') { End::Left } else { End::Right },
+                only_existing: name.ends_with('x'),
+            },
+            "lpop" | "rpop" => {
+                let key = args.next_arg()?;
+                let count = match args.next_opt() {
+                    Some(count) => Some(parse_positive(
+                        &count,
+                        "value is out of range, must be positive",
+                        0,
+                    )?),
+                    None => None,
+                };
+                Self::Pop {
+                    key,
+                    end: if name == "lpop" { End::Left } else { End::Right },
+                    count,
+                }
+            }
+            "lrange" | "ltrim" => {
+                let key = args.next_arg()?;
+                let start = args.next_i64()?;
+                let stop = args.next_i64()?;
+                if name == "lrange" {
+                    Self::Range { key, start, stop }
+                } else {
+                    Self::Trim { key, start, stop }
+                }
+            }
+            "lindex" => Self::Index {
+                key: args.next_arg()?,
+                index: args.next_i64()?,
+            },
+            "lset" => Self::Set {
+                key: args.next_arg()?,
+                index: args.next_i64()?,
+                element: args.next_arg()?,
+            },
+            "linsert" => {
+                let key = args.next_arg()?;
+                let before = match lower_bytes(&args.next_arg()?).as_slice() {
+                    b"before" => true,
+                    b"after" => false,
+                    _ => return Err(syntax_error()),
+                };
+                Self::Insert {
+                    key,
+                    before,
+                    pivot: args.next_arg()?,
+                    element: args.next_arg()?,
+                }
+            }
+            "lrem" => Self::Rem {
+                key: args.next_arg()?,
+                count: args.next_i64()?,
+                element: args.next_arg()?,
+            },
+            "llen" => Self::Len { key: args.next_arg()? },
+            "lpos" => Self::parse_lpos(args)?,
+            "lmove" => Self::Move {
+                src: args.next_arg()?,
+                dst: args.next_arg()?,
+                from: End::parse(&args.next_arg()?)?,
+                to: End::parse(&args.next_arg()?)?,
+            },
+            "rpoplpush" => Self::Move {
+                src: args.next_arg()?,
+                dst: args.next_arg()?,
+                from: End::Right,
+                to: End::Left,
+            },
+            "lmpop" => {
+                let (keys, end, count) = parse_mpop(args)?;
+                Self::MPop { keys, end, count }
+            }
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    fn parse_lpos(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let element = args.next_arg()?;
+        let mut rank = 1;
+        let mut count = None;
+        let mut max_len = 0;
+        while let Some(opt) = args.next_lower() {
+            let value = args.next_opt().ok_or_else(syntax_error)?;
+            match opt.as_slice() {
+                b"rank" => {
+                    rank = match parse_i64(&value) {
+                        Some(0) => {
+                            return Err(CommandError::Err(
+                                "RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".into(),
+                            )
+                            .into())
+                        }
+                        Some(i64::MIN) => {
+                            return Err(CommandError::Err(format!(
+                                "value is out of range, must be between {} and {}",
+                                -i64::MAX,
+                                i64::MAX
+                            ))
+                            .into())
+                        }
+                        Some(rank) => rank,
+                        None => return Err(super::not_integer_error()),
+                    }
+                }
+                b"count" => count = Some(parse_positive(&value, "COUNT can't be negative", 0)?),
+                b"maxlen" => max_len = parse_positive(&value, "MAXLEN can't be negative", 0)?,
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(Self::Pos {
+            key,
+            element,
+            rank,
+            count,
+            max_len,
+        })
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::Push {
+                key,
+                elements,
+                end,
+                only_existing,
+            } => {
+                let list = match db.lookup_mut(&key) {
+                    Some(value) => value.as_list_mut()?,
+                    None if only_existing => return Ok(Resp3Piece::Integer(0)),
+                    None => {
+                        db.set(key.clone(), Value::List(QuickList::new()), false);
+                        db.lookup_mut(&key).unwrap().as_list_mut()?
+                    }
+                };
+                for element in &elements {
+                    match end {
+                        End::Left => list.push_front(element),
+                        End::Right => list.push_back(element),
+                    }
+                }
+                Ok(Resp3Piece::Integer(list.len() as i64))
+            }
+            Self::Pop { key, end, count } => match count {
+                None => Ok(match pop(db, &key, end, 1)? {
+                    Some(mut elements) => Resp3Piece::bulk(elements.remove(0)),
+                    None => Resp3Piece::Null,
+                }),
+                Some(count) => {
+                    if count == 0 {
+                        return Ok(match db.lookup(&key) {
+                            Some(value) => {
+                                value.as_list()?;
+                                Resp3Piece::Array(vec![])
+                            }
+                            None => Resp3Piece::NullArray,
+                        });
+                    }
+                    Ok(match pop(db, &key, end, count)? {
+                        Some(elements) => bulks(elements),
+                        None => Resp3Piece::NullArray,
+                    })
+                }
+            },
+            Self::Range { key, start, stop } => {
+                let list = match db.lookup(&key) {
+                    Some(value) => value.as_list()?,
+                    None => return Ok(Resp3Piece::Array(vec![])),
+                };
+                let items = match range(list.len(), start, stop) {
+                    Some((start, stop)) => list
+                        .iter_from(start)
+                        .take(stop - start + 1)
+                        .map(|e| Resp3Piece::bulk(Bytes::copy_from_slice(e)))
+                        .collect(),
+                    None => vec![],
+                };
+                Ok(Resp3Piece::Array(items))
+            }
+            Self::Index { key, index } => {
+                let list = match db.lookup(&key) {
+                    Some(value) => value.as_list()?,
+                    None => return Ok(Resp3Piece::Null),
+                };
+                Ok(match resolve_index(list.len(), index).and_then(|i| list.get(i)) {
+                    Some(element) => Resp3Piece::bulk(Bytes::copy_from_slice(element)),
+                    None => Resp3Piece::Null,
+                })
+            }
+            Self::Set {
+                key,
+                index,
+                element,
+            } => {
+                let list = match db.lookup_mut(&key) {
+                    Some(value) => value.as_list_mut()?,
+                    None => return Err(CommandError::Err("no such key".into()).into()),
+                };
+                let index = resolve_index(list.len(), index)
+                    .ok_or_else(|| Error::from(CommandError::Err("index out of range".into())))?;
+                list.set(index, &element);
+                Ok(Resp3Piece::ok())
+            }
+            Self::Insert {
+                key,
+                before,
+                pivot,
+                element,
+            } => {
+                let list = match db.lookup_mut(&key) {
+                    Some(value) => value.as_list_mut()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let index = match list.iter().position(|e| e == &pivot[..]) {
+                    Some(index) => index,
+                    None => return Ok(Resp3Piece::Integer(-1)),
+                };
+                list.insert(if before { index } else { index + 1 }, &element);
+                Ok(Resp3Piece::Integer(list.len() as i64))
+            }
+            Self::Rem {
+                key,
+                count,
+                element,
+            } => {
+                let list = match db.lookup_mut(&key) {
+                    Some(value) => value.as_list_mut()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+                let mut indexes: Vec<usize> = enumerate(list, count < 0)
+                    .filter(|(_, e)| *e == &element[..])
+                    .take(limit)
+                    .map(|(i, _)| i)
+                    .collect();
+                // removed from the tail, so that the indexes left stay valid.
+                indexes.sort_unstable_by(|a, b| b.cmp(a));
+                for &index in &indexes {
+                    list.remove(index);
+                }
+                if list.is_empty() {
+                    db.remove(&key);
+                }
+                Ok(Resp3Piece::Integer(indexes.len() as i64))
+            }
+            Self::Trim { key, start, stop } => {
+                let list = match db.lookup_mut(&key) {
+                    Some(value) => value.as_list_mut()?,
+                    None => return Ok(Resp3Piece::ok()),
+                };
+                let len = list.len();
+                match range(len, start, stop) {
+                    Some((start, stop)) => {
+                        list.remove_range(stop + 1, len - stop - 1);
+                        list.remove_range(0, start);
+                    }
+                    None => list.remove_range(0, len),
+                }
+                if list.is_empty() {
+                    db.remove(&key);
+                }
+                Ok(Resp3Piece::ok())
+            }
+            Self::Len { key } => {
+                let len = match db.lookup(&key) {
+                    Some(value) => value.as_list()?.len(),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::Pos {
+                key,
+                element,
+                rank,
+                count,
+                max_len,
+            } => {
+                let list = match db.lookup(&key) {
+                    Some(value) => value.as_list()?,
+                    None if count.is_some() => return Ok(Resp3Piece::Array(vec![])),
+                    None => return Ok(Resp3Piece::Null),
+                };
+                let max_len = if max_len == 0 { usize::MAX } else { max_len };
+                let limit = match count {
+                    Some(0) => usize::MAX,
+                    Some(count) => count,
+                    None => 1,
+                };
+                let skip = rank.unsigned_abs() as usize - 1;
+                let matches: Vec<Resp3Piece> = enumerate(list, rank < 0)
+                    .take(max_len)
+                    .filter(|(_, e)| *e == &element[..])
+                    .skip(skip)
+                    .take(limit)
+                    .map(|(i, _)| Resp3Piece::Integer(i as i64))
+                    .collect();
+                Ok(match count {
+                    Some(_) => Resp3Piece::Array(matches),
+                    None => matches.into_iter().next().unwrap_or(Resp3Piece::Null),
+                })
+            }
+            Self::Move { src, dst, from, to } => Ok(match lmove(db, &src, &dst, from, to)? {
+                Some(element) => Resp3Piece::bulk(element),
+                None => Resp3Piece::Null,
+            }),
+            Self::MPop { keys, end, count } => Ok(match mpop(db, &keys, end, count)? {
+                Some((key, elements)) => {
+                    Resp3Piece::Array(vec![Resp3Piece::bulk(key), bulks(elements)])
+                }
+                None => Resp3Piece::NullArray,
+            }),
+        }
+    }
+}
+
+/// Parses an integer which must not be lower than `min`, replying `msg` otherwise, as
+/// `getRangeLongFromObjectOrReply` of redis does.
+pub(super) fn parse_positive(arg: &[u8], msg: &str, min: i64) -> Result<usize> {
+    match parse_i64(arg) {
+        Some(n) if n >= min => Ok(n as usize),
+        _ => Err(CommandError::Err(msg.to_string()).into()),
+    }
+}
+
+/// Parses the `numkeys key [key ...] LEFT | RIGHT [COUNT count]` arguments of LMPOP and
+/// BLMPOP.
+pub(super) fn parse_mpop(args: &mut Args) -> Result<(Vec<Bytes>, End, usize)> {
+    let numkeys = parse_positive(&args.next_arg()?, "numkeys should be greater than 0", 1)?;
+    if numkeys >= args.remaining() {
+        return Err(syntax_error());
+    }
+    let keys = (0..numkeys).map(|_| args.next_arg()).collect::<Result<_>>()?;
+    let end = End::parse(&args.next_arg()?)?;
+    let mut count = None;
+    while let Some(opt) = args.next_lower() {
+        match opt.as_slice() {
+            b"count" if count.is_none() => {
+                let value = args.next_opt().ok_or_else(syntax_error)?;
+                count = Some(parse_positive(&value, "count should be greater than 0", 1)?);
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok((keys, end, count.unwrap_or(1)))
+}
+
+/// Resolves an index counting from the end when it is negative.
+fn resolve_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index < 0 || index >= len as i64 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+/// Resolves the inclusive range of LRANGE and LTRIM, and of ranks of ZRANGE, `None`
+/// being an empty range.
+pub(super) fn range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop.min(len - 1) as usize))
+}
+
+/// Walks the elements along with their indexes, from the tail when `reverse` is set.
+fn enumerate(list: &QuickList, reverse: bool) -> Box<dyn Iterator<Item = (usize, &[u8])> + '_> {
+    let len = list.len();
+    if reverse {
+        Box::new(list.iter().rev().enumerate().map(move |(i, e)| (len - 1 - i, e)))
+    } else {
+        Box::new(list.iter().enumerate())
+    }
+}
+
+fn bulks(elements: Vec<Bytes>) -> Resp3Piece {
+    Resp3Piece::Array(elements.into_iter().map(Resp3Piece::bulk).collect())
+}
+
+/// Pops up to `count` elements from an end of the list, deleting it once empty.
+/// Returns `None` when the key does not exist.
+pub(crate) fn pop(db: &mut Db, key: &[u8], end: End, count: usize) -> Result<Option<Vec<Bytes>>> {
+    let list = match db.lookup_mut(key) {
+        Some(value) => value.as_list_mut()?,
+        None => return Ok(None),
+    };
+    let mut elements = Vec::with_capacity(count.min(list.len()));
+    while elements.len() < count {
+        let element = match end {
+            End::Left => list.pop_front(),
+            End::Right => list.pop_back(),
+        };
+        match element {
+            Some(element) => elements.push(Bytes::from(element)),
+            None => break,
+        }
+    }
+    if list.is_empty() {
+        db.remove(key);
+    }
+    Ok(Some(elements))
+}
+
+/// Pops from the first non empty list of the keys, returning the key along with the
+/// elements.
+pub(crate) fn mpop(
+    db: &mut Db,
+    keys: &[Bytes],
+    end: End,
+    count: usize,
+) -> Result<Option<(Bytes, Vec<Bytes>)>> {
+    for key in keys {
+        if let Some(elements) = pop(db, key, end, count)? {
+            return Ok(Some((key.clone(), elements)));
+        }
+    }
+    Ok(None)
+}
+
+/// Moves an element from an end of a list to an end of another, which may be the same
+/// list. Returns `None` when the source does not exist.
+pub(crate) fn lmove(
+    db: &mut Db,
+    src: &[u8],
+    dst: &[u8],
+    from: End,
+    to: End,
+) -> Result<Option<Bytes>> {
+    match db.lookup(src) {
+        Some(value) => value.as_list()?,
+        None => return Ok(None),
+    };
+    if let Some(value) = db.lookup(dst) {
+        value.as_list()?;
+    }
+    let element = pop(db, src, from, 1)?.unwrap().remove(0);
+    push(db, Bytes::copy_from_slice(dst), &element, to);
+    Ok(Some(element))
+}
+
+/// Pushes an element to a list which is known to be a list if it exists.
+pub(crate) fn push(db: &mut Db, key: Bytes, element: &[u8], end: End) {
+    if !db.exists(&key) {
+        db.set(key.clone(), Value::List(QuickList::new()), false);
+    }
+    let list = db.lookup_mut(&key).unwrap().as_list_mut().unwrap();
+    match end {
+        End::Left => list.push_front(element),
+        End::Right => list.push_back(element),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+    };
+
+    fn bulks(items: &[&str]) -> Resp3Piece {
+        Resp3Piece::Array(items.iter().map(|item| bulk(item)).collect())
+    }
+
+    fn ints(items: &[i64]) -> Resp3Piece {
+        Resp3Piece::Array(items.iter().map(|&n| Resp3Piece::Integer(n)).collect())
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["lpushx", "l", "a"]).unwrap(), Resp3Piece::Integer(0));
+        assert_eq!(exec(&mut db, &["lpush", "l", "b", "a"]).unwrap(), Resp3Piece::Integer(2));
+        assert_eq!(exec(&mut db, &["rpushx", "l", "c", "d"]).unwrap(), Resp3Piece::Integer(4));
+        assert_eq!(exec(&mut db, &["lrange", "l", "0", "-1"]).unwrap(), bulks(&["a", "b", "c", "d"]));
+        assert_eq!(exec(&mut db, &["lpop", "l"]).unwrap(), bulk("a"));
+        assert_eq!(exec(&mut db, &["rpop", "l", "2"]).unwrap(), bulks(&["d", "c"]));
+        assert_eq!(exec(&mut db, &["lpop", "l", "0"]).unwrap(), bulks(&[]));
+        assert!(exec(&mut db, &["lpop", "l", "-1"]).is_err());
+        assert_eq!(exec(&mut db, &["lpop", "l", "5"]).unwrap(), bulks(&["b"]));
+        assert!(!db.exists(b"l"));
+        assert_eq!(exec(&mut db, &["lpop", "l"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["lpop", "l", "1"]).unwrap(), Resp3Piece::NullArray);
+        exec(&mut db, &["set", "s", "v"]).unwrap();
+        assert!(exec(&mut db, &["lpush", "s", "v"]).is_err());
+        assert!(exec(&mut db, &["get", "l"]).is_ok());
+        exec(&mut db, &["rpush", "l", "v"]).unwrap();
+        assert!(exec(&mut db, &["get", "l"]).is_err());
+        assert_eq!(exec(&mut db, &["type", "l"]).unwrap(), Resp3Piece::simple("list"));
+    }
+
+    #[test]
+    fn ranges_and_indexes() {
+        let mut db = Db::new();
+        exec(&mut db, &["rpush", "l", "a", "b", "c", "d", "e"]).unwrap();
+        assert_eq!(exec(&mut db, &["lrange", "l", "-2", "100"]).unwrap(), bulks(&["d", "e"]));
+        assert_eq!(exec(&mut db, &["lrange", "l", "3", "1"]).unwrap(), bulks(&[]));
+        assert_eq!(exec(&mut db, &["lrange", "l", "-100", "0"]).unwrap(), bulks(&["a"]));
+        assert_eq!(exec(&mut db, &["lindex", "l", "-1"]).unwrap(), bulk("e"));
+        assert_eq!(exec(&mut db, &["lindex", "l", "5"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["lset", "l", "-2", "D"]).unwrap(), Resp3Piece::ok());
+        assert!(exec(&mut db, &["lset", "l", "5", "x"]).is_err());
+        assert!(exec(&mut db, &["lset", "nokey", "0", "x"]).is_err());
+        assert_eq!(exec(&mut db, &["linsert", "l", "before", "c", "x"]).unwrap(), Resp3Piece::Integer(6));
+        assert_eq!(exec(&mut db, &["linsert", "l", "AFTER", "e", "y"]).unwrap(), Resp3Piece::Integer(7));
+        assert_eq!(exec(&mut db, &["linsert", "l", "after", "z", "y"]).unwrap(), Resp3Piece::Integer(-1));
+        assert!(exec(&mut db, &["linsert", "l", "around", "c", "y"]).is_err());
+        assert_eq!(
+            exec(&mut db, &["lrange", "l", "0", "-1"]).unwrap(),
+            bulks(&["a", "b", "x", "c", "D", "e", "y"])
+        );
+        assert_eq!(exec(&mut db, &["ltrim", "l", "1", "-2"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(exec(&mut db, &["lrange", "l", "0", "-1"]).unwrap(), bulks(&["b", "x", "c", "D", "e"]));
+        assert_eq!(exec(&mut db, &["llen", "l"]).unwrap(), Resp3Piece::Integer(5));
+        assert_eq!(exec(&mut db, &["ltrim", "l", "3", "1"]).unwrap(), Resp3Piece::ok());
+        assert!(!db.exists(b"l"));
+    }
+
+    #[test]
+    fn lrem_and_lpos() {
+        let mut db = Db::new();
+        exec(&mut db, &["rpush", "l", "a", "b", "a", "c", "a", "b", "a"]).unwrap();
+        assert_eq!(exec(&mut db, &["lpos", "l", "a"]).unwrap(), Resp3Piece::Integer(0));
+        assert_eq!(exec(&mut db, &["lpos", "l", "a", "rank", "2"]).unwrap(), Resp3Piece::Integer(2));
+        assert_eq!(exec(&mut db, &["lpos", "l", "a", "rank", "-1"]).unwrap(), Resp3Piece::Integer(6));
+        assert_eq!(exec(&mut db, &["lpos", "l", "a", "count", "0"]).unwrap(), ints(&[0, 2, 4, 6]));
+        assert_eq!(exec(&mut db, &["lpos", "l", "a", "rank", "-2", "count", "2"]).unwrap(), ints(&[4, 2]));
+        assert_eq!(exec(&mut db, &["lpos", "l", "a", "count", "0", "maxlen", "3"]).unwrap(), ints(&[0, 2]));
+        assert_eq!(exec(&mut db, &["lpos", "l", "x"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["lpos", "nokey", "x", "count", "1"]).unwrap(), ints(&[]));
+        assert!(exec(&mut db, &["lpos", "l", "a", "rank", "0"]).is_err());
+        assert!(exec(&mut db, &["lpos", "l", "a", "count", "-1"]).is_err());
+        assert!(exec(&mut db, &["lpos", "l", "a", "maxlen"]).is_err());
+        assert_eq!(exec(&mut db, &["lrem", "l", "-2", "a"]).unwrap(), Resp3Piece::Integer(2));
+        assert_eq!(exec(&mut db, &["lrange", "l", "0", "-1"]).unwrap(), bulks(&["a", "b", "a", "c", "b"]));
+        assert_eq!(exec(&mut db, &["lrem", "l", "1", "b"]).unwrap(), Resp3Piece::Integer(1));
+        assert_eq!(exec(&mut db, &["lrem", "l", "0", "a"]).unwrap(), Resp3Piece::Integer(2));
+        assert_eq!(exec(&mut db, &["lrange", "l", "0", "-1"]).unwrap(), bulks(&["c", "b"]));
+        assert_eq!(exec(&mut db, &["lrem", "l", "0", "c"]).unwrap(), Resp3Piece::Integer(1));
+        assert_eq!(exec(&mut db, &["lrem", "l", "0", "b"]).unwrap(), Resp3Piece::Integer(1));
+        assert!(!db.exists(b"l"));
+    }
+
+    #[test]
+    fn move_and_mpop() {
+        let mut db = Db::new();
+        exec(&mut db, &["rpush", "a", "1", "2", "3"]).unwrap();
+        assert_eq!(exec(&mut db, &["lmove", "a", "a", "left", "right"]).unwrap(), bulk("1"));
+        assert_eq!(exec(&mut db, &["rpoplpush", "a", "b"]).unwrap(), bulk("1"));
+        assert_eq!(exec(&mut db, &["lmove", "a", "b", "RIGHT", "RIGHT"]).unwrap(), bulk("3"));
+        assert_eq!(exec(&mut db, &["lrange", "b", "0", "-1"]).unwrap(), bulks(&["1", "3"]));
+        assert_eq!(exec(&mut db, &["lmove", "x", "b", "left", "left"]).unwrap(), Resp3Piece::Null);
+        exec(&mut db, &["set", "s", "v"]).unwrap();
+        assert!(exec(&mut db, &["lmove", "a", "s", "left", "left"]).is_err());
+        assert!(exec(&mut db, &["lmove", "a", "b", "up", "left"]).is_err());
+        assert_eq!(
+            exec(&mut db, &["lmpop", "3", "x", "b", "a", "right", "count", "5"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("b"), bulks(&["3", "1"])])
+        );
+        assert_eq!(
+            exec(&mut db, &["lmpop", "2", "x", "a", "left"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("a"), bulks(&["2"])])
+        );
+        assert_eq!(exec(&mut db, &["lmpop", "1", "a", "left"]).unwrap(), Resp3Piece::NullArray);
+        assert!(exec(&mut db, &["lmpop", "0", "a", "left"]).is_err());
+        assert!(exec(&mut db, &["lmpop", "2", "a", "left"]).is_err());
+        assert!(exec(&mut db, &["lmpop", "1", "a", "left", "count", "0"]).is_err());
+        assert!(exec(&mut db, &["lmpop", "1", "s", "left"]).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Databases,
+    error::{CommandError, Error, Result},
+    protocol::Resp3Piece,
+    util::parse_i64,
+};
+
+mod bitmap;
+mod blocking;
+mod database;
+mod hash;
+mod keyspace;
+mod list;
+mod set;
+mod stream;
+mod string;
+mod zset;
+
+pub use bitmap::{BitOp, BitmapCommand};
+pub use blocking::{BlockingCommand, BlockingOp};
+pub use database::DbCommand;
+pub use hash::HashCommand;
+pub use keyspace::{Expiry, KeyCommand};
+pub use list::{End, ListCommand};
+pub use set::{SetCommand, SetOp};
+pub use stream::{
+    AddId, ClaimOptions, DeliveryTime, PendingRange, ReadGroup, StreamCommand, Trim,
+};
+pub use string::StringCommand;
+pub use zset::{AddFlags, Aggregate, RangeSpec, ZSetCommand};
+
+#[derive(Debug)]
+pub enum Command {
+    /// `HELLO [protover [AUTH username password] [SETNAME clientname]]`
+    Hello {
+        protover: Option<Bytes>,
+        auth: Option<(Bytes, Bytes)>,
+        setname: Option<Bytes>,
+    },
+    /// `MULTI`, `EXEC` and `DISCARD`, which queue the commands of a transaction.
+    Multi,
+    Exec,
+    Discard,
+    Bitmap(BitmapCommand),
+    Blocking(BlockingCommand),
+    Db(DbCommand),
+    Hash(HashCommand),
+    Key(KeyCommand),
+    List(ListCommand),
+    Set(SetCommand),
+    Stream(StreamCommand),
+    String(StringCommand),
+    ZSet(ZSetCommand),
+}
+
+impl Command {
+    /// Builds a command from the arguments of a request, the first of which is the
+    /// case-insensitive name of the command.
+    pub fn from_args(args: Vec<Bytes>) -> Result<Self> {
+        let mut args = Args::new(args)?;
+        let name = args.name().to_string();
+        match name.as_str() {
+            "hello" => return Self::parse_hello(&mut args),
+            "multi" | "exec" | "discard" => {
+                args.end()?;
+                return Ok(match name.as_str() {
+                    "multi" => Self::Multi,
+                    "exec" => Self::Exec,
+                    _ => Self::Discard,
+                });
+            }
+            _ => {}
+        }
+        if let Some(cmd) = DbCommand::parse(&name, &mut args)? {
+            return Ok(Self::Db(cmd));
+        }
+        if let Some(cmd) = BitmapCommand::parse(&name, &mut args)? {
+            return Ok(Self::Bitmap(cmd));
+        }
+        if let Some(cmd) = BlockingCommand::parse(&name, &mut args)? {
+            return Ok(Self::Blocking(cmd));
+        }
+        if let Some(cmd) = HashCommand::parse(&name, &mut args)? {
+            return Ok(Self::Hash(cmd));
+        }
+        if let Some(cmd) = KeyCommand::parse(&name, &mut args)? {
+            return Ok(Self::Key(cmd));
+        }
+        if let Some(cmd) = ListCommand::parse(&name, &mut args)? {
+            return Ok(Self::List(cmd));
+        }
+        if let Some(cmd) = SetCommand::parse(&name, &mut args)? {
+            return Ok(Self::Set(cmd));
+        }
+        if let Some(cmd) = StreamCommand::parse(&name, &mut args)? {
+            return Ok(match cmd {
+                // XREADGROUP only blocks with BLOCK.
+                StreamCommand::ReadGroup(read) if read.block.is_some() => {
+                    Self::Blocking(BlockingCommand {
+                        timeout: read.block.unwrap(),
+                        op: BlockingOp::ReadGroup(read),
+                    })
+                }
+                cmd => Self::Stream(cmd),
+            });
+        }
+        if let Some(cmd) = StringCommand::parse(&name, &mut args)? {
+            return Ok(Self::String(cmd));
+        }
+        if let Some(cmd) = ZSetCommand::parse(&name, &mut args)? {
+            return Ok(Self::ZSet(cmd));
+        }
+        Err(args.unknown_command_error())
+    }
+
+    /// Executes a command working on the databases, `selected` being the index of the
+    /// database selected by the client. Commands about the connection itself are
+    /// executed by the client, which also blocks for blocking commands, as they are
+    /// executed here without blocking.
+    pub fn execute(self, dbs: &mut Databases, selected: &mut usize) -> Result<Resp3Piece> {
+        match self {
+            Command::Bitmap(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::Blocking(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::Db(cmd) => cmd.execute(dbs, selected),
+            Command::Hash(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::Key(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::List(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::Set(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::Stream(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::String(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::ZSet(cmd) => cmd.execute(dbs.get_mut(*selected)),
+            Command::Hello { .. } | Command::Multi | Command::Exec | Command::Discard => {
+                unreachable!("executed by the client")
+            }
+        }
+    }
+
+    fn parse_hello(args: &mut Args) -> Result<Self> {
+        let protover = args.next_opt();
+        let mut auth = None;
+        let mut setname = None;
+        while let Some(opt) = args.next_opt() {
+            match lower_bytes(&opt).as_slice() {
+                b"auth" => match (args.next_opt(), args.next_opt()) {
+                    (Some(user), Some(pass)) => auth = Some((user, pass)),
+                    _ => return Err(Self::hello_syntax_error(&opt)),
+                },
+                b"setname" => match args.next_opt() {
+                    Some(name) => setname = Some(name),
+                    None => return Err(Self::hello_syntax_error(&opt)),
+                },
+                _ => return Err(Self::hello_syntax_error(&opt)),
+            }
+        }
+        Ok(Self::Hello {
+            protover,
+            auth,
+            setname,
+        })
+    }
+
+    fn hello_syntax_error(opt: &[u8]) -> Error {
+        Error::Command(CommandError::Err(format!(
+            "Syntax error in HELLO option '{}'",
+            String::from_utf8_lossy(opt)
+        )))
+    }
+}
+
+pub(crate) fn lower_bytes(src: &[u8]) -> Vec<u8> {
+    let mut dst = src.to_vec();
+    for c in dst.iter_mut() {
+        if c.is_ascii_alphabetic() {
+            c.make_ascii_lowercase();
+        }
+    }
+    dst
+}
+
+/// Arguments of a request, consumed by the parser of a command one by one.
+pub struct Args {
+    name: String,
+    iter: std::vec::IntoIter<Bytes>,
+}
+
+impl Args {
+    fn new(args: Vec<Bytes>) -> Result<Self> {
+        let mut iter = args.into_iter();
+        let name = match iter.next() {
+            Some(name) => String::from_utf8_lossy(&lower_bytes(&name)).into_owned(),
+            None => {
+                return Err(Error::BrokenProtocol(
+                    "empty lines given for command".into(),
+                ))
+            }
+        };
+        Ok(Self { name, iter })
+    }
+
+    /// Name of the command in lowercase.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Takes the next argument, which is required by the command.
+    pub fn next_arg(&mut self) -> Result<Bytes> {
+        self.iter.next().ok_or_else(|| self.arity_error())
+    }
+
+    pub fn next_opt(&mut self) -> Option<Bytes> {
+        self.iter.next()
+    }
+
+    pub fn next_i64(&mut self) -> Result<i64> {
+        let arg = self.next_arg()?;
+        parse_i64(&arg).ok_or_else(not_integer_error)
+    }
+
+    /// Takes the next argument in lowercase, used for options.
+    pub fn next_lower(&mut self) -> Option<Vec<u8>> {
+        self.iter.next().map(|arg| lower_bytes(&arg))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.iter.len()
+    }
+
+    /// Takes all the remaining arguments, at least one of which is required.
+    pub fn rest(&mut self) -> Result<Vec<Bytes>> {
+        if self.remaining() == 0 {
+            return Err(self.arity_error());
+        }
+        Ok(self.iter.by_ref().collect())
+    }
+
+    /// Takes the rest of the arguments as pairs, like the keys and values of MSET. There
+    /// must be at least one pair.
+    pub fn pairs(&mut self) -> Result<Vec<(Bytes, Bytes)>> {
+        if self.remaining() == 0 || !self.remaining().is_multiple_of(2) {
+            return Err(self.arity_error());
+        }
+        let mut pairs = Vec::with_capacity(self.remaining() / 2);
+        while let (Some(a), Some(b)) = (self.iter.next(), self.iter.next()) {
+            pairs.push((a, b));
+        }
+        Ok(pairs)
+    }
+
+    /// Checks that every argument has been consumed.
+    pub fn end(&mut self) -> Result<()> {
+        if self.remaining() > 0 {
+            return Err(self.arity_error());
+        }
+        Ok(())
+    }
+
+    pub fn arity_error(&self) -> Error {
+        Error::Command(CommandError::Err(format!(
+            "wrong number of arguments for '{}' command",
+            self.name
+        )))
+    }
+
+    /// Error of an unknown command, which quotes the beginning of the arguments as redis
+    /// does, to help telling what the client has sent.
+    fn unknown_command_error(&mut self) -> Error {
+        let quote = |arg: &[u8]| {
+            let arg = String::from_utf8_lossy(arg);
+            format!("'{}'", arg.chars().take(128).collect::<String>())
+        };
+        let args: Vec<String> = self.iter.by_ref().map(|arg| quote(&arg)).collect();
+        Error::Command(CommandError::Err(format!(
+            "unknown command {}, with args beginning with: {}",
+            quote(self.name.as_bytes()),
+            args.join(" ")
+        )))
+    }
+}
+
+pub(crate) fn syntax_error() -> Error {
+    Error::Command(CommandError::syntax())
+}
+
+pub(crate) fn not_integer_error() -> Error {
+    Error::Command(CommandError::not_integer())
+}
+
+pub(crate) fn not_float_error() -> Error {
+    Error::Command(CommandError::not_float())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use crate::{
+        db::{Databases, Db},
+        error::Result,
+        protocol::{Limits, RequestDecoder, Resp3Piece},
+    };
+
+    use super::{Command, StringCommand};
+
+    fn parse(args: &[&str]) -> Result<Command> {
+        let args = args.iter().map(|arg| Bytes::copy_from_slice(arg.as_bytes())).collect();
+        Command::from_args(args)
+    }
+
+    /// Parses and executes a command given as strings, for tests of commands working on
+    /// a single database.
+    pub(crate) fn exec(db: &mut Db, args: &[&str]) -> Result<Resp3Piece> {
+        match parse(args)? {
+            Command::Bitmap(cmd) => cmd.execute(db),
+            Command::Blocking(cmd) => cmd.execute(db),
+            Command::Hash(cmd) => cmd.execute(db),
+            Command::Key(cmd) => cmd.execute(db),
+            Command::List(cmd) => cmd.execute(db),
+            Command::Set(cmd) => cmd.execute(db),
+            Command::Stream(cmd) => cmd.execute(db),
+            Command::String(cmd) => cmd.execute(db),
+            Command::ZSet(cmd) => cmd.execute(db),
+            cmd => panic!("{:?} does not work on a single database", cmd),
+        }
+    }
+
+    /// Parses and executes a command given as strings on the databases.
+    pub(crate) fn exec_on(
+        dbs: &mut Databases,
+        selected: &mut usize,
+        args: &[&str],
+    ) -> Result<Resp3Piece> {
+        parse(args)?.execute(dbs, selected)
+    }
+
+    pub(crate) fn bulk(data: &str) -> Resp3Piece {
+        Resp3Piece::bulk(data.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn inline_command_is_case_insensitive() {
+        let mut buf = BytesMut::from(&b"GET foo\r\n\r\nGeT \"bar baz\"\n"[..]);
+        let mut decoder = RequestDecoder::new(Limits::default());
+        for expected in [&b"foo"[..], b"bar baz"] {
+            let args = decoder.decode(&mut buf).unwrap().unwrap();
+            match Command::from_args(args).unwrap() {
+                Command::String(StringCommand::Get { key }) => assert_eq!(key, expected),
+                cmd => panic!("unexpected command {:?}", cmd),
+            }
+        }
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+    }
+}
@@ -0,0 +1,514 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Result},
+    object::{RedisSet, Value},
+    protocol::Resp3Piece,
+    util::parse_i64,
+};
+
+use super::{hash::random_picks, list::parse_positive, not_integer_error, syntax_error, Args};
+
+/// Operations of the commands combining sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Commands working on sets.
+#[derive(Debug)]
+pub enum SetCommand {
+    Add {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Rem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    IsMember {
+        key: Bytes,
+        member: Bytes,
+    },
+    MIsMember {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Members {
+        key: Bytes,
+    },
+    Card {
+        key: Bytes,
+    },
+    /// `SPOP key [count]`
+    Pop {
+        key: Bytes,
+        count: Option<usize>,
+    },
+    /// `SRANDMEMBER key [count]`
+    RandMember {
+        key: Bytes,
+        count: Option<i64>,
+    },
+    /// `SMOVE source destination member`
+    Move {
+        src: Bytes,
+        dst: Bytes,
+        member: Bytes,
+    },
+    /// `SINTER`, `SUNION` and `SDIFF`, and their `STORE` variants which store the
+    /// result at `dst`.
+    Combine {
+        op: SetOp,
+        keys: Vec<Bytes>,
+        dst: Option<Bytes>,
+    },
+    /// `SINTERCARD numkeys key [key ...] [LIMIT limit]`, zero meaning no limit.
+    InterCard {
+        keys: Vec<Bytes>,
+        limit: usize,
+    },
+}
+
+impl SetCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "sadd" => Self::Add {
+                key: args.next_arg()?,
+                members: args.rest()?,
+            },
+            "srem" => Self::Rem {
+                key: args.next_arg()?,
+                members: args.rest()?,
+            },
+            "sismember" => Self::IsMember {
+                key: args.next_arg()?,
+                member: args.next_arg()?,
+            },
+            "smismember" => Self::MIsMember {
+                key: args.next_arg()?,
+                members: args.rest()?,
+            },
+            "smembers" => Self::Members { key: args.next_arg()? },
+            "scard" => Self::Card { key: args.next_arg()? },
+            "spop" => {
+                let key = args.next_arg()?;
+                let count = match args.next_opt() {
+                    Some(count) => match parse_i64(&count).ok_or_else(not_integer_error)? {
+                        count if count < 0 => {
+                            return Err(CommandError::Err(
+                                "value is out of range, must be positive".into(),
+                            )
+                            .into())
+                        }
+                        count => Some(count as usize),
+                    },
+                    None => None,
+                };
+                Self::Pop { key, count }
+            }
+            "srandmember" => {
+                let key = args.next_arg()?;
+                let count = match args.next_opt() {
+                    Some(count) => Some(parse_i64(&count).ok_or_else(not_integer_error)?),
+                    None => None,
+                };
+                Self::RandMember { key, count }
+            }
+            "smove" => Self::Move {
+                src: args.next_arg()?,
+                dst: args.next_arg()?,
+                member: args.next_arg()?,
+            },
+            "sinter" | "sunion" | "sdiff" | "sinterstore" | "sunionstore" | "sdiffstore" => {
+                let op = match &name[..name.len().min(6)] {
+                    "sinter" => SetOp::Inter,
+                    "sunion" => SetOp::Union,
+                    _ => SetOp::Diff,
+                };
+                let dst = match name.ends_with("store") {
+                    true => Some(args.next_arg()?),
+                    false => None,
+                };
+                Self::Combine {
+                    op,
+                    keys: args.rest()?,
+                    dst,
+                }
+            }
+            "sintercard" => {
+                let numkeys =
+                    parse_positive(&args.next_arg()?, "numkeys should be greater than 0", 1)?;
+                if numkeys > args.remaining() {
+                    return Err(CommandError::Err(
+                        "Number of keys can't be greater than number of args".into(),
+                    )
+                    .into());
+                }
+                let keys = (0..numkeys).map(|_| args.next_arg()).collect::<Result<_>>()?;
+                let limit = match args.next_lower().as_deref() {
+                    None => 0,
+                    Some(b"limit") => {
+                        parse_positive(&args.next_arg()?, "LIMIT can't be negative", 0)?
+                    }
+                    Some(_) => return Err(syntax_error()),
+                };
+                Self::InterCard { keys, limit }
+            }
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::Add { key, members } => {
+                let limits = *db.limits();
+                let set = set_or_create(db, key)?;
+                let added = members.iter().filter(|member| set.insert(member, &limits)).count();
+                Ok(Resp3Piece::Integer(added as i64))
+            }
+            Self::Rem { key, members } => {
+                let set = match db.lookup_mut(&key) {
+                    Some(value) => value.as_set_mut()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let removed = members.iter().filter(|member| set.remove(member)).count();
+                if set.is_empty() {
+                    db.remove(&key);
+                }
+                Ok(Resp3Piece::Integer(removed as i64))
+            }
+            Self::IsMember { key, member } => {
+                let found = match db.lookup(&key) {
+                    Some(value) => value.as_set()?.contains(&member),
+                    None => false,
+                };
+                Ok(Resp3Piece::Integer(found as i64))
+            }
+            Self::MIsMember { key, members } => {
+                let set = match db.lookup(&key) {
+                    Some(value) => Some(value.as_set()?),
+                    None => None,
+                };
+                let found = members
+                    .iter()
+                    .map(|member| set.is_some_and(|set| set.contains(member)))
+                    .map(|found| Resp3Piece::Integer(found as i64))
+                    .collect();
+                Ok(Resp3Piece::Array(found))
+            }
+            Self::Members { key } => {
+                let members = match db.lookup(&key) {
+                    Some(value) => value.as_set()?.iter().map(Resp3Piece::bulk).collect(),
+                    None => vec![],
+                };
+                Ok(Resp3Piece::Set(members))
+            }
+            Self::Card { key } => {
+                let len = match db.lookup(&key) {
+                    Some(value) => value.as_set()?.len(),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::Pop { key, count } => {
+                let set = match db.lookup_mut(&key) {
+                    Some(value) => value.as_set_mut()?,
+                    None if count.is_some() => return Ok(Resp3Piece::Set(vec![])),
+                    None => return Ok(Resp3Piece::Null),
+                };
+                let reply = match count {
+                    None => Resp3Piece::bulk(set.pop_random().unwrap()),
+                    Some(count) if count >= set.len() => {
+                        let members = set.iter().map(Resp3Piece::bulk).collect();
+                        db.remove(&key);
+                        return Ok(Resp3Piece::Set(members));
+                    }
+                    Some(count) => {
+                        let pick = || set.random_member().unwrap();
+                        let members: Vec<Bytes> =
+                            random_picks(set.len(), count as i64, pick, || set.iter().collect())
+                                .collect();
+                        for member in &members {
+                            set.remove(member);
+                        }
+                        Resp3Piece::Set(members.into_iter().map(Resp3Piece::bulk).collect())
+                    }
+                };
+                if set.is_empty() {
+                    db.remove(&key);
+                }
+                Ok(reply)
+            }
+            Self::RandMember { key, count } => {
+                let set = match db.lookup(&key) {
+                    Some(value) => value.as_set()?,
+                    None if count.is_some() => return Ok(Resp3Piece::Array(vec![])),
+                    None => return Ok(Resp3Piece::Null),
+                };
+                let count = match count {
+                    Some(count) => count,
+                    None => return Ok(Resp3Piece::bulk(set.random_member().unwrap())),
+                };
+                if count < -i64::MAX {
+                    return Err(CommandError::Err("value is out of range".into()).into());
+                }
+                let pick = || set.random_member().unwrap();
+                let members = random_picks(set.len(), count, pick, || set.iter().collect());
+                Ok(Resp3Piece::Array(members.map(Resp3Piece::bulk).collect()))
+            }
+            Self::Move { src, dst, member } => {
+                let found = match db.lookup(&src) {
+                    Some(value) => Some(value.as_set()?.contains(&member)),
+                    None => None,
+                };
+                if let Some(value) = db.lookup(&dst) {
+                    value.as_set()?;
+                }
+                let found = found.unwrap_or(false);
+                if !found || src == dst {
+                    return Ok(Resp3Piece::Integer(found as i64));
+                }
+                let set = db.lookup_mut(&src).unwrap().as_set_mut()?;
+                set.remove(&member);
+                if set.is_empty() {
+                    db.remove(&src);
+                }
+                let limits = *db.limits();
+                set_or_create(db, dst)?.insert(&member, &limits);
+                Ok(Resp3Piece::Integer(1))
+            }
+            Self::Combine { op, keys, dst } => {
+                let set = combine(db, op, &keys)?;
+                match dst {
+                    None => Ok(Resp3Piece::Set(set.iter().map(Resp3Piece::bulk).collect())),
+                    Some(dst) if set.is_empty() => {
+                        db.remove(&dst);
+                        Ok(Resp3Piece::Integer(0))
+                    }
+                    Some(dst) => {
+                        let len = set.len();
+                        db.set(dst, Value::Set(set), false);
+                        Ok(Resp3Piece::Integer(len as i64))
+                    }
+                }
+            }
+            Self::InterCard { keys, limit } => {
+                let sets = match sets_of(db, &keys)?.into_iter().collect::<Option<Vec<_>>>() {
+                    Some(sets) => sets,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let (smallest, others) = smallest_first(sets);
+                let mut card = 0;
+                for member in smallest.iter() {
+                    if others.iter().all(|set| set.contains(&member)) {
+                        card += 1;
+                        if card == limit {
+                            break;
+                        }
+                    }
+                }
+                Ok(Resp3Piece::Integer(card as i64))
+            }
+        }
+    }
+}
+
+/// Returns the set of the key, which is created when it does not exist.
+fn set_or_create(db: &mut Db, key: Bytes) -> Result<&mut RedisSet> {
+    if !db.exists(&key) {
+        db.set(key.clone(), Value::Set(RedisSet::new()), false);
+    }
+    db.lookup_mut(&key).unwrap().as_set_mut()
+}
+
+/// Looks the sets of the keys up, `None` standing for a key which does not exist, which
+/// is an empty set. Every key must hold a set.
+fn sets_of<'a>(db: &'a mut Db, keys: &[Bytes]) -> Result<Vec<Option<&'a RedisSet>>> {
+    db.lookup_many(keys)
+        .into_iter()
+        .map(|value| value.map(Value::as_set).transpose())
+        .collect()
+}
+
+/// Splits the sets into the smallest one and the others, so that an intersection only
+/// walks the smallest set.
+fn smallest_first(mut sets: Vec<&RedisSet>) -> (&RedisSet, Vec<&RedisSet>) {
+    sets.sort_by_key(|set| set.len());
+    let smallest = sets.remove(0);
+    (smallest, sets)
+}
+
+/// Computes the intersection, union or difference of the sets of the keys. The result
+/// is encoded like any set built member by member, an intset when it can.
+fn combine(db: &mut Db, op: SetOp, keys: &[Bytes]) -> Result<RedisSet> {
+    let limits = *db.limits();
+    let sets = sets_of(db, keys)?;
+    let mut result = RedisSet::new();
+    match op {
+        SetOp::Inter => {
+            if let Some(sets) = sets.into_iter().collect::<Option<Vec<_>>>() {
+                let (smallest, others) = smallest_first(sets);
+                for member in smallest.iter() {
+                    if others.iter().all(|set| set.contains(&member)) {
+                        result.insert(&member, &limits);
+                    }
+                }
+            }
+        }
+        SetOp::Union => {
+            for set in sets.into_iter().flatten() {
+                for member in set.iter() {
+                    result.insert(&member, &limits);
+                }
+            }
+        }
+        SetOp::Diff => {
+            if let Some((Some(first), others)) = sets.split_first() {
+                for member in first.iter() {
+                    if !others.iter().flatten().any(|set| set.contains(&member)) {
+                        result.insert(&member, &limits);
+                    }
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+    };
+
+    fn int(db: &mut Db, args: &[&str]) -> i64 {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Integer(n) => n,
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    /// Members of a reply, sorted as sets have no order.
+    fn members(reply: Resp3Piece) -> Vec<String> {
+        let items = match reply {
+            Resp3Piece::Array(items) | Resp3Piece::Set(items) => items,
+            other => panic!("unexpected reply {:?}", other),
+        };
+        let mut members: Vec<String> = items
+            .into_iter()
+            .map(|item| match item {
+                Resp3Piece::BulkString { data } => String::from_utf8(data.to_vec()).unwrap(),
+                other => panic!("unexpected reply {:?}", other),
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn add_remove_and_check() {
+        let mut db = Db::new();
+        assert_eq!(int(&mut db, &["sadd", "s", "3", "1", "2", "1"]), 3);
+        assert_eq!(exec(&mut db, &["object", "encoding", "s"]).unwrap(), bulk("intset"));
+        assert_eq!(
+            exec(&mut db, &["smembers", "s"]).unwrap(),
+            Resp3Piece::Set(vec![bulk("1"), bulk("2"), bulk("3")])
+        );
+        assert_eq!(int(&mut db, &["sadd", "s", "a"]), 1);
+        assert_eq!(exec(&mut db, &["object", "encoding", "s"]).unwrap(), bulk("hashtable"));
+        assert_eq!(int(&mut db, &["sismember", "s", "2"]), 1);
+        assert_eq!(int(&mut db, &["sismember", "s", "x"]), 0);
+        assert_eq!(
+            exec(&mut db, &["smismember", "s", "a", "x"]).unwrap(),
+            Resp3Piece::Array(vec![Resp3Piece::Integer(1), Resp3Piece::Integer(0)])
+        );
+        assert_eq!(int(&mut db, &["scard", "s"]), 4);
+        assert_eq!(int(&mut db, &["srem", "s", "1", "x"]), 1);
+        assert_eq!(int(&mut db, &["srem", "s", "2", "3", "a"]), 3);
+        assert_eq!(int(&mut db, &["exists", "s"]), 0);
+        assert_eq!(exec(&mut db, &["smembers", "s"]).unwrap(), Resp3Piece::Set(vec![]));
+        for i in 0..513 {
+            exec(&mut db, &["sadd", "big", &i.to_string()]).unwrap();
+            let encoding = if i < 512 { "intset" } else { "hashtable" };
+            assert_eq!(exec(&mut db, &["object", "encoding", "big"]).unwrap(), bulk(encoding));
+        }
+        exec(&mut db, &["set", "str", "v"]).unwrap();
+        assert!(exec(&mut db, &["sadd", "str", "a"]).is_err());
+        assert!(exec(&mut db, &["scard", "str"]).is_err());
+    }
+
+    #[test]
+    fn pop_random_and_move() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["spop", "s"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["srandmember", "s", "2"]).unwrap(), Resp3Piece::Array(vec![]));
+        exec(&mut db, &["sadd", "s", "a", "b", "c", "d"]).unwrap();
+        assert_eq!(members(exec(&mut db, &["srandmember", "s", "10"]).unwrap()).len(), 4);
+        assert_eq!(members(exec(&mut db, &["srandmember", "s", "-10"]).unwrap()).len(), 10);
+        assert!(exec(&mut db, &["srandmember", "s", "-9223372036854775808"]).is_err());
+        assert_eq!(members(exec(&mut db, &["srandmember", "s", "-100000"]).unwrap()).len(), 100000);
+        let popped = members(exec(&mut db, &["spop", "s", "3"]).unwrap());
+        assert_eq!(popped.len(), 3);
+        assert_eq!(int(&mut db, &["scard", "s"]), 1);
+        assert!(exec(&mut db, &["spop", "s", "-1"]).is_err());
+        let last = exec(&mut db, &["srandmember", "s"]).unwrap();
+        assert_eq!(exec(&mut db, &["spop", "s"]).unwrap(), last);
+        assert_eq!(int(&mut db, &["exists", "s"]), 0);
+
+        exec(&mut db, &["sadd", "src", "1", "2"]).unwrap();
+        assert_eq!(int(&mut db, &["smove", "src", "dst", "1"]), 1);
+        assert_eq!(int(&mut db, &["smove", "src", "dst", "1"]), 0);
+        assert_eq!(int(&mut db, &["smove", "src", "src", "2"]), 1);
+        assert_eq!(int(&mut db, &["smove", "src", "dst", "2"]), 1);
+        assert_eq!(int(&mut db, &["exists", "src"]), 0);
+        assert_eq!(members(exec(&mut db, &["smembers", "dst"]).unwrap()), vec!["1", "2"]);
+        exec(&mut db, &["set", "str", "v"]).unwrap();
+        assert!(exec(&mut db, &["smove", "dst", "str", "1"]).is_err());
+        assert!(exec(&mut db, &["smove", "nokey", "str", "1"]).is_err());
+    }
+
+    #[test]
+    fn set_algebra() {
+        let mut db = Db::new();
+        exec(&mut db, &["sadd", "a", "1", "2", "3", "x"]).unwrap();
+        exec(&mut db, &["sadd", "b", "2", "3", "4"]).unwrap();
+        exec(&mut db, &["sadd", "c", "3", "x"]).unwrap();
+        assert_eq!(members(exec(&mut db, &["sinter", "a", "b"]).unwrap()), vec!["2", "3"]);
+        assert_eq!(members(exec(&mut db, &["sinter", "a", "b", "c"]).unwrap()), vec!["3"]);
+        assert_eq!(members(exec(&mut db, &["sinter", "a", "nokey"]).unwrap()).len(), 0);
+        assert_eq!(
+            members(exec(&mut db, &["sunion", "b", "c", "nokey"]).unwrap()),
+            vec!["2", "3", "4", "x"]
+        );
+        assert_eq!(members(exec(&mut db, &["sdiff", "a", "b", "nokey"]).unwrap()), vec!["1", "x"]);
+        assert_eq!(members(exec(&mut db, &["sdiff", "nokey", "a"]).unwrap()).len(), 0);
+
+        assert_eq!(int(&mut db, &["sinterstore", "d", "a", "b"]), 2);
+        assert_eq!(exec(&mut db, &["object", "encoding", "d"]).unwrap(), bulk("intset"));
+        assert_eq!(int(&mut db, &["sunionstore", "d", "d", "c"]), 3);
+        assert_eq!(members(exec(&mut db, &["smembers", "d"]).unwrap()), vec!["2", "3", "x"]);
+        assert_eq!(int(&mut db, &["sdiffstore", "d", "c", "a"]), 0);
+        assert_eq!(int(&mut db, &["exists", "d"]), 0);
+
+        assert_eq!(int(&mut db, &["sintercard", "2", "a", "b"]), 2);
+        assert_eq!(int(&mut db, &["sintercard", "2", "a", "b", "limit", "1"]), 1);
+        assert_eq!(int(&mut db, &["sintercard", "2", "a", "b", "limit", "0"]), 2);
+        assert_eq!(int(&mut db, &["sintercard", "1", "nokey"]), 0);
+        assert!(exec(&mut db, &["sintercard", "0", "a"]).is_err());
+        assert!(exec(&mut db, &["sintercard", "3", "a", "b"]).is_err());
+        assert!(exec(&mut db, &["sintercard", "2", "a", "b", "limit", "-1"]).is_err());
+        assert!(exec(&mut db, &["sintercard", "1", "a", "b"]).is_err());
+
+        exec(&mut db, &["set", "str", "v"]).unwrap();
+        assert!(exec(&mut db, &["sunion", "a", "str"]).is_err());
+        assert!(exec(&mut db, &["sinter", "nokey", "str"]).is_err());
+    }
+}
@@ -0,0 +1,1536 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Error, Result},
+    object::{ConsumerGroup, Stream, StreamEntry, StreamId, TrimStrategy, Value},
+    protocol::Resp3Piece,
+    util::{now_ms, parse_i64},
+};
+
+use super::{lower_bytes, not_integer_error, syntax_error, Args};
+
+/// ID of an entry added by XADD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddId {
+    /// `*`, generated from the current time.
+    Auto,
+    /// `ms-*`, the sequence number being generated.
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/// How XADD and XTRIM trim a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trim {
+    pub strategy: TrimStrategy,
+    /// `~`, only whole blocks of entries are removed.
+    pub approx: bool,
+    /// Max number of entries removed with `~`, zero for no limit, `None` for the
+    /// default of 100 blocks.
+    pub limit: Option<usize>,
+}
+
+/// `XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS
+/// key [key ...] id [id ...]`, which is a blocking command when BLOCK is given.
+#[derive(Debug)]
+pub struct ReadGroup {
+    pub group: Bytes,
+    pub consumer: Bytes,
+    pub count: Option<usize>,
+    /// Timeout in milliseconds, zero to block forever.
+    pub block: Option<u64>,
+    pub noack: bool,
+    /// Keys along with the ID after which the entries owned by the consumer are read
+    /// again, `None` for `>` which reads entries never delivered to the group.
+    pub streams: Vec<(Bytes, Option<StreamId>)>,
+}
+
+/// Range of XPENDING in its extended form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRange {
+    pub min_idle: u64,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<Bytes>,
+}
+
+/// Time of the delivery set by XCLAIM, where times out of range mean now, as in redis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryTime {
+    /// `IDLE ms`
+    Idle(i64),
+    /// `TIME unix-time-milliseconds`
+    At(i64),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClaimOptions {
+    pub delivery_time: Option<DeliveryTime>,
+    /// `RETRYCOUNT count`, replacing the delivery count.
+    pub retry_count: Option<u64>,
+    /// `FORCE`, entries not pending are claimed as long as they exist.
+    pub force: bool,
+    /// `JUSTID`, only IDs are replied, and the delivery count is left alone.
+    pub justid: bool,
+    /// `LASTID id`, which the last ID delivered to the group is moved to if greater.
+    pub last_id: Option<StreamId>,
+}
+
+/// Commands working on streams.
+#[derive(Debug)]
+pub enum StreamCommand {
+    /// `XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]]
+    /// * | id field value [field value ...]`
+    Add {
+        key: Bytes,
+        nomkstream: bool,
+        trim: Option<Trim>,
+        id: AddId,
+        fields: Vec<Bytes>,
+    },
+    Len {
+        key: Bytes,
+    },
+    /// `XRANGE key start end [COUNT count]` and `XREVRANGE key end start`.
+    Range {
+        key: Bytes,
+        start: StreamId,
+        end: StreamId,
+        rev: bool,
+        count: Option<usize>,
+    },
+    Del {
+        key: Bytes,
+        ids: Vec<StreamId>,
+    },
+    /// `XTRIM key MAXLEN | MINID [= | ~] threshold [LIMIT count]`
+    Trim {
+        key: Bytes,
+        trim: Trim,
+    },
+    /// `XGROUP CREATE key group id | $ [MKSTREAM] [ENTRIESREAD entries-read]`, the ID
+    /// being `None` for `$`.
+    GroupCreate {
+        key: Bytes,
+        group: Bytes,
+        id: Option<StreamId>,
+        mkstream: bool,
+        entries_read: Option<u64>,
+    },
+    /// `XGROUP SETID key group id | $ [ENTRIESREAD entries-read]`
+    GroupSetId {
+        key: Bytes,
+        group: Bytes,
+        id: Option<StreamId>,
+        entries_read: Option<u64>,
+    },
+    GroupDestroy {
+        key: Bytes,
+        group: Bytes,
+    },
+    GroupCreateConsumer {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+    },
+    GroupDelConsumer {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+    },
+    ReadGroup(ReadGroup),
+    Ack {
+        key: Bytes,
+        group: Bytes,
+        ids: Vec<StreamId>,
+    },
+    /// `XPENDING key group [[IDLE min-idle-time] start end count [consumer]]`
+    Pending {
+        key: Bytes,
+        group: Bytes,
+        range: Option<PendingRange>,
+    },
+    /// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+    /// [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID id]`
+    Claim {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        options: ClaimOptions,
+    },
+    /// `XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]`
+    AutoClaim {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        justid: bool,
+    },
+    /// `XINFO STREAM key`
+    InfoStream {
+        key: Bytes,
+    },
+    InfoGroups {
+        key: Bytes,
+    },
+    InfoConsumers {
+        key: Bytes,
+        group: Bytes,
+    },
+}
+
+impl StreamCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "xadd" => Self::parse_xadd(args)?,
+            "xlen" => Self::Len { key: args.next_arg()? },
+            "xrange" | "xrevrange" => {
+                let key = args.next_arg()?;
+                let rev = name == "xrevrange";
+                let (first, second) = (args.next_arg()?, args.next_arg()?);
+                let (start, end) = if rev { (second, first) } else { (first, second) };
+                let start = parse_range_id(&start, false)?;
+                let end = parse_range_id(&end, true)?;
+                let count = match args.next_lower().as_deref() {
+                    None => None,
+                    Some(b"count") => {
+                        let count = parse_i64(&args.next_arg()?).ok_or_else(not_integer_error)?;
+                        Some(count.max(0) as usize)
+                    }
+                    Some(_) => return Err(syntax_error()),
+                };
+                Self::Range {
+                    key,
+                    start,
+                    end,
+                    rev,
+                    count,
+                }
+            }
+            "xdel" => Self::Del {
+                key: args.next_arg()?,
+                ids: args
+                    .rest()?
+                    .iter()
+                    .map(|id| parse_id(id, 0))
+                    .collect::<Result<_>>()?,
+            },
+            "xtrim" => {
+                let key = args.next_arg()?;
+                let kind = args.next_arg()?;
+                let mut trim = match parse_trim(&kind, args)? {
+                    Some(trim) => trim,
+                    None => return Err(syntax_error()),
+                };
+                match args.next_lower().as_deref() {
+                    None => {}
+                    Some(b"limit") => parse_limit(&mut trim, &args.next_arg()?)?,
+                    Some(_) => return Err(syntax_error()),
+                }
+                Self::Trim { key, trim }
+            }
+            "xgroup" => Self::parse_xgroup(args)?,
+            "xreadgroup" => Self::ReadGroup(ReadGroup::parse(args)?),
+            "xack" => Self::Ack {
+                key: args.next_arg()?,
+                group: args.next_arg()?,
+                ids: args
+                    .rest()?
+                    .iter()
+                    .map(|id| parse_id(id, 0))
+                    .collect::<Result<_>>()?,
+            },
+            "xpending" => {
+                let key = args.next_arg()?;
+                let group = args.next_arg()?;
+                let range = match args.next_opt() {
+                    Some(arg) => Some(parse_pending_range(arg, args)?),
+                    None => None,
+                };
+                Self::Pending { key, group, range }
+            }
+            "xclaim" => Self::parse_xclaim(args)?,
+            "xautoclaim" => {
+                let key = args.next_arg()?;
+                let group = args.next_arg()?;
+                let consumer = args.next_arg()?;
+                let min_idle = args.next_i64()?.max(0) as u64;
+                let start = parse_range_id(&args.next_arg()?, false)?;
+                let mut count = 100;
+                let mut justid = false;
+                while let Some(option) = args.next_lower() {
+                    match option.as_slice() {
+                        b"count" => match args.next_i64()? {
+                            count if count < 1 => {
+                                return Err(CommandError::Err("COUNT must be > 0".into()).into())
+                            }
+                            n => count = n as usize,
+                        },
+                        b"justid" => justid = true,
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                Self::AutoClaim {
+                    key,
+                    group,
+                    consumer,
+                    min_idle,
+                    start,
+                    count,
+                    justid,
+                }
+            }
+            "xinfo" => {
+                let sub = args.next_arg()?;
+                match lower_bytes(&sub).as_slice() {
+                    b"stream" => Self::InfoStream { key: args.next_arg()? },
+                    b"groups" => Self::InfoGroups { key: args.next_arg()? },
+                    b"consumers" => Self::InfoConsumers {
+                        key: args.next_arg()?,
+                        group: args.next_arg()?,
+                    },
+                    _ => return Err(unknown_subcommand_error("XINFO", &sub)),
+                }
+            }
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    fn parse_xadd(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let mut nomkstream = false;
+        let mut trim: Option<Trim> = None;
+        let id = loop {
+            let arg = args.next_arg()?;
+            match lower_bytes(&arg).as_slice() {
+                b"nomkstream" => nomkstream = true,
+                b"limit" => match trim.as_mut() {
+                    Some(trim) => parse_limit(trim, &args.next_arg()?)?,
+                    None => return Err(syntax_error()),
+                },
+                _ => match parse_trim(&arg, args)? {
+                    Some(_) if trim.is_some() => {
+                        return Err(CommandError::Err(
+                            "syntax error, MAXLEN and MINID options at the same time are not \
+                             compatible"
+                                .into(),
+                        )
+                        .into())
+                    }
+                    Some(parsed) => trim = Some(parsed),
+                    None => break arg,
+                },
+            }
+        };
+        let id = match &id[..] {
+            b"*" => AddId::Auto,
+            [ms @ .., b'-', b'*'] => AddId::AutoSeq(parse_u64(ms).ok_or_else(invalid_id_error)?),
+            _ => AddId::Explicit(parse_id(&id, 0)?),
+        };
+        let fields = args.rest()?;
+        if !fields.len().is_multiple_of(2) {
+            return Err(args.arity_error());
+        }
+        Ok(Self::Add {
+            key,
+            nomkstream,
+            trim,
+            id,
+            fields,
+        })
+    }
+
+    fn parse_xgroup(args: &mut Args) -> Result<Self> {
+        let name = args.next_arg()?;
+        let sub = lower_bytes(&name);
+        let cmd = match sub.as_slice() {
+            b"create" | b"setid" => {
+                let key = args.next_arg()?;
+                let group = args.next_arg()?;
+                let id = match &args.next_arg()?[..] {
+                    b"$" => None,
+                    id => Some(parse_id(id, 0)?),
+                };
+                let mut mkstream = false;
+                let mut entries_read = None;
+                while let Some(option) = args.next_lower() {
+                    match option.as_slice() {
+                        b"mkstream" if sub == b"create" => mkstream = true,
+                        b"entriesread" => match args.next_i64()? {
+                            -1 => entries_read = None,
+                            n if n < 0 => {
+                                return Err(CommandError::Err(
+                                    "value for ENTRIESREAD must be positive or -1".into(),
+                                )
+                                .into())
+                            }
+                            n => entries_read = Some(n as u64),
+                        },
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                if sub == b"create" {
+                    Self::GroupCreate {
+                        key,
+                        group,
+                        id,
+                        mkstream,
+                        entries_read,
+                    }
+                } else {
+                    Self::GroupSetId {
+                        key,
+                        group,
+                        id,
+                        entries_read,
+                    }
+                }
+            }
+            b"destroy" => Self::GroupDestroy {
+                key: args.next_arg()?,
+                group: args.next_arg()?,
+            },
+            b"createconsumer" => Self::GroupCreateConsumer {
+                key: args.next_arg()?,
+                group: args.next_arg()?,
+                consumer: args.next_arg()?,
+            },
+            b"delconsumer" => Self::GroupDelConsumer {
+                key: args.next_arg()?,
+                group: args.next_arg()?,
+                consumer: args.next_arg()?,
+            },
+            _ => return Err(unknown_subcommand_error("XGROUP", &name)),
+        };
+        Ok(cmd)
+    }
+
+    /// Parses XCLAIM, whose IDs go on until the first argument which is not one.
+    fn parse_xclaim(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let group = args.next_arg()?;
+        let consumer = args.next_arg()?;
+        let min_idle = args.next_i64()?.max(0) as u64;
+        let mut rest = args.rest()?.into_iter();
+        let mut ids = vec![];
+        let mut options = ClaimOptions::default();
+        let mut option = None;
+        for arg in rest.by_ref() {
+            match parse_id(&arg, 0) {
+                Ok(id) => ids.push(id),
+                Err(_) => {
+                    option = Some(arg);
+                    break;
+                }
+            }
+        }
+        while let Some(arg) = option.take().or_else(|| rest.next()) {
+            let mut value = || rest.next().ok_or_else(syntax_error);
+            match lower_bytes(&arg).as_slice() {
+                b"idle" => {
+                    let idle = parse_int(&value()?)?;
+                    options.delivery_time = Some(DeliveryTime::Idle(idle));
+                }
+                b"time" => {
+                    let time = parse_int(&value()?)?;
+                    options.delivery_time = Some(DeliveryTime::At(time));
+                }
+                // a negative count is ignored, as in redis.
+                b"retrycount" => options.retry_count = u64::try_from(parse_int(&value()?)?).ok(),
+                b"force" => options.force = true,
+                b"justid" => options.justid = true,
+                b"lastid" => options.last_id = Some(parse_id(&value()?, 0)?),
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(Self::Claim {
+            key,
+            group,
+            consumer,
+            min_idle,
+            ids,
+            options,
+        })
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::Add {
+                key,
+                nomkstream,
+                trim,
+                id,
+                fields,
+            } => {
+                let last_id = match db.lookup(&key) {
+                    Some(value) => value.as_stream()?.last_id(),
+                    None if nomkstream => return Ok(Resp3Piece::Null),
+                    None => StreamId::MIN,
+                };
+                let id = next_id(id, last_id)?;
+                if !db.exists(&key) {
+                    db.set(key.clone(), Value::Stream(Stream::new()), false);
+                }
+                let limits = *db.limits();
+                let stream = db.lookup_mut(&key).unwrap().as_stream_mut()?;
+                stream.append(id, &fields, &limits);
+                if let Some(trim) = trim {
+                    apply_trim(stream, trim, limits.stream_node_max_entries);
+                }
+                db.signal_stream_ready(&key);
+                Ok(Resp3Piece::bulk(id.to_string()))
+            }
+            Self::Len { key } => {
+                let len = match db.lookup(&key) {
+                    Some(value) => value.as_stream()?.len(),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::Range {
+                key,
+                start,
+                end,
+                rev,
+                count,
+            } => {
+                let entries = match db.lookup(&key) {
+                    Some(value) => value.as_stream()?.range(start, end, rev, count),
+                    None => vec![],
+                };
+                Ok(entries_reply(entries))
+            }
+            Self::Del { key, ids } => {
+                let stream = match db.lookup_mut(&key) {
+                    Some(value) => value.as_stream_mut()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let deleted = ids.into_iter().filter(|&id| stream.delete(id)).count();
+                Ok(Resp3Piece::Integer(deleted as i64))
+            }
+            Self::Trim { key, trim } => {
+                let max_entries = db.limits().stream_node_max_entries;
+                let removed = match db.lookup_mut(&key) {
+                    Some(value) => apply_trim(value.as_stream_mut()?, trim, max_entries),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(removed as i64))
+            }
+            Self::GroupCreate {
+                key,
+                group,
+                id,
+                mkstream,
+                entries_read,
+            } => {
+                if !db.exists(&key) {
+                    if !mkstream {
+                        return Err(key_required_error());
+                    }
+                    db.set(key.clone(), Value::Stream(Stream::new()), false);
+                }
+                let stream = db.lookup_mut(&key).unwrap().as_stream_mut()?;
+                let id = id.unwrap_or_else(|| stream.last_id());
+                if !stream.create_group(group, ConsumerGroup::new(id, entries_read)) {
+                    return Err(CommandError::BusyGroup.into());
+                }
+                Ok(Resp3Piece::simple("OK"))
+            }
+            Self::GroupSetId {
+                key,
+                group,
+                id,
+                entries_read,
+            } => {
+                let stream = match db.lookup_mut(&key) {
+                    Some(value) => value.as_stream_mut()?,
+                    None => return Err(key_required_error()),
+                };
+                let id = id.unwrap_or_else(|| stream.last_id());
+                let group = match stream.group_mut(&group) {
+                    Some(group) => group,
+                    None => return Err(no_such_group_error(&key, &group)),
+                };
+                group.last_id = id;
+                group.entries_read = entries_read;
+                Ok(Resp3Piece::simple("OK"))
+            }
+            Self::GroupDestroy { key, group } => {
+                let stream = match db.lookup_mut(&key) {
+                    Some(value) => value.as_stream_mut()?,
+                    None => return Err(key_required_error()),
+                };
+                Ok(Resp3Piece::Integer(stream.destroy_group(&group) as i64))
+            }
+            Self::GroupCreateConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let stream = match db.lookup_mut(&key) {
+                    Some(value) => value.as_stream_mut()?,
+                    None => return Err(key_required_error()),
+                };
+                let created = match stream.group_mut(&group) {
+                    Some(group) => group.create_consumer(&consumer, now_ms()),
+                    None => return Err(no_such_group_error(&key, &group)),
+                };
+                Ok(Resp3Piece::Integer(created as i64))
+            }
+            Self::GroupDelConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let stream = match db.lookup_mut(&key) {
+                    Some(value) => value.as_stream_mut()?,
+                    None => return Err(key_required_error()),
+                };
+                let pending = match stream.group_mut(&group) {
+                    Some(group) => group.delete_consumer(&consumer).unwrap_or(0),
+                    None => return Err(no_such_group_error(&key, &group)),
+                };
+                Ok(Resp3Piece::Integer(pending as i64))
+            }
+            Self::ReadGroup(read) => Ok(read.read(db, None)?.unwrap_or(Resp3Piece::NullArray)),
+            Self::Ack { key, group, ids } => {
+                let group = match db.lookup_mut(&key) {
+                    Some(value) => value.as_stream_mut()?.group_mut(&group),
+                    None => None,
+                };
+                let acked = match group {
+                    Some(group) => ids.into_iter().filter(|&id| group.ack(id)).count(),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(acked as i64))
+            }
+            Self::Pending { key, group, range } => {
+                let stream = group_stream(db, &key, &group)?;
+                let group = stream.group(&group).unwrap();
+                match range {
+                    Some(range) => Ok(pending_range_reply(group, range, now_ms())),
+                    None => Ok(pending_summary_reply(group)),
+                }
+            }
+            Self::Claim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                ids,
+                options,
+            } => {
+                let now = now_ms();
+                let stream = group_stream(db, &key, &group)?;
+                let entries: Vec<_> = ids.iter().map(|&id| stream.get(id)).collect();
+                let group = stream.group_mut(&group).unwrap();
+                let delivery_time = match options.delivery_time {
+                    Some(time) => time.resolve(now),
+                    None => now,
+                };
+                if let Some(last_id) = options.last_id {
+                    group.last_id = group.last_id.max(last_id);
+                }
+                group.seen(&consumer, now);
+                let mut claimed = vec![];
+                for (id, entry) in ids.into_iter().zip(entries) {
+                    let delivery_count = match (group.pending().get(&id), &entry) {
+                        // entries deleted from the stream leave the PEL.
+                        (Some(_), None) => {
+                            group.ack(id);
+                            continue;
+                        }
+                        (Some(pending), Some(_)) => {
+                            if now.saturating_sub(pending.delivery_time) < min_idle {
+                                continue;
+                            }
+                            pending.delivery_count
+                        }
+                        (None, Some(_)) if options.force => 1,
+                        (None, _) => continue,
+                    };
+                    let delivery_count = match options.retry_count {
+                        Some(count) => count,
+                        None if options.justid => delivery_count,
+                        None => delivery_count + 1,
+                    };
+                    group.assign(id, &consumer, delivery_time, delivery_count);
+                    claimed.push(entry.unwrap());
+                }
+                if !claimed.is_empty() {
+                    group.seen(&consumer, now).active_time = Some(now);
+                }
+                Ok(claimed_reply(claimed, options.justid))
+            }
+            Self::AutoClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                start,
+                count,
+                justid,
+            } => {
+                let now = now_ms();
+                let stream = group_stream(db, &key, &group)?;
+                stream.group_mut(&group).unwrap().seen(&consumer, now);
+                // as in redis, at most ten times as many pending entries as asked for are
+                // looked at, one more telling where the next call should start.
+                let attempts = count.saturating_mul(10);
+                let ids: Vec<StreamId> = stream
+                    .group(&group)
+                    .unwrap()
+                    .pending()
+                    .range(start..)
+                    .map(|(&id, _)| id)
+                    .take(attempts.saturating_add(1))
+                    .collect();
+                let mut ids = ids.into_iter();
+                let mut claimed = vec![];
+                let mut deleted = vec![];
+                for id in ids.by_ref().take(attempts) {
+                    let entry = stream.get(id);
+                    let group = stream.group_mut(&group).unwrap();
+                    let pending = &group.pending()[&id];
+                    let delivery_count = match entry {
+                        None => {
+                            group.ack(id);
+                            deleted.push(Resp3Piece::bulk(id.to_string()));
+                            continue;
+                        }
+                        Some(_) if now.saturating_sub(pending.delivery_time) < min_idle => {
+                            continue
+                        }
+                        Some(_) if justid => pending.delivery_count,
+                        Some(_) => pending.delivery_count + 1,
+                    };
+                    group.assign(id, &consumer, now, delivery_count);
+                    claimed.push(entry.unwrap());
+                    if claimed.len() == count {
+                        break;
+                    }
+                }
+                if !claimed.is_empty() {
+                    let group = stream.group_mut(&group).unwrap();
+                    group.seen(&consumer, now).active_time = Some(now);
+                }
+                let cursor = ids.next().unwrap_or(StreamId::MIN);
+                Ok(Resp3Piece::Array(vec![
+                    Resp3Piece::bulk(cursor.to_string()),
+                    claimed_reply(claimed, justid),
+                    Resp3Piece::Array(deleted),
+                ]))
+            }
+            Self::InfoStream { key } => {
+                let stream = match db.lookup(&key) {
+                    Some(value) => value.as_stream()?,
+                    None => return Err(CommandError::Err("no such key".into()).into()),
+                };
+                let id = |id: StreamId| Resp3Piece::bulk(id.to_string());
+                let entry = |entry: Option<StreamEntry>| match entry {
+                    Some(entry) => entry_reply(entry),
+                    None => Resp3Piece::Null,
+                };
+                let first_id = stream.first_entry().map_or(StreamId::MIN, |(id, _)| id);
+                let fields = vec![
+                    ("length", Resp3Piece::Integer(stream.len() as i64)),
+                    ("radix-tree-keys", Resp3Piece::Integer(stream.blocks() as i64)),
+                    ("last-generated-id", id(stream.last_id())),
+                    ("max-deleted-entry-id", id(stream.max_deleted_id())),
+                    ("entries-added", Resp3Piece::Integer(stream.entries_added() as i64)),
+                    ("recorded-first-entry-id", id(first_id)),
+                    ("groups", Resp3Piece::Integer(stream.groups().len() as i64)),
+                    ("first-entry", entry(stream.first_entry())),
+                    ("last-entry", entry(stream.last_entry())),
+                ];
+                Ok(map_reply(fields))
+            }
+            Self::InfoGroups { key } => {
+                let stream = match db.lookup(&key) {
+                    Some(value) => value.as_stream()?,
+                    None => return Err(CommandError::Err("no such key".into()).into()),
+                };
+                let optional =
+                    |n: Option<u64>| n.map_or(Resp3Piece::Null, |n| Resp3Piece::Integer(n as i64));
+                let groups = stream.groups().iter().map(|(name, group)| {
+                    map_reply(vec![
+                        ("name", Resp3Piece::bulk(name.clone())),
+                        ("consumers", Resp3Piece::Integer(group.consumers().len() as i64)),
+                        ("pending", Resp3Piece::Integer(group.pending().len() as i64)),
+                        ("last-delivered-id", Resp3Piece::bulk(group.last_id.to_string())),
+                        ("entries-read", optional(group.entries_read)),
+                        ("lag", optional(stream.lag(group))),
+                    ])
+                });
+                Ok(Resp3Piece::Array(groups.collect()))
+            }
+            Self::InfoConsumers { key, group } => {
+                let stream = match db.lookup(&key) {
+                    Some(value) => value.as_stream()?,
+                    None => return Err(CommandError::Err("no such key".into()).into()),
+                };
+                let group = match stream.group(&group) {
+                    Some(group) => group,
+                    None => return Err(no_such_group_error(&key, &group)),
+                };
+                let now = now_ms();
+                let since = |time: u64| Resp3Piece::Integer(now.saturating_sub(time) as i64);
+                let consumers = group.consumers().iter().map(|(name, consumer)| {
+                    map_reply(vec![
+                        ("name", Resp3Piece::bulk(name.clone())),
+                        ("pending", Resp3Piece::Integer(consumer.pending().len() as i64)),
+                        ("idle", since(consumer.seen_time)),
+                        ("inactive", consumer.active_time.map_or(Resp3Piece::Integer(-1), since)),
+                    ])
+                });
+                Ok(Resp3Piece::Array(consumers.collect()))
+            }
+        }
+    }
+}
+
+impl ReadGroup {
+    fn parse(args: &mut Args) -> Result<Self> {
+        if args.next_lower().as_deref() != Some(b"group") {
+            return Err(syntax_error());
+        }
+        let group = args.next_arg()?;
+        let consumer = args.next_arg()?;
+        let mut count = None;
+        let mut block = None;
+        let mut noack = false;
+        loop {
+            match lower_bytes(&args.next_arg()?).as_slice() {
+                b"count" => count = Some(args.next_i64()?.max(0) as usize).filter(|&n| n > 0),
+                b"block" => match args.next_i64()? {
+                    ms if ms < 0 => {
+                        return Err(CommandError::Err("timeout is negative".into()).into())
+                    }
+                    ms => block = Some(ms as u64),
+                },
+                b"noack" => noack = true,
+                b"streams" => break,
+                _ => return Err(syntax_error()),
+            }
+        }
+        let mut keys = args.rest()?;
+        if !keys.len().is_multiple_of(2) {
+            return Err(CommandError::Err(
+                "Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' \
+                 must be specified."
+                    .into(),
+            )
+            .into());
+        }
+        let ids = keys.split_off(keys.len() / 2);
+        let streams = keys
+            .into_iter()
+            .zip(ids)
+            .map(|(key, id)| match &id[..] {
+                b">" => Ok((key, None)),
+                id => Ok((key, Some(parse_id(id, 0)?))),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            group,
+            consumer,
+            count,
+            block,
+            noack,
+            streams,
+        })
+    }
+
+    pub fn keys(&self) -> Vec<Bytes> {
+        self.streams.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// Reads from the streams, or only from those of `only` if given, `None` meaning
+    /// that none of them had entries for the consumer, so that the client may block.
+    /// Reading the entries already delivered to the consumer always replies, even with
+    /// no entry.
+    pub fn read(&self, db: &mut Db, only: Option<&[Bytes]>) -> Result<Option<Resp3Piece>> {
+        let streams: Vec<_> = match only {
+            Some(keys) => self.streams.iter().filter(|(key, _)| keys.contains(key)).collect(),
+            None => self.streams.iter().collect(),
+        };
+        // the groups must all exist before any of them is read.
+        for (key, _) in &streams {
+            let exists = match db.lookup(key) {
+                Some(value) => value.as_stream()?.group(&self.group).is_some(),
+                None => false,
+            };
+            if !exists {
+                return Err(CommandError::NoGroup(format!(
+                    "No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(&self.group)
+                ))
+                .into());
+            }
+        }
+        let now = now_ms();
+        let mut replies = vec![];
+        for (key, id) in streams {
+            let stream = db.lookup_mut(key).unwrap().as_stream_mut()?;
+            let entries = match id {
+                None => {
+                    let entries = stream.read_group(
+                        &self.group,
+                        &self.consumer,
+                        self.count,
+                        self.noack,
+                        now,
+                    );
+                    if entries.is_empty() {
+                        continue;
+                    }
+                    entries_reply(entries)
+                }
+                Some(after) => {
+                    let entries =
+                        stream.read_pending(&self.group, &self.consumer, *after, self.count, now);
+                    let entries = entries.into_iter().map(|(id, fields)| match fields {
+                        Some(fields) => entry_reply((id, fields)),
+                        None => Resp3Piece::Array(vec![
+                            Resp3Piece::bulk(id.to_string()),
+                            Resp3Piece::NullArray,
+                        ]),
+                    });
+                    Resp3Piece::Array(entries.collect())
+                }
+            };
+            // an array of pairs rather than a map, which is what clients of RESP2 expect.
+            replies.push(Resp3Piece::Array(vec![Resp3Piece::bulk(key.clone()), entries]));
+        }
+        Ok((!replies.is_empty()).then_some(Resp3Piece::Array(replies)))
+    }
+}
+
+impl DeliveryTime {
+    fn resolve(self, now: u64) -> u64 {
+        let time = match self {
+            DeliveryTime::Idle(idle) => (now as i64).saturating_sub(idle),
+            DeliveryTime::At(time) => time,
+        };
+        if time < 0 || time as u64 > now {
+            now
+        } else {
+            time as u64
+        }
+    }
+}
+
+fn unknown_subcommand_error(cmd: &str, sub: &[u8]) -> Error {
+    CommandError::Err(format!(
+        "unknown subcommand '{}'. Try {} HELP.",
+        String::from_utf8_lossy(sub),
+        cmd
+    ))
+    .into()
+}
+
+fn key_required_error() -> Error {
+    CommandError::Err(
+        "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want \
+         to use the MKSTREAM option to create an empty stream automatically."
+            .into(),
+    )
+    .into()
+}
+
+fn no_such_group_error(key: &[u8], group: &[u8]) -> Error {
+    CommandError::NoGroup(format!(
+        "No such consumer group '{}' for key name '{}'",
+        String::from_utf8_lossy(group),
+        String::from_utf8_lossy(key)
+    ))
+    .into()
+}
+
+/// Looks up the stream of the key, which must have the consumer group.
+fn group_stream<'a>(db: &'a mut Db, key: &[u8], group: &[u8]) -> Result<&'a mut Stream> {
+    if let Some(value) = db.lookup_mut(key) {
+        let stream = value.as_stream_mut()?;
+        if stream.group(group).is_some() {
+            return Ok(stream);
+        }
+    }
+    Err(CommandError::NoGroup(format!(
+        "No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    ))
+    .into())
+}
+
+fn invalid_id_error() -> Error {
+    CommandError::Err("Invalid stream ID specified as stream command argument".into()).into()
+}
+
+fn parse_u64(arg: &[u8]) -> Option<u64> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+fn parse_int(arg: &[u8]) -> Result<i64> {
+    parse_i64(arg).ok_or_else(not_integer_error)
+}
+
+/// Parses an ID given as `ms-seq`, or as `ms` in which case the sequence number is
+/// `missing_seq`.
+pub(super) fn parse_id(arg: &[u8], missing_seq: u64) -> Result<StreamId> {
+    let mut parts = arg.splitn(2, |&b| b == b'-');
+    let ms = parse_u64(parts.next().unwrap()).ok_or_else(invalid_id_error)?;
+    let seq = match parts.next() {
+        Some(seq) => parse_u64(seq).ok_or_else(invalid_id_error)?,
+        None => missing_seq,
+    };
+    Ok(StreamId::new(ms, seq))
+}
+
+/// Parses an end of a range of IDs, which may be `-` or `+`, and is exclusive when
+/// prefixed by `(`. A missing sequence number covers the whole millisecond.
+pub(super) fn parse_range_id(arg: &[u8], end: bool) -> Result<StreamId> {
+    let missing_seq = if end { u64::MAX } else { 0 };
+    match arg {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        [b'(', id @ ..] => {
+            let id = parse_id(id, missing_seq)?;
+            let id = if end { id.prev() } else { id.next() };
+            id.ok_or_else(|| {
+                let which = if end { "end" } else { "start" };
+                CommandError::Err(format!("invalid {} ID for the interval", which)).into()
+            })
+        }
+        _ => parse_id(arg, missing_seq),
+    }
+}
+
+/// Parses the extended form of XPENDING, from its first argument on.
+fn parse_pending_range(first: Bytes, args: &mut Args) -> Result<PendingRange> {
+    let (min_idle, start) = match &lower_bytes(&first)[..] {
+        b"idle" => (args.next_i64()?.max(0) as u64, args.next_arg()?),
+        _ => (0, first),
+    };
+    Ok(PendingRange {
+        min_idle,
+        start: parse_range_id(&start, false)?,
+        end: parse_range_id(&args.next_arg()?, true)?,
+        count: args.next_i64()?.max(0) as usize,
+        consumer: args.next_opt(),
+    })
+}
+
+/// Parses a trim strategy, `MAXLEN` or `MINID` followed by `=` or `~` and a
+/// threshold, returning `None` when `kind` is neither.
+fn parse_trim(kind: &[u8], args: &mut Args) -> Result<Option<Trim>> {
+    let maxlen = match lower_bytes(kind).as_slice() {
+        b"maxlen" => true,
+        b"minid" => false,
+        _ => return Ok(None),
+    };
+    let mut threshold = args.next_arg()?;
+    let approx = match &threshold[..] {
+        b"~" | b"=" => {
+            let approx = &threshold[..] == b"~";
+            threshold = args.next_arg()?;
+            approx
+        }
+        _ => false,
+    };
+    let strategy = if maxlen {
+        match parse_i64(&threshold).ok_or_else(not_integer_error)? {
+            len if len < 0 => {
+                return Err(CommandError::Err("The MAXLEN argument must be >= 0.".into()).into())
+            }
+            len => TrimStrategy::MaxLen(len as usize),
+        }
+    } else {
+        TrimStrategy::MinId(parse_id(&threshold, 0)?)
+    };
+    Ok(Some(Trim {
+        strategy,
+        approx,
+        limit: None,
+    }))
+}
+
+fn parse_limit(trim: &mut Trim, arg: &[u8]) -> Result<()> {
+    let limit = parse_i64(arg).ok_or_else(not_integer_error)?;
+    if limit < 0 {
+        return Err(CommandError::Err("The LIMIT argument must be >= 0.".into()).into());
+    }
+    if !trim.approx {
+        return Err(CommandError::Err(
+            "syntax error, LIMIT cannot be used without the special ~ option".into(),
+        )
+        .into());
+    }
+    trim.limit = Some(limit as usize);
+    Ok(())
+}
+
+/// Resolves the ID of an entry added to a stream whose last ID is `last`.
+fn next_id(id: AddId, last: StreamId) -> Result<StreamId> {
+    let err = |msg: &str| -> Error { CommandError::Err(msg.to_string()).into() };
+    let too_small = || {
+        err("The ID specified in XADD is equal or smaller than the target stream top item")
+    };
+    match id {
+        AddId::Auto => {
+            let ms = now_ms();
+            if ms > last.ms {
+                Ok(StreamId::new(ms, 0))
+            } else {
+                last.next().ok_or_else(|| {
+                    err("The stream has exhausted the last possible ID, unable to add more items")
+                })
+            }
+        }
+        AddId::AutoSeq(ms) if ms > last.ms => Ok(StreamId::new(ms, 0)),
+        AddId::AutoSeq(ms) if ms == last.ms => {
+            let seq = last.seq.checked_add(1).ok_or_else(too_small)?;
+            Ok(StreamId::new(ms, seq))
+        }
+        AddId::AutoSeq(_) => Err(too_small()),
+        AddId::Explicit(StreamId::MIN) => {
+            Err(err("The ID specified in XADD must be greater than 0-0"))
+        }
+        AddId::Explicit(id) if id > last => Ok(id),
+        AddId::Explicit(_) => Err(too_small()),
+    }
+}
+
+/// Trims the stream, `~` removing up to 100 blocks of entries unless a limit is given.
+fn apply_trim(stream: &mut Stream, trim: Trim, max_entries: usize) -> usize {
+    let limit = match trim.limit {
+        _ if !trim.approx => 0,
+        Some(limit) => limit,
+        None => 100 * max_entries,
+    };
+    stream.trim(trim.strategy, trim.approx, limit)
+}
+
+/// Reply of an entry, its ID and its fields and values.
+pub(super) fn entry_reply((id, fields): StreamEntry) -> Resp3Piece {
+    Resp3Piece::Array(vec![
+        Resp3Piece::bulk(id.to_string()),
+        Resp3Piece::Array(fields.into_iter().map(Resp3Piece::bulk).collect()),
+    ])
+}
+
+pub(super) fn entries_reply(entries: Vec<StreamEntry>) -> Resp3Piece {
+    Resp3Piece::Array(entries.into_iter().map(entry_reply).collect())
+}
+
+/// Reply of claimed entries, only their IDs with `JUSTID`.
+fn claimed_reply(entries: Vec<StreamEntry>, justid: bool) -> Resp3Piece {
+    if justid {
+        let ids = entries.into_iter().map(|(id, _)| Resp3Piece::bulk(id.to_string()));
+        Resp3Piece::Array(ids.collect())
+    } else {
+        entries_reply(entries)
+    }
+}
+
+fn map_reply(fields: Vec<(&'static str, Resp3Piece)>) -> Resp3Piece {
+    Resp3Piece::Map(
+        fields
+            .into_iter()
+            .map(|(name, value)| (Resp3Piece::bulk(name), value))
+            .collect(),
+    )
+}
+
+/// Summary of the PEL of the group: its size, its smallest and greatest IDs, and the
+/// number of entries each consumer owns.
+fn pending_summary_reply(group: &ConsumerGroup) -> Resp3Piece {
+    let (first, last) = match (group.pending().keys().next(), group.pending().keys().last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            return Resp3Piece::Array(vec![
+                Resp3Piece::Integer(0),
+                Resp3Piece::Null,
+                Resp3Piece::Null,
+                Resp3Piece::NullArray,
+            ])
+        }
+    };
+    let consumers = group
+        .consumers()
+        .iter()
+        .filter(|(_, consumer)| !consumer.pending().is_empty())
+        .map(|(name, consumer)| {
+            Resp3Piece::Array(vec![
+                Resp3Piece::bulk(name.clone()),
+                Resp3Piece::bulk(consumer.pending().len().to_string()),
+            ])
+        });
+    Resp3Piece::Array(vec![
+        Resp3Piece::Integer(group.pending().len() as i64),
+        Resp3Piece::bulk(first.to_string()),
+        Resp3Piece::bulk(last.to_string()),
+        Resp3Piece::Array(consumers.collect()),
+    ])
+}
+
+/// Pending entries in the range, with their owner, idle time and delivery count.
+fn pending_range_reply(group: &ConsumerGroup, range: PendingRange, now: u64) -> Resp3Piece {
+    if range.start > range.end {
+        return Resp3Piece::Array(vec![]);
+    }
+    let entries = group
+        .pending()
+        .range(range.start..=range.end)
+        .filter(|(_, pending)| match &range.consumer {
+            Some(consumer) => pending.consumer == consumer,
+            None => true,
+        })
+        .map(|(id, pending)| (id, pending, now.saturating_sub(pending.delivery_time)))
+        .filter(|&(_, _, idle)| idle >= range.min_idle)
+        .take(range.count)
+        .map(|(id, pending, idle)| {
+            Resp3Piece::Array(vec![
+                Resp3Piece::bulk(id.to_string()),
+                Resp3Piece::bulk(pending.consumer.clone()),
+                Resp3Piece::Integer(idle as i64),
+                Resp3Piece::Integer(pending.delivery_count as i64),
+            ])
+        });
+    Resp3Piece::Array(entries.collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+    };
+
+    fn entry(id: &str, fields: &[&str]) -> Resp3Piece {
+        Resp3Piece::Array(vec![
+            bulk(id),
+            Resp3Piece::Array(fields.iter().map(|item| bulk(item)).collect()),
+        ])
+    }
+
+    fn int(db: &mut Db, args: &[&str]) -> i64 {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Integer(n) => n,
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn add_with_ids() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["xadd", "s", "5-1", "f", "v"]).unwrap(), bulk("5-1"));
+        assert_eq!(exec(&mut db, &["xadd", "s", "5-*", "f", "v"]).unwrap(), bulk("5-2"));
+        assert_eq!(exec(&mut db, &["xadd", "s", "6", "f", "v"]).unwrap(), bulk("6-0"));
+        assert_eq!(exec(&mut db, &["xadd", "s", "7-*", "f", "v"]).unwrap(), bulk("7-0"));
+        assert!(exec(&mut db, &["xadd", "s", "7-0", "f", "v"]).is_err());
+        assert!(exec(&mut db, &["xadd", "s", "6-*", "f", "v"]).is_err());
+        assert!(exec(&mut db, &["xadd", "new", "0-0", "f", "v"]).is_err());
+        assert!(exec(&mut db, &["xadd", "s", "x-1", "f", "v"]).is_err());
+        assert!(exec(&mut db, &["xadd", "s", "*", "f"]).is_err());
+        assert_eq!(int(&mut db, &["exists", "new"]), 0);
+        match exec(&mut db, &["xadd", "s", "*", "f", "v"]).unwrap() {
+            Resp3Piece::BulkString { data } => assert!(data.ends_with(b"-0")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(
+            exec(&mut db, &["xadd", "none", "nomkstream", "*", "f", "v"]).unwrap(),
+            Resp3Piece::Null
+        );
+        assert_eq!(int(&mut db, &["exists", "none"]), 0);
+        assert_eq!(exec(&mut db, &["xadd", "z", "0-*", "a", "1"]).unwrap(), bulk("0-1"));
+        exec(&mut db, &["xadd", "max", "18446744073709551615-18446744073709551615", "f", "v"])
+            .unwrap();
+        assert!(exec(&mut db, &["xadd", "max", "*", "f", "v"]).is_err());
+        assert_eq!(int(&mut db, &["xlen", "s"]), 5);
+        assert_eq!(int(&mut db, &["xlen", "none"]), 0);
+        assert_eq!(exec(&mut db, &["type", "s"]).unwrap(), Resp3Piece::simple("stream"));
+        exec(&mut db, &["set", "str", "v"]).unwrap();
+        assert!(exec(&mut db, &["xadd", "str", "*", "f", "v"]).is_err());
+    }
+
+    #[test]
+    fn ranges_and_deletes() {
+        let mut db = Db::new();
+        for id in ["1-1", "1-2", "2-1", "3-1", "3-2"] {
+            exec(&mut db, &["xadd", "s", id, "id", id]).unwrap();
+        }
+        let entries = |ids: &[&str]| {
+            Resp3Piece::Array(ids.iter().map(|id| entry(id, &["id", id])).collect())
+        };
+        assert_eq!(
+            exec(&mut db, &["xrange", "s", "1", "2"]).unwrap(),
+            entries(&["1-1", "1-2", "2-1"])
+        );
+        assert_eq!(
+            exec(&mut db, &["xrange", "s", "(1-1", "+", "count", "2"]).unwrap(),
+            entries(&["1-2", "2-1"])
+        );
+        assert_eq!(
+            exec(&mut db, &["xrevrange", "s", "+", "(2-1"]).unwrap(),
+            entries(&["3-2", "3-1"])
+        );
+        assert_eq!(
+            exec(&mut db, &["xrevrange", "s", "3", "-", "count", "1"]).unwrap(),
+            entries(&["3-2"])
+        );
+        assert_eq!(exec(&mut db, &["xrange", "s", "4", "+"]).unwrap(), entries(&[]));
+        assert_eq!(exec(&mut db, &["xrange", "none", "-", "+"]).unwrap(), entries(&[]));
+        assert!(exec(&mut db, &["xrange", "s", "(18446744073709551615-18446744073709551615", "+"])
+            .is_err());
+        assert!(exec(&mut db, &["xrange", "s", "a", "+"]).is_err());
+
+        assert_eq!(int(&mut db, &["xdel", "s", "1-2", "2-1", "9-9"]), 2);
+        assert_eq!(int(&mut db, &["xdel", "s", "1-2"]), 0);
+        assert_eq!(
+            exec(&mut db, &["xrange", "s", "-", "+"]).unwrap(),
+            entries(&["1-1", "3-1", "3-2"])
+        );
+        assert_eq!(int(&mut db, &["xlen", "s"]), 3);
+        match exec(&mut db, &["xinfo", "stream", "s"]).unwrap() {
+            Resp3Piece::Map(fields) => {
+                let field = |name: &str| {
+                    fields.iter().find(|(k, _)| *k == bulk(name)).unwrap().1.clone()
+                };
+                assert_eq!(field("length"), Resp3Piece::Integer(3));
+                assert_eq!(field("last-generated-id"), bulk("3-2"));
+                assert_eq!(field("max-deleted-entry-id"), bulk("2-1"));
+                assert_eq!(field("entries-added"), Resp3Piece::Integer(5));
+                assert_eq!(field("recorded-first-entry-id"), bulk("1-1"));
+                assert_eq!(field("first-entry"), entry("1-1", &["id", "1-1"]));
+                assert_eq!(field("last-entry"), entry("3-2", &["id", "3-2"]));
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(exec(&mut db, &["xinfo", "stream", "none"]).is_err());
+        assert!(exec(&mut db, &["xinfo", "nothing", "s"]).is_err());
+    }
+
+    #[test]
+    fn trims() {
+        let mut db = Db::new();
+        for i in 1..=300 {
+            exec(&mut db, &["xadd", "s", &i.to_string(), "f", "v"]).unwrap();
+        }
+        // blocks hold 100 entries, so `~` keeps a few more entries than asked.
+        assert_eq!(int(&mut db, &["xtrim", "s", "maxlen", "~", "150"]), 100);
+        assert_eq!(int(&mut db, &["xtrim", "s", "maxlen", "~", "150", "limit", "50"]), 0);
+        assert_eq!(int(&mut db, &["xtrim", "s", "maxlen", "=", "150"]), 50);
+        assert_eq!(int(&mut db, &["xtrim", "s", "minid", "251"]), 100);
+        assert_eq!(int(&mut db, &["xlen", "s"]), 50);
+        exec(&mut db, &["xadd", "s", "maxlen", "10", "*", "f", "v"]).unwrap();
+        assert_eq!(int(&mut db, &["xlen", "s"]), 10);
+        exec(&mut db, &["xadd", "s", "minid", "~", "0", "limit", "10", "*", "f", "v"]).unwrap();
+        assert_eq!(int(&mut db, &["xlen", "s"]), 11);
+
+        assert!(exec(&mut db, &["xtrim", "s", "maxlen", "-1"]).is_err());
+        assert!(exec(&mut db, &["xtrim", "s", "maxlen", "1", "limit", "10"]).is_err());
+        assert!(exec(&mut db, &["xtrim", "s", "size", "1"]).is_err());
+        assert!(exec(&mut db, &["xadd", "s", "maxlen", "1", "minid", "1", "*", "f", "v"]).is_err());
+        assert!(exec(&mut db, &["xadd", "s", "limit", "1", "*", "f", "v"]).is_err());
+        assert_eq!(int(&mut db, &["xtrim", "none", "maxlen", "0"]), 0);
+    }
+
+    #[test]
+    fn groups_and_reads() {
+        let mut db = Db::new();
+        assert!(exec(&mut db, &["xgroup", "create", "s", "g", "$"]).is_err());
+        exec(&mut db, &["xgroup", "create", "s", "g", "$", "mkstream"]).unwrap();
+        assert!(exec(&mut db, &["xgroup", "create", "s", "g", "0"]).is_err());
+        for id in ["1-1", "2-1", "3-1"] {
+            exec(&mut db, &["xadd", "s", id, "id", id]).unwrap();
+        }
+        let read = |db: &mut Db, consumer: &str, count: &str, id: &str| {
+            exec(db, &["xreadgroup", "group", "g", consumer, "count", count, "streams", "s", id])
+                .unwrap()
+        };
+        let reply = |entries: Vec<Resp3Piece>| {
+            Resp3Piece::Array(vec![Resp3Piece::Array(vec![bulk("s"), Resp3Piece::Array(entries)])])
+        };
+        assert_eq!(
+            read(&mut db, "alice", "2", ">"),
+            reply(vec![entry("1-1", &["id", "1-1"]), entry("2-1", &["id", "2-1"])])
+        );
+        assert_eq!(read(&mut db, "bob", "0", ">"), reply(vec![entry("3-1", &["id", "3-1"])]));
+        assert_eq!(read(&mut db, "bob", "0", ">"), Resp3Piece::NullArray);
+        // history reads only see the entries of the consumer, even deleted ones.
+        exec(&mut db, &["xdel", "s", "1-1"]).unwrap();
+        assert_eq!(
+            read(&mut db, "alice", "10", "0"),
+            reply(vec![
+                Resp3Piece::Array(vec![bulk("1-1"), Resp3Piece::NullArray]),
+                entry("2-1", &["id", "2-1"]),
+            ])
+        );
+        assert_eq!(read(&mut db, "bob", "10", "3-1"), reply(vec![]));
+        assert!(exec(&mut db, &["xreadgroup", "group", "x", "c", "streams", "s", ">"]).is_err());
+        assert!(exec(&mut db, &["xreadgroup", "group", "g", "c", "streams", "s"]).is_err());
+
+        assert_eq!(
+            exec(&mut db, &["xpending", "s", "g"]).unwrap(),
+            Resp3Piece::Array(vec![
+                Resp3Piece::Integer(3),
+                bulk("1-1"),
+                bulk("3-1"),
+                Resp3Piece::Array(vec![
+                    Resp3Piece::Array(vec![bulk("alice"), bulk("2")]),
+                    Resp3Piece::Array(vec![bulk("bob"), bulk("1")]),
+                ]),
+            ])
+        );
+        match exec(&mut db, &["xpending", "s", "g", "-", "+", "10", "alice"]).unwrap() {
+            Resp3Piece::Array(entries) => {
+                assert_eq!(entries.len(), 2);
+                match &entries[1] {
+                    Resp3Piece::Array(fields) => {
+                        assert_eq!(fields[0], bulk("2-1"));
+                        assert_eq!(fields[1], bulk("alice"));
+                        // delivered again by the history read.
+                        assert_eq!(fields[3], Resp3Piece::Integer(2));
+                    }
+                    other => panic!("unexpected reply {:?}", other),
+                }
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(
+            exec(&mut db, &["xpending", "s", "g", "idle", "60000", "-", "+", "10"]).unwrap(),
+            Resp3Piece::Array(vec![])
+        );
+        assert_eq!(int(&mut db, &["xack", "s", "g", "1-1", "3-1", "9-9"]), 2);
+        assert_eq!(int(&mut db, &["xack", "s", "none", "2-1"]), 0);
+        assert_eq!(int(&mut db, &["xgroup", "delconsumer", "s", "g", "alice"]), 1);
+        assert_eq!(
+            exec(&mut db, &["xpending", "s", "g"]).unwrap(),
+            Resp3Piece::Array(vec![
+                Resp3Piece::Integer(0),
+                Resp3Piece::Null,
+                Resp3Piece::Null,
+                Resp3Piece::NullArray,
+            ])
+        );
+
+        assert_eq!(int(&mut db, &["xgroup", "createconsumer", "s", "g", "carol"]), 1);
+        assert_eq!(int(&mut db, &["xgroup", "createconsumer", "s", "g", "carol"]), 0);
+        exec(&mut db, &["xgroup", "setid", "s", "g", "0", "entriesread", "0"]).unwrap();
+        assert!(exec(&mut db, &["xgroup", "setid", "s", "none", "0"]).is_err());
+        match exec(&mut db, &["xinfo", "groups", "s"]).unwrap() {
+            Resp3Piece::Array(groups) => match &groups[..] {
+                [Resp3Piece::Map(fields)] => {
+                    let field = |name: &str| {
+                        fields.iter().find(|(k, _)| *k == bulk(name)).unwrap().1.clone()
+                    };
+                    assert_eq!(field("name"), bulk("g"));
+                    assert_eq!(field("consumers"), Resp3Piece::Integer(2));
+                    assert_eq!(field("last-delivered-id"), bulk("0-0"));
+                    assert_eq!(field("entries-read"), Resp3Piece::Integer(0));
+                    // the deleted entry is the oldest one, so the lag is still known.
+                    assert_eq!(field("lag"), Resp3Piece::Integer(2));
+                }
+                other => panic!("unexpected groups {:?}", other),
+            },
+            other => panic!("unexpected reply {:?}", other),
+        }
+        match exec(&mut db, &["xinfo", "consumers", "s", "g"]).unwrap() {
+            Resp3Piece::Array(consumers) => assert_eq!(consumers.len(), 2),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(int(&mut db, &["xgroup", "destroy", "s", "g"]), 1);
+        assert_eq!(int(&mut db, &["xgroup", "destroy", "s", "g"]), 0);
+        assert!(exec(&mut db, &["xinfo", "consumers", "s", "g"]).is_err());
+        assert!(exec(&mut db, &["xgroup", "nothing", "s", "g"]).is_err());
+    }
+
+    #[test]
+    fn claims() {
+        let mut db = Db::new();
+        for id in ["1-1", "2-1", "3-1", "4-1"] {
+            exec(&mut db, &["xadd", "s", id, "id", id]).unwrap();
+        }
+        exec(&mut db, &["xgroup", "create", "s", "g", "0"]).unwrap();
+        exec(&mut db, &["xreadgroup", "group", "g", "alice", "streams", "s", ">"]).unwrap();
+        let ids = |ids: &[&str]| Resp3Piece::Array(ids.iter().map(|id| bulk(id)).collect());
+        // the entries have just been delivered, so they are not idle long enough.
+        assert_eq!(
+            exec(&mut db, &["xclaim", "s", "g", "bob", "60000", "1-1", "2-1"]).unwrap(),
+            ids(&[])
+        );
+        assert_eq!(
+            exec(&mut db, &["xclaim", "s", "g", "bob", "0", "1-1", "9-9"]).unwrap(),
+            Resp3Piece::Array(vec![entry("1-1", &["id", "1-1"])])
+        );
+        assert_eq!(
+            exec(&mut db, &["xclaim", "s", "g", "bob", "0", "2-1", "retrycount", "7", "justid"])
+                .unwrap(),
+            ids(&["2-1"])
+        );
+        let pending = |db: &mut Db, id: &str| {
+            match exec(db, &["xpending", "s", "g", id, id, "1"]).unwrap() {
+                Resp3Piece::Array(entries) => match &entries[..] {
+                    [Resp3Piece::Array(fields)] => (fields[1].clone(), fields[3].clone()),
+                    other => panic!("unexpected entries {:?}", other),
+                },
+                other => panic!("unexpected reply {:?}", other),
+            }
+        };
+        assert_eq!(pending(&mut db, "1-1"), (bulk("bob"), Resp3Piece::Integer(2)));
+        assert_eq!(pending(&mut db, "2-1"), (bulk("bob"), Resp3Piece::Integer(7)));
+
+        exec(&mut db, &["xdel", "s", "3-1"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["xautoclaim", "s", "g", "carol", "0", "0", "count", "1", "justid"])
+                .unwrap(),
+            Resp3Piece::Array(vec![bulk("2-1"), ids(&["1-1"]), ids(&[])])
+        );
+        assert_eq!(
+            exec(&mut db, &["xautoclaim", "s", "g", "carol", "0", "2-1"]).unwrap(),
+            Resp3Piece::Array(vec![
+                bulk("0-0"),
+                Resp3Piece::Array(vec![
+                    entry("2-1", &["id", "2-1"]),
+                    entry("4-1", &["id", "4-1"]),
+                ]),
+                ids(&["3-1"]),
+            ])
+        );
+        assert_eq!(pending(&mut db, "4-1"), (bulk("carol"), Resp3Piece::Integer(2)));
+        assert_eq!(int(&mut db, &["xack", "s", "g", "3-1"]), 0);
+
+        // FORCE claims entries which are not pending, as long as they exist.
+        exec(&mut db, &["xack", "s", "g", "1-1"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["xclaim", "s", "g", "dave", "0", "1-1", "3-1", "force", "justid"])
+                .unwrap(),
+            ids(&["1-1"])
+        );
+        exec(&mut db, &["xclaim", "s", "g", "dave", "0", "1-1", "lastid", "9-9"]).unwrap();
+        match exec(&mut db, &["xinfo", "groups", "s"]).unwrap() {
+            Resp3Piece::Array(groups) => match &groups[0] {
+                Resp3Piece::Map(fields) => {
+                    assert!(fields.contains(&(bulk("last-delivered-id"), bulk("9-9"))))
+                }
+                other => panic!("unexpected group {:?}", other),
+            },
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(exec(&mut db, &["xclaim", "s", "none", "bob", "0", "1-1"]).is_err());
+        assert!(exec(&mut db, &["xclaim", "s", "g", "bob", "0", "1-1", "idle"]).is_err());
+        assert!(exec(&mut db, &["xautoclaim", "s", "g", "bob", "0", "0", "count", "0"]).is_err());
+        assert!(exec(&mut db, &["xpending", "none", "g"]).is_err());
+    }
+}
@@ -0,0 +1,836 @@
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Error, Result},
+    object::{RedisString, Value},
+    protocol::Resp3Piece,
+    util::{format_f64, parse_f64, parse_i64},
+};
+
+use super::{not_float_error, not_integer_error, syntax_error, Args, Expiry};
+
+/// Max size of a string value, 512MB as in redis.
+pub(super) const STRING_MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Condition of SET on the existence of the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    Always,
+    /// Only set the key if it does not exist.
+    Nx,
+    /// Only set the key if it already exists.
+    Xx,
+}
+
+/// Commands working on string values.
+#[derive(Debug)]
+pub enum StringCommand {
+    Get {
+        key: Bytes,
+    },
+    /// `GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+    /// PXAT unix-time-milliseconds | PERSIST]`
+    GetEx {
+        key: Bytes,
+        expiry: Option<Expiry>,
+        persist: bool,
+    },
+    /// `SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
+    /// EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]`
+    Set {
+        key: Bytes,
+        value: Bytes,
+        condition: SetCondition,
+        get: bool,
+        expiry: Option<Expiry>,
+        keep_ttl: bool,
+    },
+    /// `INCR`, `DECR`, `INCRBY` and `DECRBY`.
+    IncrBy {
+        key: Bytes,
+        by: i64,
+    },
+    IncrByFloat {
+        key: Bytes,
+        by: f64,
+    },
+    Append {
+        key: Bytes,
+        value: Bytes,
+    },
+    StrLen {
+        key: Bytes,
+    },
+    /// `GETRANGE key start end`, also known as `SUBSTR`.
+    GetRange {
+        key: Bytes,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        key: Bytes,
+        offset: i64,
+        value: Bytes,
+    },
+    /// `MSET` and `MSETNX`, the latter setting nothing if any of the keys exists.
+    MSet {
+        pairs: Vec<(Bytes, Bytes)>,
+        nx: bool,
+    },
+    MGet {
+        keys: Vec<Bytes>,
+    },
+    GetDel {
+        key: Bytes,
+    },
+    SetNx {
+        key: Bytes,
+        value: Bytes,
+    },
+    /// `SETEX` and `PSETEX`.
+    SetEx {
+        key: Bytes,
+        expiry: Expiry,
+        value: Bytes,
+    },
+    /// `LCS key1 key2 [LEN] [IDX] [MINMATCHLEN min-match-len] [WITHMATCHLEN]`
+    Lcs {
+        keys: (Bytes, Bytes),
+        len: bool,
+        idx: bool,
+        min_match_len: usize,
+        with_match_len: bool,
+    },
+}
+
+impl StringCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "get" => Self::Get { key: args.next_arg()? },
+            "getex" => Self::parse_getex(args)?,
+            "set" => Self::parse_set(args)?,
+            "incr" | "decr" => Self::IncrBy {
+                key: args.next_arg()?,
+                by: if name == "incr" { 1 } else { -1 },
+            },
+            "incrby" => Self::IncrBy {
+                key: args.next_arg()?,
+                by: args.next_i64()?,
+            },
+            "decrby" => {
+                let key = args.next_arg()?;
+                let by = args.next_i64()?.checked_neg().ok_or_else(|| {
+                    Error::from(CommandError::Err("decrement would overflow".into()))
+                })?;
+                Self::IncrBy { key, by }
+            }
+            "incrbyfloat" => Self::IncrByFloat {
+                key: args.next_arg()?,
+                by: parse_f64(&args.next_arg()?).ok_or_else(not_float_error)?,
+            },
+            "append" => Self::Append {
+                key: args.next_arg()?,
+                value: args.next_arg()?,
+            },
+            "strlen" => Self::StrLen { key: args.next_arg()? },
+            "getrange" | "substr" => Self::GetRange {
+                key: args.next_arg()?,
+                start: args.next_i64()?,
+                end: args.next_i64()?,
+            },
+            "setrange" => Self::SetRange {
+                key: args.next_arg()?,
+                offset: args.next_i64()?,
+                value: args.next_arg()?,
+            },
+            "mset" | "msetnx" => Self::MSet {
+                pairs: args.pairs()?,
+                nx: name == "msetnx",
+            },
+            "mget" => Self::MGet { keys: args.rest()? },
+            "getset" => Self::Set {
+                key: args.next_arg()?,
+                value: args.next_arg()?,
+                condition: SetCondition::Always,
+                get: true,
+                expiry: None,
+                keep_ttl: false,
+            },
+            "getdel" => Self::GetDel { key: args.next_arg()? },
+            "setnx" => Self::SetNx {
+                key: args.next_arg()?,
+                value: args.next_arg()?,
+            },
+            "setex" | "psetex" => {
+                let key = args.next_arg()?;
+                let unit = if name == "setex" { b"ex".as_slice() } else { b"px" };
+                let expiry = Expiry::parse(unit, &args.next_arg()?)?;
+                Self::SetEx {
+                    key,
+                    expiry,
+                    value: args.next_arg()?,
+                }
+            }
+            "lcs" => Self::parse_lcs(args)?,
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    fn parse_getex(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let mut expiry = None;
+        let mut persist = false;
+        while let Some(opt) = args.next_lower() {
+            match opt.as_slice() {
+                b"persist" if expiry.is_none() && !persist => persist = true,
+                opt if Expiry::is_expire_option(opt) && expiry.is_none() && !persist => {
+                    let value = args.next_opt().ok_or_else(syntax_error)?;
+                    expiry = Some(Expiry::parse(opt, &value)?);
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(Self::GetEx {
+            key,
+            expiry,
+            persist,
+        })
+    }
+
+    fn parse_lcs(args: &mut Args) -> Result<Self> {
+        let keys = (args.next_arg()?, args.next_arg()?);
+        let mut len = false;
+        let mut idx = false;
+        let mut min_match_len = 0;
+        let mut with_match_len = false;
+        while let Some(opt) = args.next_lower() {
+            match opt.as_slice() {
+                b"len" => len = true,
+                b"idx" => idx = true,
+                b"withmatchlen" => with_match_len = true,
+                b"minmatchlen" => {
+                    let value = args.next_opt().ok_or_else(syntax_error)?;
+                    let value = parse_i64(&value).ok_or_else(not_integer_error)?;
+                    min_match_len = value.max(0) as usize;
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+        if len && idx {
+            return Err(CommandError::Err(
+                "If you want both the length and indexes, please just use IDX.".into(),
+            )
+            .into());
+        }
+        Ok(Self::Lcs {
+            keys,
+            len,
+            idx,
+            min_match_len,
+            with_match_len,
+        })
+    }
+
+    fn parse_set(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let value = args.next_arg()?;
+        let mut condition = SetCondition::Always;
+        let mut get = false;
+        let mut expiry = None;
+        let mut keep_ttl = false;
+        while let Some(opt) = args.next_lower() {
+            match opt.as_slice() {
+                b"nx" if condition != SetCondition::Xx => condition = SetCondition::Nx,
+                b"xx" if condition != SetCondition::Nx => condition = SetCondition::Xx,
+                b"get" => get = true,
+                b"keepttl" if expiry.is_none() => keep_ttl = true,
+                opt if Expiry::is_expire_option(opt) && expiry.is_none() && !keep_ttl => {
+                    let value = args.next_opt().ok_or_else(syntax_error)?;
+                    expiry = Some(Expiry::parse(opt, &value)?);
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(Self::Set {
+            key,
+            value,
+            condition,
+            get,
+            expiry,
+            keep_ttl,
+        })
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::Get { key } => Ok(match db.lookup(&key) {
+                Some(value) => Resp3Piece::bulk(value.as_string()?.to_bytes()),
+                None => Resp3Piece::Null,
+            }),
+            Self::GetEx {
+                key,
+                expiry,
+                persist,
+            } => {
+                let when = match expiry {
+                    Some(expiry) => Some(expiry.resolve("getex")?),
+                    None => None,
+                };
+                let data = match db.lookup(&key) {
+                    Some(value) => value.as_string()?.to_bytes(),
+                    None => return Ok(Resp3Piece::Null),
+                };
+                if let Some(when) = when {
+                    db.set_expire(&key, when);
+                } else if persist {
+                    db.persist(&key);
+                }
+                Ok(Resp3Piece::bulk(data))
+            }
+            Self::Set {
+                key,
+                value,
+                condition,
+                get,
+                expiry,
+                keep_ttl,
+            } => {
+                let when = match expiry {
+                    Some(expiry) => Some(expiry.resolve("set")?),
+                    None => None,
+                };
+                let old = match db.lookup(&key) {
+                    // SET overwrites values of any type, but GET only replies strings.
+                    Some(value) if get => Some(value.as_string()?.to_bytes()),
+                    Some(_) => Some(Bytes::new()),
+                    None => None,
+                };
+                let old_reply = || match &old {
+                    Some(data) => Resp3Piece::bulk(data.clone()),
+                    None => Resp3Piece::Null,
+                };
+                let blocked = match condition {
+                    SetCondition::Always => false,
+                    SetCondition::Nx => old.is_some(),
+                    SetCondition::Xx => old.is_none(),
+                };
+                if blocked {
+                    return Ok(if get { old_reply() } else { Resp3Piece::Null });
+                }
+                db.set(key.clone(), Value::String(RedisString::new(value)), keep_ttl);
+                if let Some(when) = when {
+                    db.set_expire(&key, when);
+                }
+                Ok(if get { old_reply() } else { Resp3Piece::ok() })
+            }
+            Self::IncrBy { key, by } => {
+                let value = match db.lookup_mut(&key) {
+                    Some(value) => value.as_string_mut()?,
+                    None => {
+                        db.set(key, Value::String(RedisString::Int(by)), false);
+                        return Ok(Resp3Piece::Integer(by));
+                    }
+                };
+                let n = value.as_i64().ok_or_else(not_integer_error)?;
+                let n = n.checked_add(by).ok_or_else(|| {
+                    Error::from(CommandError::Err(
+                        "increment or decrement would overflow".into(),
+                    ))
+                })?;
+                // the expire time of the key is kept.
+                *value = RedisString::Int(n);
+                Ok(Resp3Piece::Integer(n))
+            }
+            Self::IncrByFloat { key, by } => {
+                let current = match db.lookup(&key) {
+                    Some(value) => {
+                        parse_f64(&value.as_string()?.to_bytes()).ok_or_else(not_float_error)?
+                    }
+                    None => 0.0,
+                };
+                let n = current + by;
+                if !n.is_finite() {
+                    return Err(CommandError::Err(
+                        "increment would produce NaN or Infinity".into(),
+                    )
+                    .into());
+                }
+                let data = Bytes::from(format_f64(n));
+                db.set(key, Value::String(RedisString::plain(data.clone())), true);
+                Ok(Resp3Piece::bulk(data))
+            }
+            Self::Append { key, value } => {
+                let old = match db.lookup_mut(&key) {
+                    Some(old) => old.as_string_mut()?,
+                    None => {
+                        let len = value.len();
+                        db.set(key, Value::String(RedisString::new(value)), false);
+                        return Ok(Resp3Piece::Integer(len as i64));
+                    }
+                };
+                check_string_length(old.len() as u64 + value.len() as u64)?;
+                let len = old.modify(|data| {
+                    data.extend_from_slice(&value);
+                    data.len()
+                });
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::StrLen { key } => {
+                let len = match db.lookup(&key) {
+                    Some(value) => value.as_string()?.len(),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::GetRange { key, start, end } => {
+                let data = match db.lookup(&key) {
+                    Some(value) => value.as_string()?.to_bytes(),
+                    None => return Ok(Resp3Piece::bulk(Bytes::new())),
+                };
+                Ok(Resp3Piece::bulk(get_range(data, start, end)))
+            }
+            Self::SetRange { key, offset, value } => {
+                if offset < 0 {
+                    return Err(CommandError::Err("offset is out of range".into()).into());
+                }
+                let offset = offset as usize;
+                let old = match db.lookup_mut(&key) {
+                    Some(old) => old.as_string_mut()?,
+                    None => {
+                        // an empty string is not created.
+                        if value.is_empty() {
+                            return Ok(Resp3Piece::Integer(0));
+                        }
+                        check_string_length(offset as u64 + value.len() as u64)?;
+                        let mut data = vec![0; offset + value.len()];
+                        data[offset..].copy_from_slice(&value);
+                        db.set(key, Value::String(RedisString::Raw(data.into())), false);
+                        return Ok(Resp3Piece::Integer((offset + value.len()) as i64));
+                    }
+                };
+                if value.is_empty() {
+                    return Ok(Resp3Piece::Integer(old.len() as i64));
+                }
+                check_string_length(offset as u64 + value.len() as u64)?;
+                let len = old.modify(|data| {
+                    if data.len() < offset + value.len() {
+                        data.resize(offset + value.len(), 0);
+                    }
+                    data[offset..offset + value.len()].copy_from_slice(&value);
+                    data.len()
+                });
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::MSet { pairs, nx } => {
+                if nx && pairs.iter().any(|(key, _)| db.exists(key)) {
+                    return Ok(Resp3Piece::Integer(0));
+                }
+                for (key, value) in pairs {
+                    db.set(key, Value::String(RedisString::new(value)), false);
+                }
+                Ok(if nx { Resp3Piece::Integer(1) } else { Resp3Piece::ok() })
+            }
+            Self::MGet { keys } => {
+                let values = keys
+                    .iter()
+                    .map(|key| match db.lookup(key).map(Value::as_string) {
+                        Some(Ok(value)) => Resp3Piece::bulk(value.to_bytes()),
+                        // values of other types are replied as missing.
+                        _ => Resp3Piece::Null,
+                    })
+                    .collect();
+                Ok(Resp3Piece::Array(values))
+            }
+            Self::GetDel { key } => {
+                let data = match db.lookup(&key) {
+                    Some(value) => value.as_string()?.to_bytes(),
+                    None => return Ok(Resp3Piece::Null),
+                };
+                db.remove(&key);
+                Ok(Resp3Piece::bulk(data))
+            }
+            Self::SetNx { key, value } => {
+                if db.exists(&key) {
+                    return Ok(Resp3Piece::Integer(0));
+                }
+                db.set(key, Value::String(RedisString::new(value)), false);
+                Ok(Resp3Piece::Integer(1))
+            }
+            Self::SetEx { key, expiry, value } => {
+                let cmd = if let Expiry::Ex(_) = expiry { "setex" } else { "psetex" };
+                let when = expiry.resolve(cmd)?;
+                db.set(key.clone(), Value::String(RedisString::new(value)), false);
+                db.set_expire(&key, when);
+                Ok(Resp3Piece::ok())
+            }
+            Self::Lcs {
+                keys,
+                len,
+                idx,
+                min_match_len,
+                with_match_len,
+            } => {
+                let mut get = |key: &[u8]| -> Result<Bytes> {
+                    match db.lookup(key).map(Value::as_string) {
+                        Some(Ok(value)) => Ok(value.to_bytes()),
+                        Some(Err(_)) => Err(CommandError::Err(
+                            "The specified keys must contain string values".into(),
+                        )
+                        .into()),
+                        None => Ok(Bytes::new()),
+                    }
+                };
+                let a = get(&keys.0)?;
+                let b = get(&keys.1)?;
+                lcs(&a, &b, len, idx, min_match_len, with_match_len)
+            }
+        }
+    }
+}
+
+/// Checks that a string would not grow beyond the max size of strings.
+fn check_string_length(len: u64) -> Result<()> {
+    if len > STRING_MAX_SIZE {
+        return Err(CommandError::Err(
+            "string exceeds maximum allowed size (proto-max-bulk-len)".into(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Returns the substring between the inclusive offsets, which count from the end when
+/// they are negative, as GETRANGE does.
+fn get_range(data: Bytes, start: i64, end: i64) -> Bytes {
+    let len = data.len() as i64;
+    if (start < 0 && end < 0 && start > end) || len == 0 {
+        return Bytes::new();
+    }
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+    if start > end {
+        return Bytes::new();
+    }
+    data.slice(start as usize..=end as usize)
+}
+
+/// Computes the longest common subsequence of the strings, replied as LCS does: the
+/// subsequence itself, its length with LEN, or with IDX the ranges of the matches from
+/// the last to the first along with the length.
+fn lcs(
+    a: &[u8],
+    b: &[u8],
+    len_only: bool,
+    idx: bool,
+    min_match_len: usize,
+    with_match_len: bool,
+) -> Result<Resp3Piece> {
+    let (alen, blen) = (a.len(), b.len());
+    // the table of the lengths of the LCS of all the prefixes, LCS(i, j) being the one of
+    // a[..i] and b[..j].
+    let cells = (alen as u64 + 1) * (blen as u64 + 1);
+    if cells.saturating_mul(4) > STRING_MAX_SIZE {
+        return Err(CommandError::Err(
+            "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len".into(),
+        )
+        .into());
+    }
+    let width = blen + 1;
+    let mut table = vec![0u32; cells as usize];
+    for i in 1..=alen {
+        for j in 1..=blen {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+    let len = table[alen * width + blen] as usize;
+    if len_only {
+        return Ok(Resp3Piece::Integer(len as i64));
+    }
+
+    // walk the table back from the end, collecting the subsequence and the ranges of
+    // contiguous matches.
+    let mut result = vec![0; len];
+    let mut matches = vec![];
+    let (mut i, mut j, mut k) = (alen, blen, len);
+    // the current range, `arange_start == alen` telling there is none.
+    let (mut arange_start, mut arange_end, mut brange_start, mut brange_end) = (alen, 0, 0, 0);
+    while i > 0 && j > 0 {
+        let mut emit_range = false;
+        if a[i - 1] == b[j - 1] {
+            result[k - 1] = a[i - 1];
+            if arange_start == alen {
+                arange_start = i - 1;
+                arange_end = i - 1;
+                brange_start = j - 1;
+                brange_end = j - 1;
+            } else if arange_start == i && brange_start == j {
+                // the range is contiguous, extend it backward.
+                arange_start -= 1;
+                brange_start -= 1;
+            } else {
+                emit_range = true;
+            }
+            // the loop is about to end on the first byte of one of the strings.
+            if arange_start == 0 || brange_start == 0 {
+                emit_range = true;
+            }
+            k -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            if arange_start != alen {
+                emit_range = true;
+            }
+        }
+        if emit_range {
+            let match_len = arange_end - arange_start + 1;
+            if min_match_len == 0 || match_len >= min_match_len {
+                let range = |start: usize, end: usize| {
+                    Resp3Piece::Array(vec![
+                        Resp3Piece::Integer(start as i64),
+                        Resp3Piece::Integer(end as i64),
+                    ])
+                };
+                let mut m = vec![
+                    range(arange_start, arange_end),
+                    range(brange_start, brange_end),
+                ];
+                if with_match_len {
+                    m.push(Resp3Piece::Integer(match_len as i64));
+                }
+                matches.push(Resp3Piece::Array(m));
+            }
+            arange_start = alen;
+        }
+    }
+    if !idx {
+        return Ok(Resp3Piece::bulk(result));
+    }
+    Ok(Resp3Piece::Map(vec![
+        (Resp3Piece::bulk(&b"matches"[..]), Resp3Piece::Array(matches)),
+        (Resp3Piece::bulk(&b"len"[..]), Resp3Piece::Integer(len as i64)),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+        util::now_ms,
+    };
+
+    #[test]
+    fn set_options() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["set", "k", "v1", "xx"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["set", "k", "v1", "nx"]).unwrap(), Resp3Piece::ok());
+        assert_eq!(exec(&mut db, &["set", "k", "v2", "nx", "get"]).unwrap(), bulk("v1"));
+        assert_eq!(exec(&mut db, &["set", "k", "v2", "get", "ex", "100"]).unwrap(), bulk("v1"));
+        let ttl = db.get_expire(b"k").unwrap() - now_ms();
+        assert!(ttl > 99_000 && ttl <= 100_000);
+        assert_eq!(exec(&mut db, &["set", "k", "v3", "keepttl"]).unwrap(), Resp3Piece::ok());
+        assert!(db.get_expire(b"k").is_some());
+        assert_eq!(exec(&mut db, &["set", "k", "v4"]).unwrap(), Resp3Piece::ok());
+        assert!(db.get_expire(b"k").is_none());
+        assert_eq!(exec(&mut db, &["get", "k"]).unwrap(), bulk("v4"));
+    }
+
+    #[test]
+    fn set_invalid_options() {
+        let mut db = Db::new();
+        for args in [
+            &["set", "k", "v", "nx", "xx"][..],
+            &["set", "k", "v", "ex", "10", "px", "100"],
+            &["set", "k", "v", "keepttl", "ex", "10"],
+            &["set", "k", "v", "ex"],
+            &["set", "k", "v", "ex", "0"],
+            &["set", "k", "v", "px", "abc"],
+            &["set", "k", "v", "ex", "9223372036854775807"],
+            &["set", "k"],
+        ] {
+            assert!(exec(&mut db, args).is_err(), "{:?}", args);
+        }
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn getex() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["getex", "k", "ex", "10"]).unwrap(), Resp3Piece::Null);
+        exec(&mut db, &["set", "k", "v"]).unwrap();
+        assert_eq!(exec(&mut db, &["getex", "k", "px", "10000"]).unwrap(), bulk("v"));
+        assert!(db.get_expire(b"k").is_some());
+        assert_eq!(exec(&mut db, &["getex", "k", "persist"]).unwrap(), bulk("v"));
+        assert!(db.get_expire(b"k").is_none());
+        assert!(exec(&mut db, &["getex", "k", "persist", "ex", "10"]).is_err());
+        assert!(exec(&mut db, &["getex", "k", "ex", "-1"]).is_err());
+        assert_eq!(exec(&mut db, &["getex", "k", "pxat", "1"]).unwrap(), bulk("v"));
+        assert!(!db.exists(b"k"));
+    }
+
+    #[test]
+    fn incr_family() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["incr", "n"]).unwrap(), Resp3Piece::Integer(1));
+        assert_eq!(exec(&mut db, &["incrby", "n", "-11"]).unwrap(), Resp3Piece::Integer(-10));
+        assert_eq!(exec(&mut db, &["decrby", "n", "5"]).unwrap(), Resp3Piece::Integer(-15));
+        assert_eq!(exec(&mut db, &["decr", "n"]).unwrap(), Resp3Piece::Integer(-16));
+        assert_eq!(exec(&mut db, &["get", "n"]).unwrap(), bulk("-16"));
+        exec(&mut db, &["set", "n", "9223372036854775806", "ex", "100"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["incr", "n"]).unwrap(),
+            Resp3Piece::Integer(i64::MAX)
+        );
+        assert!(db.get_expire(b"n").is_some());
+        assert!(exec(&mut db, &["incr", "n"]).is_err());
+        assert!(exec(&mut db, &["decrby", "n", "-9223372036854775808"]).is_err());
+        assert!(exec(&mut db, &["incrby", "n", "1.5"]).is_err());
+        for value in ["abc", " 1", "01", "1.0", ""] {
+            exec(&mut db, &["set", "s", value]).unwrap();
+            assert!(exec(&mut db, &["incr", "s"]).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn incrbyfloat() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["incrbyfloat", "f", "10.5"]).unwrap(), bulk("10.5"));
+        assert_eq!(exec(&mut db, &["incrbyfloat", "f", "0.1"]).unwrap(), bulk("10.6"));
+        assert_eq!(exec(&mut db, &["incrbyfloat", "f", "-5.6"]).unwrap(), bulk("5"));
+        assert_eq!(exec(&mut db, &["incrbyfloat", "f", "5.0e3"]).unwrap(), bulk("5005"));
+        assert_eq!(exec(&mut db, &["incr", "f"]).unwrap(), Resp3Piece::Integer(5006));
+        assert!(exec(&mut db, &["incrbyfloat", "f", "inf"]).is_err());
+        assert!(exec(&mut db, &["incrbyfloat", "f", "x"]).is_err());
+        exec(&mut db, &["set", "s", "1 "]).unwrap();
+        assert!(exec(&mut db, &["incrbyfloat", "s", "1"]).is_err());
+        assert_eq!(exec(&mut db, &["get", "f"]).unwrap(), bulk("5006"));
+        exec(&mut db, &["set", "k", "0.1"]).unwrap();
+        assert_eq!(exec(&mut db, &["incrbyfloat", "k", "0.2"]).unwrap(), bulk("0.3"));
+        assert_eq!(exec(&mut db, &["incrbyfloat", "tiny", "1e-20"]).unwrap(), bulk("0"));
+    }
+
+    #[test]
+    fn append_and_ranges() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["append", "k", "12"]).unwrap(), Resp3Piece::Integer(2));
+        assert_eq!(exec(&mut db, &["append", "k", "345"]).unwrap(), Resp3Piece::Integer(5));
+        assert_eq!(exec(&mut db, &["object", "encoding", "k"]).unwrap(), bulk("raw"));
+        assert_eq!(exec(&mut db, &["strlen", "k"]).unwrap(), Resp3Piece::Integer(5));
+        assert_eq!(exec(&mut db, &["strlen", "nokey"]).unwrap(), Resp3Piece::Integer(0));
+        for (start, end, expected) in [
+            ("0", "-1", "12345"),
+            ("-3", "-2", "34"),
+            ("-1", "-3", ""),
+            ("3", "100", "45"),
+            ("-100", "1", "12"),
+            ("4", "2", ""),
+        ] {
+            assert_eq!(exec(&mut db, &["getrange", "k", start, end]).unwrap(), bulk(expected));
+        }
+        assert_eq!(exec(&mut db, &["setrange", "k", "1", "ab"]).unwrap(), Resp3Piece::Integer(5));
+        assert_eq!(exec(&mut db, &["get", "k"]).unwrap(), bulk("1ab45"));
+        assert_eq!(exec(&mut db, &["setrange", "k", "7", "x"]).unwrap(), Resp3Piece::Integer(8));
+        assert_eq!(exec(&mut db, &["get", "k"]).unwrap(), bulk("1ab45\0\0x"));
+        assert_eq!(exec(&mut db, &["setrange", "new", "2", ""]).unwrap(), Resp3Piece::Integer(0));
+        assert!(!db.exists(b"new"));
+        assert_eq!(exec(&mut db, &["setrange", "new", "2", "y"]).unwrap(), Resp3Piece::Integer(3));
+        assert_eq!(exec(&mut db, &["get", "new"]).unwrap(), bulk("\0\0y"));
+        exec(&mut db, &["set", "new", "xy"]).unwrap();
+        assert_eq!(exec(&mut db, &["object", "encoding", "new"]).unwrap(), bulk("embstr"));
+        exec(&mut db, &["setrange", "new", "0", "z"]).unwrap();
+        assert_eq!(exec(&mut db, &["object", "encoding", "new"]).unwrap(), bulk("raw"));
+        assert!(exec(&mut db, &["setrange", "k", "-1", "x"]).is_err());
+        assert!(exec(&mut db, &["setrange", "k", "536870912", "x"]).is_err());
+        assert_eq!(exec(&mut db, &["get", "k"]).unwrap(), bulk("1ab45\0\0x"));
+    }
+
+    #[test]
+    fn multiple_keys() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["mset", "a", "1", "b", "2"]).unwrap(), Resp3Piece::ok());
+        assert!(exec(&mut db, &["mset", "a", "1", "b"]).is_err());
+        assert_eq!(
+            exec(&mut db, &["msetnx", "c", "3", "a", "0"]).unwrap(),
+            Resp3Piece::Integer(0)
+        );
+        assert_eq!(
+            exec(&mut db, &["mget", "a", "c", "b"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("1"), Resp3Piece::Null, bulk("2")])
+        );
+        assert_eq!(exec(&mut db, &["getset", "a", "x"]).unwrap(), bulk("1"));
+        assert_eq!(exec(&mut db, &["getdel", "a"]).unwrap(), bulk("x"));
+        assert_eq!(exec(&mut db, &["getdel", "a"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["setnx", "b", "x"]).unwrap(), Resp3Piece::Integer(0));
+        assert_eq!(exec(&mut db, &["setnx", "c", "x"]).unwrap(), Resp3Piece::Integer(1));
+        assert_eq!(exec(&mut db, &["psetex", "c", "10000", "y"]).unwrap(), Resp3Piece::ok());
+        assert!(db.get_expire(b"c").is_some());
+        assert!(exec(&mut db, &["setex", "c", "0", "y"]).is_err());
+    }
+
+    #[test]
+    fn lcs() {
+        let mut db = Db::new();
+        exec(&mut db, &["mset", "a", "ohmytext", "b", "mynewtext"]).unwrap();
+        assert_eq!(exec(&mut db, &["lcs", "a", "b"]).unwrap(), bulk("mytext"));
+        assert_eq!(exec(&mut db, &["lcs", "a", "b", "len"]).unwrap(), Resp3Piece::Integer(6));
+        assert_eq!(exec(&mut db, &["lcs", "a", "nokey"]).unwrap(), bulk(""));
+        assert!(exec(&mut db, &["lcs", "a", "b", "len", "idx"]).is_err());
+        let range = |start: i64, end: i64| {
+            Resp3Piece::Array(vec![Resp3Piece::Integer(start), Resp3Piece::Integer(end)])
+        };
+        assert_eq!(
+            exec(&mut db, &["lcs", "a", "b", "idx", "minmatchlen", "4", "withmatchlen"]).unwrap(),
+            Resp3Piece::Map(vec![
+                (
+                    bulk("matches"),
+                    Resp3Piece::Array(vec![Resp3Piece::Array(vec![
+                        range(4, 7),
+                        range(5, 8),
+                        Resp3Piece::Integer(4),
+                    ])])
+                ),
+                (bulk("len"), Resp3Piece::Integer(6)),
+            ])
+        );
+        assert_eq!(
+            exec(&mut db, &["lcs", "a", "b", "idx"]).unwrap(),
+            Resp3Piece::Map(vec![
+                (
+                    bulk("matches"),
+                    Resp3Piece::Array(vec![
+                        Resp3Piece::Array(vec![range(4, 7), range(5, 8)]),
+                        Resp3Piece::Array(vec![range(2, 3), range(0, 1)]),
+                    ])
+                ),
+                (bulk("len"), Resp3Piece::Integer(6)),
+            ])
+        );
+    }
+
+    #[test]
+    fn expired_key_is_gone() {
+        let mut db = Db::new();
+        exec(&mut db, &["set", "k", "v", "pxat", "1"]).unwrap();
+        assert_eq!(exec(&mut db, &["get", "k"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(exec(&mut db, &["exists", "k"]).unwrap(), Resp3Piece::Integer(0));
+        exec(&mut db, &["set", "a", "1"]).unwrap();
+        exec(&mut db, &["set", "b", "1", "pxat", "1"]).unwrap();
+        assert_eq!(exec(&mut db, &["exists", "a", "a", "b"]).unwrap(), Resp3Piece::Integer(2));
+        assert_eq!(exec(&mut db, &["del", "a", "b", "c"]).unwrap(), Resp3Piece::Integer(1));
+        assert_eq!(exec(&mut db, &["type", "a"]).unwrap(), Resp3Piece::simple("none"));
+    }
+}
@@ -0,0 +1,1225 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::{
+    db::Db,
+    error::{CommandError, Error, Result},
+    object::{LexBound, LexRange, RedisSet, RedisZSet, ScoreRange, Value},
+    protocol::Resp3Piece,
+    util::{parse_f64, parse_i64, random},
+};
+
+use super::{
+    hash::random_picks,
+    list::{self, parse_positive},
+    lower_bytes, not_float_error, not_integer_error, syntax_error, Args, SetOp,
+};
+
+/// Options of ZADD. NX and XX apply to members which are new or not, GT and LT to the
+/// scores of members which are updated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AddFlags {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    /// Count the members whose score changed along with the added ones.
+    pub ch: bool,
+    /// Increment the score instead of setting it, as ZINCRBY does.
+    pub incr: bool,
+}
+
+/// A range of elements of ZRANGE and friends.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeSpec {
+    /// Ranks, which may count from the end when negative, both ends included.
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// How ZUNION and ZINTER combine the scores a member has in several sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // adding infinities of both signs gives zero rather than NaN, as in redis.
+            Self::Sum => zero_if_nan(a + b),
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+}
+
+/// Commands working on sorted sets.
+#[derive(Debug)]
+pub enum ZSetCommand {
+    /// `ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]`, and
+    /// `ZINCRBY key increment member` which is ZADD INCR.
+    Add {
+        key: Bytes,
+        flags: AddFlags,
+        elements: Vec<(f64, Bytes)>,
+    },
+    Rem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Score {
+        key: Bytes,
+        member: Bytes,
+    },
+    MScore {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Card {
+        key: Bytes,
+    },
+    /// `ZCOUNT key min max` and `ZLEXCOUNT key min max`.
+    Count {
+        key: Bytes,
+        range: RangeSpec,
+    },
+    /// `ZRANK key member [WITHSCORE]` and `ZREVRANK`.
+    Rank {
+        key: Bytes,
+        member: Bytes,
+        rev: bool,
+        with_score: bool,
+    },
+    /// `ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`,
+    /// the older `ZREVRANGE`, `ZRANGEBYSCORE`, `ZREVRANGEBYSCORE`, `ZRANGEBYLEX` and
+    /// `ZREVRANGEBYLEX`, and `ZRANGESTORE dst src ...` which stores the range at `dst`.
+    Range {
+        key: Bytes,
+        dst: Option<Bytes>,
+        range: RangeSpec,
+        rev: bool,
+        limit: Option<(i64, i64)>,
+        with_scores: bool,
+    },
+    /// `ZREMRANGEBYRANK`, `ZREMRANGEBYSCORE` and `ZREMRANGEBYLEX`.
+    RemRange {
+        key: Bytes,
+        range: RangeSpec,
+    },
+    /// `ZPOPMIN key [count]` and `ZPOPMAX`.
+    Pop {
+        key: Bytes,
+        max: bool,
+        count: Option<usize>,
+    },
+    /// `ZMPOP numkeys key [key ...] MIN | MAX [COUNT count]`
+    MPop {
+        keys: Vec<Bytes>,
+        max: bool,
+        count: usize,
+    },
+    /// `ZRANDMEMBER key [count [WITHSCORES]]`
+    RandMember {
+        key: Bytes,
+        count: Option<i64>,
+        with_scores: bool,
+    },
+    /// `ZUNION numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM | MIN | MAX]
+    /// [WITHSCORES]`, `ZINTER`, `ZDIFF` which takes neither weights nor aggregate, and
+    /// their `STORE` variants which store the result at `dst`.
+    Combine {
+        op: SetOp,
+        keys: Vec<Bytes>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+        with_scores: bool,
+        dst: Option<Bytes>,
+    },
+}
+
+impl ZSetCommand {
+    pub fn parse(name: &str, args: &mut Args) -> Result<Option<Self>> {
+        let cmd = match name {
+            "zadd" => Self::parse_zadd(args)?,
+            "zincrby" => {
+                let key = args.next_arg()?;
+                let by = parse_f64(&args.next_arg()?).ok_or_else(not_float_error)?;
+                Self::Add {
+                    key,
+                    flags: AddFlags {
+                        incr: true,
+                        ..AddFlags::default()
+                    },
+                    elements: vec![(by, args.next_arg()?)],
+                }
+            }
+            "zrem" => Self::Rem {
+                key: args.next_arg()?,
+                members: args.rest()?,
+            },
+            "zscore" => Self::Score {
+                key: args.next_arg()?,
+                member: args.next_arg()?,
+            },
+            "zmscore" => Self::MScore {
+                key: args.next_arg()?,
+                members: args.rest()?,
+            },
+            "zcard" => Self::Card { key: args.next_arg()? },
+            "zcount" | "zlexcount" => {
+                let key = args.next_arg()?;
+                let (min, max) = (args.next_arg()?, args.next_arg()?);
+                let range = if name == "zcount" {
+                    RangeSpec::Score(parse_score_range(&min, &max)?)
+                } else {
+                    RangeSpec::Lex(parse_lex_range(&min, &max)?)
+                };
+                Self::Count { key, range }
+            }
+            "zrank" | "zrevrank" => {
+                let key = args.next_arg()?;
+                let member = args.next_arg()?;
+                let with_score = match args.next_lower().as_deref() {
+                    None => false,
+                    Some(b"withscore") => true,
+                    Some(_) => return Err(syntax_error()),
+                };
+                Self::Rank {
+                    key,
+                    member,
+                    rev: name == "zrevrank",
+                    with_score,
+                }
+            }
+            "zrange" | "zrangestore" | "zrevrange" | "zrangebyscore" | "zrevrangebyscore"
+            | "zrangebylex" | "zrevrangebylex" => Self::parse_range(name, args)?,
+            "zremrangebyrank" | "zremrangebyscore" | "zremrangebylex" => {
+                let key = args.next_arg()?;
+                let (min, max) = (args.next_arg()?, args.next_arg()?);
+                let range = match name {
+                    "zremrangebyrank" => RangeSpec::Rank(
+                        parse_i64(&min).ok_or_else(not_integer_error)?,
+                        parse_i64(&max).ok_or_else(not_integer_error)?,
+                    ),
+                    "zremrangebyscore" => RangeSpec::Score(parse_score_range(&min, &max)?),
+                    _ => RangeSpec::Lex(parse_lex_range(&min, &max)?),
+                };
+                Self::RemRange { key, range }
+            }
+            "zpopmin" | "zpopmax" => {
+                let key = args.next_arg()?;
+                let count = match args.next_opt() {
+                    Some(count) => Some(parse_count(&count)?),
+                    None => None,
+                };
+                Self::Pop {
+                    key,
+                    max: name == "zpopmax",
+                    count,
+                }
+            }
+            "zmpop" => {
+                let (keys, max, count) = parse_mpop(args)?;
+                Self::MPop { keys, max, count }
+            }
+            "zrandmember" => {
+                let key = args.next_arg()?;
+                let count = match args.next_opt() {
+                    Some(count) => Some(parse_i64(&count).ok_or_else(not_integer_error)?),
+                    None => None,
+                };
+                let with_scores = match args.next_lower().as_deref() {
+                    None => false,
+                    Some(b"withscores") if args.remaining() == 0 => true,
+                    Some(_) => return Err(syntax_error()),
+                };
+                Self::RandMember {
+                    key,
+                    count,
+                    with_scores,
+                }
+            }
+            "zunion" | "zinter" | "zdiff" | "zunionstore" | "zinterstore" | "zdiffstore" => {
+                Self::parse_combine(name, args)?
+            }
+            _ => return Ok(None),
+        };
+        args.end()?;
+        Ok(Some(cmd))
+    }
+
+    fn parse_zadd(args: &mut Args) -> Result<Self> {
+        let key = args.next_arg()?;
+        let mut flags = AddFlags::default();
+        let score = loop {
+            let arg = args.next_arg()?;
+            match lower_bytes(&arg).as_slice() {
+                b"nx" => flags.nx = true,
+                b"xx" => flags.xx = true,
+                b"gt" => flags.gt = true,
+                b"lt" => flags.lt = true,
+                b"ch" => flags.ch = true,
+                b"incr" => flags.incr = true,
+                _ => break arg,
+            }
+        };
+        let mut items = vec![score];
+        items.extend(args.rest()?);
+        if items.len() % 2 != 0 {
+            return Err(syntax_error());
+        }
+        let err = |msg: &str| -> Error { CommandError::Err(msg.to_string()).into() };
+        if flags.nx && flags.xx {
+            return Err(err("XX and NX options at the same time are not compatible"));
+        }
+        if [flags.gt, flags.lt, flags.nx].iter().filter(|&&set| set).count() > 1 {
+            return Err(err("GT, LT, and/or NX options at the same time are not compatible"));
+        }
+        if flags.incr && items.len() > 2 {
+            return Err(err("INCR option supports a single increment-element pair"));
+        }
+        let elements = items
+            .chunks(2)
+            .map(|pair| {
+                let score = parse_f64(&pair[0]).ok_or_else(not_float_error)?;
+                Ok((score, pair[1].clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::Add {
+            key,
+            flags,
+            elements,
+        })
+    }
+
+    fn parse_range(name: &str, args: &mut Args) -> Result<Self> {
+        let dst = match name {
+            "zrangestore" => Some(args.next_arg()?),
+            _ => None,
+        };
+        let key = args.next_arg()?;
+        let (start, stop) = (args.next_arg()?, args.next_arg()?);
+        // options only ZRANGE and ZRANGESTORE take.
+        let modern = name == "zrange" || name == "zrangestore";
+        let mut by_score = name.ends_with("byscore");
+        let mut by_lex = name.ends_with("bylex");
+        let mut rev = name.starts_with("zrev");
+        let mut limit = None;
+        let mut with_scores = false;
+        while let Some(opt) = args.next_lower() {
+            match opt.as_slice() {
+                b"withscores" if dst.is_none() => with_scores = true,
+                b"limit" => {
+                    let (offset, count) = match (args.next_opt(), args.next_opt()) {
+                        (Some(offset), Some(count)) => (offset, count),
+                        _ => return Err(syntax_error()),
+                    };
+                    limit = Some((
+                        parse_i64(&offset).ok_or_else(not_integer_error)?,
+                        parse_i64(&count).ok_or_else(not_integer_error)?,
+                    ));
+                }
+                b"byscore" if modern => by_score = true,
+                b"bylex" if modern => by_lex = true,
+                b"rev" if modern => rev = true,
+                _ => return Err(syntax_error()),
+            }
+        }
+        let err = |msg: &str| -> Error { CommandError::Err(msg.to_string()).into() };
+        if by_score && by_lex {
+            return Err(syntax_error());
+        }
+        if limit.is_some() && !by_score && !by_lex {
+            return Err(err(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+            ));
+        }
+        if with_scores && by_lex {
+            return Err(err("syntax error, WITHSCORES not supported in combination with BYLEX"));
+        }
+        // scores and members are given from the end of the range in reverse.
+        let (min, max) = if rev { (&stop, &start) } else { (&start, &stop) };
+        let range = if by_score {
+            RangeSpec::Score(parse_score_range(min, max)?)
+        } else if by_lex {
+            RangeSpec::Lex(parse_lex_range(min, max)?)
+        } else {
+            RangeSpec::Rank(
+                parse_i64(&start).ok_or_else(not_integer_error)?,
+                parse_i64(&stop).ok_or_else(not_integer_error)?,
+            )
+        };
+        Ok(Self::Range {
+            key,
+            dst,
+            range,
+            rev,
+            limit,
+            with_scores,
+        })
+    }
+
+    fn parse_combine(name: &str, args: &mut Args) -> Result<Self> {
+        let op = match &name[..name.len().min(6)] {
+            "zunion" => SetOp::Union,
+            "zinter" => SetOp::Inter,
+            _ => SetOp::Diff,
+        };
+        let dst = match name.ends_with("store") {
+            true => Some(args.next_arg()?),
+            false => None,
+        };
+        let numkeys = parse_i64(&args.next_arg()?).ok_or_else(not_integer_error)?;
+        if numkeys < 1 {
+            return Err(CommandError::Err(format!(
+                "at least 1 input key is needed for '{}' command",
+                name
+            ))
+            .into());
+        }
+        if numkeys as usize > args.remaining() {
+            return Err(syntax_error());
+        }
+        let keys: Vec<Bytes> = (0..numkeys).map(|_| args.next_arg()).collect::<Result<_>>()?;
+        let mut weights = vec![];
+        let mut aggregate = Aggregate::Sum;
+        let mut with_scores = false;
+        while let Some(opt) = args.next_lower() {
+            match opt.as_slice() {
+                b"weights" if op != SetOp::Diff && args.remaining() >= keys.len() => {
+                    weights = (0..keys.len())
+                        .map(|_| {
+                            parse_f64(&args.next_arg()?).ok_or_else(|| {
+                                CommandError::Err("weight value is not a float".into()).into()
+                            })
+                        })
+                        .collect::<Result<_>>()?;
+                }
+                b"aggregate" if op != SetOp::Diff => {
+                    aggregate = match args.next_lower().as_deref() {
+                        Some(b"sum") => Aggregate::Sum,
+                        Some(b"min") => Aggregate::Min,
+                        Some(b"max") => Aggregate::Max,
+                        _ => return Err(syntax_error()),
+                    };
+                }
+                b"withscores" if dst.is_none() => with_scores = true,
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(Self::Combine {
+            op,
+            keys,
+            weights,
+            aggregate,
+            with_scores,
+            dst,
+        })
+    }
+
+    pub fn execute(self, db: &mut Db) -> Result<Resp3Piece> {
+        match self {
+            Self::Add {
+                key,
+                flags,
+                elements,
+            } => {
+                let current = match db.lookup(&key) {
+                    Some(value) => Some(value.as_zset()?),
+                    None if flags.xx => {
+                        return Ok(if flags.incr {
+                            Resp3Piece::Null
+                        } else {
+                            Resp3Piece::Integer(0)
+                        })
+                    }
+                    None => None,
+                };
+                if flags.incr {
+                    let (by, member) = &elements[0];
+                    let old = current.and_then(|zset| zset.score(member));
+                    if old.is_some_and(|old| (old + by).is_nan()) {
+                        return Err(CommandError::Err(
+                            "resulting score is not a number (NaN)".into(),
+                        )
+                        .into());
+                    }
+                }
+                let zset = zset_or_create(db, key)?;
+                let (mut added, mut changed) = (0, 0);
+                let mut score_set = None;
+                for (score, member) in &elements {
+                    let old = zset.score(member);
+                    let score = match old {
+                        Some(old) if flags.incr => old + score,
+                        _ => *score,
+                    };
+                    match old {
+                        None if flags.xx => continue,
+                        Some(_) if flags.nx => continue,
+                        Some(old) if (flags.gt && score <= old) || (flags.lt && score >= old) => {
+                            continue
+                        }
+                        None => added += 1,
+                        Some(old) if old != score => changed += 1,
+                        Some(_) => {}
+                    }
+                    zset.insert(member, score);
+                    score_set = Some(score);
+                }
+                Ok(if flags.incr {
+                    score_set.map_or(Resp3Piece::Null, Resp3Piece::Double)
+                } else if flags.ch {
+                    Resp3Piece::Integer(added + changed)
+                } else {
+                    Resp3Piece::Integer(added)
+                })
+            }
+            Self::Rem { key, members } => {
+                let zset = match db.lookup_mut(&key) {
+                    Some(value) => value.as_zset_mut()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let removed = members.iter().filter(|member| zset.remove(member)).count();
+                if zset.is_empty() {
+                    db.remove(&key);
+                }
+                Ok(Resp3Piece::Integer(removed as i64))
+            }
+            Self::Score { key, member } => {
+                let score = match db.lookup(&key) {
+                    Some(value) => value.as_zset()?.score(&member),
+                    None => None,
+                };
+                Ok(score.map_or(Resp3Piece::Null, Resp3Piece::Double))
+            }
+            Self::MScore { key, members } => {
+                let zset = match db.lookup(&key) {
+                    Some(value) => Some(value.as_zset()?),
+                    None => None,
+                };
+                let scores = members
+                    .iter()
+                    .map(|member| zset.and_then(|zset| zset.score(member)))
+                    .map(|score| score.map_or(Resp3Piece::Null, Resp3Piece::Double))
+                    .collect();
+                Ok(Resp3Piece::Array(scores))
+            }
+            Self::Card { key } => {
+                let len = match db.lookup(&key) {
+                    Some(value) => value.as_zset()?.len(),
+                    None => 0,
+                };
+                Ok(Resp3Piece::Integer(len as i64))
+            }
+            Self::Count { key, range } => {
+                let zset = match db.lookup(&key) {
+                    Some(value) => value.as_zset()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let count = match &range {
+                    RangeSpec::Score(range) => zset.count(range),
+                    RangeSpec::Lex(range) => zset.count(range),
+                    RangeSpec::Rank(..) => unreachable!("counted by score or member"),
+                };
+                Ok(Resp3Piece::Integer(count as i64))
+            }
+            Self::Rank {
+                key,
+                member,
+                rev,
+                with_score,
+            } => {
+                let rank = match db.lookup(&key) {
+                    Some(value) => value.as_zset()?.rank(&member, rev),
+                    None => None,
+                };
+                Ok(match rank {
+                    Some((rank, score)) if with_score => Resp3Piece::Array(vec![
+                        Resp3Piece::Integer(rank as i64),
+                        Resp3Piece::Double(score),
+                    ]),
+                    Some((rank, _)) => Resp3Piece::Integer(rank as i64),
+                    None if with_score => Resp3Piece::NullArray,
+                    None => Resp3Piece::Null,
+                })
+            }
+            Self::Range {
+                key,
+                dst,
+                range,
+                rev,
+                limit,
+                with_scores,
+            } => {
+                let elements = match db.lookup(&key) {
+                    Some(value) => select(value.as_zset()?, &range, rev, limit),
+                    None => vec![],
+                };
+                match dst {
+                    None => Ok(elements_reply(elements, with_scores)),
+                    Some(dst) => {
+                        let mut zset = RedisZSet::new();
+                        for (member, score) in &elements {
+                            zset.insert(member, *score);
+                        }
+                        Ok(store(db, dst, zset))
+                    }
+                }
+            }
+            Self::RemRange { key, range } => {
+                let zset = match db.lookup_mut(&key) {
+                    Some(value) => value.as_zset_mut()?,
+                    None => return Ok(Resp3Piece::Integer(0)),
+                };
+                let elements = select(zset, &range, false, None);
+                for (member, _) in &elements {
+                    zset.remove(member);
+                }
+                if zset.is_empty() {
+                    db.remove(&key);
+                }
+                Ok(Resp3Piece::Integer(elements.len() as i64))
+            }
+            Self::Pop { key, max, count } => {
+                let elements = pop(db, &key, max, count.unwrap_or(1))?;
+                Ok(match count {
+                    None => Resp3Piece::Array(
+                        elements
+                            .into_iter()
+                            .flat_map(|(member, score)| {
+                                [Resp3Piece::bulk(member), Resp3Piece::Double(score)]
+                            })
+                            .collect(),
+                    ),
+                    Some(_) => elements_reply(elements, true),
+                })
+            }
+            Self::MPop { keys, max, count } => Ok(match mpop(db, &keys, max, count)? {
+                Some((key, elements)) => mpop_reply(key, elements),
+                None => Resp3Piece::NullArray,
+            }),
+            Self::RandMember {
+                key,
+                count,
+                with_scores,
+            } => {
+                let zset = match db.lookup(&key) {
+                    Some(value) => value.as_zset()?,
+                    None if count.is_some() => return Ok(Resp3Piece::Array(vec![])),
+                    None => return Ok(Resp3Piece::Null),
+                };
+                // elements are picked by random rank, found in O(log n).
+                let pick = || random() as usize % zset.len();
+                let count = match count {
+                    Some(count) => count,
+                    None => {
+                        let (member, _) = zset.get_by_rank(pick()).unwrap();
+                        return Ok(Resp3Piece::bulk(member.clone()));
+                    }
+                };
+                if count < -i64::MAX || (with_scores && count < -i64::MAX / 2) {
+                    return Err(CommandError::Err("value is out of range".into()).into());
+                }
+                let elements = random_picks(zset.len(), count, pick, || (0..zset.len()).collect())
+                    .map(|rank| {
+                        let (member, score) = zset.get_by_rank(rank).unwrap();
+                        (member.clone(), score)
+                    })
+                    .collect();
+                Ok(elements_reply(elements, with_scores))
+            }
+            Self::Combine {
+                op,
+                keys,
+                weights,
+                aggregate,
+                with_scores,
+                dst,
+            } => {
+                let zset = combine(db, op, &keys, &weights, aggregate)?;
+                match dst {
+                    None => {
+                        let elements = zset.iter().map(|(m, s)| (m.clone(), s)).collect();
+                        Ok(elements_reply(elements, with_scores))
+                    }
+                    Some(dst) => Ok(store(db, dst, zset)),
+                }
+            }
+        }
+    }
+}
+
+/// Parses a count of ZPOPMIN and friends, which must not be negative.
+pub(super) fn parse_count(arg: &[u8]) -> Result<usize> {
+    match parse_i64(arg).ok_or_else(not_integer_error)? {
+        count if count < 0 => {
+            Err(CommandError::Err("value is out of range, must be positive".into()).into())
+        }
+        count => Ok(count as usize),
+    }
+}
+
+/// Parses the `numkeys key [key ...] MIN | MAX [COUNT count]` arguments of ZMPOP and
+/// BZMPOP, `MAX` giving `true`.
+pub(super) fn parse_mpop(args: &mut Args) -> Result<(Vec<Bytes>, bool, usize)> {
+    let numkeys = parse_positive(&args.next_arg()?, "numkeys should be greater than 0", 1)?;
+    if numkeys >= args.remaining() {
+        return Err(syntax_error());
+    }
+    let keys = (0..numkeys).map(|_| args.next_arg()).collect::<Result<_>>()?;
+    let max = match lower_bytes(&args.next_arg()?).as_slice() {
+        b"min" => false,
+        b"max" => true,
+        _ => return Err(syntax_error()),
+    };
+    let mut count = None;
+    while let Some(opt) = args.next_lower() {
+        match opt.as_slice() {
+            b"count" if count.is_none() => {
+                let value = args.next_opt().ok_or_else(syntax_error)?;
+                count = Some(parse_positive(&value, "count should be greater than 0", 1)?);
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok((keys, max, count.unwrap_or(1)))
+}
+
+/// Parses the ends of a range of scores, which are exclusive when prefixed by `(`.
+fn parse_score_range(min: &[u8], max: &[u8]) -> Result<ScoreRange> {
+    let bound = |arg: &[u8]| {
+        let (value, ex) = match arg {
+            [b'(', rest @ ..] => (rest, true),
+            _ => (arg, false),
+        };
+        match parse_f64(value) {
+            Some(value) => Ok((value, ex)),
+            None => Err(Error::from(CommandError::Err("min or max is not a float".into()))),
+        }
+    };
+    let ((min, min_ex), (max, max_ex)) = (bound(min)?, bound(max)?);
+    Ok(ScoreRange {
+        min,
+        min_ex,
+        max,
+        max_ex,
+    })
+}
+
+/// Parses the ends of a range of members, `-`, `+`, or a member prefixed by `[` when
+/// it is inclusive or by `(` when it is exclusive.
+fn parse_lex_range(min: &[u8], max: &[u8]) -> Result<LexRange> {
+    let bound = |arg: &[u8]| match arg {
+        b"-" => Ok(LexBound::Lowest),
+        b"+" => Ok(LexBound::Highest),
+        [b'[', rest @ ..] => Ok(LexBound::Inclusive(Bytes::copy_from_slice(rest))),
+        [b'(', rest @ ..] => Ok(LexBound::Exclusive(Bytes::copy_from_slice(rest))),
+        _ => Err(Error::from(CommandError::Err(
+            "min or max not valid string range item".into(),
+        ))),
+    };
+    Ok(LexRange {
+        min: bound(min)?,
+        max: bound(max)?,
+    })
+}
+
+fn zero_if_nan(score: f64) -> f64 {
+    if score.is_nan() {
+        0.0
+    } else {
+        score
+    }
+}
+
+/// Returns the sorted set of the key, which is created when it does not exist.
+fn zset_or_create(db: &mut Db, key: Bytes) -> Result<&mut RedisZSet> {
+    if !db.exists(&key) {
+        db.set(key.clone(), Value::ZSet(RedisZSet::new()), false);
+    }
+    db.lookup_mut(&key).unwrap().as_zset_mut()
+}
+
+/// Selects the elements in the range, skipping `offset` and taking at most `count` of
+/// them when LIMIT is given. A negative count takes all of them.
+fn select(
+    zset: &RedisZSet,
+    range: &RangeSpec,
+    rev: bool,
+    limit: Option<(i64, i64)>,
+) -> Vec<(Bytes, f64)> {
+    let (offset, count) = match limit {
+        None => (0, None),
+        Some((offset, _)) if offset < 0 => return vec![],
+        Some((offset, count)) => (offset as usize, usize::try_from(count).ok()),
+    };
+    match range {
+        RangeSpec::Rank(start, stop) => match list::range(zset.len(), *start, *stop) {
+            Some((start, stop)) => zset.range_by_rank(start, stop, rev),
+            None => vec![],
+        },
+        RangeSpec::Score(range) => zset.range(range, rev, offset, count),
+        RangeSpec::Lex(range) => zset.range(range, rev, offset, count),
+    }
+}
+
+/// Replies the members, along with their scores when `with_scores` is set, as
+/// [member, score] pairs which RESP2 clients get flattened.
+fn elements_reply(elements: Vec<(Bytes, f64)>, with_scores: bool) -> Resp3Piece {
+    if with_scores {
+        Resp3Piece::Pairs(
+            elements
+                .into_iter()
+                .map(|(member, score)| (Resp3Piece::bulk(member), Resp3Piece::Double(score)))
+                .collect(),
+        )
+    } else {
+        Resp3Piece::Array(
+            elements
+                .into_iter()
+                .map(|(member, _)| Resp3Piece::bulk(member))
+                .collect(),
+        )
+    }
+}
+
+/// Stores the sorted set at `dst`, which is deleted instead when the set is empty, and
+/// replies its size.
+fn store(db: &mut Db, dst: Bytes, zset: RedisZSet) -> Resp3Piece {
+    let len = zset.len();
+    if len == 0 {
+        db.remove(&dst);
+    } else {
+        db.set(dst, Value::ZSet(zset), false);
+    }
+    Resp3Piece::Integer(len as i64)
+}
+
+/// Pops up to `count` elements with the lowest scores, or the highest ones when `max`
+/// is set. The key is deleted once empty.
+pub(crate) fn pop(db: &mut Db, key: &[u8], max: bool, count: usize) -> Result<Vec<(Bytes, f64)>> {
+    let zset = match db.lookup_mut(key) {
+        Some(value) => value.as_zset_mut()?,
+        None => return Ok(vec![]),
+    };
+    let elements = (0..count).map_while(|_| zset.pop(max)).collect();
+    if zset.is_empty() {
+        db.remove(key);
+    }
+    Ok(elements)
+}
+
+/// A member and its score.
+pub(crate) type Element = (Bytes, f64);
+
+/// Pops elements from the first of the keys holding a sorted set, returning the key
+/// along with them, or `None` when none of the keys exists.
+pub(crate) fn mpop(
+    db: &mut Db,
+    keys: &[Bytes],
+    max: bool,
+    count: usize,
+) -> Result<Option<(Bytes, Vec<Element>)>> {
+    for key in keys {
+        let elements = pop(db, key, max, count)?;
+        if !elements.is_empty() {
+            return Ok(Some((key.clone(), elements)));
+        }
+    }
+    Ok(None)
+}
+
+/// Reply of ZMPOP and BZMPOP, the key and the elements popped from it, each of them a
+/// member and its score.
+pub(crate) fn mpop_reply(key: Bytes, elements: Vec<Element>) -> Resp3Piece {
+    let elements = elements
+        .into_iter()
+        .map(|(member, score)| {
+            Resp3Piece::Array(vec![Resp3Piece::bulk(member), Resp3Piece::Double(score)])
+        })
+        .collect();
+    Resp3Piece::Array(vec![Resp3Piece::bulk(key), Resp3Piece::Array(elements)])
+}
+
+/// An input of ZUNION and friends, which may also be a set, its members having a score
+/// of 1.
+enum Input<'a> {
+    Set(&'a RedisSet),
+    ZSet(&'a RedisZSet),
+}
+
+impl<'a> Input<'a> {
+    fn len(&self) -> usize {
+        match self {
+            Self::Set(set) => set.len(),
+            Self::ZSet(zset) => zset.len(),
+        }
+    }
+
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Self::Set(set) => set.contains(member).then_some(1.0),
+            Self::ZSet(zset) => zset.score(member),
+        }
+    }
+
+    fn elements(&self) -> Box<dyn Iterator<Item = (Bytes, f64)> + 'a> {
+        match *self {
+            Self::Set(set) => Box::new(set.iter().map(|member| (member, 1.0))),
+            Self::ZSet(zset) => {
+                Box::new(zset.iter().map(|(member, score)| (member.clone(), score)))
+            }
+        }
+    }
+}
+
+/// Computes the union, intersection or difference of the sets of the keys. Scores are
+/// multiplied by the weight of their set, then combined by `aggregate`.
+fn combine(
+    db: &mut Db,
+    op: SetOp,
+    keys: &[Bytes],
+    weights: &[f64],
+    aggregate: Aggregate,
+) -> Result<RedisZSet> {
+    let inputs = db
+        .lookup_many(keys)
+        .into_iter()
+        .map(|value| match value {
+            None => Ok(None),
+            Some(Value::Set(set)) => Ok(Some(Input::Set(set))),
+            Some(Value::ZSet(zset)) => Ok(Some(Input::ZSet(zset))),
+            Some(_) => Err(CommandError::WrongType.into()),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let weight = |i: usize| weights.get(i).copied().unwrap_or(1.0);
+    let mut scores: HashMap<Bytes, f64> = HashMap::new();
+    match op {
+        SetOp::Union => {
+            for (i, input) in inputs.iter().enumerate() {
+                for (member, score) in input.iter().flat_map(Input::elements) {
+                    let score = zero_if_nan(score * weight(i));
+                    scores
+                        .entry(member)
+                        .and_modify(|acc| *acc = aggregate.apply(*acc, score))
+                        .or_insert(score);
+                }
+            }
+        }
+        SetOp::Inter => {
+            let inputs = inputs.into_iter().enumerate();
+            if let Some(mut inputs) = inputs
+                .map(|(i, input)| input.map(|input| (i, input)))
+                .collect::<Option<Vec<_>>>()
+            {
+                // walk the smallest set, looking its members up in the others.
+                inputs.sort_by_key(|(_, input)| input.len());
+                let ((first, smallest), others) = inputs.split_first().unwrap();
+                'members: for (member, score) in smallest.elements() {
+                    let mut acc = zero_if_nan(score * weight(*first));
+                    for (i, input) in others {
+                        match input.score(&member) {
+                            Some(score) => {
+                                acc = aggregate.apply(acc, zero_if_nan(score * weight(*i)))
+                            }
+                            None => continue 'members,
+                        }
+                    }
+                    scores.insert(member, acc);
+                }
+            }
+        }
+        SetOp::Diff => {
+            if let Some((Some(first), others)) = inputs.split_first() {
+                for (member, score) in first.elements() {
+                    if !others.iter().flatten().any(|input| input.score(&member).is_some()) {
+                        scores.insert(member, score);
+                    }
+                }
+            }
+        }
+    }
+    let mut zset = RedisZSet::new();
+    for (member, score) in scores {
+        zset.insert(&member, score);
+    }
+    Ok(zset)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command::tests::{bulk, exec},
+        db::Db,
+        protocol::Resp3Piece,
+    };
+
+    fn int(db: &mut Db, args: &[&str]) -> i64 {
+        match exec(db, args).unwrap() {
+            Resp3Piece::Integer(n) => n,
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    fn bulks(items: &[&str]) -> Resp3Piece {
+        Resp3Piece::Array(items.iter().map(|item| bulk(item)).collect())
+    }
+
+    fn scored(items: &[(&str, f64)]) -> Resp3Piece {
+        Resp3Piece::Pairs(
+            items
+                .iter()
+                .map(|(member, score)| (bulk(member), Resp3Piece::Double(*score)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn add_with_options() {
+        let mut db = Db::new();
+        assert_eq!(int(&mut db, &["zadd", "z", "1", "a", "2", "b"]), 2);
+        assert_eq!(int(&mut db, &["zadd", "z", "nx", "5", "a", "3", "c"]), 1);
+        assert_eq!(int(&mut db, &["zadd", "z", "xx", "ch", "5", "a", "4", "d"]), 1);
+        assert_eq!(int(&mut db, &["zadd", "z", "gt", "ch", "1", "a", "9", "b"]), 1);
+        assert_eq!(int(&mut db, &["zadd", "z", "lt", "ch", "6", "a", "1", "c"]), 1);
+        assert_eq!(
+            exec(&mut db, &["zmscore", "z", "a", "b", "c", "x"]).unwrap(),
+            Resp3Piece::Array(vec![
+                Resp3Piece::Double(5.0),
+                Resp3Piece::Double(9.0),
+                Resp3Piece::Double(1.0),
+                Resp3Piece::Null,
+            ])
+        );
+        assert_eq!(
+            exec(&mut db, &["zadd", "z", "incr", "2.5", "a"]).unwrap(),
+            Resp3Piece::Double(7.5)
+        );
+        assert_eq!(
+            exec(&mut db, &["zadd", "z", "incr", "nx", "1", "a"]).unwrap(),
+            Resp3Piece::Null
+        );
+        assert_eq!(
+            exec(&mut db, &["zincrby", "z", "-1", "new"]).unwrap(),
+            Resp3Piece::Double(-1.0)
+        );
+        assert_eq!(exec(&mut db, &["zscore", "z", "new"]).unwrap(), Resp3Piece::Double(-1.0));
+        assert_eq!(int(&mut db, &["zcard", "z"]), 4);
+        assert_eq!(
+            exec(&mut db, &["zadd", "n", "xx", "incr", "1", "a"]).unwrap(),
+            Resp3Piece::Null
+        );
+        assert_eq!(int(&mut db, &["exists", "n"]), 0);
+        assert_eq!(exec(&mut db, &["object", "encoding", "z"]).unwrap(), bulk("skiplist"));
+
+        assert!(exec(&mut db, &["zadd", "z", "nx", "xx", "1", "a"]).is_err());
+        assert!(exec(&mut db, &["zadd", "z", "gt", "lt", "1", "a"]).is_err());
+        assert!(exec(&mut db, &["zadd", "z", "incr", "1", "a", "2", "b"]).is_err());
+        assert!(exec(&mut db, &["zadd", "z", "1", "a", "2"]).is_err());
+        assert!(exec(&mut db, &["zadd", "z", "x", "a"]).is_err());
+        exec(&mut db, &["zadd", "inf", "inf", "a"]).unwrap();
+        assert!(exec(&mut db, &["zincrby", "inf", "-inf", "a"]).is_err());
+
+        assert_eq!(int(&mut db, &["zrem", "z", "a", "x"]), 1);
+        assert_eq!(int(&mut db, &["zrem", "z", "b", "c", "new"]), 3);
+        assert_eq!(int(&mut db, &["exists", "z"]), 0);
+        exec(&mut db, &["set", "s", "v"]).unwrap();
+        assert!(exec(&mut db, &["zadd", "s", "1", "a"]).is_err());
+    }
+
+    #[test]
+    fn ranks_and_ranges() {
+        let mut db = Db::new();
+        exec(&mut db, &["zadd", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e"]).unwrap();
+        assert_eq!(int(&mut db, &["zrank", "z", "b"]), 1);
+        assert_eq!(int(&mut db, &["zrevrank", "z", "b"]), 3);
+        assert_eq!(
+            exec(&mut db, &["zrank", "z", "c", "withscore"]).unwrap(),
+            Resp3Piece::Array(vec![Resp3Piece::Integer(2), Resp3Piece::Double(3.0)])
+        );
+        assert_eq!(exec(&mut db, &["zrank", "z", "x"]).unwrap(), Resp3Piece::Null);
+        assert_eq!(int(&mut db, &["zcount", "z", "(1", "3"]), 2);
+        assert_eq!(int(&mut db, &["zcount", "z", "-inf", "+inf"]), 5);
+        assert_eq!(int(&mut db, &["zcount", "z", "4", "2"]), 0);
+
+        assert_eq!(exec(&mut db, &["zrange", "z", "1", "-3"]).unwrap(), bulks(&["b", "c"]));
+        assert_eq!(exec(&mut db, &["zrange", "z", "0", "1", "rev"]).unwrap(), bulks(&["e", "d"]));
+        assert_eq!(exec(&mut db, &["zrevrange", "z", "-1", "-1"]).unwrap(), bulks(&["a"]));
+        assert_eq!(
+            exec(&mut db, &["zrange", "z", "0", "0", "withscores"]).unwrap(),
+            scored(&[("a", 1.0)])
+        );
+        assert_eq!(
+            exec(&mut db, &["zrange", "z", "(2", "4", "byscore", "limit", "1", "5"]).unwrap(),
+            bulks(&["d"])
+        );
+        assert_eq!(
+            exec(&mut db, &["zrange", "z", "+inf", "3", "byscore", "rev", "limit", "0", "-1"])
+                .unwrap(),
+            bulks(&["e", "d", "c"])
+        );
+        assert_eq!(
+            exec(&mut db, &["zrevrangebyscore", "z", "2", "-inf", "withscores"]).unwrap(),
+            scored(&[("b", 2.0), ("a", 1.0)])
+        );
+        assert_eq!(
+            exec(&mut db, &["zrangebyscore", "z", "1", "5", "limit", "-1", "2"]).unwrap(),
+            bulks(&[])
+        );
+        assert!(exec(&mut db, &["zrange", "z", "0", "1", "limit", "0", "1"]).is_err());
+        assert!(exec(&mut db, &["zrangebyscore", "z", "x", "1"]).is_err());
+        assert!(exec(&mut db, &["zrangebyscore", "z", "0", "1", "rev"]).is_err());
+
+        exec(&mut db, &["zadd", "lex", "0", "a", "0", "b", "0", "c", "0", "d"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["zrange", "lex", "[b", "(d", "bylex"]).unwrap(),
+            bulks(&["b", "c"])
+        );
+        assert_eq!(
+            exec(&mut db, &["zrevrangebylex", "lex", "+", "-", "limit", "1", "2"]).unwrap(),
+            bulks(&["c", "b"])
+        );
+        assert_eq!(int(&mut db, &["zlexcount", "lex", "(a", "+"]), 3);
+        assert!(exec(&mut db, &["zrangebylex", "lex", "a", "+"]).is_err());
+        assert!(exec(&mut db, &["zrange", "lex", "-", "+", "bylex", "withscores"]).is_err());
+
+        assert_eq!(int(&mut db, &["zrangestore", "dst", "z", "2", "4", "byscore"]), 3);
+        assert_eq!(exec(&mut db, &["zrange", "dst", "0", "-1"]).unwrap(), bulks(&["b", "c", "d"]));
+        assert_eq!(int(&mut db, &["zrangestore", "dst", "z", "10", "20"]), 0);
+        assert_eq!(int(&mut db, &["exists", "dst"]), 0);
+        assert!(exec(&mut db, &["zrangestore", "dst", "z", "0", "1", "withscores"]).is_err());
+
+        assert_eq!(int(&mut db, &["zremrangebyscore", "z", "(4", "inf"]), 1);
+        assert_eq!(int(&mut db, &["zremrangebyrank", "z", "0", "1"]), 2);
+        assert_eq!(exec(&mut db, &["zrange", "z", "0", "-1"]).unwrap(), bulks(&["c", "d"]));
+        assert_eq!(int(&mut db, &["zremrangebylex", "lex", "-", "[c"]), 3);
+        assert_eq!(int(&mut db, &["zremrangebylex", "lex", "-", "+"]), 1);
+        assert_eq!(int(&mut db, &["exists", "lex"]), 0);
+    }
+
+    #[test]
+    fn ranks_of_a_leaderboard() {
+        let mut db = Db::new();
+        for i in 0..1000 {
+            exec(&mut db, &["zadd", "board", &(i * 10).to_string(), &format!("player{}", i)])
+                .unwrap();
+        }
+        assert_eq!(int(&mut db, &["zrevrank", "board", "player999"]), 0);
+        assert_eq!(int(&mut db, &["zrank", "board", "player500"]), 500);
+        exec(&mut db, &["zincrby", "board", "10000", "player0"]).unwrap();
+        assert_eq!(int(&mut db, &["zrevrank", "board", "player0"]), 0);
+        assert_eq!(int(&mut db, &["zrank", "board", "player500"]), 499);
+        assert_eq!(
+            exec(&mut db, &["zrange", "board", "0", "2", "rev"]).unwrap(),
+            bulks(&["player0", "player999", "player998"])
+        );
+    }
+
+    #[test]
+    fn pops_and_random_members() {
+        let mut db = Db::new();
+        assert_eq!(exec(&mut db, &["zpopmin", "z"]).unwrap(), bulks(&[]));
+        assert_eq!(exec(&mut db, &["zrandmember", "z"]).unwrap(), Resp3Piece::Null);
+        exec(&mut db, &["zadd", "z", "1", "a", "2", "b", "3", "c", "4", "d"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["zpopmin", "z"]).unwrap(),
+            Resp3Piece::Array(vec![bulk("a"), Resp3Piece::Double(1.0)])
+        );
+        assert_eq!(
+            exec(&mut db, &["zpopmax", "z", "2"]).unwrap(),
+            scored(&[("d", 4.0), ("c", 3.0)])
+        );
+        assert!(exec(&mut db, &["zpopmax", "z", "-1"]).is_err());
+        assert_eq!(exec(&mut db, &["zrandmember", "z"]).unwrap(), bulk("b"));
+        assert_eq!(
+            exec(&mut db, &["zrandmember", "z", "-3", "withscores"]).unwrap(),
+            scored(&[("b", 2.0), ("b", 2.0), ("b", 2.0)])
+        );
+        assert_eq!(exec(&mut db, &["zrandmember", "z", "5"]).unwrap(), bulks(&["b"]));
+        for i in 0..100 {
+            exec(&mut db, &["zadd", "big", &i.to_string(), &format!("m{}", i)]).unwrap();
+        }
+        match exec(&mut db, &["zrandmember", "big", "10", "withscores"]).unwrap() {
+            Resp3Piece::Pairs(pairs) => {
+                assert_eq!(pairs.len(), 10);
+                for (member, score) in pairs {
+                    let score = match score {
+                        Resp3Piece::Double(score) => score,
+                        other => panic!("unexpected score {:?}", other),
+                    };
+                    assert_eq!(member, bulk(&format!("m{}", score)));
+                }
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(exec(&mut db, &["zrandmember", "z", "-9223372036854775808"]).is_err());
+        let args = ["zrandmember", "z", "-9223372036854775807", "withscores"];
+        assert!(exec(&mut db, &args).is_err());
+        assert_eq!(exec(&mut db, &["zpopmin", "z", "5"]).unwrap(), scored(&[("b", 2.0)]));
+        assert_eq!(int(&mut db, &["exists", "z"]), 0);
+
+        exec(&mut db, &["zadd", "y", "1", "a", "2", "b", "3", "c"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["zmpop", "2", "z", "y", "max", "count", "2"]).unwrap(),
+            Resp3Piece::Array(vec![
+                bulk("y"),
+                Resp3Piece::Array(vec![
+                    Resp3Piece::Array(vec![bulk("c"), Resp3Piece::Double(3.0)]),
+                    Resp3Piece::Array(vec![bulk("b"), Resp3Piece::Double(2.0)]),
+                ]),
+            ])
+        );
+        assert_eq!(exec(&mut db, &["zmpop", "1", "z", "min"]).unwrap(), Resp3Piece::NullArray);
+        assert!(exec(&mut db, &["zmpop", "0", "y", "min"]).is_err());
+        assert!(exec(&mut db, &["zmpop", "1", "y", "left"]).is_err());
+        assert!(exec(&mut db, &["zmpop", "1", "y", "min", "count", "0"]).is_err());
+    }
+
+    #[test]
+    fn union_inter_and_diff() {
+        let mut db = Db::new();
+        exec(&mut db, &["zadd", "a", "1", "x", "2", "y", "3", "z"]).unwrap();
+        exec(&mut db, &["zadd", "b", "10", "y", "20", "z", "30", "w"]).unwrap();
+        exec(&mut db, &["sadd", "s", "z", "w"]).unwrap();
+        assert_eq!(
+            exec(&mut db, &["zunion", "2", "a", "b", "withscores"]).unwrap(),
+            scored(&[("x", 1.0), ("y", 12.0), ("z", 23.0), ("w", 30.0)])
+        );
+        assert_eq!(
+            exec(&mut db, &["zinter", "3", "a", "b", "s", "weights", "2", "1", "5", "withscores"])
+                .unwrap(),
+            scored(&[("z", 31.0)])
+        );
+        assert_eq!(
+            exec(&mut db, &["zinter", "2", "a", "b", "aggregate", "max", "withscores"]).unwrap(),
+            scored(&[("y", 10.0), ("z", 20.0)])
+        );
+        assert_eq!(
+            exec(&mut db, &["zunion", "2", "a", "nokey", "aggregate", "min"]).unwrap(),
+            bulks(&["x", "y", "z"])
+        );
+        assert_eq!(exec(&mut db, &["zdiff", "2", "a", "b"]).unwrap(), bulks(&["x"]));
+        assert_eq!(exec(&mut db, &["zinter", "2", "a", "nokey"]).unwrap(), bulks(&[]));
+
+        assert_eq!(int(&mut db, &["zunionstore", "dst", "2", "a", "s"]), 4);
+        assert_eq!(exec(&mut db, &["zscore", "dst", "z"]).unwrap(), Resp3Piece::Double(4.0));
+        assert_eq!(int(&mut db, &["zinterstore", "dst", "2", "a", "nokey"]), 0);
+        assert_eq!(int(&mut db, &["exists", "dst"]), 0);
+        assert_eq!(int(&mut db, &["zdiffstore", "dst", "2", "b", "s"]), 1);
+
+        assert!(exec(&mut db, &["zunion", "0", "a"]).is_err());
+        assert!(exec(&mut db, &["zunion", "3", "a", "b"]).is_err());
+        assert!(exec(&mut db, &["zunion", "2", "a", "b", "weights", "1"]).is_err());
+        assert!(exec(&mut db, &["zunion", "1", "a", "weights", "x"]).is_err());
+        assert!(exec(&mut db, &["zdiff", "1", "a", "weights", "1"]).is_err());
+        assert!(exec(&mut db, &["zunionstore", "d", "1", "a", "withscores"]).is_err());
+        exec(&mut db, &["set", "str", "v"]).unwrap();
+        assert!(exec(&mut db, &["zunion", "2", "a", "str"]).is_err());
+    }
+}
@@ -1,4 +1,38 @@
-
 pub struct Config {
     pub addr: String,
-}
\ No newline at end of file
+    /// Max length of a bulk argument of a request, `proto-max-bulk-len` of redis.
+    pub proto_max_bulk_len: usize,
+    /// Max number of arguments of a request.
+    pub proto_max_multibulk_len: usize,
+    /// Frequency of the background tasks of the server, like the active expire cycle.
+    pub hz: u64,
+    /// Number of databases, selected by clients with SELECT.
+    pub databases: usize,
+    /// Hashes with more fields than this are converted from listpacks to hash tables.
+    pub hash_max_listpack_entries: usize,
+    /// Hashes with a field or a value longer than this are converted to hash tables.
+    pub hash_max_listpack_value: usize,
+    /// Sets of integers with more members than this are converted to hash sets.
+    pub set_max_intset_entries: usize,
+    /// Max number of entries of a block of a stream, zero for no limit.
+    pub stream_node_max_entries: usize,
+    /// Max size in bytes of a block of a stream, zero for no limit.
+    pub stream_node_max_bytes: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:6379".to_string(),
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            proto_max_multibulk_len: 1024 * 1024,
+            hz: 10,
+            databases: 16,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            stream_node_max_entries: 100,
+            stream_node_max_bytes: 4096,
+        }
+    }
+}
@@ -0,0 +1,537 @@
+use bytes::Bytes;
+
+use crate::{
+    blocking::{BlockedClients, ReplyReceiver},
+    command::BlockingCommand,
+    dict::Dict,
+    object::{EncodingLimits, Value},
+    util::{now_ms, random},
+};
+
+/// Number of volatile keys sampled by each loop of the active expire cycle.
+const ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP: usize = 20;
+/// The cycle keeps looping while more than this percentage of sampled keys are expired.
+const ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE: usize = 10;
+
+/// Expire times of volatile keys. Keys are also kept in a vector, so that random keys
+/// can be sampled by the active expire cycle in O(1).
+#[derive(Debug, Default)]
+struct Expires {
+    /// Unix time in milliseconds at which a key expires, and the index of the key in
+    /// `keys`.
+    map: Dict<Bytes, (u64, usize)>,
+    keys: Vec<Bytes>,
+}
+
+impl Expires {
+    fn get(&self, key: &[u8]) -> Option<u64> {
+        self.map.get(key).map(|&(when, _)| when)
+    }
+
+    fn insert(&mut self, key: Bytes, when: u64) {
+        match self.map.get_mut(&key) {
+            Some(entry) => entry.0 = when,
+            None => {
+                self.map.insert(key.clone(), (when, self.keys.len()));
+                self.keys.push(key);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<u64> {
+        let (when, index) = self.map.remove(key)?;
+        self.keys.swap_remove(index);
+        if let Some(moved) = self.keys.get(index) {
+            self.map.get_mut(moved).unwrap().1 = index;
+        }
+        Some(when)
+    }
+
+    fn random_key(&self) -> Option<&Bytes> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        self.keys.get(random() as usize % self.keys.len())
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+/// A keyspace holding keys and their values, along with the expire time of volatile
+/// keys.
+///
+/// Keys are expired in two ways, as redis does. Lazily, when an expired key is
+/// accessed it is removed and looks like it never existed. And actively, by
+/// [`Db::active_expire_cycle`] which is run periodically by the server, so keys which
+/// are never accessed again do not stay in memory forever.
+///
+/// Fields of hashes expire the same way. Hashes with volatile fields are indexed by the
+/// time their first field expires, and their expired fields are removed when the hash
+/// is accessed or sampled. A hash whose last field expires is deleted.
+#[derive(Debug, Default)]
+pub struct Db {
+    dict: Dict<Bytes, Value>,
+    expires: Expires,
+    /// Hashes with volatile fields, and the time at which their first field expires.
+    hash_expires: Expires,
+    /// Keys which got a value that may serve blocked clients, since they were last
+    /// served.
+    ready_keys: Vec<Bytes>,
+    limits: EncodingLimits,
+}
+
+impl Db {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: EncodingLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Thresholds of the compact encodings of the values.
+    pub fn limits(&self) -> &EncodingLimits {
+        &self.limits
+    }
+
+    /// Removes the key if it has expired, and tells whether it has been removed. The
+    /// expired fields of a hash are removed as well, along with the hash when none is
+    /// left.
+    fn expire_if_needed(&mut self, key: &[u8]) -> bool {
+        let now = now_ms();
+        match self.expires.get(key) {
+            Some(when) if when <= now => {
+                self.remove(key);
+                true
+            }
+            _ => match self.hash_expires.get(key) {
+                Some(when) if when <= now => self.expire_fields(key, now),
+                _ => false,
+            },
+        }
+    }
+
+    /// Removes the expired fields of the hash, and tells whether the hash has been
+    /// removed as no field is left.
+    fn expire_fields(&mut self, key: &[u8], now: u64) -> bool {
+        if let Some(Value::Hash(hash)) = self.dict.get_mut(key) {
+            hash.expire_fields(now);
+            if hash.is_empty() {
+                self.remove(key);
+                return true;
+            }
+        }
+        self.sync_field_expires(key);
+        false
+    }
+
+    /// Updates the index of hashes with volatile fields after the expire times of the
+    /// fields of the hash have changed. Hash commands setting expire times on fields
+    /// call it, removing fields or their expire times may leave the hash indexed at an
+    /// earlier time, which is only looked at again for nothing.
+    pub fn sync_field_expires(&mut self, key: &[u8]) {
+        let next = match self.dict.get_key_value(key) {
+            Some((key, Value::Hash(hash))) => hash.next_expire().map(|when| (key.clone(), when)),
+            _ => None,
+        };
+        match next {
+            Some((key, when)) => self.hash_expires.insert(key, when),
+            None => {
+                self.hash_expires.remove(key);
+            }
+        }
+    }
+
+    /// Looks the key up for reading. Expired keys are removed on access, so they are
+    /// never seen by commands.
+    pub fn lookup(&mut self, key: &[u8]) -> Option<&Value> {
+        self.expire_if_needed(key);
+        self.dict.get(key)
+    }
+
+    pub fn lookup_mut(&mut self, key: &[u8]) -> Option<&mut Value> {
+        self.expire_if_needed(key);
+        self.dict.get_mut(key)
+    }
+
+    /// Looks several keys up for reading at once, for commands combining the values of
+    /// their keys. Expired keys are removed first, as [`Db::lookup`] does.
+    pub fn lookup_many(&mut self, keys: &[Bytes]) -> Vec<Option<&Value>> {
+        for key in keys {
+            self.expire_if_needed(key);
+        }
+        keys.iter().map(|key| self.dict.get(key)).collect()
+    }
+
+    pub fn exists(&mut self, key: &[u8]) -> bool {
+        self.lookup(key).is_some()
+    }
+
+    /// Sets the value of the key. The expire time of the key is cleared, unless
+    /// `keep_ttl` is set.
+    pub fn set(&mut self, key: Bytes, value: Value, keep_ttl: bool) {
+        if !keep_ttl {
+            self.expires.remove(&key);
+        }
+        self.signal_ready(&key, &value);
+        self.dict.insert(key.clone(), value);
+        self.sync_field_expires(&key);
+    }
+
+    /// Signals the key as ready when the value may serve clients blocked on it. Values
+    /// which may do so are always new, as lists and sorted sets are never left empty.
+    fn signal_ready(&mut self, key: &Bytes, value: &Value) {
+        if matches!(value, Value::List(_) | Value::ZSet(_)) {
+            self.ready_keys.push(key.clone());
+        }
+    }
+
+    /// Signals the key as ready once entries have been added to the stream it holds,
+    /// which may be new or not, for clients blocked by XREADGROUP.
+    pub fn signal_stream_ready(&mut self, key: &Bytes) {
+        self.ready_keys.push(key.clone());
+    }
+
+    /// Deletes the key, and tells whether it existed. An expired key does not count.
+    pub fn delete(&mut self, key: &[u8]) -> bool {
+        !self.expire_if_needed(key) && self.remove(key).is_some()
+    }
+
+    /// Removes all the keys. The old content is returned, so that it may be dropped
+    /// somewhere else, as freeing a big keyspace takes a while.
+    pub fn flush(&mut self) -> Db {
+        std::mem::replace(self, Db::with_limits(self.limits))
+    }
+
+    /// Removes the key, along with its expire time, so that it may be moved to another
+    /// database with [`Db::insert_entry`].
+    pub fn take_entry(&mut self, key: &[u8]) -> Option<(Bytes, Value, Option<u64>)> {
+        self.expire_if_needed(key);
+        let (key, value) = self.dict.remove_entry(key)?;
+        let when = self.expires.remove(&key);
+        self.hash_expires.remove(&key);
+        Some((key, value, when))
+    }
+
+    pub fn insert_entry(&mut self, key: Bytes, value: Value, when: Option<u64>) {
+        if let Some(when) = when {
+            self.expires.insert(key.clone(), when);
+        }
+        self.signal_ready(&key, &value);
+        self.dict.insert(key.clone(), value);
+        self.sync_field_expires(&key);
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Value> {
+        self.expires.remove(key);
+        self.hash_expires.remove(key);
+        self.dict.remove(key)
+    }
+
+    /// Sets the unix time in milliseconds at which an existing key expires. A time in
+    /// the past deletes the key right away.
+    pub fn set_expire(&mut self, key: &[u8], when: u64) {
+        if when <= now_ms() {
+            self.remove(key);
+            return;
+        }
+        if let Some((key, _)) = self.dict.get_key_value(key) {
+            self.expires.insert(key.clone(), when);
+        }
+    }
+
+    pub fn get_expire(&self, key: &[u8]) -> Option<u64> {
+        self.expires.get(key)
+    }
+
+    /// Makes the key persistent, and tells whether it had an expire time.
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        self.expires.remove(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.dict.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dict.is_empty()
+    }
+
+    /// Rehashes the dict of the keys for a millisecond if it is rehashing, or else the
+    /// dict of the expire times. Tells whether there was anything to rehash.
+    fn incrementally_rehash(&mut self) -> bool {
+        if self.dict.is_rehashing() {
+            self.dict.rehash_for(1);
+        } else if self.expires.map.is_rehashing() {
+            self.expires.map.rehash_for(1);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Number of keys with an expire time.
+    pub fn volatile_len(&self) -> usize {
+        self.expires.len()
+    }
+
+    /// Samples random volatile keys and removes the expired ones. The sampling is
+    /// repeated while a good share of the sampled keys are found expired, as many more
+    /// are likely to be, until `budget_ms` milliseconds have elapsed. Hashes with
+    /// volatile fields are then sampled the same way, with the time left.
+    /// Returns the number of keys removed.
+    pub fn active_expire_cycle(&mut self, budget_ms: u64) -> usize {
+        let start = now_ms();
+        let mut removed = 0;
+        loop {
+            let samples = ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP.min(self.expires.len());
+            if samples == 0 {
+                break;
+            }
+            let now = now_ms();
+            let mut expired = 0;
+            for _ in 0..samples {
+                let key = self.expires.random_key().unwrap().clone();
+                if self.expires.get(&key).unwrap() <= now {
+                    self.remove(&key);
+                    expired += 1;
+                }
+            }
+            removed += expired;
+            if expired * 100 <= samples * ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE
+                || now_ms() - start >= budget_ms
+            {
+                break;
+            }
+        }
+        loop {
+            let samples = ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP.min(self.hash_expires.len());
+            if samples == 0 || now_ms() - start >= budget_ms {
+                break;
+            }
+            let now = now_ms();
+            let mut expired = 0;
+            for _ in 0..samples {
+                let key = self.hash_expires.random_key().unwrap().clone();
+                if self.hash_expires.get(&key).unwrap() <= now {
+                    removed += self.expire_fields(&key, now) as usize;
+                    expired += 1;
+                }
+            }
+            if expired * 100 <= samples * ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE {
+                break;
+            }
+        }
+        removed
+    }
+}
+
+/// The databases of the server, which are numbered from zero. Each of them is an
+/// independent keyspace, selected per client with SELECT.
+#[derive(Debug)]
+pub struct Databases {
+    dbs: Vec<Db>,
+    /// Database where the next active expire cycle starts, so that all of them get their
+    /// turn even when the time budget runs out.
+    expire_cursor: usize,
+    blocked: BlockedClients,
+}
+
+impl Databases {
+    pub fn new(count: usize) -> Self {
+        Self::with_limits(count, EncodingLimits::default())
+    }
+
+    pub fn with_limits(count: usize, limits: EncodingLimits) -> Self {
+        Self {
+            dbs: (0..count.max(1)).map(|_| Db::with_limits(limits)).collect(),
+            expire_cursor: 0,
+            blocked: BlockedClients::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.dbs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dbs.is_empty()
+    }
+
+    /// Returns the database of the index, which must be in range.
+    pub fn get_mut(&mut self, index: usize) -> &mut Db {
+        &mut self.dbs[index]
+    }
+
+    /// Returns two distinct databases at once.
+    pub fn get_pair_mut(&mut self, a: usize, b: usize) -> (&mut Db, &mut Db) {
+        assert_ne!(a, b);
+        if a < b {
+            let (left, right) = self.dbs.split_at_mut(b);
+            (&mut left[a], &mut right[0])
+        } else {
+            let (left, right) = self.dbs.split_at_mut(a);
+            (&mut right[0], &mut left[b])
+        }
+    }
+
+    /// Swaps the content of two databases, so that clients see the keys of the other
+    /// one. Clients stay blocked on the database they have selected, and the keys they
+    /// wait for may now be ready.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.dbs.swap(a, b);
+        for index in [a, b] {
+            let keys: Vec<Bytes> = self.blocked.keys_of(index).cloned().collect();
+            self.dbs[index].ready_keys.extend(keys);
+        }
+    }
+
+    /// Removes all the keys of all the databases, returning the old content.
+    pub fn flush_all(&mut self) -> Vec<Db> {
+        self.dbs.iter_mut().map(|db| db.flush()).collect()
+    }
+
+    /// Blocks the client on the keys of the command in the database, until it is served
+    /// by [`Databases::serve_blocked`] or unblocked.
+    pub fn block(&mut self, id: u64, db: usize, cmd: BlockingCommand) -> ReplyReceiver {
+        self.blocked.block(id, db, cmd)
+    }
+
+    pub fn unblock(&mut self, id: u64) -> bool {
+        self.blocked.unblock(id)
+    }
+
+    /// Serves the clients blocked on the keys signaled as ready, which is done after
+    /// each command. Serving a client may make more keys ready, e.g. the destination of
+    /// BLMOVE, so this goes on until no key is ready.
+    pub fn serve_blocked(&mut self) {
+        loop {
+            let mut ready = vec![];
+            for (index, db) in self.dbs.iter_mut().enumerate() {
+                ready.extend(db.ready_keys.drain(..).map(|key| (index, key)));
+            }
+            if ready.is_empty() {
+                break;
+            }
+            if self.blocked.is_empty() {
+                continue;
+            }
+            for (index, key) in ready {
+                self.blocked.serve(index, &mut self.dbs[index], key);
+            }
+        }
+    }
+
+    /// Runs the active expire cycle on the databases in turn, until `budget_ms`
+    /// milliseconds have elapsed. Returns the number of keys removed.
+    pub fn active_expire_cycle(&mut self, budget_ms: u64) -> usize {
+        let start = now_ms();
+        let mut removed = 0;
+        for _ in 0..self.dbs.len() {
+            let elapsed = now_ms() - start;
+            if elapsed >= budget_ms {
+                break;
+            }
+            let index = self.expire_cursor;
+            self.expire_cursor = (index + 1) % self.dbs.len();
+            removed += self.dbs[index].active_expire_cycle(budget_ms - elapsed);
+        }
+        removed
+    }
+
+    /// Rehashes the dicts of the first database found rehashing for a millisecond, as
+    /// the server cron of redis does when `activerehashing` is on.
+    pub fn incrementally_rehash(&mut self) {
+        for db in &mut self.dbs {
+            if db.incrementally_rehash() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::{
+        object::{EncodingLimits, RedisHash, RedisString, Value},
+        util::now_ms,
+    };
+
+    use super::Db;
+
+    #[test]
+    fn active_expire_cycle() {
+        let mut db = Db::new();
+        let value = Value::String(RedisString::new(Bytes::from_static(b"v")));
+        for i in 0..1000 {
+            let key = Bytes::from(format!("key:{}", i));
+            db.set(key.clone(), value.clone(), false);
+            db.set_expire(&key, now_ms() + 1000);
+        }
+        db.set(Bytes::from_static(b"forever"), value.clone(), false);
+        for i in 0..1000 {
+            // expire them behind the back of the db, as if time had passed.
+            db.expires.insert(Bytes::from(format!("key:{}", i)), now_ms() - 1);
+        }
+        let mut removed = 0;
+        while db.volatile_len() > 0 {
+            removed += db.active_expire_cycle(25);
+        }
+        assert_eq!(removed, 1000);
+        assert_eq!(db.len(), 1);
+        assert!(db.exists(b"forever"));
+    }
+
+    #[test]
+    fn expires_index_stays_consistent() {
+        let mut db = Db::new();
+        let value = Value::String(RedisString::new(Bytes::from_static(b"v")));
+        for key in ["a", "b", "c"] {
+            db.set(Bytes::from(key), value.clone(), false);
+            db.set_expire(key.as_bytes(), now_ms() + 10_000);
+        }
+        assert!(db.persist(b"a"));
+        assert!(!db.persist(b"a"));
+        db.remove(b"c");
+        assert_eq!(db.volatile_len(), 1);
+        assert!(db.get_expire(b"b").is_some());
+        db.set(Bytes::from("b"), value, true);
+        assert!(db.get_expire(b"b").is_some());
+    }
+
+    #[test]
+    fn expiring_hash_fields() {
+        let mut db = Db::new();
+        let limits = EncodingLimits::default();
+        for i in 0..100 {
+            let mut hash = RedisHash::new();
+            hash.set(b"volatile", b"v", false, &limits);
+            hash.set_expire(b"volatile", now_ms() + 1000);
+            if i % 2 == 0 {
+                hash.set(b"forever", b"v", false, &limits);
+            }
+            let key = Bytes::from(format!("key:{}", i));
+            db.set(key.clone(), Value::Hash(hash), false);
+        }
+        // expire the fields behind the back of the db, as if time had passed.
+        for i in 0..100 {
+            let key = format!("key:{}", i);
+            let hash = db.dict.get_mut(key.as_bytes()).unwrap().as_hash_mut().unwrap();
+            hash.set_expire(b"volatile", now_ms() - 1);
+            db.sync_field_expires(key.as_bytes());
+        }
+        assert!(db.lookup(b"key:1").is_none());
+        assert_eq!(db.lookup(b"key:0").unwrap().as_hash().unwrap().len(), 1);
+        while db.len() > 50 || db.dict.values().any(|value| value.as_hash().unwrap().len() > 1) {
+            db.active_expire_cycle(25);
+        }
+        assert_eq!(db.hash_expires.len(), 0);
+    }
+}
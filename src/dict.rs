@@ -0,0 +1,495 @@
+//! The hash table of the keyspace, like the dict of redis.
+//!
+//! Keys are chained in buckets, and the number of buckets is a power of two. When the
+//! table grows or shrinks, the entries are not moved all at once, which would stall
+//! the server for as long as a big keyspace takes to rehash. A second table is made,
+//! and every write moves one bucket of the old table to the new one, until the old
+//! table is empty and replaced by the new one. Lookups look into both tables while
+//! rehashing. The server cron also rehashes for a while on each run, so a dict which
+//! is not written to does not stay with two tables.
+
+use std::{
+    borrow::Borrow,
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    time::Instant,
+};
+
+use crate::util::random;
+
+/// Number of buckets of a new table.
+const INITIAL_SIZE: usize = 4;
+/// A table is shrunk once less than one bucket in this many is used.
+const MIN_FILL: usize = 8;
+/// Number of buckets rehashed at once by [`Dict::rehash_for`], before the time is
+/// checked again.
+const REHASH_BATCH: usize = 100;
+
+#[derive(Debug, Clone)]
+struct Entry<K, V> {
+    hash: u64,
+    key: K,
+    value: V,
+    next: Option<Box<Entry<K, V>>>,
+}
+
+type Bucket<K, V> = Option<Box<Entry<K, V>>>;
+
+#[derive(Debug, Clone)]
+struct Table<K, V> {
+    buckets: Vec<Bucket<K, V>>,
+    used: usize,
+}
+
+impl<K, V> Table<K, V> {
+    fn with_size(size: usize) -> Self {
+        Self {
+            buckets: std::iter::repeat_with(|| None).take(size).collect(),
+            used: 0,
+        }
+    }
+
+    fn index(&self, hash: u64) -> usize {
+        hash as usize & (self.buckets.len() - 1)
+    }
+
+    fn push(&mut self, mut entry: Box<Entry<K, V>>) {
+        let index = self.index(entry.hash);
+        entry.next = self.buckets[index].take();
+        self.buckets[index] = Some(entry);
+        self.used += 1;
+    }
+}
+
+impl<K, V> Default for Table<K, V> {
+    fn default() -> Self {
+        Self {
+            buckets: Vec::new(),
+            used: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Dict<K, V> {
+    tables: [Table<K, V>; 2],
+    /// Next bucket of the first table to move to the second one, while rehashing.
+    rehash_index: Option<usize>,
+    hasher: RandomState,
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        Self {
+            tables: Default::default(),
+            rehash_index: None,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<K, V> Dict<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables[0].used + self.tables[1].used
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_rehashing(&self) -> bool {
+        self.rehash_index.is_some()
+    }
+
+    /// Iterates the entries, in no particular order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            dict: self,
+            table: 0,
+            bucket: 0,
+            entry: None,
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Picks a random entry, like `dictGetRandomKey` of redis: a random non-empty
+    /// bucket is found first, then a random entry of its chain. Tables are kept at
+    /// least 1/8 full, so this takes a few tries.
+    pub fn random_entry(&self) -> Option<(&K, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        let [first, second] = &self.tables;
+        let head = loop {
+            let bucket = match self.rehash_index {
+                // buckets of the first table before the rehash index are all empty.
+                Some(index) => {
+                    let span = first.buckets.len() + second.buckets.len() - index;
+                    let i = index + random() as usize % span;
+                    match first.buckets.get(i) {
+                        Some(bucket) => bucket,
+                        None => &second.buckets[i - first.buckets.len()],
+                    }
+                }
+                None => &first.buckets[first.index(random())],
+            };
+            if let Some(head) = bucket {
+                break head;
+            }
+        };
+        let chain = std::iter::successors(Some(&**head), |entry| entry.next.as_deref());
+        let entry = chain.clone().nth(random() as usize % chain.count()).unwrap();
+        Some((&entry.key, &entry.value))
+    }
+
+    /// Moves up to `steps` buckets of the first table to the second one, visiting no
+    /// more than ten empty buckets per step. Tells whether the rehashing is still in
+    /// progress.
+    pub fn rehash(&mut self, steps: usize) -> bool {
+        let mut index = match self.rehash_index {
+            Some(index) => index,
+            None => return false,
+        };
+        let [first, second] = &mut self.tables;
+        let mut empty_visits = steps * 10;
+        for _ in 0..steps {
+            if first.used == 0 {
+                break;
+            }
+            while first.buckets[index].is_none() {
+                index += 1;
+                empty_visits -= 1;
+                if empty_visits == 0 {
+                    self.rehash_index = Some(index);
+                    return true;
+                }
+            }
+            let mut next = first.buckets[index].take();
+            while let Some(mut entry) = next {
+                next = entry.next.take();
+                first.used -= 1;
+                second.push(entry);
+            }
+            index += 1;
+        }
+        if first.used == 0 {
+            self.tables[0] = std::mem::take(&mut self.tables[1]);
+            self.rehash_index = None;
+            return false;
+        }
+        self.rehash_index = Some(index);
+        true
+    }
+
+    /// Rehashes for about `ms` milliseconds, like the server cron of redis does for the
+    /// dicts of the keyspace. Tells whether the rehashing is still in progress.
+    pub fn rehash_for(&mut self, ms: u64) -> bool {
+        let start = Instant::now();
+        while self.rehash(REHASH_BATCH) {
+            if start.elapsed().as_millis() as u64 >= ms {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Rehashes a single bucket, as every write does while rehashing.
+    fn rehash_step(&mut self) {
+        if self.is_rehashing() {
+            self.rehash(1);
+        }
+    }
+
+    /// Starts moving the entries to a table of `size` buckets, rounded up to a power of
+    /// two. A dict without buckets gets its table right away.
+    fn resize(&mut self, size: usize) {
+        let size = size.max(INITIAL_SIZE).next_power_of_two();
+        if size == self.tables[0].buckets.len() {
+            return;
+        }
+        if self.tables[0].buckets.is_empty() {
+            self.tables[0] = Table::with_size(size);
+            return;
+        }
+        self.tables[1] = Table::with_size(size);
+        self.rehash_index = Some(0);
+    }
+
+    /// Grows the table once it has as many entries as buckets.
+    fn expand_if_needed(&mut self) {
+        let table = &self.tables[0];
+        if !self.is_rehashing() && table.used >= table.buckets.len() {
+            self.resize(table.used + 1);
+        }
+    }
+
+    /// Shrinks the table once it is less than 1/8 full.
+    fn shrink_if_needed(&mut self) {
+        let table = &self.tables[0];
+        if !self.is_rehashing()
+            && table.buckets.len() > INITIAL_SIZE
+            && table.used * MIN_FILL <= table.buckets.len()
+        {
+            self.resize(table.used);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    fn hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<&Entry<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_empty() {
+            return None;
+        }
+        let hash = self.hash(key);
+        let tables = if self.is_rehashing() { 2 } else { 1 };
+        self.tables[..tables].iter().find_map(|table| {
+            let mut entry = table.buckets[table.index(hash)].as_deref();
+            while let Some(e) = entry {
+                if e.hash == hash && e.key.borrow() == key {
+                    return Some(e);
+                }
+                entry = e.next.as_deref();
+            }
+            None
+        })
+    }
+
+    fn find_mut<Q>(&mut self, key: &Q) -> Option<&mut Entry<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_empty() {
+            return None;
+        }
+        let hash = self.hash(key);
+        let tables = if self.is_rehashing() { 2 } else { 1 };
+        self.tables[..tables].iter_mut().find_map(|table| {
+            let index = table.index(hash);
+            let mut entry = table.buckets[index].as_deref_mut();
+            while let Some(e) = entry {
+                if e.hash == hash && e.key.borrow() == key {
+                    return Some(e);
+                }
+                entry = e.next.as_deref_mut();
+            }
+            None
+        })
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).map(|entry| &entry.value)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).map(|entry| (&entry.key, &entry.value))
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash_step();
+        self.find_mut(key).map(|entry| &mut entry.value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    /// Inserts the entry, returning the old value of the key if it existed. New keys
+    /// go to the second table while rehashing, so the first one only gets emptier.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.rehash_step();
+        if let Some(entry) = self.find_mut(&key) {
+            return Some(std::mem::replace(&mut entry.value, value));
+        }
+        self.expand_if_needed();
+        let hash = self.hash(&key);
+        let table = if self.is_rehashing() { 1 } else { 0 };
+        self.tables[table].push(Box::new(Entry {
+            hash,
+            key,
+            value,
+            next: None,
+        }));
+        None
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash_step();
+        if self.is_empty() {
+            return None;
+        }
+        let hash = self.hash(key);
+        let tables = if self.is_rehashing() { 2 } else { 1 };
+        let entry = self.tables[..tables].iter_mut().find_map(|table| {
+            let index = table.index(hash);
+            let mut link = &mut table.buckets[index];
+            while link
+                .as_ref()
+                .is_some_and(|e| e.hash != hash || e.key.borrow() != key)
+            {
+                link = &mut link.as_mut().unwrap().next;
+            }
+            let mut entry = link.take()?;
+            *link = entry.next.take();
+            table.used -= 1;
+            Some(entry)
+        })?;
+        self.shrink_if_needed();
+        Some((entry.key, entry.value))
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for Dict<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut dict = Self::new();
+        for (key, value) in iter {
+            dict.insert(key, value);
+        }
+        dict
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> PartialEq for Dict<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key).is_some_and(|v| v == value))
+    }
+}
+
+impl<K: Hash + Eq, V: Eq> Eq for Dict<K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Dict<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Iterator over the entries of a [`Dict`], the first table then the second one.
+pub struct Iter<'a, K, V> {
+    dict: &'a Dict<K, V>,
+    table: usize,
+    bucket: usize,
+    entry: Option<&'a Entry<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entry {
+                self.entry = entry.next.as_deref();
+                return Some((&entry.key, &entry.value));
+            }
+            let buckets = &self.dict.tables[self.table].buckets;
+            if self.bucket < buckets.len() {
+                self.entry = buckets[self.bucket].as_deref();
+                self.bucket += 1;
+            } else if self.table == 0 && self.dict.is_rehashing() {
+                self.table = 1;
+                self.bucket = 0;
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::Dict;
+
+    #[test]
+    fn rehashed_incrementally() {
+        let mut dict = Dict::new();
+        for i in 0..1000 {
+            assert_eq!(dict.insert(i, i * 2), None);
+        }
+        assert_eq!(dict.insert(7, 0), Some(14));
+        assert_eq!(dict.len(), 1000);
+        // keys are found in both tables while rehashing.
+        while !dict.is_rehashing() {
+            dict.insert(dict.len(), 0);
+        }
+        assert!((0..dict.len()).all(|i| dict.contains_key(&i)));
+        assert_eq!(dict.iter().count(), dict.len());
+        assert!(!dict.rehash_for(100));
+        assert!((0..dict.len()).all(|i| dict.contains_key(&i)));
+        assert_eq!(dict.get(&1), Some(&2));
+    }
+
+    #[test]
+    fn shrunk_on_removal() {
+        let mut dict: Dict<u64, ()> = (0..1000).map(|i| (i, ())).collect();
+        let size = dict.tables[0].buckets.len();
+        for i in 0..990 {
+            assert!(dict.remove(&i).is_some());
+        }
+        assert_eq!(dict.remove(&0), None);
+        dict.rehash_for(100);
+        assert!(dict.tables[0].buckets.len() < size);
+        assert_eq!(dict.len(), 10);
+        assert_eq!(dict.remove_entry(&995), Some((995, ())));
+        assert_eq!(dict, (990..1000).filter(|&i| i != 995).map(|i| (i, ())).collect());
+    }
+
+    #[test]
+    fn random_entries() {
+        let mut dict = Dict::new();
+        assert_eq!(dict.random_entry(), None);
+        for i in 0..100 {
+            dict.insert(i, ());
+        }
+        let picked: HashSet<_> = (0..10_000).map(|_| *dict.random_entry().unwrap().0).collect();
+        assert_eq!(picked.len(), 100);
+    }
+}
@@ -1,17 +1,31 @@
 use std::{error, fmt::Display, io, string::FromUtf8Error};
 
+use bytes::Bytes;
+
+use crate::protocol::Resp3Piece;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// 系统错误。一般只包含可能引起系统宕机的错误。
+/// Errors of a single command are carried by `Command`, and are replied to the client
+/// without affecting the connection.
 #[derive(Debug)]
 pub enum Error {
     IO(io::Error),
     BrokenProtocol(String),
     Unsupported(String),
     Encode(String),
+    Command(CommandError),
 
     EOF,
-    NotReady,
+}
+
+impl Error {
+    /// Fatal errors leave the connection in an unknown state, so it is closed after the
+    /// error is replied.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Error::IO(_) | Error::BrokenProtocol(_) | Error::EOF)
+    }
 }
 
 impl Display for Error {
@@ -33,3 +47,123 @@ impl From<FromUtf8Error> for Error {
         Error::Encode("invalid utf8 format".into())
     }
 }
+
+impl From<CommandError> for Error {
+    fn from(e: CommandError) -> Self {
+        Error::Command(e)
+    }
+}
+
+/// Errors replied to a command. Each kind is replied with the prefix used by redis, so
+/// that clients can tell them apart, e.g. `-WRONGTYPE Operation against a key holding
+/// the wrong kind of value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// Generic error with the `ERR` prefix.
+    Err(String),
+    WrongType,
+    NoPerm(String),
+    WrongPass,
+    NoProto,
+    ExecAbort,
+    /// A consumer group of the name already exists.
+    BusyGroup,
+    /// The key or the consumer group does not exist.
+    NoGroup(String),
+}
+
+impl CommandError {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            CommandError::Err(_) => "ERR",
+            CommandError::WrongType => "WRONGTYPE",
+            CommandError::NoPerm(_) => "NOPERM",
+            CommandError::WrongPass => "WRONGPASS",
+            CommandError::NoProto => "NOPROTO",
+            CommandError::ExecAbort => "EXECABORT",
+            CommandError::BusyGroup => "BUSYGROUP",
+            CommandError::NoGroup(_) => "NOGROUP",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            CommandError::Err(msg) | CommandError::NoPerm(msg) | CommandError::NoGroup(msg) => {
+                msg.clone()
+            }
+            CommandError::WrongType => {
+                "Operation against a key holding the wrong kind of value".into()
+            }
+            CommandError::WrongPass => {
+                "invalid username-password pair or user is disabled.".into()
+            }
+            CommandError::NoProto => "unsupported protocol version".into(),
+            CommandError::ExecAbort => {
+                "Transaction discarded because of previous errors.".into()
+            }
+            CommandError::BusyGroup => "Consumer Group name already exists".into(),
+        }
+    }
+
+    pub fn syntax() -> Self {
+        CommandError::Err("syntax error".into())
+    }
+
+    pub fn not_integer() -> Self {
+        CommandError::Err("value is not an integer or out of range".into())
+    }
+
+    pub fn not_float() -> Self {
+        CommandError::Err("value is not a valid float".into())
+    }
+}
+
+impl From<&CommandError> for Resp3Piece {
+    /// Error replies are single lines, so line breaks of the message, which may come
+    /// from arguments of the client, are replaced by spaces as redis does.
+    fn from(e: &CommandError) -> Self {
+        let cause: Vec<u8> = e
+            .message()
+            .bytes()
+            .map(|c| if c == b'\r' || c == b'\n' { b' ' } else { c })
+            .collect();
+        Resp3Piece::Error {
+            typ: Bytes::from_static(e.prefix().as_bytes()),
+            cause: cause.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::protocol::Resp3Piece;
+
+    use super::CommandError;
+
+    #[test]
+    fn error_replies() {
+        let reply = |e: CommandError| match Resp3Piece::from(&e) {
+            Resp3Piece::Error { typ, cause } => {
+                let mut line = typ.to_vec();
+                line.push(b' ');
+                line.extend_from_slice(&cause);
+                Bytes::from(line)
+            }
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            reply(CommandError::WrongType),
+            "WRONGTYPE Operation against a key holding the wrong kind of value"
+        );
+        assert_eq!(
+            reply(CommandError::NoGroup("No such consumer group 'g'".into())),
+            "NOGROUP No such consumer group 'g'"
+        );
+        assert_eq!(
+            reply(CommandError::Err("unknown command 'a\r\nb'".into())),
+            "ERR unknown command 'a  b'"
+        );
+    }
+}
@@ -1,10 +1,16 @@
+pub mod blocking;
 pub mod client;
+pub mod command;
 pub mod config;
 pub mod conn;
+pub mod db;
+pub mod dict;
 pub mod error;
+pub mod object;
 pub mod protocol;
+pub mod rax;
 pub mod server;
-pub mod command;
+pub mod util;
 
 // fn main() {
 //     let conf = Config{ addr: "".to_string(), };
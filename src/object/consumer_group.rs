@@ -0,0 +1,174 @@
+//! Consumer groups of streams, like the `streamCG` of redis.
+//!
+//! A group remembers the last ID delivered to its consumers, and the entries delivered
+//! but not acknowledged yet: the pending entries list, or PEL. Each pending entry is
+//! owned by a consumer, which also lists the entries it owns, so that both the PEL of
+//! the group and the one of a consumer are ordered by ID.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bytes::Bytes;
+
+use super::StreamId;
+
+/// An entry delivered to a consumer and not acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    /// Unix time in milliseconds of the last delivery.
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Consumer {
+    /// Unix time in milliseconds at which the consumer last interacted with the group.
+    pub seen_time: u64,
+    /// Unix time in milliseconds at which the consumer was last delivered or claimed
+    /// entries, if ever.
+    pub active_time: Option<u64>,
+    pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    /// IDs of the entries the consumer owns.
+    pub fn pending(&self) -> &BTreeSet<StreamId> {
+        &self.pending
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    /// ID of the last entry delivered to the consumers of the group.
+    pub last_id: StreamId,
+    /// Number of entries of the stream the group has read, unless it is unknown, used
+    /// to tell the lag of the group.
+    pub entries_read: Option<u64>,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<Bytes, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// The PEL of the group.
+    pub fn pending(&self) -> &BTreeMap<StreamId, PendingEntry> {
+        &self.pending
+    }
+
+    pub fn consumers(&self) -> &BTreeMap<Bytes, Consumer> {
+        &self.consumers
+    }
+
+    /// Creates the consumer, and tells whether it is new.
+    pub fn create_consumer(&mut self, name: &[u8], now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        let consumer = Consumer {
+            seen_time: now,
+            ..Consumer::default()
+        };
+        self.consumers.insert(Bytes::copy_from_slice(name), consumer);
+        true
+    }
+
+    /// Notes that the consumer interacted with the group, creating it if needed.
+    pub fn seen(&mut self, name: &[u8], now: u64) -> &mut Consumer {
+        self.create_consumer(name, now);
+        let consumer = self.consumers.get_mut(name).unwrap();
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Deletes the consumer along with the entries it owns, returning their number.
+    pub fn delete_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    /// Gives the entry to the consumer, which must exist, taking it from the consumer
+    /// which owned it if any.
+    pub fn assign(
+        &mut self,
+        id: StreamId,
+        name: &Bytes,
+        delivery_time: u64,
+        delivery_count: u64,
+    ) {
+        let entry = PendingEntry {
+            consumer: name.clone(),
+            delivery_time,
+            delivery_count,
+        };
+        if let Some(old) = self.pending.insert(id, entry) {
+            if let Some(owner) = self.consumers.get_mut(&old.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        self.consumers.get_mut(name).unwrap().pending.insert(id);
+    }
+
+    /// Notes that the pending entry has been delivered again to its owner.
+    pub fn redeliver(&mut self, id: StreamId, now: u64) {
+        if let Some(entry) = self.pending.get_mut(&id) {
+            entry.delivery_time = now;
+            entry.delivery_count += 1;
+        }
+    }
+
+    /// Removes the entry from the PEL, and tells whether it was there.
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        match self.pending.remove(&id) {
+            Some(entry) => {
+                if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+                    owner.pending.remove(&id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::object::StreamId;
+
+    use super::ConsumerGroup;
+
+    #[test]
+    fn pending_entries_change_owner() {
+        let mut group = ConsumerGroup::new(StreamId::MIN, Some(0));
+        let (alice, bob) = (Bytes::from("alice"), Bytes::from("bob"));
+        assert!(group.create_consumer(&alice, 10));
+        assert!(!group.create_consumer(&alice, 20));
+        group.seen(&bob, 30);
+        let id = |seq| StreamId::new(1, seq);
+        group.assign(id(1), &alice, 40, 1);
+        group.assign(id(2), &alice, 40, 1);
+        group.assign(id(1), &bob, 50, 2);
+        assert_eq!(group.pending()[&id(1)].consumer, bob);
+        assert_eq!(group.consumers()[&alice].pending().len(), 1);
+        assert_eq!(group.consumers()[&bob].seen_time, 30);
+        group.redeliver(id(2), 60);
+        assert_eq!(group.pending()[&id(2)].delivery_count, 2);
+        assert!(group.ack(id(2)));
+        assert!(!group.ack(id(2)));
+        assert_eq!(group.delete_consumer(&bob), Some(1));
+        assert_eq!(group.delete_consumer(&bob), None);
+        assert!(group.pending().is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bytes::Bytes;
+
+use crate::{dict::Dict, util::random};
+
+use super::{listpack::Listpack, EncodingLimits};
+
+/// A hash value. Small hashes are kept in a listpack, fields and values alternating,
+/// which is compact and fast enough to scan. Once a hash has more fields than
+/// `hash-max-listpack-entries`, or a field or value longer than
+/// `hash-max-listpack-value`, it is converted for good to a hash table, the same
+/// [`Dict`] as the keyspace.
+///
+/// Fields may expire on their own. Their expire times are kept aside, also ordered by
+/// time so that the expired fields are found without scanning the hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedisHash {
+    entries: Entries,
+    /// Unix time in milliseconds at which volatile fields expire.
+    expires: HashMap<Bytes, u64>,
+    by_time: BTreeSet<(u64, Bytes)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entries {
+    Listpack(Listpack),
+    Table(Dict<Bytes, Bytes>),
+}
+
+impl Default for Entries {
+    fn default() -> Self {
+        Self::Listpack(Listpack::new())
+    }
+}
+
+impl RedisHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.entries {
+            Entries::Listpack(lp) => lp.len() / 2,
+            Entries::Table(table) => table.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as replied by OBJECT ENCODING. Listpacks with volatile
+    /// fields are `listpackex` for redis.
+    pub fn encoding(&self) -> &'static str {
+        match &self.entries {
+            Entries::Listpack(_) if self.expires.is_empty() => "listpack",
+            Entries::Listpack(_) => "listpackex",
+            Entries::Table(_) => "hashtable",
+        }
+    }
+
+    /// Index in the listpack of the entry holding the field.
+    fn find(lp: &Listpack, field: &[u8]) -> Option<usize> {
+        lp.iter().step_by(2).position(|f| f == field).map(|i| i * 2)
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&[u8]> {
+        match &self.entries {
+            Entries::Listpack(lp) => Self::find(lp, field).and_then(|i| lp.get(i + 1)),
+            Entries::Table(table) => table.get(field).map(|value| &value[..]),
+        }
+    }
+
+    pub fn contains(&self, field: &[u8]) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// Sets the value of the field, and tells whether the field is new. The expire time
+    /// of the field is cleared, unless `keep_ttl` is set.
+    pub fn set(
+        &mut self,
+        field: &[u8],
+        value: &[u8],
+        keep_ttl: bool,
+        limits: &EncodingLimits,
+    ) -> bool {
+        if !keep_ttl {
+            self.persist(field);
+        }
+        if let Entries::Listpack(lp) = &mut self.entries {
+            match Self::find(lp, field) {
+                Some(i) if value.len() <= limits.hash_max_listpack_value => {
+                    lp.replace(i + 1, value);
+                    return false;
+                }
+                None if lp.len() / 2 < limits.hash_max_listpack_entries
+                    && field.len() <= limits.hash_max_listpack_value
+                    && value.len() <= limits.hash_max_listpack_value =>
+                {
+                    lp.push_back(field);
+                    lp.push_back(value);
+                    return true;
+                }
+                _ => self.convert(),
+            }
+        }
+        match &mut self.entries {
+            Entries::Table(table) => table
+                .insert(Bytes::copy_from_slice(field), Bytes::copy_from_slice(value))
+                .is_none(),
+            Entries::Listpack(_) => unreachable!(),
+        }
+    }
+
+    /// Removes the field, and tells whether it existed.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        self.persist(field);
+        match &mut self.entries {
+            Entries::Listpack(lp) => match Self::find(lp, field) {
+                Some(i) => {
+                    lp.remove_range(i, 2);
+                    true
+                }
+                None => false,
+            },
+            Entries::Table(table) => table.remove(field).is_some(),
+        }
+    }
+
+    /// Converts a listpack encoded hash to a hash table.
+    fn convert(&mut self) {
+        if let Entries::Listpack(lp) = &self.entries {
+            let table = Self::pairs(lp)
+                .map(|(f, v)| (Bytes::copy_from_slice(f), Bytes::copy_from_slice(v)))
+                .collect();
+            self.entries = Entries::Table(table);
+        }
+    }
+
+    fn pairs(lp: &Listpack) -> impl Iterator<Item = (&[u8], &[u8])> {
+        let mut entries = lp.iter();
+        std::iter::from_fn(move || Some((entries.next()?, entries.next()?)))
+    }
+
+    /// Iterates the fields along with their values. Listpack encoded hashes keep the
+    /// order fields were added in, hash tables have no order.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        match &self.entries {
+            Entries::Listpack(lp) => Box::new(Self::pairs(lp)),
+            Entries::Table(table) => Box::new(table.iter().map(|(f, v)| (&f[..], &v[..]))),
+        }
+    }
+
+    /// Returns a function picking a random field along with its value on each call,
+    /// which must not be called on an empty hash. Hash tables pick in O(1), and a
+    /// listpack is scanned once for all the picks, as it is small.
+    pub fn random_picker<'a>(&'a self) -> impl FnMut() -> (&'a [u8], &'a [u8]) + 'a {
+        let pairs: Vec<_> = match &self.entries {
+            Entries::Listpack(lp) => Self::pairs(lp).collect(),
+            Entries::Table(_) => vec![],
+        };
+        move || match &self.entries {
+            Entries::Listpack(_) => pairs[random() as usize % pairs.len()],
+            Entries::Table(table) => {
+                let (field, value) = table.random_entry().unwrap();
+                (&field[..], &value[..])
+            }
+        }
+    }
+
+    /// Unix time in milliseconds at which the field expires.
+    pub fn get_expire(&self, field: &[u8]) -> Option<u64> {
+        self.expires.get(field).copied()
+    }
+
+    /// Sets the time at which an existing field expires.
+    pub fn set_expire(&mut self, field: &[u8], when: u64) {
+        let field = Bytes::copy_from_slice(field);
+        if let Some(old) = self.expires.insert(field.clone(), when) {
+            self.by_time.remove(&(old, field.clone()));
+        }
+        self.by_time.insert((when, field));
+    }
+
+    /// Makes the field persistent, and tells whether it had an expire time.
+    pub fn persist(&mut self, field: &[u8]) -> bool {
+        match self.expires.remove_entry(field) {
+            Some((field, when)) => {
+                self.by_time.remove(&(when, field));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Time at which the first volatile field expires.
+    pub fn next_expire(&self) -> Option<u64> {
+        self.by_time.first().map(|(when, _)| *when)
+    }
+
+    /// Removes the fields which have expired at `now`, returning how many.
+    pub fn expire_fields(&mut self, now: u64) -> usize {
+        let mut expired = 0;
+        while let Some((when, field)) = self.by_time.first().cloned() {
+            if when > now {
+                break;
+            }
+            self.remove(&field);
+            expired += 1;
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::object::EncodingLimits;
+
+    use super::RedisHash;
+
+    #[test]
+    fn converted_past_the_limits() {
+        let limits = EncodingLimits {
+            hash_max_listpack_entries: 4,
+            hash_max_listpack_value: 8,
+            ..EncodingLimits::default()
+        };
+        let mut hash = RedisHash::new();
+        for i in 0..4 {
+            assert!(hash.set(format!("f{}", i).as_bytes(), b"v", false, &limits));
+        }
+        assert!(!hash.set(b"f1", b"w", false, &limits));
+        assert!(hash.remove(b"f0"));
+        assert!(!hash.remove(b"f0"));
+        assert_eq!(hash.encoding(), "listpack");
+        assert_eq!(hash.iter().next(), Some((&b"f1"[..], &b"w"[..])));
+        assert!(!hash.set(b"f2", b"longer than 8", false, &limits));
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.get(b"f2"), Some(&b"longer than 8"[..]));
+        assert_eq!(hash.len(), 3);
+
+        let mut hash = RedisHash::new();
+        for i in 0..5 {
+            hash.set(format!("f{}", i).as_bytes(), b"v", false, &limits);
+        }
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.len(), 5);
+        assert!(hash.contains(b"f0") && hash.contains(b"f4"));
+    }
+
+    #[test]
+    fn expiring_fields() {
+        let limits = EncodingLimits::default();
+        let mut hash = RedisHash::new();
+        for field in ["a", "b", "c"] {
+            hash.set(field.as_bytes(), b"v", false, &limits);
+        }
+        hash.set_expire(b"a", 300);
+        hash.set_expire(b"b", 100);
+        hash.set_expire(b"b", 200);
+        assert_eq!(hash.encoding(), "listpackex");
+        assert_eq!(hash.next_expire(), Some(200));
+        hash.set(b"a", b"w", true, &limits);
+        assert_eq!(hash.get_expire(b"a"), Some(300));
+        assert_eq!(hash.expire_fields(250), 1);
+        assert!(!hash.contains(b"b"));
+        hash.set(b"a", b"x", false, &limits);
+        assert_eq!(hash.next_expire(), None);
+        hash.set_expire(b"c", 10);
+        assert!(hash.persist(b"c"));
+        assert!(!hash.persist(b"c"));
+        hash.set_expire(b"c", 10);
+        assert!(hash.remove(b"c"));
+        assert_eq!(hash.next_expire(), None);
+        assert_eq!(hash.expire_fields(u64::MAX), 0);
+        assert_eq!(hash.len(), 1);
+    }
+}
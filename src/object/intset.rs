@@ -0,0 +1,168 @@
+//! A sorted set of integers in a single buffer, like the intset of redis.
+//!
+//! All the integers are stored little endian with the same width, the smallest of 2, 4
+//! and 8 bytes which fits every one of them. The set is upgraded to a larger width when
+//! an integer which does not fit is added, and never downgraded. Lookups are binary
+//! searches.
+
+use std::{cmp::Ordering, fmt};
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct IntSet {
+    buf: Vec<u8>,
+    /// Width in bytes of each integer.
+    width: usize,
+}
+
+impl Default for IntSet {
+    fn default() -> Self {
+        Self {
+            buf: vec![],
+            width: 2,
+        }
+    }
+}
+
+/// Smallest width which fits the integer.
+fn width_of(n: i64) -> usize {
+    if i16::try_from(n).is_ok() {
+        2
+    } else if i32::try_from(n).is_ok() {
+        4
+    } else {
+        8
+    }
+}
+
+impl IntSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len() / self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<i64> {
+        let start = index * self.width;
+        let bytes = self.buf.get(start..start + self.width)?;
+        Some(match self.width {
+            2 => i16::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            4 => i32::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(bytes.try_into().unwrap()),
+        })
+    }
+
+    /// Index of the integer, or the index where it would be inserted.
+    fn search(&self, n: i64) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.get(mid).unwrap().cmp(&n) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
+    pub fn contains(&self, n: i64) -> bool {
+        width_of(n) <= self.width && self.search(n).is_ok()
+    }
+
+    /// Adds the integer, and tells whether it is new.
+    pub fn insert(&mut self, n: i64) -> bool {
+        if width_of(n) > self.width {
+            // the integer is out of the range of all the others, so it goes to one end.
+            self.upgrade(width_of(n));
+            let bytes = &n.to_le_bytes()[..self.width];
+            if n < 0 {
+                self.buf.splice(0..0, bytes.iter().copied());
+            } else {
+                self.buf.extend_from_slice(bytes);
+            }
+            return true;
+        }
+        match self.search(n) {
+            Ok(_) => false,
+            Err(index) => {
+                let start = index * self.width;
+                let bytes = &n.to_le_bytes()[..self.width];
+                self.buf.splice(start..start, bytes.iter().copied());
+                true
+            }
+        }
+    }
+
+    /// Removes the integer, and tells whether it was there.
+    pub fn remove(&mut self, n: i64) -> bool {
+        if width_of(n) > self.width {
+            return false;
+        }
+        match self.search(n) {
+            Ok(index) => {
+                let start = index * self.width;
+                self.buf.drain(start..start + self.width);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Rewrites the integers with a larger width.
+    fn upgrade(&mut self, width: usize) {
+        let mut buf = Vec::with_capacity(self.len() * width);
+        for n in self.iter() {
+            buf.extend_from_slice(&n.to_le_bytes()[..width]);
+        }
+        self.buf = buf;
+        self.width = width;
+    }
+
+    /// Iterates the integers in ascending order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = i64> + ExactSizeIterator + '_ {
+        (0..self.len()).map(|i| self.get(i).unwrap())
+    }
+}
+
+impl fmt::Debug for IntSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntSet;
+
+    #[test]
+    fn sorted_and_upgraded() {
+        let mut set = IntSet::new();
+        assert!(set.insert(5));
+        assert!(set.insert(-3));
+        assert!(set.insert(100));
+        assert!(!set.insert(5));
+        assert_eq!(set.width, 2);
+        assert!(set.insert(70_000));
+        assert_eq!(set.width, 4);
+        assert!(set.insert(i64::MIN));
+        assert_eq!(set.width, 8);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![i64::MIN, -3, 5, 100, 70_000]);
+        assert!(set.contains(70_000) && !set.contains(6));
+        assert!(set.remove(-3));
+        assert!(!set.remove(-3));
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.get(1), Some(5));
+        assert_eq!(set.get(4), None);
+
+        let mut set = IntSet::new();
+        set.insert(1);
+        assert!(!set.contains(1 << 40));
+        assert!(!set.remove(1 << 40));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+
+use super::listpack::Listpack;
+
+/// Max size in bytes of a node, as `list-max-listpack-size -2` of redis. An element
+/// bigger than that gets a node of its own.
+const NODE_MAX_SIZE: usize = 8 * 1024;
+
+/// A list value, like the quicklist of redis: a deque of listpack nodes, so that small
+/// elements are packed together instead of each of them being allocated on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuickList {
+    nodes: VecDeque<Listpack>,
+    len: usize,
+}
+
+impl QuickList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Name of the encoding, as replied by OBJECT ENCODING. A list fitting in a single
+    /// node is a plain listpack for redis.
+    pub fn encoding(&self) -> &'static str {
+        if self.nodes.len() <= 1 {
+            "listpack"
+        } else {
+            "quicklist"
+        }
+    }
+
+    fn fits(node: &Listpack, data: &[u8]) -> bool {
+        node.bytes() + Listpack::entry_size(data) <= NODE_MAX_SIZE
+    }
+
+    pub fn push_front(&mut self, data: &[u8]) {
+        match self.nodes.front_mut() {
+            Some(node) if Self::fits(node, data) => node.push_front(data),
+            _ => {
+                let mut node = Listpack::new();
+                node.push_back(data);
+                self.nodes.push_front(node);
+            }
+        }
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, data: &[u8]) {
+        match self.nodes.back_mut() {
+            Some(node) if Self::fits(node, data) => node.push_back(data),
+            _ => {
+                let mut node = Listpack::new();
+                node.push_back(data);
+                self.nodes.push_back(node);
+            }
+        }
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        let node = self.nodes.front_mut()?;
+        let data = node.pop_front()?;
+        if node.is_empty() {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        Some(data)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Vec<u8>> {
+        let node = self.nodes.back_mut()?;
+        let data = node.pop_back()?;
+        if node.is_empty() {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        Some(data)
+    }
+
+    /// Finds the node holding the element at `index`, walking from the nearest end, and
+    /// returns it along with the offset of the element in the node.
+    fn locate(&self, index: usize) -> (usize, usize) {
+        assert!(index < self.len);
+        if index < self.len / 2 {
+            let mut index = index;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if index < node.len() {
+                    return (i, index);
+                }
+                index -= node.len();
+            }
+        } else {
+            let mut from_end = self.len - index;
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                if from_end <= node.len() {
+                    return (i, node.len() - from_end);
+                }
+                from_end -= node.len();
+            }
+        }
+        unreachable!("the length of the list is out of sync")
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.len {
+            return None;
+        }
+        let (node, offset) = self.locate(index);
+        self.nodes[node].get(offset)
+    }
+
+    pub fn set(&mut self, index: usize, data: &[u8]) {
+        let (node, offset) = self.locate(index);
+        self.nodes[node].replace(offset, data);
+        self.split_if_needed(node);
+    }
+
+    /// Inserts an element so that it ends up at `index`, which may be the length.
+    pub fn insert(&mut self, index: usize, data: &[u8]) {
+        if index == 0 {
+            return self.push_front(data);
+        }
+        if index == self.len {
+            return self.push_back(data);
+        }
+        let (node, offset) = self.locate(index);
+        self.nodes[node].insert(offset, data);
+        self.len += 1;
+        self.split_if_needed(node);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Vec<u8> {
+        let (node, offset) = self.locate(index);
+        let data = self.nodes[node].remove(offset);
+        self.len -= 1;
+        self.after_removal(node);
+        data
+    }
+
+    /// Removes `count` elements from `index`. Whole nodes in the range are dropped at
+    /// once.
+    pub fn remove_range(&mut self, index: usize, count: usize) {
+        assert!(index + count <= self.len);
+        let mut remaining = count;
+        while remaining > 0 {
+            let (node, offset) = self.locate(index);
+            let n = remaining.min(self.nodes[node].len() - offset);
+            if offset == 0 && n == self.nodes[node].len() {
+                self.nodes.remove(node);
+            } else {
+                self.nodes[node].remove_range(offset, n);
+            }
+            self.len -= n;
+            remaining -= n;
+        }
+        if index < self.len {
+            let (node, _) = self.locate(index);
+            self.after_removal(node);
+        }
+    }
+
+    /// Splits a node grown beyond the max size by an insertion.
+    fn split_if_needed(&mut self, node: usize) {
+        let lp = &mut self.nodes[node];
+        if lp.bytes() > NODE_MAX_SIZE && lp.len() > 1 {
+            let tail = lp.split_off(lp.len() / 2);
+            self.nodes.insert(node + 1, tail);
+        }
+    }
+
+    /// Drops a node left empty by removals, or merges it with a neighbor when both fit
+    /// in a single node, so that lists do not end up as many tiny nodes.
+    fn after_removal(&mut self, node: usize) {
+        if self.nodes[node].is_empty() {
+            self.nodes.remove(node);
+            return;
+        }
+        for (left, right) in [(node, node + 1), (node.wrapping_sub(1), node)] {
+            if right >= self.nodes.len() || left >= self.nodes.len() {
+                continue;
+            }
+            if self.nodes[left].bytes() + self.nodes[right].bytes() <= NODE_MAX_SIZE {
+                let mut right_node = self.nodes.remove(right).unwrap();
+                self.nodes[left].append(&mut right_node);
+                return;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &[u8]> {
+        self.nodes.iter().flat_map(|node| node.iter())
+    }
+
+    /// Iterates forward from the element at `index`.
+    pub fn iter_from(&self, index: usize) -> impl Iterator<Item = &[u8]> {
+        let (node, offset) = if index < self.len {
+            self.locate(index)
+        } else {
+            (self.nodes.len(), 0)
+        };
+        let first = self.nodes.get(node).map(|lp| lp.iter_from(offset));
+        first
+            .into_iter()
+            .flatten()
+            .chain(self.nodes.iter().skip(node + 1).flat_map(|lp| lp.iter()))
+    }
+}
+
+impl<T: AsRef<[u8]>> FromIterator<T> for QuickList {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = QuickList::new();
+        for data in iter {
+            list.push_back(data.as_ref());
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QuickList;
+
+    fn items(list: &QuickList) -> Vec<String> {
+        list.iter().map(|e| String::from_utf8_lossy(e).into_owned()).collect()
+    }
+
+    #[test]
+    fn grows_in_nodes() {
+        let mut list = QuickList::new();
+        for i in 0..10_000 {
+            list.push_back(i.to_string().as_bytes());
+        }
+        assert_eq!(list.encoding(), "quicklist");
+        assert!(list.nodes.len() > 1 && list.nodes.len() < 100);
+        assert_eq!(list.get(0), Some(&b"0"[..]));
+        assert_eq!(list.get(9_999), Some(&b"9999"[..]));
+        assert_eq!(list.get(5_000), Some(&b"5000"[..]));
+        assert_eq!(list.iter().next_back(), Some(&b"9999"[..]));
+        assert_eq!(list.iter_from(4_998).nth(3), Some(&b"5001"[..]));
+        list.remove_range(100, 9_800);
+        assert_eq!(list.len(), 200);
+        assert_eq!(list.get(100), Some(&b"9900"[..]));
+        assert_eq!(list.encoding(), "listpack");
+        while list.pop_front().is_some() {}
+        assert!(list.is_empty());
+        assert!(list.nodes.is_empty());
+    }
+
+    #[test]
+    fn edit_in_the_middle() {
+        let mut list: QuickList = ["a", "b", "c"].into_iter().collect();
+        list.insert(1, b"x");
+        list.insert(4, b"y");
+        list.set(0, b"A");
+        assert_eq!(list.remove(2), b"b");
+        list.push_front(b"0");
+        assert_eq!(items(&list), ["0", "A", "x", "c", "y"]);
+        let big = vec![b'z'; 10_000];
+        list.insert(2, &big);
+        assert_eq!(list.get(2), Some(&big[..]));
+        assert_eq!(list.get(3), Some(&b"x"[..]));
+        assert_eq!(list.len(), 6);
+        assert_eq!(list.remove(2), big);
+        assert_eq!(items(&list), ["0", "A", "x", "c", "y"]);
+    }
+}
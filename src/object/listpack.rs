@@ -0,0 +1,357 @@
+//! A compact sequence of byte strings in a single buffer, like the listpack of redis.
+//!
+//! Each entry is laid out as `[header][data][backlen]`. The header is the length of the
+//! data as a LEB128 varint. The backlen is the length of header and data, written so
+//! that it can be read from its last byte backward: its lowest 7 bits are in the last
+//! byte, and the high bit of a byte tells whether more bytes are on its left. Thanks to
+//! it, entries can be walked in both directions.
+
+use std::fmt;
+
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Listpack {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+/// Writes `n` as a LEB128 varint.
+fn write_varint(buf: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Reads a LEB128 varint at `pos`, returning it along with the position after it.
+fn read_varint(buf: &[u8], mut pos: usize) -> (usize, usize) {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[pos];
+        pos += 1;
+        n |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return (n, pos);
+        }
+        shift += 7;
+    }
+}
+
+/// Writes `n` as a backlen.
+fn write_backlen(buf: &mut Vec<u8>, mut n: usize) {
+    let mut groups = vec![];
+    loop {
+        groups.push(n as u8 & 0x7f);
+        n >>= 7;
+        if n == 0 {
+            break;
+        }
+    }
+    // the highest group comes first, and is the only one without continuation bit.
+    let highest = groups.len() - 1;
+    for (i, group) in groups.into_iter().enumerate().rev() {
+        buf.push(if i == highest { group } else { group | 0x80 });
+    }
+}
+
+/// Number of bytes of `n` encoded as a varint or a backlen.
+fn varint_size(n: usize) -> usize {
+    ((usize::BITS - n.leading_zeros()).max(1) as usize).div_ceil(7)
+}
+
+/// Reads the backlen ending right before `end`, returning it along with its size.
+fn read_backlen(buf: &[u8], end: usize) -> (usize, usize) {
+    let mut n = 0;
+    let mut shift = 0;
+    let mut pos = end;
+    loop {
+        pos -= 1;
+        let byte = buf[pos];
+        n |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return (n, end - pos);
+        }
+        shift += 7;
+    }
+}
+
+/// Encodes a whole entry.
+fn encode_entry(data: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(data.len() + 6);
+    write_varint(&mut entry, data.len());
+    entry.extend_from_slice(data);
+    let len = entry.len();
+    write_backlen(&mut entry, len);
+    entry
+}
+
+impl Listpack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of the encoded entries in bytes.
+    pub fn bytes(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Size an entry holding `data` takes once encoded.
+    pub fn entry_size(data: &[u8]) -> usize {
+        let header = varint_size(data.len());
+        header + data.len() + varint_size(header + data.len())
+    }
+
+    /// Returns the data of the entry at `pos` and the position of the next entry.
+    fn entry_at(&self, pos: usize) -> (&[u8], usize) {
+        let (len, start) = read_varint(&self.buf, pos);
+        let end = start + len;
+        (&self.buf[start..end], end + varint_size(end - pos))
+    }
+
+    /// Returns the position of the entry before the one at `pos`.
+    fn prev_pos(&self, pos: usize) -> usize {
+        let (len, size) = read_backlen(&self.buf, pos);
+        pos - size - len
+    }
+
+    /// Position of the entry at `index`, walking from the nearest end.
+    fn pos_of(&self, index: usize) -> usize {
+        if index <= self.len / 2 {
+            let mut pos = 0;
+            for _ in 0..index {
+                pos = self.entry_at(pos).1;
+            }
+            pos
+        } else {
+            let mut pos = self.buf.len();
+            for _ in index..self.len {
+                pos = self.prev_pos(pos);
+            }
+            pos
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.len {
+            return None;
+        }
+        Some(self.entry_at(self.pos_of(index)).0)
+    }
+
+    pub fn first(&self) -> Option<&[u8]> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&[u8]> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.entry_at(self.prev_pos(self.buf.len())).0)
+    }
+
+    pub fn push_back(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(&encode_entry(data));
+        self.len += 1;
+    }
+
+    pub fn push_front(&mut self, data: &[u8]) {
+        self.insert(0, data);
+    }
+
+    /// Inserts an entry so that it ends up at `index`, which may be the length.
+    pub fn insert(&mut self, index: usize, data: &[u8]) {
+        assert!(index <= self.len);
+        let pos = if index == self.len { self.buf.len() } else { self.pos_of(index) };
+        self.buf.splice(pos..pos, encode_entry(data));
+        self.len += 1;
+    }
+
+    /// Removes the entry at `index`, returning its data.
+    pub fn remove(&mut self, index: usize) -> Vec<u8> {
+        assert!(index < self.len);
+        let pos = self.pos_of(index);
+        let (data, next) = self.entry_at(pos);
+        let data = data.to_vec();
+        self.buf.drain(pos..next);
+        self.len -= 1;
+        data
+    }
+
+    /// Removes `count` entries from `index`.
+    pub fn remove_range(&mut self, index: usize, count: usize) {
+        assert!(index + count <= self.len);
+        let start = self.pos_of(index);
+        let mut end = start;
+        for _ in 0..count {
+            end = self.entry_at(end).1;
+        }
+        self.buf.drain(start..end);
+        self.len -= count;
+    }
+
+    pub fn replace(&mut self, index: usize, data: &[u8]) {
+        assert!(index < self.len);
+        let pos = self.pos_of(index);
+        let next = self.entry_at(pos).1;
+        self.buf.splice(pos..next, encode_entry(data));
+    }
+
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.remove(0))
+    }
+
+    pub fn pop_back(&mut self) -> Option<Vec<u8>> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.remove(self.len - 1))
+    }
+
+    /// Moves the entries from `index` into a new listpack.
+    pub fn split_off(&mut self, index: usize) -> Listpack {
+        assert!(index <= self.len);
+        let pos = if index == self.len { self.buf.len() } else { self.pos_of(index) };
+        let tail = Listpack {
+            buf: self.buf.split_off(pos),
+            len: self.len - index,
+        };
+        self.len = index;
+        tail
+    }
+
+    /// Moves the entries of `other` at the end of this listpack.
+    pub fn append(&mut self, other: &mut Listpack) {
+        self.buf.append(&mut other.buf);
+        self.len += other.len;
+        other.len = 0;
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            lp: self,
+            front: 0,
+            back: self.buf.len(),
+            remaining: self.len,
+        }
+    }
+
+    /// Iterates from the entry at `index`.
+    pub fn iter_from(&self, index: usize) -> Iter<'_> {
+        let index = index.min(self.len);
+        let front = if index == self.len { self.buf.len() } else { self.pos_of(index) };
+        Iter {
+            lp: self,
+            front,
+            back: self.buf.len(),
+            remaining: self.len - index,
+        }
+    }
+}
+
+impl fmt::Debug for Listpack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.iter().map(String::from_utf8_lossy))
+            .finish()
+    }
+}
+
+pub struct Iter<'a> {
+    lp: &'a Listpack,
+    front: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (data, next) = self.lp.entry_at(self.front);
+        self.front = next;
+        self.remaining -= 1;
+        Some(data)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.back = self.lp.prev_pos(self.back);
+        self.remaining -= 1;
+        Some(self.lp.entry_at(self.back).0)
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::Listpack;
+
+    #[test]
+    fn push_pop_both_ends() {
+        let mut lp = Listpack::new();
+        let long = vec![b'x'; 300];
+        lp.push_back(b"b");
+        lp.push_front(b"a");
+        lp.push_back(&long);
+        lp.push_back(b"");
+        assert_eq!(lp.len(), 4);
+        assert_eq!(lp.get(2), Some(&long[..]));
+        assert_eq!(lp.last(), Some(&b""[..]));
+        let items: Vec<&[u8]> = lp.iter().rev().collect();
+        assert_eq!(items, [&b""[..], &long, b"b", b"a"]);
+        assert_eq!(lp.pop_back().unwrap(), b"");
+        assert_eq!(lp.pop_front().unwrap(), b"a");
+        assert_eq!(lp.pop_back().unwrap(), long);
+        assert_eq!(lp.pop_back().unwrap(), b"b");
+        assert_eq!(lp.pop_back(), None);
+        assert_eq!(lp.bytes(), 0);
+    }
+
+    #[test]
+    fn edit_in_the_middle() {
+        let mut lp = Listpack::new();
+        for i in 0..200 {
+            lp.push_back(i.to_string().as_bytes());
+        }
+        lp.insert(150, &vec![b'y'; 20000]);
+        lp.replace(10, b"ten");
+        assert_eq!(lp.remove(151), b"150");
+        lp.remove_range(0, 5);
+        assert_eq!(lp.len(), 195);
+        assert_eq!(lp.get(5), Some(&b"ten"[..]));
+        assert_eq!(lp.get(145).unwrap().len(), 20000);
+        let tail = lp.split_off(145);
+        assert_eq!(tail.iter().nth(1), Some(&b"151"[..]));
+        assert_eq!(lp.iter_from(140).map(|e| e.to_vec()).collect::<Vec<_>>().len(), 5);
+        let items: Vec<Vec<u8>> = tail.iter().rev().map(|e| e.to_vec()).collect();
+        assert_eq!(items.len(), 50);
+        assert_eq!(items[0], b"199");
+        assert_eq!(Listpack::entry_size(&[0; 20000]), 20000 + 3 + 3);
+        assert_eq!(Listpack::entry_size(b"ab"), 4);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    config::Config,
+    error::{CommandError, Result},
+};
+
+mod consumer_group;
+mod hash;
+mod intset;
+mod list;
+mod listpack;
+mod set;
+mod skiplist;
+mod stream;
+mod string;
+mod zset;
+
+pub use consumer_group::{Consumer, ConsumerGroup, PendingEntry};
+pub use hash::RedisHash;
+pub use list::QuickList;
+pub use listpack::Listpack;
+pub use set::RedisSet;
+pub use stream::{Stream, StreamEntry, StreamId, TrimStrategy};
+pub use string::RedisString;
+pub use zset::{LexBound, LexRange, RedisZSet, ScoreRange, ZRange};
+
+/// Thresholds past which values leave their compact encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingLimits {
+    /// Max number of fields of a listpack encoded hash.
+    pub hash_max_listpack_entries: usize,
+    /// Max length of a field or a value of a listpack encoded hash.
+    pub hash_max_listpack_value: usize,
+    /// Max number of members of an intset encoded set.
+    pub set_max_intset_entries: usize,
+    /// Max number of entries of a block of a stream, zero for no limit.
+    pub stream_node_max_entries: usize,
+    /// Max size in bytes of a block of a stream, zero for no limit.
+    pub stream_node_max_bytes: usize,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        Self::from(&Config::default())
+    }
+}
+
+impl From<&Config> for EncodingLimits {
+    fn from(conf: &Config) -> Self {
+        Self {
+            hash_max_listpack_entries: conf.hash_max_listpack_entries,
+            hash_max_listpack_value: conf.hash_max_listpack_value,
+            set_max_intset_entries: conf.set_max_intset_entries,
+            stream_node_max_entries: conf.stream_node_max_entries,
+            stream_node_max_bytes: conf.stream_node_max_bytes,
+        }
+    }
+}
+
+/// A value stored in the keyspace, like `robj` of redis.
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(RedisString),
+    List(QuickList),
+    Hash(RedisHash),
+    Set(RedisSet),
+    ZSet(RedisZSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// Name of the type, as replied by TYPE.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Name of the encoding, as replied by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(s) => s.encoding(),
+            Value::List(list) => list.encoding(),
+            Value::Hash(hash) => hash.encoding(),
+            Value::Set(set) => set.encoding(),
+            Value::ZSet(zset) => zset.encoding(),
+            Value::Stream(stream) => stream.encoding(),
+        }
+    }
+
+    /// Returns the string, for commands working on strings only.
+    pub fn as_string(&self) -> Result<&RedisString> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_string_mut(&mut self) -> Result<&mut RedisString> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&QuickList> {
+        match self {
+            Value::List(list) => Ok(list),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Result<&mut QuickList> {
+        match self {
+            Value::List(list) => Ok(list),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_hash(&self) -> Result<&RedisHash> {
+        match self {
+            Value::Hash(hash) => Ok(hash),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_hash_mut(&mut self) -> Result<&mut RedisHash> {
+        match self {
+            Value::Hash(hash) => Ok(hash),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_set(&self) -> Result<&RedisSet> {
+        match self {
+            Value::Set(set) => Ok(set),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_set_mut(&mut self) -> Result<&mut RedisSet> {
+        match self {
+            Value::Set(set) => Ok(set),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_zset(&self) -> Result<&RedisZSet> {
+        match self {
+            Value::ZSet(zset) => Ok(zset),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_zset_mut(&mut self) -> Result<&mut RedisZSet> {
+        match self {
+            Value::ZSet(zset) => Ok(zset),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_stream(&self) -> Result<&Stream> {
+        match self {
+            Value::Stream(stream) => Ok(stream),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+
+    pub fn as_stream_mut(&mut self) -> Result<&mut Stream> {
+        match self {
+            Value::Stream(stream) => Ok(stream),
+            _ => Err(CommandError::WrongType.into()),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use bytes::Bytes;
+
+use crate::{
+    dict::Dict,
+    util::{parse_i64, random},
+};
+
+use super::{intset::IntSet, EncodingLimits};
+
+/// A set value. Sets whose members are all integers are kept in an intset while they
+/// have no more than `set-max-intset-entries` members, they are converted for good to
+/// a hash table otherwise, a [`Dict`] of members without values as redis has.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedisSet {
+    members: Members,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Members {
+    IntSet(IntSet),
+    Table(Dict<Bytes, ()>),
+}
+
+impl Default for Members {
+    fn default() -> Self {
+        Self::IntSet(IntSet::new())
+    }
+}
+
+impl RedisSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.members {
+            Members::IntSet(set) => set.len(),
+            Members::Table(table) => table.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &self.members {
+            Members::IntSet(set) => set.is_empty(),
+            Members::Table(table) => table.is_empty(),
+        }
+    }
+
+    /// Name of the encoding, as replied by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match &self.members {
+            Members::IntSet(_) => "intset",
+            Members::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.members {
+            Members::IntSet(set) => parse_i64(member).is_some_and(|n| set.contains(n)),
+            Members::Table(table) => table.contains_key(member),
+        }
+    }
+
+    /// Adds the member, and tells whether it is new.
+    pub fn insert(&mut self, member: &[u8], limits: &EncodingLimits) -> bool {
+        if let Members::IntSet(set) = &mut self.members {
+            match parse_i64(member) {
+                Some(n) if set.contains(n) => return false,
+                Some(n) if set.len() < limits.set_max_intset_entries => return set.insert(n),
+                _ => self.convert(),
+            }
+        }
+        match &mut self.members {
+            Members::Table(table) => table.insert(Bytes::copy_from_slice(member), ()).is_none(),
+            Members::IntSet(_) => unreachable!(),
+        }
+    }
+
+    /// Removes the member, and tells whether it was there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.members {
+            Members::IntSet(set) => parse_i64(member).is_some_and(|n| set.remove(n)),
+            Members::Table(table) => table.remove(member).is_some(),
+        }
+    }
+
+    /// Converts an intset encoded set to a hash set.
+    fn convert(&mut self) {
+        if let Members::IntSet(set) = &self.members {
+            let table = set.iter().map(|n| (Bytes::from(n.to_string()), ())).collect();
+            self.members = Members::Table(table);
+        }
+    }
+
+    /// Iterates the members. Intsets give them in ascending order, hash sets in no
+    /// order.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match &self.members {
+            Members::IntSet(set) => Box::new(set.iter().map(|n| Bytes::from(n.to_string()))),
+            Members::Table(table) => Box::new(table.iter().map(|(member, _)| member.clone())),
+        }
+    }
+
+    /// Picks a random member, in O(1).
+    pub fn random_member(&self) -> Option<Bytes> {
+        match &self.members {
+            Members::IntSet(set) if set.is_empty() => None,
+            Members::IntSet(set) => {
+                let n = set.get(random() as usize % set.len())?;
+                Some(Bytes::from(n.to_string()))
+            }
+            Members::Table(table) => table.random_entry().map(|(member, _)| member.clone()),
+        }
+    }
+
+    /// Removes a random member and returns it.
+    pub fn pop_random(&mut self) -> Option<Bytes> {
+        let member = self.random_member()?;
+        self.remove(&member);
+        Some(member)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::object::EncodingLimits;
+
+    use super::RedisSet;
+
+    #[test]
+    fn converted_past_the_limits() {
+        let limits = EncodingLimits {
+            set_max_intset_entries: 3,
+            ..EncodingLimits::default()
+        };
+        let mut set = RedisSet::new();
+        for member in ["3", "-1", "2"] {
+            assert!(set.insert(member.as_bytes(), &limits));
+        }
+        assert!(!set.insert(b"2", &limits));
+        assert_eq!(set.encoding(), "intset");
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec!["-1", "2", "3"]);
+        assert!(set.contains(b"3") && !set.contains(b"03") && !set.contains(b"a"));
+        assert!(set.remove(b"3"));
+        assert!(!set.remove(b"x"));
+        assert!(set.insert(b"007", &limits));
+        assert_eq!(set.encoding(), "hashtable");
+        assert!(set.contains(b"-1") && set.contains(b"007") && !set.contains(b"7"));
+
+        let mut set = RedisSet::new();
+        for i in 0..4 {
+            set.insert(i.to_string().as_bytes(), &limits);
+        }
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.len(), 4);
+        while let Some(member) = set.pop_random() {
+            assert!(!set.contains(&member));
+        }
+        assert!(set.random_member().is_none());
+    }
+}
@@ -0,0 +1,390 @@
+//! The skiplist of sorted sets, like the zskiplist of redis.
+//!
+//! Nodes are ordered by score, then by member. Each level of a node links to the next
+//! node having that level, along with the span of the link, the number of nodes it
+//! skips plus one. Summing the spans walked to reach a node gives its rank, so ranks
+//! are found in O(log n) like nodes are. The first level also links backward, for
+//! reverse iteration.
+//!
+//! Nodes live in a vector and link to each other by index, the header being the first
+//! node. The slots of deleted nodes are reused.
+
+use bytes::Bytes;
+
+use crate::util::random;
+
+use super::zset::ZRange;
+
+/// Max number of levels of a node, enough for 2^64 nodes.
+const MAX_LEVEL: usize = 32;
+/// Probability for a node to have one more level, as a fraction of `u64::MAX`.
+const LEVEL_P: u64 = u64::MAX / 4;
+
+/// Index of no node.
+const NIL: usize = usize::MAX;
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Level {
+    forward: usize,
+    span: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    backward: usize,
+    levels: Vec<Level>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    /// Slots of deleted nodes.
+    free: Vec<usize>,
+    tail: usize,
+    len: usize,
+    /// Number of levels in use by the nodes.
+    level: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            backward: NIL,
+            levels: vec![Level { forward: NIL, span: 0 }; MAX_LEVEL],
+        };
+        Self {
+            nodes: vec![head],
+            free: vec![],
+            tail: NIL,
+            len: 0,
+            level: 1,
+        }
+    }
+}
+
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < MAX_LEVEL && random() < LEVEL_P {
+        level += 1;
+    }
+    level
+}
+
+/// Handle of a node, valid until the skiplist is modified.
+pub type NodeId = usize;
+
+impl SkipList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn forward(&self, node: usize, level: usize) -> usize {
+        self.nodes[node].levels[level].forward
+    }
+
+    fn span(&self, node: usize, level: usize) -> usize {
+        self.nodes[node].levels[level].span
+    }
+
+    /// Whether the node comes before the element `(score, member)`.
+    fn is_before(&self, node: usize, score: f64, member: &[u8]) -> bool {
+        let node = &self.nodes[node];
+        node.score < score || (node.score == score && &node.member[..] < member)
+    }
+
+    /// Finds for each level the last node before the element, along with its rank.
+    fn find_update(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !self.is_before(next, score, member) {
+                    break;
+                }
+                rank[i] += self.span(x, i);
+                x = next;
+            }
+            update[i] = x;
+        }
+        (update, rank)
+    }
+
+    /// Inserts an element, which must not be in the skiplist yet.
+    pub fn insert(&mut self, score: f64, member: Bytes) {
+        let (mut update, mut rank) = self.find_update(score, &member);
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+        let node = Node {
+            member,
+            score,
+            backward: if update[0] == HEAD { NIL } else { update[0] },
+            levels: vec![Level { forward: NIL, span: 0 }; level],
+        };
+        let x = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let prev = update[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[x].levels[i] = Level {
+                forward: self.forward(prev, i),
+                span: self.span(prev, i) - skipped,
+            };
+            self.nodes[prev].levels[i] = Level {
+                forward: x,
+                span: skipped + 1,
+            };
+        }
+        // links above the node skip it from now on.
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+        match self.forward(x, 0) {
+            NIL => self.tail = x,
+            next => self.nodes[next].backward = x,
+        }
+        self.len += 1;
+    }
+
+    /// Deletes an element, and tells whether it was there.
+    pub fn delete(&mut self, score: f64, member: &[u8]) -> bool {
+        let (update, _) = self.find_update(score, member);
+        let x = self.forward(update[0], 0);
+        if x == NIL || self.nodes[x].score != score || self.nodes[x].member != member {
+            return false;
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == x {
+                self.nodes[prev].levels[i] = Level {
+                    forward: self.forward(x, i),
+                    span: self.span(prev, i) + self.span(x, i) - 1,
+                };
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        let backward = self.nodes[x].backward;
+        match self.forward(x, 0) {
+            NIL => self.tail = backward,
+            next => self.nodes[next].backward = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1) == NIL {
+            self.level -= 1;
+        }
+        self.nodes[x].member = Bytes::new();
+        self.nodes[x].levels = vec![];
+        self.free.push(x);
+        self.len -= 1;
+        true
+    }
+
+    /// Rank of the element, zero based.
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL {
+                    break;
+                }
+                let node = &self.nodes[next];
+                if node.score > score || (node.score == score && &node.member[..] > member) {
+                    break;
+                }
+                rank += self.span(x, i);
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].score == score && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Node at the rank, zero based.
+    pub fn by_rank(&self, rank: usize) -> Option<NodeId> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || traversed + self.span(x, i) > target {
+                    break;
+                }
+                traversed += self.span(x, i);
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// First node in the range.
+    pub fn first_in(&self, range: &impl ZRange) -> Option<NodeId> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || range.above_min(self.nodes[next].score, &self.nodes[next].member)
+                {
+                    break;
+                }
+                x = next;
+            }
+        }
+        let x = self.forward(x, 0);
+        (x != NIL && range.below_max(self.nodes[x].score, &self.nodes[x].member)).then_some(x)
+    }
+
+    /// Last node in the range.
+    pub fn last_in(&self, range: &impl ZRange) -> Option<NodeId> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !range.below_max(self.nodes[next].score, &self.nodes[next].member)
+                {
+                    break;
+                }
+                x = next;
+            }
+        }
+        (x != HEAD && range.above_min(self.nodes[x].score, &self.nodes[x].member)).then_some(x)
+    }
+
+    pub fn first(&self) -> Option<NodeId> {
+        self.next(HEAD)
+    }
+
+    pub fn last(&self) -> Option<NodeId> {
+        (self.tail != NIL).then_some(self.tail)
+    }
+
+    pub fn next(&self, node: NodeId) -> Option<NodeId> {
+        match self.forward(node, 0) {
+            NIL => None,
+            next => Some(next),
+        }
+    }
+
+    pub fn prev(&self, node: NodeId) -> Option<NodeId> {
+        match self.nodes[node].backward {
+            NIL => None,
+            prev => Some(prev),
+        }
+    }
+
+    /// Member and score of the node.
+    pub fn get(&self, node: NodeId) -> (&Bytes, f64) {
+        let node = &self.nodes[node];
+        (&node.member, node.score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::object::zset::ScoreRange;
+
+    use super::SkipList;
+
+    #[test]
+    fn ranks_follow_the_order() {
+        let mut list = SkipList::default();
+        for i in (0..1000).rev() {
+            list.insert((i / 2) as f64, Bytes::from(format!("m{:04}", i)));
+        }
+        assert_eq!(list.len(), 1000);
+        for i in 0..1000 {
+            let member = format!("m{:04}", i);
+            assert_eq!(list.rank((i / 2) as f64, member.as_bytes()), Some(i));
+            let node = list.by_rank(i).unwrap();
+            assert_eq!(list.get(node).0, &member);
+        }
+        assert_eq!(list.by_rank(1000), None);
+        assert_eq!(list.rank(1.0, b"m0000"), None);
+
+        for i in (0..1000).step_by(2) {
+            assert!(list.delete((i / 2) as f64, format!("m{:04}", i).as_bytes()));
+        }
+        assert!(!list.delete(0.0, b"m0000"));
+        assert_eq!(list.len(), 500);
+        for i in 0..500 {
+            let node = list.by_rank(i).unwrap();
+            let (member, score) = list.get(node);
+            assert_eq!(member, &format!("m{:04}", i * 2 + 1));
+            assert_eq!(list.rank(score, member), Some(i));
+        }
+        // the slots of the deleted nodes are reused.
+        list.insert(-1.0, Bytes::from_static(b"first"));
+        assert_eq!(list.nodes.len(), 1001);
+        assert_eq!(list.get(list.first().unwrap()).0, "first");
+    }
+
+    #[test]
+    fn walks_score_ranges() {
+        let mut list = SkipList::default();
+        for i in 0..10 {
+            list.insert(i as f64, Bytes::from(i.to_string()));
+        }
+        let range = ScoreRange {
+            min: 2.0,
+            min_ex: true,
+            max: 5.0,
+            max_ex: false,
+        };
+        let first = list.first_in(&range).unwrap();
+        let last = list.last_in(&range).unwrap();
+        assert_eq!(list.get(first), (&Bytes::from("3"), 3.0));
+        assert_eq!(list.get(last), (&Bytes::from("5"), 5.0));
+        assert_eq!(list.get(list.prev(last).unwrap()).1, 4.0);
+        assert_eq!(list.get(list.next(first).unwrap()).1, 4.0);
+        assert_eq!(list.prev(list.first().unwrap()), None);
+        assert_eq!(list.next(list.last().unwrap()), None);
+        let outside = ScoreRange {
+            min: 10.0,
+            min_ex: false,
+            max: 20.0,
+            max_ex: false,
+        };
+        assert_eq!(list.first_in(&outside), None);
+        assert_eq!(list.last_in(&outside), None);
+    }
+}
@@ -0,0 +1,598 @@
+//! A stream value, like the stream of redis.
+//!
+//! Entries are appended to blocks, listpacks holding up to `stream-node-max-entries`
+//! entries or `stream-node-max-bytes` bytes. As in redis, blocks are kept in a [`Rax`]
+//! keyed by the ID of their first entry, the master entry, encoded big endian so that
+//! the keys are ordered as the IDs are. A block is laid out as:
+//!
+//! ```text
+//! count | deleted | master field count | master fields... | entries...
+//! ```
+//!
+//! where `count` is the number of entries which are not deleted. Each entry is:
+//!
+//! ```text
+//! flags | ms diff | seq diff | values...                          (SAME_FIELDS)
+//! flags | ms diff | seq diff | field count | field | value | ...  (otherwise)
+//! ```
+//!
+//! its ID being stored as the difference with the master ID. Entries having the fields
+//! of the master entry only store their values. Deleted entries are only flagged, and a
+//! block is removed once all of its entries are deleted.
+//!
+//! Consumer groups are kept by name along with the entries, see [`ConsumerGroup`].
+
+use std::{collections::BTreeMap, fmt};
+
+use bytes::Bytes;
+
+use crate::rax::{Rax, Seek};
+
+use super::{consumer_group::ConsumerGroup, listpack::Listpack, EncodingLimits};
+
+/// The entry has been deleted.
+const FLAG_DELETED: u64 = 1;
+/// The entry has the same fields as the master entry.
+const FLAG_SAME_FIELDS: u64 = 2;
+
+/// Index in a block of the first master field.
+const MASTER_FIELDS: usize = 3;
+
+/// ID of a stream entry, the unix time in milliseconds at which it has been added and a
+/// sequence number among the entries added in the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// The ID right after this one, if any.
+    pub fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self::new(self.ms, seq)),
+            None => Some(Self::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The ID right before this one, if any.
+    pub fn prev(self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(Self::new(self.ms, seq)),
+            None => Some(Self::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+
+    fn to_key(self) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[..8].copy_from_slice(&self.ms.to_be_bytes());
+        key[8..].copy_from_slice(&self.seq.to_be_bytes());
+        key
+    }
+
+    fn from_key(key: &[u8]) -> Self {
+        Self::new(
+            u64::from_be_bytes(key[..8].try_into().unwrap()),
+            u64::from_be_bytes(key[8..].try_into().unwrap()),
+        )
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// An entry of a stream, its ID and its fields and values alternating.
+pub type StreamEntry = (StreamId, Vec<Bytes>);
+
+/// How XADD and XTRIM trim a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// Keep at most this number of entries.
+    MaxLen(usize),
+    /// Remove the entries with a lower ID.
+    MinId(StreamId),
+}
+
+/// An entry decoded from a block.
+struct BlockEntry {
+    /// Index in the block of the flags of the entry.
+    index: usize,
+    id: StreamId,
+    deleted: bool,
+    fields: Vec<Bytes>,
+}
+
+fn push_int(lp: &mut Listpack, n: u64) {
+    lp.push_back(n.to_string().as_bytes());
+}
+
+fn read_int(data: &[u8]) -> u64 {
+    std::str::from_utf8(data).unwrap().parse().unwrap()
+}
+
+/// Number of entries of the block which are not deleted, and of deleted ones.
+fn counts(lp: &Listpack) -> (usize, usize) {
+    (read_int(lp.get(0).unwrap()) as usize, read_int(lp.get(1).unwrap()) as usize)
+}
+
+fn set_counts(lp: &mut Listpack, count: usize, deleted: usize) {
+    lp.replace(0, count.to_string().as_bytes());
+    lp.replace(1, deleted.to_string().as_bytes());
+}
+
+fn master_fields(lp: &Listpack) -> Vec<&[u8]> {
+    let count = read_int(lp.get(2).unwrap()) as usize;
+    lp.iter().skip(MASTER_FIELDS).take(count).collect()
+}
+
+/// Decodes the entries of the block whose master entry has the ID of `key`.
+fn decode(key: &[u8], lp: &Listpack) -> Vec<BlockEntry> {
+    let master = StreamId::from_key(key);
+    let master_fields = master_fields(lp);
+    let mut iter = lp.iter().enumerate().skip(MASTER_FIELDS + master_fields.len());
+    let mut entries = vec![];
+    while let Some((index, flags)) = iter.next() {
+        let flags = read_int(flags);
+        let mut next = || iter.next().unwrap().1;
+        let id = StreamId::new(
+            master.ms + read_int(next()),
+            master.seq.wrapping_add(read_int(next())),
+        );
+        let fields = if flags & FLAG_SAME_FIELDS != 0 {
+            master_fields
+                .iter()
+                .flat_map(|field| [Bytes::copy_from_slice(field), Bytes::copy_from_slice(next())])
+                .collect()
+        } else {
+            let count = read_int(next()) as usize * 2;
+            (0..count).map(|_| Bytes::copy_from_slice(next())).collect()
+        };
+        entries.push(BlockEntry {
+            index,
+            id,
+            deleted: flags & FLAG_DELETED != 0,
+            fields,
+        });
+    }
+    entries
+}
+
+/// Flags the entry of the block as deleted.
+fn mark_deleted(lp: &mut Listpack, entry: &BlockEntry) {
+    let flags = read_int(lp.get(entry.index).unwrap()) | FLAG_DELETED;
+    lp.replace(entry.index, flags.to_string().as_bytes());
+    let (count, deleted) = counts(lp);
+    set_counts(lp, count - 1, deleted + 1);
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stream {
+    /// Blocks of entries, keyed by the big endian ID of their master entry.
+    blocks: Rax<Listpack>,
+    len: usize,
+    /// ID of the last entry added, which may have been deleted since.
+    last_id: StreamId,
+    /// Highest ID of the entries deleted by XDEL.
+    max_deleted_id: StreamId,
+    /// Number of entries added over the life of the stream.
+    entries_added: u64,
+    groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Name of the encoding, as replied by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        "stream"
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// Number of blocks of entries.
+    pub fn blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Appends an entry, whose ID must be greater than the last ID of the stream.
+    pub fn append(&mut self, id: StreamId, fields: &[Bytes], limits: &EncodingLimits) {
+        debug_assert!(id > self.last_id || self.entries_added == 0);
+        let full = match self.blocks.last() {
+            Some((_, lp)) => {
+                let (count, deleted) = counts(lp);
+                (limits.stream_node_max_bytes > 0 && lp.bytes() >= limits.stream_node_max_bytes)
+                    || (limits.stream_node_max_entries > 0
+                        && count + deleted >= limits.stream_node_max_entries)
+            }
+            None => true,
+        };
+        if full {
+            let mut lp = Listpack::new();
+            push_int(&mut lp, 0);
+            push_int(&mut lp, 0);
+            push_int(&mut lp, fields.len() as u64 / 2);
+            for field in fields.iter().step_by(2) {
+                lp.push_back(field);
+            }
+            self.blocks.insert(&id.to_key(), lp);
+        }
+        let (key, _) = self.blocks.last().unwrap();
+        let master = StreamId::from_key(&key);
+        let lp = self.blocks.get_mut(&key).unwrap();
+        let same_fields = master_fields(lp)
+            .into_iter()
+            .eq(fields.iter().step_by(2).map(|field| &field[..]));
+        push_int(lp, if same_fields { FLAG_SAME_FIELDS } else { 0 });
+        push_int(lp, id.ms - master.ms);
+        push_int(lp, id.seq.wrapping_sub(master.seq));
+        if same_fields {
+            for value in fields.iter().skip(1).step_by(2) {
+                lp.push_back(value);
+            }
+        } else {
+            push_int(lp, fields.len() as u64 / 2);
+            for item in fields {
+                lp.push_back(item);
+            }
+        }
+        let (count, deleted) = counts(lp);
+        set_counts(lp, count + 1, deleted);
+        self.len += 1;
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Entries from `start` to `end`, both included, from the end when `rev` is set.
+    /// At most `count` entries are returned.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        rev: bool,
+        count: Option<usize>,
+    ) -> Vec<StreamEntry> {
+        let mut entries = vec![];
+        if start > end || count == Some(0) {
+            return entries;
+        }
+        // the block holding `start` begins at or before it.
+        let first = match self.blocks.seek(Seek::Le, &start.to_key()) {
+            Some((key, _)) => key,
+            None => StreamId::MIN.to_key().to_vec(),
+        };
+        let end_key = end.to_key();
+        let (seek, next) = if rev { (Seek::Le, Seek::Lt) } else { (Seek::Ge, Seek::Gt) };
+        let blocks = std::iter::successors(
+            self.blocks.seek(seek, if rev { &end_key } else { &first }),
+            |(key, _)| self.blocks.seek(next, key),
+        )
+        .take_while(|(key, _)| first[..] <= key[..] && key[..] <= end_key[..]);
+        for (key, lp) in blocks {
+            let mut decoded = decode(&key, lp);
+            if rev {
+                decoded.reverse();
+            }
+            for entry in decoded {
+                if entry.deleted || entry.id < start || entry.id > end {
+                    continue;
+                }
+                entries.push((entry.id, entry.fields));
+                if count == Some(entries.len()) {
+                    return entries;
+                }
+            }
+        }
+        entries
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<StreamEntry> {
+        self.range(id, id, false, Some(1)).pop()
+    }
+
+    pub fn first_entry(&self) -> Option<StreamEntry> {
+        self.range(StreamId::MIN, StreamId::MAX, false, Some(1)).pop()
+    }
+
+    pub fn last_entry(&self) -> Option<StreamEntry> {
+        self.range(StreamId::MIN, StreamId::MAX, true, Some(1)).pop()
+    }
+
+    /// Deletes the entry, and tells whether it was there.
+    pub fn delete(&mut self, id: StreamId) -> bool {
+        let key = match self.blocks.seek(Seek::Le, &id.to_key()) {
+            Some((key, _)) => key,
+            None => return false,
+        };
+        let lp = self.blocks.get_mut(&key).unwrap();
+        let entry = match decode(&key, lp).into_iter().find(|entry| entry.id == id) {
+            Some(entry) if !entry.deleted => entry,
+            _ => return false,
+        };
+        mark_deleted(lp, &entry);
+        if counts(lp).0 == 0 {
+            self.blocks.remove(&key);
+        }
+        self.len -= 1;
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    pub fn groups(&self) -> &BTreeMap<Bytes, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Creates a consumer group, and tells whether it is new.
+    pub fn create_group(&mut self, name: Bytes, group: ConsumerGroup) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        self.groups.insert(name, group);
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Whether entries from `start` on may have been deleted by XDEL, in which case the
+    /// number of entries read by a group can not be counted.
+    fn has_tombstones(&self, start: StreamId) -> bool {
+        self.len > 0 && self.max_deleted_id != StreamId::MIN && self.max_deleted_id >= start
+    }
+
+    /// Number of entries added up to the one with the ID, when it can be told without
+    /// counting them.
+    fn entries_up_to(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if id >= self.last_id {
+            return (id == self.last_id).then_some(self.entries_added);
+        }
+        if self.len == 0 {
+            return Some(self.entries_added);
+        }
+        let first = self.first_entry().unwrap().0;
+        // no entry has been deleted after the first one, so all of them are counted.
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first {
+            let before = self.entries_added - self.len as u64;
+            if id < first {
+                return Some(before);
+            } else if id == first {
+                return Some(before + 1);
+            }
+        }
+        None
+    }
+
+    /// Number of entries the group has not read yet, unless it can not be told.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let read = match group.entries_read {
+            Some(read) if !self.has_tombstones(group.last_id) => Some(read),
+            _ => self.entries_up_to(group.last_id),
+        };
+        read.map(|read| self.entries_added - read)
+    }
+
+    /// Delivers to the consumer of the group, which must exist, at most `count` entries
+    /// never delivered to the group. They are added to the PEL unless `noack` is set.
+    pub fn read_group(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        count: Option<usize>,
+        noack: bool,
+        now: u64,
+    ) -> Vec<StreamEntry> {
+        self.groups.get_mut(group).unwrap().seen(consumer, now);
+        let entries = match self.groups[group].last_id.next() {
+            Some(start) => self.range(start, StreamId::MAX, false, count),
+            None => vec![],
+        };
+        for (id, _) in &entries {
+            let read = self.groups[group].entries_read;
+            let read = match read {
+                Some(read) if !self.has_tombstones(*id) => Some(read + 1),
+                _ => self.entries_up_to(*id),
+            };
+            let group = self.groups.get_mut(group).unwrap();
+            group.entries_read = read;
+            group.last_id = *id;
+            if !noack {
+                group.assign(*id, consumer, now, 1);
+            }
+        }
+        if !entries.is_empty() {
+            self.groups.get_mut(group).unwrap().seen(consumer, now).active_time = Some(now);
+        }
+        entries
+    }
+
+    /// Delivers again to the consumer of the group, which must exist, at most `count`
+    /// of the entries it owns after `after`. Entries deleted since they were delivered
+    /// come without fields.
+    pub fn read_pending(
+        &mut self,
+        group: &[u8],
+        consumer: &Bytes,
+        after: StreamId,
+        count: Option<usize>,
+        now: u64,
+    ) -> Vec<(StreamId, Option<Vec<Bytes>>)> {
+        let owner = self.groups.get_mut(group).unwrap().seen(consumer, now);
+        let ids: Vec<StreamId> = match after.next() {
+            Some(start) => owner
+                .pending()
+                .range(start..)
+                .take(count.unwrap_or(usize::MAX))
+                .copied()
+                .collect(),
+            None => vec![],
+        };
+        let mut entries = Vec::with_capacity(ids.len());
+        for id in ids {
+            let fields = self.get(id).map(|(_, fields)| fields);
+            if fields.is_some() {
+                self.groups.get_mut(group).unwrap().redeliver(id, now);
+            }
+            entries.push((id, fields));
+        }
+        entries
+    }
+
+    /// Trims the stream from its oldest entries, returning the number of entries
+    /// removed. When `approx` is set, only whole blocks are removed, and no more than
+    /// `limit` entries unless it is zero.
+    pub fn trim(&mut self, strategy: TrimStrategy, approx: bool, limit: usize) -> usize {
+        let mut removed = 0;
+        while let Some((key, _)) = self.blocks.first() {
+            let lp = self.blocks.get_mut(&key).unwrap();
+            let (count, _) = counts(lp);
+            let entries = decode(&key, lp);
+            let whole = match strategy {
+                TrimStrategy::MaxLen(max) => self.len - count >= max,
+                TrimStrategy::MinId(min) => entries.last().unwrap().id < min,
+            };
+            if whole {
+                if approx && limit > 0 && removed + count > limit {
+                    break;
+                }
+                self.blocks.remove(&key);
+                self.len -= count;
+                removed += count;
+                continue;
+            }
+            if approx {
+                break;
+            }
+            for entry in entries.iter().filter(|entry| !entry.deleted) {
+                let done = match strategy {
+                    TrimStrategy::MaxLen(max) => self.len <= max,
+                    TrimStrategy::MinId(min) => entry.id >= min,
+                };
+                if done {
+                    break;
+                }
+                mark_deleted(lp, entry);
+                self.len -= 1;
+                removed += 1;
+            }
+            break;
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::object::EncodingLimits;
+
+    use super::{Stream, StreamId, TrimStrategy};
+
+    fn fields(items: &[&str]) -> Vec<Bytes> {
+        items.iter().map(|item| Bytes::copy_from_slice(item.as_bytes())).collect()
+    }
+
+    fn ids(entries: Vec<(StreamId, Vec<Bytes>)>) -> Vec<u64> {
+        entries.into_iter().map(|(id, _)| id.seq).collect()
+    }
+
+    #[test]
+    fn blocks_ranges_and_trims() {
+        let limits = EncodingLimits {
+            stream_node_max_entries: 4,
+            ..EncodingLimits::default()
+        };
+        let mut stream = Stream::new();
+        for seq in 1..=10 {
+            let items = if seq == 5 { fields(&["other", "x"]) } else { fields(&["f", "v"]) };
+            stream.append(StreamId::new(7, seq), &items, &limits);
+        }
+        assert_eq!(stream.len(), 10);
+        assert_eq!(stream.blocks(), 3);
+        assert!(StreamId::new(7, 3).to_key() < StreamId::new(8, 0).to_key());
+        let all = stream.range(StreamId::MIN, StreamId::MAX, false, None);
+        assert_eq!(all[4], (StreamId::new(7, 5), fields(&["other", "x"])));
+        assert_eq!(all[5], (StreamId::new(7, 6), fields(&["f", "v"])));
+        assert_eq!(
+            ids(stream.range(StreamId::new(7, 3), StreamId::new(7, 6), false, None)),
+            vec![3, 4, 5, 6]
+        );
+        assert_eq!(
+            ids(stream.range(StreamId::new(7, 3), StreamId::MAX, true, Some(2))),
+            vec![10, 9]
+        );
+
+        assert!(stream.delete(StreamId::new(7, 2)));
+        assert!(!stream.delete(StreamId::new(7, 2)));
+        assert!(!stream.delete(StreamId::new(6, 0)));
+        assert_eq!(stream.max_deleted_id(), StreamId::new(7, 2));
+        assert_eq!(stream.first_entry().unwrap().0, StreamId::new(7, 1));
+
+        // only the first block can go as a whole.
+        assert_eq!(stream.trim(TrimStrategy::MaxLen(5), true, 0), 3);
+        assert_eq!(stream.len(), 6);
+        assert_eq!(stream.trim(TrimStrategy::MaxLen(5), false, 0), 1);
+        assert_eq!(stream.first_entry().unwrap().0, StreamId::new(7, 6));
+        assert_eq!(stream.trim(TrimStrategy::MinId(StreamId::new(7, 9)), false, 0), 3);
+        assert_eq!(ids(stream.range(StreamId::MIN, StreamId::MAX, false, None)), vec![9, 10]);
+        assert_eq!(stream.blocks(), 1);
+        assert_eq!(stream.last_id(), StreamId::new(7, 10));
+        assert_eq!(stream.entries_added(), 10);
+    }
+
+    #[test]
+    fn next_and_previous_ids() {
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(StreamId::new(2, 0).prev(), Some(StreamId::new(1, u64::MAX)));
+        assert_eq!(StreamId::MIN.prev(), None);
+        assert_eq!(StreamId::new(5, 1).to_string(), "5-1");
+    }
+}
@@ -0,0 +1,138 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::util::parse_i64;
+
+/// Longest string which may be the representation of an `i64`.
+const MAX_INT_STR_LEN: usize = 20;
+/// Strings up to this length are `embstr` encoded in redis, which OBJECT ENCODING tells.
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// A string value. Strings which are the canonical representation of an integer are
+/// kept as integers, as the `int` encoding of redis, which saves memory and makes
+/// counters cheap to increment.
+///
+/// Short strings built at once are `embstr` encoded in redis, allocated along with
+/// their object so they can not grow. Strings modified in place, by APPEND or SETRANGE
+/// for instance, are `raw` encoded for good, which the two variants tell apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedisString {
+    EmbStr(Bytes),
+    Raw(Bytes),
+    Int(i64),
+}
+
+impl RedisString {
+    /// Builds a string, integer encoded when possible.
+    pub fn new(data: Bytes) -> Self {
+        if data.len() <= MAX_INT_STR_LEN {
+            if let Some(n) = parse_i64(&data) {
+                return Self::Int(n);
+            }
+        }
+        Self::plain(data)
+    }
+
+    /// Builds a string which is not integer encoded, as redis does for the result of
+    /// INCRBYFLOAT.
+    pub fn plain(data: Bytes) -> Self {
+        if data.len() <= EMBSTR_SIZE_LIMIT {
+            Self::EmbStr(data)
+        } else {
+            Self::Raw(data)
+        }
+    }
+
+    /// Returns the content of the string. Integers are formatted on each call.
+    pub fn to_bytes(&self) -> Bytes {
+        match self {
+            Self::EmbStr(data) | Self::Raw(data) => data.clone(),
+            Self::Int(n) => Bytes::from(n.to_string()),
+        }
+    }
+
+    /// Modifies the content of the string in place, which makes it `raw` encoded. The
+    /// buffer is only copied when it is shared, like by a reply which has not been
+    /// written yet.
+    pub fn modify<R>(&mut self, f: impl FnOnce(&mut BytesMut) -> R) -> R {
+        let mut data = match std::mem::replace(self, Self::Raw(Bytes::new())) {
+            Self::EmbStr(data) | Self::Raw(data) => BytesMut::from(data),
+            Self::Int(n) => BytesMut::from(n.to_string().as_bytes()),
+        };
+        let result = f(&mut data);
+        *self = Self::Raw(data.freeze());
+        result
+    }
+
+    /// Returns the integer the string represents, if any.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::EmbStr(data) | Self::Raw(data) => parse_i64(data),
+            Self::Int(n) => Some(*n),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::EmbStr(data) | Self::Raw(data) => data.len(),
+            Self::Int(n) => {
+                let mut len = if *n < 0 { 2 } else { 1 };
+                let mut n = n.unsigned_abs();
+                while n >= 10 {
+                    n /= 10;
+                    len += 1;
+                }
+                len
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as replied by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::Int(_) => "int",
+            Self::EmbStr(_) => "embstr",
+            Self::Raw(_) => "raw",
+        }
+    }
+}
+
+impl From<i64> for RedisString {
+    fn from(n: i64) -> Self {
+        Self::Int(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::RedisString;
+
+    #[test]
+    fn int_encoding() {
+        for (data, int) in [("0", true), ("-123", true), ("007", false), ("1.5", false), ("+1", false)] {
+            let s = RedisString::new(Bytes::from(data));
+            assert_eq!(matches!(s, RedisString::Int(_)), int, "{}", data);
+            assert_eq!(s.to_bytes(), data);
+            assert_eq!(s.len(), data.len());
+        }
+        assert_eq!(RedisString::from(i64::MIN).len(), 20);
+        assert_eq!(RedisString::new(Bytes::from("a".repeat(45))).encoding(), "raw");
+    }
+
+    #[test]
+    fn raw_once_modified() {
+        let mut s = RedisString::new(Bytes::from("abc"));
+        assert_eq!(s.encoding(), "embstr");
+        s.modify(|data| data.extend_from_slice(b"d"));
+        assert_eq!(s.encoding(), "raw");
+        assert_eq!(s.to_bytes(), "abcd");
+        let mut s = RedisString::from(12);
+        s.modify(|data| data[0] = b'3');
+        assert_eq!((s.encoding(), s.as_i64()), ("raw", Some(32)));
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use super::skiplist::{NodeId, SkipList};
+
+/// A range of elements of a sorted set, by score or by member.
+pub trait ZRange {
+    /// Whether the element is not before the start of the range.
+    fn above_min(&self, score: f64, member: &[u8]) -> bool;
+    /// Whether the element is not after the end of the range.
+    fn below_max(&self, score: f64, member: &[u8]) -> bool;
+    /// Whether no element can be in the range.
+    fn is_empty(&self) -> bool;
+}
+
+/// A range of scores, each end being inclusive unless it is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub min_ex: bool,
+    pub max: f64,
+    pub max_ex: bool,
+}
+
+impl ZRange for ScoreRange {
+    fn above_min(&self, score: f64, _: &[u8]) -> bool {
+        if self.min_ex {
+            score > self.min
+        } else {
+            score >= self.min
+        }
+    }
+
+    fn below_max(&self, score: f64, _: &[u8]) -> bool {
+        if self.max_ex {
+            score < self.max
+        } else {
+            score <= self.max
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min > self.max || (self.min == self.max && (self.min_ex || self.max_ex))
+    }
+}
+
+/// An end of a range of members, `-` and `+` being the lowest and the highest member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    Lowest,
+    Highest,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+/// A range of members, which is only meaningful when all the elements have the same
+/// score, so that they are ordered by member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl ZRange for LexRange {
+    fn above_min(&self, _: f64, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::Lowest => true,
+            LexBound::Highest => false,
+            LexBound::Inclusive(min) => member >= &min[..],
+            LexBound::Exclusive(min) => member > &min[..],
+        }
+    }
+
+    fn below_max(&self, _: f64, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::Lowest => false,
+            LexBound::Highest => true,
+            LexBound::Inclusive(max) => member <= &max[..],
+            LexBound::Exclusive(max) => member < &max[..],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        let (min, min_ex) = match &self.min {
+            LexBound::Lowest => return matches!(self.max, LexBound::Lowest),
+            LexBound::Highest => return true,
+            LexBound::Inclusive(min) => (min, false),
+            LexBound::Exclusive(min) => (min, true),
+        };
+        let (max, max_ex) = match &self.max {
+            LexBound::Lowest => return true,
+            LexBound::Highest => return false,
+            LexBound::Inclusive(max) => (max, false),
+            LexBound::Exclusive(max) => (max, true),
+        };
+        min > max || (min == max && (min_ex || max_ex))
+    }
+}
+
+/// A sorted set value, like redis does it with a skiplist ordering the elements by
+/// score, and a hash table giving the score of a member in O(1).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RedisZSet {
+    dict: HashMap<Bytes, f64>,
+    list: SkipList,
+}
+
+impl RedisZSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Name of the encoding, as replied by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        "skiplist"
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.dict.get(member).copied()
+    }
+
+    /// Adds the member or updates its score, returning the score it had.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> Option<f64> {
+        let old = self.score(member);
+        match old {
+            Some(old) if old == score => return Some(old),
+            Some(old) => {
+                self.list.delete(old, member);
+            }
+            None => {}
+        }
+        let member = Bytes::copy_from_slice(member);
+        self.list.insert(score, member.clone());
+        self.dict.insert(member, score);
+        old
+    }
+
+    /// Removes the member, and tells whether it was there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.dict.remove(member) {
+            Some(score) => self.list.delete(score, member),
+            None => false,
+        }
+    }
+
+    /// Rank of the member, zero based, from the highest score when `rev` is set. The
+    /// score of the member comes along.
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<(usize, f64)> {
+        let score = self.score(member)?;
+        let rank = self.list.rank(score, member)?;
+        Some((if rev { self.len() - 1 - rank } else { rank }, score))
+    }
+
+    /// Element at the rank, zero based, found in O(log n) like ranks are.
+    pub fn get_by_rank(&self, rank: usize) -> Option<(&Bytes, f64)> {
+        self.list.by_rank(rank).map(|node| self.list.get(node))
+    }
+
+    fn entry(&self, node: NodeId) -> (Bytes, f64) {
+        let (member, score) = self.list.get(node);
+        (member.clone(), score)
+    }
+
+    /// Elements from rank `start` to rank `stop`, both included and within the set.
+    pub fn range_by_rank(&self, start: usize, stop: usize, rev: bool) -> Vec<(Bytes, f64)> {
+        let first = if rev { self.len() - 1 - start } else { start };
+        let mut node = self.list.by_rank(first);
+        let mut elements = Vec::with_capacity(stop - start + 1);
+        while let Some(x) = node {
+            if elements.len() > stop - start {
+                break;
+            }
+            elements.push(self.entry(x));
+            node = if rev { self.list.prev(x) } else { self.list.next(x) };
+        }
+        elements
+    }
+
+    /// Elements in the range, from the end of the range when `rev` is set. The first
+    /// `offset` elements are skipped, by rank so that it costs O(log n), and at most
+    /// `count` elements are returned.
+    pub fn range(
+        &self,
+        range: &impl ZRange,
+        rev: bool,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Vec<(Bytes, f64)> {
+        let start = if rev { self.list.last_in(range) } else { self.list.first_in(range) };
+        let mut node = match start {
+            Some(start) if offset > 0 => {
+                let (member, score) = self.list.get(start);
+                let rank = self.list.rank(score, member).unwrap();
+                let rank = if rev { rank.checked_sub(offset) } else { Some(rank + offset) };
+                rank.and_then(|rank| self.list.by_rank(rank))
+            }
+            start => start,
+        };
+        let mut elements = vec![];
+        while let Some(x) = node {
+            if count == Some(elements.len()) {
+                break;
+            }
+            let (member, score) = self.list.get(x);
+            let in_range = if rev {
+                range.above_min(score, member)
+            } else {
+                range.below_max(score, member)
+            };
+            if !in_range {
+                break;
+            }
+            elements.push(self.entry(x));
+            node = if rev { self.list.prev(x) } else { self.list.next(x) };
+        }
+        elements
+    }
+
+    /// Number of elements in the range, found in O(log n) from the ranks of its ends.
+    pub fn count(&self, range: &impl ZRange) -> usize {
+        let rank = |node| {
+            let (member, score) = self.list.get(node);
+            self.list.rank(score, member).unwrap()
+        };
+        match (self.list.first_in(range), self.list.last_in(range)) {
+            (Some(first), Some(last)) => rank(last) - rank(first) + 1,
+            _ => 0,
+        }
+    }
+
+    /// Removes the element with the lowest score, or the highest one when `max` is set.
+    pub fn pop(&mut self, max: bool) -> Option<(Bytes, f64)> {
+        let node = if max { self.list.last() } else { self.list.first() }?;
+        let (member, score) = self.entry(node);
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// Iterates the elements in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> + '_ {
+        std::iter::successors(self.list.first(), |&node| self.list.next(node))
+            .map(|node| self.list.get(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{LexBound, LexRange, RedisZSet, ScoreRange, ZRange};
+
+    #[test]
+    fn scores_ranks_and_ranges() {
+        let mut zset = RedisZSet::new();
+        for (member, score) in [("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 4.0)] {
+            assert_eq!(zset.insert(member.as_bytes(), score), None);
+        }
+        assert_eq!(zset.insert(b"a", 5.0), Some(1.0));
+        assert_eq!(zset.insert(b"a", 5.0), Some(5.0));
+        assert_eq!(zset.rank(b"a", false), Some((3, 5.0)));
+        assert_eq!(zset.rank(b"a", true), Some((0, 5.0)));
+        assert_eq!(zset.rank(b"x", false), None);
+        let members = |elements: Vec<(Bytes, f64)>| -> Vec<Bytes> {
+            elements.into_iter().map(|(member, _)| member).collect()
+        };
+        assert_eq!(members(zset.range_by_rank(1, 2, false)), vec!["c", "d"]);
+        assert_eq!(members(zset.range_by_rank(0, 3, true)), vec!["a", "d", "c", "b"]);
+
+        let range = ScoreRange {
+            min: 2.0,
+            min_ex: false,
+            max: 5.0,
+            max_ex: true,
+        };
+        assert_eq!(members(zset.range(&range, false, 0, None)), vec!["b", "c", "d"]);
+        assert_eq!(members(zset.range(&range, false, 1, Some(1))), vec!["c"]);
+        assert_eq!(members(zset.range(&range, true, 1, None)), vec!["c", "b"]);
+        assert_eq!(members(zset.range(&range, true, 3, None)), Vec::<Bytes>::new());
+        assert_eq!(zset.count(&range), 3);
+
+        let mut lex = RedisZSet::new();
+        for member in ["a", "b", "c", "d"] {
+            lex.insert(member.as_bytes(), 0.0);
+        }
+        let range = LexRange {
+            min: LexBound::Exclusive(Bytes::from("b")),
+            max: LexBound::Highest,
+        };
+        assert_eq!(lex.count(&range), 2);
+        assert_eq!(members(lex.range(&range, true, 0, Some(1))), vec!["d"]);
+        assert!(LexRange {
+            min: LexBound::Inclusive(Bytes::from("b")),
+            max: LexBound::Exclusive(Bytes::from("b")),
+        }
+        .is_empty());
+
+        assert_eq!(zset.pop(true), Some((Bytes::from("a"), 5.0)));
+        assert_eq!(zset.pop(false), Some((Bytes::from("b"), 2.0)));
+        assert!(zset.remove(b"c"));
+        assert!(!zset.remove(b"c"));
+        assert_eq!(zset.iter().collect::<Vec<_>>(), vec![(&Bytes::from("d"), 4.0)]);
+    }
+}
@@ -1,43 +1,40 @@
-use std::io::Write;
-
 use async_trait::async_trait;
-use log::debug;
-use tokio::io::AsyncBufReadExt;
-use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use bytes::{Bytes, BytesMut};
+use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncWrite, BufWriter};
 
 use crate::error::{Error, Result};
 
-use super::{
-    Protocol,
-};
-
-const PREFIX_SIMPLE_STRING: u8 = '+' as u8;
-const PREFIX_ERROR: u8 = '-' as u8;
-const PREFIX_INTEGER: u8 = ':' as u8;
-const PREFIX_BULK_STRING: u8 = '$' as u8;
-const PREFIX_ARRAY: u8 = '*' as u8;
-
-#[derive(Debug)]
-struct LeadingUnit {
-    /// for type
-    prefix_char: u8,
-    data: Vec<u8>,
+use super::{constants, Limits, Protocol};
+
+const PREFIX_SIMPLE_STRING: u8 = b'+';
+const PREFIX_ERROR: u8 = b'-';
+const PREFIX_INTEGER: u8 = b':';
+const PREFIX_BULK_STRING: u8 = b'$';
+const PREFIX_ARRAY: u8 = b'*';
+
+pub(super) async fn write_decimal<W>(w: &mut BufWriter<W>, i: i64) -> Result<usize>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let digits = i.to_string();
+    w.write_all(digits.as_bytes()).await?;
+    Ok(digits.len())
 }
 
-impl LeadingUnit {
-    fn read_int(&self) -> Option<i64> {
-        parse_int(&self.data)
-    }
+pub(super) fn parse_int(src: &[u8]) -> Option<i64> {
+    std::str::from_utf8(src).ok()?.parse::<i64>().ok()
 }
 
-fn parse_int(src: &Vec<u8>) -> Option<i64> {
-    match String::from_utf8(src.clone()) {
-        Ok(s) => match s.parse::<i64>() {
-            Ok(i) => Some(i),
-            Err(_) => None,
-        },
-        Err(_) => None,
+/// Returns the position of the `\r` which ends the line starting at `from`.
+pub(super) fn find_crlf(buf: &[u8], from: usize) -> Option<usize> {
+    if from >= buf.len() {
+        return None;
     }
+    buf[from..]
+        .windows(constants::CRLF.len())
+        .position(|w| w == constants::CRLF)
+        .map(|offset| from + offset)
 }
 
 /// Messages by RESP consist of lines, which is called unit by myself.
@@ -50,86 +47,204 @@ fn parse_int(src: &Vec<u8>) -> Option<i64> {
 /// 1. a `char` as prefix, indicates what type the unit is.
 /// 2. content of the unit. it may be an integer or a string.
 /// 3. \r\n, a fixed suffix ends the unit.
+///
 /// A payload unit, on the other hand, is much simpler than the leading one. It merely consists of
 /// two parts: a string of content and a \r\n suffix. And the length of the string is given by
 /// its previous leading unit.
-#[derive(Debug)]
-enum Unit {
-    Leading(LeadingUnit),
-    Payload(Vec<u8>),
+///
+/// Decoding is done in two passes over the read buffer. `scan` walks the units without
+/// copying anything, and tells whether a whole piece has been received and where it ends.
+/// Only then is the piece split off the buffer and parsed, with every string being a
+/// `Bytes` slice of the buffer.
+pub(super) struct Scanner<'a> {
+    buf: &'a [u8],
+    /// Enables the types which are only available in RESP3.
+    resp3: bool,
+    limits: &'a Limits,
+}
+
+impl<'a> Scanner<'a> {
+    pub(super) fn new(buf: &'a [u8], resp3: bool, limits: &'a Limits) -> Self {
+        Self { buf, resp3, limits }
+    }
+
+    /// Returns where the piece starting at `pos` ends, or `None` if it has not been
+    /// received completely.
+    pub(super) fn scan(&self, pos: usize) -> Result<Option<usize>> {
+        self.scan_nested(pos, 0)
+    }
+
+    fn scan_nested(&self, pos: usize, depth: usize) -> Result<Option<usize>> {
+        if pos >= self.buf.len() {
+            return Ok(None);
+        }
+        if depth > self.limits.max_nesting_depth {
+            return Err(Error::BrokenProtocol("too deep nesting of aggregates".into()));
+        }
+        let line_end = match find_crlf(self.buf, pos + 1) {
+            Some(line_end) => line_end,
+            None if self.buf.len() - pos > constants::PROTO_INLINE_MAX_SIZE => {
+                return Err(Error::BrokenProtocol("too big count string".into()))
+            }
+            None => return Ok(None),
+        };
+        let prefix = self.buf[pos];
+        let line = &self.buf[pos + 1..line_end];
+        let next = line_end + constants::CRLF.len();
+        match prefix {
+            b'+' | b'-' | b':' => Ok(Some(next)),
+            b'_' | b'#' | b',' | b'(' if self.resp3 => Ok(Some(next)),
+            b'$' => self.scan_blob(line, next, true),
+            b'!' | b'=' if self.resp3 => self.scan_blob(line, next, false),
+            b'*' => self.scan_aggregate(line, next, depth, 1, true),
+            b'~' | b'>' if self.resp3 => self.scan_aggregate(line, next, depth, 1, false),
+            b'%' if self.resp3 => self.scan_aggregate(line, next, depth, 2, false),
+            b'|' if self.resp3 => match self.scan_aggregate(line, next, depth, 2, false)? {
+                // attributes are followed by the piece they decorate.
+                Some(end) => self.scan_nested(end, depth),
+                None => Ok(None),
+            },
+            _ => Err(Error::BrokenProtocol(format!(
+                "unknown type prefix '{}'",
+                prefix as char
+            ))),
+        }
+    }
+
+    fn scan_blob(&self, line: &[u8], next: usize, nullable: bool) -> Result<Option<usize>> {
+        let len = match parse_int(line) {
+            Some(-1) if nullable => return Ok(Some(next)),
+            Some(len) if len >= 0 && len as usize <= self.limits.max_bulk_len => len as usize,
+            _ => return Err(Error::BrokenProtocol("invalid bulk length".into())),
+        };
+        let end = next + len + constants::CRLF.len();
+        if self.buf.len() < end {
+            return Ok(None);
+        }
+        if &self.buf[end - constants::CRLF.len()..end] != constants::CRLF {
+            return Err(Error::BrokenProtocol(
+                "bulk string is not terminated by CRLF".into(),
+            ));
+        }
+        Ok(Some(end))
+    }
+
+    fn scan_aggregate(
+        &self,
+        line: &[u8],
+        next: usize,
+        depth: usize,
+        width: usize,
+        nullable: bool,
+    ) -> Result<Option<usize>> {
+        let len = match parse_int(line) {
+            Some(-1) if nullable => return Ok(Some(next)),
+            Some(len) if len >= 0 && len as usize <= self.limits.max_multibulk_len => {
+                len as usize
+            }
+            _ => return Err(Error::BrokenProtocol("invalid multibulk length".into())),
+        };
+        let mut pos = next;
+        for _ in 0..len * width {
+            match self.scan_nested(pos, depth + 1)? {
+                Some(end) => pos = end,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(pos))
+    }
 }
 
+/// Reads pieces from a frame which has been checked by [`Scanner`], so the frame is
+/// known to be complete and well delimited.
+pub(super) struct FrameReader {
+    frame: Bytes,
+    pos: usize,
+}
+
+impl FrameReader {
+    pub(super) fn new(frame: Bytes) -> Self {
+        Self { frame, pos: 0 }
+    }
+
+    /// Returns the prefix and the content of the next line.
+    pub(super) fn read_line(&mut self) -> (u8, Bytes) {
+        let line_end = find_crlf(&self.frame, self.pos).unwrap();
+        let prefix = self.frame[self.pos];
+        let data = self.frame.slice(self.pos + 1..line_end);
+        self.pos = line_end + constants::CRLF.len();
+        (prefix, data)
+    }
+
+    pub(super) fn read_int(data: &[u8]) -> Result<i64> {
+        parse_int(data).ok_or_else(|| Error::BrokenProtocol("invalid integer".into()))
+    }
+
+    /// Reads a payload of `len` bytes, the payload may contain any byte including
+    /// `\r`, `\n` and `\0`.
+    pub(super) fn read_payload(&mut self, len: usize) -> Bytes {
+        let data = self.frame.slice(self.pos..self.pos + len);
+        self.pos += len + constants::CRLF.len();
+        data
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum RawPiece {
-    SimpleString { data: Vec<u8> },
-    Error { typ: Vec<u8>, cause: Vec<u8> },
+    SimpleString { data: Bytes },
+    Error { typ: Bytes, cause: Bytes },
     Integer(i64),
-    BulkString { data: Vec<u8> },
+    BulkString { data: Bytes },
     Array(Vec<RawPiece>),
+    /// Null bulk string, `$-1\r\n`.
     Null,
+    /// Null array, `*-1\r\n`.
+    NullArray,
 }
 
 impl RawPiece {
-    pub fn read_string(&self) -> Option<&Vec<u8>> {
+    pub fn read_string(&self) -> Option<&Bytes> {
         match self {
-            RawPiece::SimpleString { data } => Some(&data),
-            RawPiece::BulkString { data } => Some(&data),
+            RawPiece::SimpleString { data } => Some(data),
+            RawPiece::BulkString { data } => Some(data),
             _ => None,
         }
     }
 
-    fn space_pos(data: &[u8]) -> Option<usize> {
-        for (pos, c) in data.iter().enumerate() {
-            if *c as char == ' ' {
-                return Some(pos);
-            }
+    pub(super) fn split_error(data: Bytes) -> (Bytes, Bytes) {
+        match data.iter().position(|&c| c == b' ') {
+            Some(offset) => (data.slice(..offset), data.slice(offset + 1..)),
+            None => (data, Bytes::new()),
         }
-        None
     }
 
-    async fn read_unit<R: AsyncRead + Unpin>(r: &mut BufReader<R>) -> Result<Unit> {
-        let mut buffer = bytes::BytesMut::new();
-        loop {
-            let mut cur = Vec::new();
-            debug!("read line before: {:?}, bufferLen: {}", cur, buffer.len());
-            match r.read_until('\n' as u8, &mut cur).await {
-                Ok(read_size) => {
-                    if read_size == 0 {
-                        if buffer.len() == 0 {
-                            return Err(Error::EOF);
-                        } else {
-                            break;
-                        }
-                    } else {
-                        // let cur = cur.chars().filter(|&c| c!='\0').map(|c| c as u8).collect::<Vec<char>>();
-                        let cur = cur.iter().map(|&c| c).filter(|&c| c != '\0' as u8).collect::<Vec<u8>>();
-                        debug!("Got buf({:?}): {} {:?}", read_size, cur.len(), cur);
-                        if cur.len() > 0 {
-                            buffer.extend(&cur);
-                            // buffer.extend_from_slice(&cur);
-                            // buf.push_str(cur.trim_start());
-                        }
-                        
-                        if cur.ends_with(&['\r' as u8, '\n' as u8]) {
-                            break;
-                        }
+    fn read(r: &mut FrameReader) -> Result<Self> {
+        let (prefix, data) = r.read_line();
+        match prefix {
+            PREFIX_SIMPLE_STRING => Ok(Self::SimpleString { data }),
+            PREFIX_ERROR => {
+                let (typ, cause) = Self::split_error(data);
+                Ok(Self::Error { typ, cause })
+            }
+            PREFIX_INTEGER => Ok(Self::Integer(FrameReader::read_int(&data)?)),
+            PREFIX_BULK_STRING => match FrameReader::read_int(&data)? {
+                -1 => Ok(Self::Null),
+                len => Ok(Self::BulkString {
+                    data: r.read_payload(len as usize),
+                }),
+            },
+            PREFIX_ARRAY => match FrameReader::read_int(&data)? {
+                -1 => Ok(Self::NullArray),
+                len => {
+                    let mut arr = Vec::with_capacity(len as usize);
+                    for _ in 0..len {
+                        arr.push(Self::read(r)?);
                     }
+                    Ok(Self::Array(arr))
                 }
-                Err(e) => return Err(Error::IO(e)),
-            }
+            },
+            _ => unreachable!("rejected by the scanner"),
         }
-        let data = buffer.to_vec();
-        // let data: Vec<char> = buffer.chars().collect();
-        debug!("read line: `{:?}` buf.len={} data[0] = {:?} data={:?}", buffer, buffer.len(), data[0] as char, data);
-        let unit = match data[0] as char {
-            '+' | '-' | ':' | '$' | '*' => Unit::Leading(LeadingUnit {
-                prefix_char: data[0],
-                data: data[1..].to_vec(),
-            }),
-            _ => Unit::Payload(data.to_vec()),
-        };
-        debug!("Got Unit: {:?}", unit);
-        Ok(unit)
-        // Err(Error::EOF)
     }
 }
 
@@ -143,138 +258,225 @@ impl Protocol for RawPiece {
             RawPiece::BulkString { data: _ } => PREFIX_BULK_STRING,
             RawPiece::Array(_) => PREFIX_ARRAY,
             RawPiece::Null => PREFIX_BULK_STRING,
+            RawPiece::NullArray => PREFIX_ARRAY,
         }
     }
 
-    // fn marshal<W: AsyncWrite>(&self, w: &mut BufWriter<W>) -> Result<usize> {
-    //     let mut total_size = w.write(&vec![self.prefix(); 1])?;
-    //     match *self {
-    //         RawPiece::SimpleString { data } => {
-    //             total_size += w.write(&data)?;
-    //             total_size += w.write(&constants::CRLF)?;
-    //         }
-    //         RawPiece::Error { typ, cause } => {
-    //             total_size += w.write(&typ)?;
-    //             if cause.len() > 0 {
-    //                 total_size += w.write(&vec![' ' as u8; 1])?;
-    //                 total_size += w.write(&cause)?;
-    //             }
-    //         }
-    //         RawPiece::Integer(i) => {
-    //             total_size += w.write(i.to_string().as_bytes())?;
-    //         }
-    //         RawPiece::BulkString { data } => {
-    //             total_size += w.write(data.len().to_string().as_bytes())?;
-    //             total_size += w.write(&constants::CRLF)?;
-    //             total_size += w.write(&data)?;
-    //         }
-    //         RawPiece::Array(arr) => {
-    //             total_size += w.write(arr.len().to_string().as_bytes())?;
-    //             let sum: Result<usize> = arr.iter().map(|each| each.marshal(w)).sum();
-    //             total_size += sum?;
-    //         }
-    //         RawPiece::Null => {
-    //             total_size += w.write((-1).to_string().as_bytes())?;
-    //         }
-    //     };
-    //     total_size += w.write(&constants::CRLF)?;
-    //     Ok(total_size)
-    // }
-
-    async fn parse<R>(r: &mut BufReader<R>) -> Result<Self>
+    async fn marshal<W>(&self, w: &mut BufWriter<W>) -> Result<usize>
     where
-        R: AsyncRead + Unpin + Send,
+        W: AsyncWrite + Unpin + Send,
     {
-        // let mut wrap = BufReader::new(r);
-        match Self::read_unit(r).await? {
-            Unit::Leading(LeadingUnit { prefix_char, data }) => match prefix_char {
-                PREFIX_SIMPLE_STRING => Ok(Self::SimpleString { data }),
-                PREFIX_ERROR => {
-                    let offset = Self::space_pos(&data);
-                    let (typ, cause) = if let Some(offset) = offset {
-                        (data[..offset].to_vec(), data[offset + 1..].to_vec())
-                    } else {
-                        (data, vec![])
-                    };
-                    Ok(Self::Error { typ, cause })
-                }
-                PREFIX_INTEGER => {
-                    if let Some(i) = parse_int(&data) {
-                        Ok(Self::Integer(i))
-                    } else {
-                        Err(Error::BrokenProtocol("invalid integer".into()))
-                    }
-                }
-                PREFIX_BULK_STRING => {
-                    let len = parse_int(&data);
-                    if len.is_none() {
-                        return Err(Error::BrokenProtocol(
-                            "invalid length of bulk string".into(),
-                        ));
-                    }
-                    let len = len.unwrap();
-                    if len == 0 {
-                        return Ok(Self::BulkString { data: Vec::new() });
-                    } else if len == -1 {
-                        return Ok(Self::Null);
-                    } else if len < 0 {
-                        return Err(Error::BrokenProtocol("invalid length given".into()));
-                    }
-                    let len = len as usize;
-                    let payload_unit = Self::read_unit(r).await?;
-                    match payload_unit {
-                        Unit::Leading(_) => {
-                            return Err(Error::BrokenProtocol("missing payload unit".into()))
-                        }
-                        Unit::Payload(data) => {
-                            if data.len() != len {
-                                return Err(Error::BrokenProtocol(
-                                    "length of payload mismatches".into(),
-                                ));
-                            }
-                            Ok(Self::BulkString { data })
-                        }
-                    }
+        w.write_all(&[self.prefix()]).await?;
+        let mut total_size = 1;
+        match self {
+            RawPiece::SimpleString { data } => {
+                w.write_all(data).await?;
+                total_size += data.len();
+            }
+            RawPiece::Error { typ, cause } => {
+                w.write_all(typ).await?;
+                total_size += typ.len();
+                if !cause.is_empty() {
+                    w.write_all(b" ").await?;
+                    w.write_all(cause).await?;
+                    total_size += cause.len() + 1;
                 }
-                PREFIX_ARRAY => {
-                    let len = parse_int(&data);
-                    if len.is_none() {
-                        return Err(Error::BrokenProtocol(
-                            "invalid length of bulk string".into(),
-                        ));
-                    }
-                    let len = len.unwrap() as usize;
-                    let mut arr = Vec::with_capacity(len);
-                    if len == 0 {
-                        return Ok(Self::Array(arr));
-                    }
-                    for _ in 0..len {
-                        let piece = Self::parse::<R>(r).await?;
-                        arr.push(piece);
-                    }
-                    Ok(Self::Array(arr))
+            }
+            RawPiece::Integer(i) => {
+                total_size += write_decimal(w, *i).await?;
+            }
+            RawPiece::BulkString { data } => {
+                total_size += write_decimal(w, data.len() as i64).await?;
+                w.write_all(constants::CRLF).await?;
+                w.write_all(data).await?;
+                total_size += constants::CRLF.len() + data.len();
+            }
+            RawPiece::Array(arr) => {
+                total_size += write_decimal(w, arr.len() as i64).await?;
+                w.write_all(constants::CRLF).await?;
+                total_size += constants::CRLF.len();
+                for each in arr {
+                    total_size += each.marshal(w).await?;
                 }
-                _ => panic!("never here"),
-            },
-            Unit::Payload(unit) => Err(Error::BrokenProtocol("missing leading unit".into())),
-        }
+                // every element has ended with its own CRLF.
+                return Ok(total_size);
+            }
+            RawPiece::Null | RawPiece::NullArray => {
+                total_size += write_decimal(w, -1).await?;
+            }
+        };
+        w.write_all(constants::CRLF).await?;
+        total_size += constants::CRLF.len();
+        Ok(total_size)
+    }
+
+    fn decode(buf: &mut BytesMut, limits: &Limits) -> Result<Option<Self>> {
+        let end = match Scanner::new(buf, false, limits).scan(0)? {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let mut r = FrameReader::new(buf.split_to(end).freeze());
+        Self::read(&mut r).map(Some)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::BufWriter;
+    use bytes::{Bytes, BytesMut};
+    use tokio::io::{AsyncWriteExt, BufWriter};
 
-    use crate::protocol::Protocol;
+    use crate::protocol::{Limits, Protocol};
 
     use super::RawPiece;
 
+    async fn encode(piece: &RawPiece) -> (usize, Vec<u8>) {
+        let mut buffer = BufWriter::new(Vec::new());
+        let size = piece.marshal(&mut buffer).await.unwrap();
+        buffer.flush().await.unwrap();
+        (size, buffer.into_inner())
+    }
+
+    fn decode(input: &[u8]) -> crate::error::Result<RawPiece> {
+        let mut buf = BytesMut::from(input);
+        let piece = RawPiece::decode(&mut buf, &Limits::default())
+            .map(|p| p.expect("incomplete piece"));
+        if piece.is_ok() {
+            assert!(buf.is_empty());
+        }
+        piece
+    }
+
+    fn bulk(data: &'static [u8]) -> RawPiece {
+        RawPiece::BulkString {
+            data: Bytes::from_static(data),
+        }
+    }
+
+    #[test]
+    fn parse_binary_safe_bulk_string() {
+        let cases: Vec<(&[u8], &'static [u8])> = vec![
+            (b"$0\r\n\r\n", b""),
+            (b"$5\r\nhello\r\n", b"hello"),
+            (b"$4\r\na\r\nb\r\n", b"a\r\nb"),
+            (b"$3\r\n\n\n\n\r\n", b"\n\n\n"),
+            (b"$4\r\n\0a\0\0\r\n", b"\0a\0\0"),
+            (b"$2\r\n\r\n\r\n", b"\r\n"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(decode(input).unwrap(), bulk(expected));
+        }
+    }
+
+    #[test]
+    fn parse_array_of_binary_bulk_strings() {
+        let input = b"*3\r\n$3\r\nset\r\n$1\r\n\0\r\n$4\r\n\r\n\0\n\r\n";
+        assert_eq!(
+            decode(input).unwrap(),
+            RawPiece::Array(vec![bulk(b"set"), bulk(b"\0"), bulk(b"\r\n\0\n")])
+        );
+    }
+
+    #[test]
+    fn parse_bulk_string_errors() {
+        assert!(matches!(
+            decode(b"$3\r\nabcd\r\n"),
+            Err(crate::error::Error::BrokenProtocol(_))
+        ));
+        assert_eq!(decode(b"$-1\r\n").unwrap(), RawPiece::Null);
+        assert_eq!(decode(b"*-1\r\n").unwrap(), RawPiece::NullArray);
+    }
+
+    #[test]
+    fn decode_partial_frames() {
+        let input = b"*2\r\n$3\r\nget\r\n$3\r\nkey\r\n:1\r\n";
+        let mut buf = BytesMut::new();
+        // feeding byte by byte, the array is only returned once it is complete.
+        for (i, c) in input[..input.len() - 4].iter().enumerate() {
+            buf.extend_from_slice(&[*c]);
+            let piece = RawPiece::decode(&mut buf, &Limits::default()).unwrap();
+            if i + 1 < input.len() - 4 {
+                assert_eq!(piece, None);
+            } else {
+                assert_eq!(piece, Some(RawPiece::Array(vec![bulk(b"get"), bulk(b"key")])));
+            }
+        }
+        assert!(buf.is_empty());
+        buf.extend_from_slice(&input[input.len() - 4..]);
+        assert_eq!(
+            RawPiece::decode(&mut buf, &Limits::default()).unwrap(),
+            Some(RawPiece::Integer(1))
+        );
+    }
+
     #[test]
-    fn marshal() {
-        // let input = RawPiece::Array(vec![]);
-        // let inner = Vec::new();
-        // let mut buffer = BufWriter::new(inner);
-        // assert_eq!(input.marshal(&mut buffer).unwrap(), 4);
-        // assert_eq!(inner, b"*0\r\n".to_vec());
+    fn decode_beyond_limits() {
+        let limits = Limits {
+            max_bulk_len: 4,
+            max_multibulk_len: 2,
+            max_nesting_depth: 2,
+        };
+        let cases: Vec<&[u8]> = vec![
+            b"$5\r\nhello\r\n",
+            b"*3\r\n:1\r\n:2\r\n:3\r\n",
+            b"*1\r\n*1\r\n*1\r\n*1\r\n:1\r\n",
+            b"*-2\r\n",
+            b"$-2\r\n",
+            // the length is checked before the payload arrives.
+            b"*999999999\r\n",
+        ];
+        for input in cases {
+            let mut buf = BytesMut::from(input);
+            assert!(matches!(
+                RawPiece::decode(&mut buf, &limits),
+                Err(crate::error::Error::BrokenProtocol(_))
+            ));
+        }
+        let mut buf = BytesMut::from(&b"*1\r\n*1\r\n*0\r\n"[..]);
+        assert!(RawPiece::decode(&mut buf, &limits).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn marshal() {
+        let cases = vec![
+            (RawPiece::Array(vec![]), b"*0\r\n".to_vec()),
+            (
+                RawPiece::SimpleString {
+                    data: Bytes::from_static(b"OK"),
+                },
+                b"+OK\r\n".to_vec(),
+            ),
+            (
+                RawPiece::Error {
+                    typ: Bytes::from_static(b"ERR"),
+                    cause: Bytes::from_static(b"unknown command"),
+                },
+                b"-ERR unknown command\r\n".to_vec(),
+            ),
+            (
+                RawPiece::Error {
+                    typ: Bytes::from_static(b"ERR"),
+                    cause: Bytes::new(),
+                },
+                b"-ERR\r\n".to_vec(),
+            ),
+            (RawPiece::Integer(-42), b":-42\r\n".to_vec()),
+            (bulk(b"a\r\nb"), b"$4\r\na\r\nb\r\n".to_vec()),
+            (bulk(b""), b"$0\r\n\r\n".to_vec()),
+            (RawPiece::Null, b"$-1\r\n".to_vec()),
+            (RawPiece::NullArray, b"*-1\r\n".to_vec()),
+            (
+                RawPiece::Array(vec![
+                    RawPiece::Integer(1),
+                    RawPiece::Array(vec![bulk(b"x")]),
+                    RawPiece::Null,
+                ]),
+                b"*3\r\n:1\r\n*1\r\n$1\r\nx\r\n$-1\r\n".to_vec(),
+            ),
+        ];
+        for (piece, expected) in cases {
+            let (size, out) = encode(&piece).await;
+            assert_eq!(out, expected);
+            assert_eq!(size, expected.len());
+        }
     }
 }
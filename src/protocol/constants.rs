@@ -0,0 +1,9 @@
+pub const CRLF: &[u8] = b"\r\n";
+
+/// Max length of an inline request, or of a line holding a length. Same as redis.
+pub const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+
+/// Bulk arguments from this length on are big ones, for which redis reads straight into
+/// the argument. No more than this is reserved ahead of the bytes of an argument, so
+/// that a bulk header alone does not make the server allocate the declared length.
+pub const PROTO_MBULK_BIG_ARG: usize = 32 * 1024;
@@ -1,19 +1,65 @@
 use async_trait::async_trait;
-use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use bytes::BytesMut;
+use tokio::io::{AsyncWrite, BufWriter};
 
-use crate::error::Result;
+use crate::{config::Config, error::Result};
 
 mod base;
-pub(self) mod constants;
+mod constants;
+pub mod request;
 pub mod resp2;
 pub mod resp3;
 pub use base::*;
+pub use request::RequestDecoder;
+pub use resp3::Resp3Piece;
+
+/// Version of RESP spoken on a connection. Every connection starts with RESP2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    Resp2,
+    Resp3,
+}
+
+/// Bounds on what a peer is allowed to send, so that a malformed or malicious request
+/// can not make the server allocate without limit.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Max length of a bulk string.
+    pub max_bulk_len: usize,
+    /// Max number of elements of an aggregate, such as the arguments of a request.
+    pub max_multibulk_len: usize,
+    /// Max depth of nested aggregates.
+    pub max_nesting_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::from(&Config::default())
+    }
+}
+
+impl From<&Config> for Limits {
+    fn from(conf: &Config) -> Self {
+        Self {
+            max_bulk_len: conf.proto_max_bulk_len,
+            max_multibulk_len: conf.proto_max_multibulk_len,
+            max_nesting_depth: MAX_NESTING_DEPTH,
+        }
+    }
+}
+
+/// Aggregates nested deeper than this are rejected, to bound the recursion of decoding.
+pub const MAX_NESTING_DEPTH: usize = 128;
 
 #[async_trait]
 pub trait Protocol: Sized {
     fn prefix(&self) -> u8;
-    // async fn marshal<W: AsyncWrite>(&self, w: &mut BufWriter<W>) -> Result<usize>;
-    async fn parse<R>(r: &mut BufReader<R>) -> Result<Self>
+    /// Writes the encoded piece into `w` and returns the number of bytes written.
+    /// Nothing is flushed here, the caller decides when to flush.
+    async fn marshal<W>(&self, w: &mut BufWriter<W>) -> Result<usize>
     where
-        R: AsyncRead + Unpin + Send;
+        W: AsyncWrite + Unpin + Send;
+    /// Decodes a piece from the front of `buf`. `Ok(None)` is returned and `buf` is left
+    /// untouched if the piece has not been received completely.
+    fn decode(buf: &mut BytesMut, limits: &Limits) -> Result<Option<Self>>;
 }
@@ -0,0 +1,359 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::error::{Error, Result};
+
+use super::{constants, find_crlf, parse_int, Limits};
+
+/// Incremental decoder of client requests, which works on the read buffer of a client
+/// like `processInputBuffer` of redis does.
+///
+/// A request starting with `*` is a multibulk request, and anything else is an inline
+/// command terminated by a newline, such as `set key "hello world"` typed from telnet.
+/// The progress of a partially received multibulk request is kept in the decoder, so
+/// bytes already consumed are never examined again. Arguments are copied out of the
+/// buffer, like redis makes an object of each: they are stored as keys and values, and
+/// a slice of the buffer would keep all of it alive for as long as they are.
+///
+/// Requests beyond `limits` are rejected with [`Error::BrokenProtocol`] before anything
+/// is allocated for them, after which the connection is expected to be closed.
+#[derive(Debug, Default)]
+pub struct RequestDecoder {
+    limits: Limits,
+    /// Number of arguments of the multibulk request in progress, 0 if there is none.
+    multibulk_len: usize,
+    /// Length of the next bulk argument, if its header has been consumed.
+    bulk_len: Option<usize>,
+    args: Vec<Bytes>,
+}
+
+impl RequestDecoder {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Finds the end of a line holding a length, which must be short.
+    fn find_count_line(buf: &[u8], what: &str) -> Result<Option<usize>> {
+        match find_crlf(buf, 0) {
+            None if buf.len() > constants::PROTO_INLINE_MAX_SIZE => Err(Error::BrokenProtocol(
+                format!("too big {} count string", what),
+            )),
+            line_end => Ok(line_end),
+        }
+    }
+
+    /// Decodes the next request from `buf`, consuming its bytes. `Ok(None)` means more
+    /// data is needed. Empty requests are skipped silently, as redis does.
+    pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<Bytes>>> {
+        loop {
+            if self.multibulk_len == 0 {
+                if buf.is_empty() {
+                    return Ok(None);
+                }
+                if buf[0] != b'*' {
+                    match self.decode_inline(buf)? {
+                        Some(args) if args.is_empty() => continue,
+                        other => return Ok(other),
+                    }
+                }
+                let line_end = match Self::find_count_line(buf, "mbulk")? {
+                    Some(line_end) => line_end,
+                    None => return Ok(None),
+                };
+                let len = match parse_int(&buf[1..line_end]) {
+                    Some(len) if len <= self.limits.max_multibulk_len as i64 => len,
+                    _ => return Err(Error::BrokenProtocol("invalid multibulk length".into())),
+                };
+                buf.advance(line_end + constants::CRLF.len());
+                if len <= 0 {
+                    continue;
+                }
+                self.multibulk_len = len as usize;
+                self.args = Vec::with_capacity(self.multibulk_len.min(1024));
+            }
+            return self.decode_multibulk(buf);
+        }
+    }
+
+    fn decode_inline(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<Bytes>>> {
+        let line_end = match buf.iter().position(|&c| c == b'\n') {
+            Some(line_end) => line_end,
+            None if buf.len() > constants::PROTO_INLINE_MAX_SIZE => {
+                return Err(Error::BrokenProtocol("too big inline request".into()))
+            }
+            None => return Ok(None),
+        };
+        let mut line = buf.split_to(line_end + 1);
+        line.truncate(line_end);
+        if line.ends_with(b"\r") {
+            line.truncate(line_end - 1);
+        }
+        Ok(Some(
+            Self::split_args(&line)?.into_iter().map(Bytes::from).collect(),
+        ))
+    }
+
+    fn decode_multibulk(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<Bytes>>> {
+        while self.args.len() < self.multibulk_len {
+            let len = match self.bulk_len {
+                Some(len) => len,
+                None => {
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+                    if buf[0] != b'$' {
+                        return Err(Error::BrokenProtocol(format!(
+                            "expected '$', got '{}'",
+                            buf[0] as char
+                        )));
+                    }
+                    let line_end = match Self::find_count_line(buf, "bulk")? {
+                        Some(line_end) => line_end,
+                        None => return Ok(None),
+                    };
+                    let len = match parse_int(&buf[1..line_end]) {
+                        Some(len) if len >= 0 && len as usize <= self.limits.max_bulk_len => {
+                            len as usize
+                        }
+                        _ => return Err(Error::BrokenProtocol("invalid bulk length".into())),
+                    };
+                    buf.advance(line_end + constants::CRLF.len());
+                    self.bulk_len = Some(len);
+                    len
+                }
+            };
+            if buf.len() < len + constants::CRLF.len() {
+                // make room for more of the argument, so it is read with fewer syscalls,
+                // but never trust the declared length for more than a big argument.
+                let missing = len + constants::CRLF.len() - buf.len();
+                buf.reserve(missing.min(constants::PROTO_MBULK_BIG_ARG));
+                return Ok(None);
+            }
+            if &buf[len..len + constants::CRLF.len()] != constants::CRLF {
+                return Err(Error::BrokenProtocol(
+                    "bulk string is not terminated by CRLF".into(),
+                ));
+            }
+            let arg = Bytes::copy_from_slice(&buf[..len]);
+            buf.advance(len + constants::CRLF.len());
+            self.args.push(arg);
+            self.bulk_len = None;
+        }
+        self.multibulk_len = 0;
+        Ok(Some(std::mem::take(&mut self.args)))
+    }
+
+    /// Splits an inline command into arguments with the same rules as `sdssplitargs`
+    /// of redis. Arguments are separated by whitespaces, and may be quoted:
+    /// in double quotes `\xHH`, `\n`, `\r`, `\t`, `\b`, `\a` and `\<c>` are unescaped,
+    /// while in single quotes only `\'` is. A closing quote must be followed by a space.
+    pub fn split_args(src: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let unbalanced = || Error::BrokenProtocol("unbalanced quotes in request".into());
+        let mut result = Vec::new();
+        let mut pos = 0;
+        loop {
+            while pos < src.len() && src[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= src.len() {
+                return Ok(result);
+            }
+            let mut current = Vec::new();
+            let mut in_double_quotes = false;
+            let mut in_single_quotes = false;
+            loop {
+                if in_double_quotes {
+                    match src.get(pos) {
+                        None => return Err(unbalanced()),
+                        Some(b'\\') if pos + 3 < src.len()
+                            && src[pos + 1] == b'x'
+                            && src[pos + 2].is_ascii_hexdigit()
+                            && src[pos + 3].is_ascii_hexdigit() =>
+                        {
+                            let hex = std::str::from_utf8(&src[pos + 2..pos + 4]).unwrap();
+                            current.push(u8::from_str_radix(hex, 16).unwrap());
+                            pos += 3;
+                        }
+                        Some(b'\\') if pos + 1 < src.len() => {
+                            pos += 1;
+                            current.push(match src[pos] {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 0x08,
+                                b'a' => 0x07,
+                                c => c,
+                            });
+                        }
+                        Some(b'"') => {
+                            // closing quote must be followed by a space or nothing at all.
+                            if pos + 1 < src.len() && !src[pos + 1].is_ascii_whitespace() {
+                                return Err(unbalanced());
+                            }
+                            pos += 1;
+                            break;
+                        }
+                        Some(&c) => current.push(c),
+                    }
+                } else if in_single_quotes {
+                    match src.get(pos) {
+                        None => return Err(unbalanced()),
+                        Some(b'\\') if src.get(pos + 1) == Some(&b'\'') => {
+                            pos += 1;
+                            current.push(b'\'');
+                        }
+                        Some(b'\'') => {
+                            if pos + 1 < src.len() && !src[pos + 1].is_ascii_whitespace() {
+                                return Err(unbalanced());
+                            }
+                            pos += 1;
+                            break;
+                        }
+                        Some(&c) => current.push(c),
+                    }
+                } else {
+                    match src.get(pos) {
+                        None => break,
+                        Some(c) if c.is_ascii_whitespace() => break,
+                        Some(b'"') => in_double_quotes = true,
+                        Some(b'\'') => in_single_quotes = true,
+                        Some(&c) => current.push(c),
+                    }
+                }
+                pos += 1;
+            }
+            result.push(current);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use crate::protocol::Limits;
+
+    use super::RequestDecoder;
+
+    fn split(src: &[u8]) -> Vec<Vec<u8>> {
+        RequestDecoder::split_args(src).unwrap()
+    }
+
+    #[test]
+    fn split_inline_args() {
+        assert_eq!(split(b"get key"), vec![b"get".to_vec(), b"key".to_vec()]);
+        assert_eq!(split(b"  set   a  b  "), vec![b"set".to_vec(), b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(split(b""), Vec::<Vec<u8>>::new());
+        assert_eq!(
+            split(b"set k \"hello world\""),
+            vec![b"set".to_vec(), b"k".to_vec(), b"hello world".to_vec()]
+        );
+        assert_eq!(split(b"\"\\x00\\x41\\n\\\"\""), vec![b"\0A\n\"".to_vec()]);
+        assert_eq!(split(b"'it\\'s' '\\n'"), vec![b"it's".to_vec(), b"\\n".to_vec()]);
+        assert_eq!(split(b"\"\""), vec![b"".to_vec()]);
+    }
+
+    #[test]
+    fn split_unbalanced_quotes() {
+        for src in [&b"get \"key"[..], b"get 'key", b"get \"a\"b", b"get 'a'b"] {
+            assert!(matches!(
+                RequestDecoder::split_args(src),
+                Err(crate::error::Error::BrokenProtocol(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn decode_pipelined_requests() {
+        let mut decoder = RequestDecoder::new(Limits::default());
+        let mut buf = BytesMut::from(
+            &b"*2\r\n$3\r\nget\r\n$1\r\na\r\n*0\r\nPING\r\n\r\n*1\r\n$4\r\nquit\r\n"[..],
+        );
+        let mut requests = vec![];
+        while let Some(args) = decoder.decode(&mut buf).unwrap() {
+            requests.push(args);
+        }
+        assert_eq!(
+            requests,
+            vec![vec!["get", "a"], vec!["PING"], vec!["quit"]]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decoded_args_do_not_share_the_buffer() {
+        let mut buf = BytesMut::with_capacity(16 * 1024);
+        buf.extend_from_slice(b"*3\r\n$3\r\nset\r\n$1\r\nk\r\n$1\r\nv\r\n");
+        let range = buf.as_ptr_range();
+        let args = RequestDecoder::new(Limits::default()).decode(&mut buf).unwrap().unwrap();
+        for arg in args {
+            assert!(!range.contains(&arg.as_ptr()));
+        }
+    }
+
+    #[test]
+    fn decode_partial_request() {
+        let input = b"*2\r\n$3\r\nset\r\n$9\r\n\r\n\0\r\n\0\r\n\0\r\n";
+        let mut decoder = RequestDecoder::new(Limits::default());
+        let mut buf = BytesMut::new();
+        for (i, c) in input.iter().enumerate() {
+            buf.extend_from_slice(&[*c]);
+            let request = decoder.decode(&mut buf).unwrap();
+            if i + 1 < input.len() {
+                assert_eq!(request, None);
+            } else {
+                assert_eq!(request.unwrap(), vec![&b"set"[..], &b"\r\n\0\r\n\0\r\n\0"[..]]);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_beyond_limits() {
+        let limits = Limits {
+            max_bulk_len: 4,
+            max_multibulk_len: 2,
+            max_nesting_depth: 1,
+        };
+        let too_long = vec![b'a'; 64 * 1024 + 1];
+        let mut cases: Vec<&[u8]> = vec![
+            b"*3\r\n",
+            b"*1\r\n$5\r\n",
+            b"*2147483648\r\n",
+            b"*1\r\n$9223372036854775807\r\n",
+            &too_long,
+        ];
+        let mut mbulk_count = b"*".to_vec();
+        mbulk_count.extend_from_slice(&too_long);
+        cases.push(&mbulk_count);
+        for input in cases {
+            let mut buf = BytesMut::from(input);
+            assert!(matches!(
+                RequestDecoder::new(limits).decode(&mut buf),
+                Err(crate::error::Error::BrokenProtocol(_))
+            ));
+        }
+        let mut buf = BytesMut::from(&b"*2\r\n$4\r\nabcd\r\n$0\r\n\r\n"[..]);
+        assert!(RequestDecoder::new(limits).decode(&mut buf).unwrap().is_some());
+    }
+
+    #[test]
+    fn decode_huge_bulk_header() {
+        let mut decoder = RequestDecoder::new(Limits::default());
+        let mut buf = BytesMut::from(&b"*1\r\n$536870912\r\nabc"[..]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        assert!(buf.capacity() <= 64 * 1024);
+    }
+
+    #[test]
+    fn decode_malformed_request() {
+        for input in [&b"*2\r\n+get\r\n"[..], b"*x\r\n", b"*1\r\n$-3\r\n", b"*1\r\n$1\r\nab\r\n"] {
+            let mut buf = BytesMut::from(input);
+            assert!(matches!(
+                RequestDecoder::new(Limits::default()).decode(&mut buf),
+                Err(crate::error::Error::BrokenProtocol(_))
+            ));
+        }
+    }
+}
@@ -1,3 +1,37 @@
-use crate::error::Result;
+use super::{resp3::format_double, RawPiece, Resp3Piece};
 
-use super::Protocol;
+/// Downgrades a RESP3 reply for clients speaking RESP2, following what redis does:
+/// maps and pairs are flattened into arrays, sets and pushes become arrays, doubles
+/// and big numbers become bulk strings, booleans become integers and attributes are
+/// dropped.
+impl From<Resp3Piece> for RawPiece {
+    fn from(piece: Resp3Piece) -> Self {
+        match piece {
+            Resp3Piece::SimpleString { data } => RawPiece::SimpleString { data },
+            Resp3Piece::Error { typ, cause } | Resp3Piece::BlobError { typ, cause } => {
+                RawPiece::Error { typ, cause }
+            }
+            Resp3Piece::Integer(i) => RawPiece::Integer(i),
+            Resp3Piece::BulkString { data } | Resp3Piece::VerbatimString { format: _, data } => {
+                RawPiece::BulkString { data }
+            }
+            Resp3Piece::BigNumber(data) => RawPiece::BulkString { data },
+            Resp3Piece::Double(d) => RawPiece::BulkString {
+                data: format_double(d).into(),
+            },
+            Resp3Piece::Boolean(b) => RawPiece::Integer(b as i64),
+            Resp3Piece::Null => RawPiece::Null,
+            Resp3Piece::NullArray => RawPiece::NullArray,
+            Resp3Piece::Array(items) | Resp3Piece::Set(items) | Resp3Piece::Push(items) => {
+                RawPiece::Array(items.into_iter().map(RawPiece::from).collect())
+            }
+            Resp3Piece::Map(pairs) | Resp3Piece::Pairs(pairs) => RawPiece::Array(
+                pairs
+                    .into_iter()
+                    .flat_map(|(k, v)| [RawPiece::from(k), RawPiece::from(v)])
+                    .collect(),
+            ),
+            Resp3Piece::Attribute { attrs: _, data } => RawPiece::from(*data),
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+
+use crate::error::{Error, Result};
+
+use super::{constants, write_decimal, FrameReader, Limits, Protocol, RawPiece, Scanner};
+
+const PREFIX_SIMPLE_STRING: u8 = b'+';
+const PREFIX_ERROR: u8 = b'-';
+const PREFIX_INTEGER: u8 = b':';
+const PREFIX_BULK_STRING: u8 = b'$';
+const PREFIX_ARRAY: u8 = b'*';
+const PREFIX_NULL: u8 = b'_';
+const PREFIX_BOOLEAN: u8 = b'#';
+const PREFIX_DOUBLE: u8 = b',';
+const PREFIX_BIG_NUMBER: u8 = b'(';
+const PREFIX_BLOB_ERROR: u8 = b'!';
+const PREFIX_VERBATIM_STRING: u8 = b'=';
+const PREFIX_MAP: u8 = b'%';
+const PREFIX_SET: u8 = b'~';
+const PREFIX_ATTRIBUTE: u8 = b'|';
+const PREFIX_PUSH: u8 = b'>';
+
+/// A RESP3 message. It is a superset of [`RawPiece`], and every reply of the server is
+/// built as a `Resp3Piece`. Clients which still speak RESP2 receive the downgraded
+/// form, see `impl From<Resp3Piece> for RawPiece`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Resp3Piece {
+    SimpleString { data: Bytes },
+    Error { typ: Bytes, cause: Bytes },
+    Integer(i64),
+    BulkString { data: Bytes },
+    Array(Vec<Resp3Piece>),
+    /// `_\r\n`, downgraded to a null bulk string.
+    Null,
+    /// Encoded as `_\r\n` too, but downgraded to a null array for RESP2 clients.
+    NullArray,
+    Boolean(bool),
+    Double(f64),
+    /// Digits of an integer which may not fit into i64, sign included.
+    BigNumber(Bytes),
+    BlobError { typ: Bytes, cause: Bytes },
+    /// `format` is always three bytes, such as `txt` or `mkd`.
+    VerbatimString { format: Bytes, data: Bytes },
+    Map(Vec<(Resp3Piece, Resp3Piece)>),
+    /// An array of two-element arrays, which RESP2 clients get flattened as maps are,
+    /// such as the members and scores of ZRANGE WITHSCORES.
+    Pairs(Vec<(Resp3Piece, Resp3Piece)>),
+    Set(Vec<Resp3Piece>),
+    /// Out-of-band attributes followed by the piece they decorate.
+    Attribute {
+        attrs: Vec<(Resp3Piece, Resp3Piece)>,
+        data: Box<Resp3Piece>,
+    },
+    Push(Vec<Resp3Piece>),
+}
+
+impl Resp3Piece {
+    pub fn simple(data: &'static str) -> Self {
+        Self::SimpleString {
+            data: Bytes::from_static(data.as_bytes()),
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::simple("OK")
+    }
+
+    pub fn bulk(data: impl Into<Bytes>) -> Self {
+        Self::BulkString { data: data.into() }
+    }
+
+    pub fn read_string(&self) -> Option<&Bytes> {
+        match self {
+            Self::SimpleString { data } | Self::BulkString { data } => Some(data),
+            Self::VerbatimString { format: _, data } => Some(data),
+            _ => None,
+        }
+    }
+
+    fn read_pairs(r: &mut FrameReader, len: i64) -> Result<Vec<(Self, Self)>> {
+        let mut pairs = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            let key = Self::read(r)?;
+            let value = Self::read(r)?;
+            pairs.push((key, value));
+        }
+        Ok(pairs)
+    }
+
+    fn read_pieces(r: &mut FrameReader, len: i64) -> Result<Vec<Self>> {
+        let mut pieces = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            pieces.push(Self::read(r)?);
+        }
+        Ok(pieces)
+    }
+
+    fn read(r: &mut FrameReader) -> Result<Self> {
+        let (prefix, data) = r.read_line();
+        match prefix {
+            PREFIX_SIMPLE_STRING => Ok(Self::SimpleString { data }),
+            PREFIX_ERROR => {
+                let (typ, cause) = RawPiece::split_error(data);
+                Ok(Self::Error { typ, cause })
+            }
+            PREFIX_INTEGER => Ok(Self::Integer(FrameReader::read_int(&data)?)),
+            PREFIX_BULK_STRING => match FrameReader::read_int(&data)? {
+                -1 => Ok(Self::Null),
+                len => Ok(Self::BulkString {
+                    data: r.read_payload(len as usize),
+                }),
+            },
+            PREFIX_ARRAY => match FrameReader::read_int(&data)? {
+                -1 => Ok(Self::NullArray),
+                len => Ok(Self::Array(Self::read_pieces(r, len)?)),
+            },
+            PREFIX_NULL => {
+                if !data.is_empty() {
+                    return Err(Error::BrokenProtocol("invalid null".into()));
+                }
+                Ok(Self::Null)
+            }
+            PREFIX_BOOLEAN => match &data[..] {
+                b"t" => Ok(Self::Boolean(true)),
+                b"f" => Ok(Self::Boolean(false)),
+                _ => Err(Error::BrokenProtocol("invalid boolean".into())),
+            },
+            PREFIX_DOUBLE => parse_double(&data)
+                .map(Self::Double)
+                .ok_or_else(|| Error::BrokenProtocol("invalid double".into())),
+            PREFIX_BIG_NUMBER => {
+                let digits = match data.first() {
+                    Some(b'-' | b'+') => &data[1..],
+                    _ => &data[..],
+                };
+                if digits.is_empty() || !digits.iter().all(|c| c.is_ascii_digit()) {
+                    return Err(Error::BrokenProtocol("invalid big number".into()));
+                }
+                Ok(Self::BigNumber(data))
+            }
+            PREFIX_BLOB_ERROR => {
+                let len = FrameReader::read_int(&data)? as usize;
+                let (typ, cause) = RawPiece::split_error(r.read_payload(len));
+                Ok(Self::BlobError { typ, cause })
+            }
+            PREFIX_VERBATIM_STRING => {
+                let len = FrameReader::read_int(&data)? as usize;
+                let data = r.read_payload(len);
+                if data.len() < 4 || data[3] != b':' {
+                    return Err(Error::BrokenProtocol("invalid verbatim string".into()));
+                }
+                Ok(Self::VerbatimString {
+                    format: data.slice(..3),
+                    data: data.slice(4..),
+                })
+            }
+            PREFIX_MAP => {
+                let len = FrameReader::read_int(&data)?;
+                Ok(Self::Map(Self::read_pairs(r, len)?))
+            }
+            PREFIX_SET => {
+                let len = FrameReader::read_int(&data)?;
+                Ok(Self::Set(Self::read_pieces(r, len)?))
+            }
+            PREFIX_PUSH => {
+                let len = FrameReader::read_int(&data)?;
+                Ok(Self::Push(Self::read_pieces(r, len)?))
+            }
+            PREFIX_ATTRIBUTE => {
+                let len = FrameReader::read_int(&data)?;
+                let attrs = Self::read_pairs(r, len)?;
+                let data = Box::new(Self::read(r)?);
+                Ok(Self::Attribute { attrs, data })
+            }
+            _ => unreachable!("rejected by the scanner"),
+        }
+    }
+}
+
+/// Formats a double as RESP3 does. The shortest representation which reads back to
+/// the same value is used, and infinities are written as `inf`/`-inf`.
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        "nan".into()
+    } else if d.is_infinite() {
+        if d > 0.0 { "inf".into() } else { "-inf".into() }
+    } else if d != 0.0 && (d.abs() >= 1e17 || d.abs() < 1e-5) {
+        // `{:e}` gives `1.5e20`, while redis gives `1.5e+20`.
+        let repr = format!("{:e}", d);
+        match repr.split_once('e') {
+            Some((mantissa, exp)) if !exp.starts_with('-') => format!("{}e+{}", mantissa, exp),
+            _ => repr,
+        }
+    } else {
+        format!("{}", d)
+    }
+}
+
+fn parse_double(data: &[u8]) -> Option<f64> {
+    match data {
+        b"inf" | b"+inf" => Some(f64::INFINITY),
+        b"-inf" => Some(f64::NEG_INFINITY),
+        b"nan" => Some(f64::NAN),
+        _ => std::str::from_utf8(data).ok()?.parse().ok(),
+    }
+}
+
+async fn write_line<W>(w: &mut BufWriter<W>, prefix: u8, data: &[u8]) -> Result<usize>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    w.write_all(&[prefix]).await?;
+    w.write_all(data).await?;
+    w.write_all(constants::CRLF).await?;
+    Ok(1 + data.len() + constants::CRLF.len())
+}
+
+async fn write_length<W>(w: &mut BufWriter<W>, prefix: u8, len: usize) -> Result<usize>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    w.write_all(&[prefix]).await?;
+    let size = write_decimal(w, len as i64).await?;
+    w.write_all(constants::CRLF).await?;
+    Ok(1 + size + constants::CRLF.len())
+}
+
+async fn write_blob<W>(w: &mut BufWriter<W>, prefix: u8, parts: &[&[u8]]) -> Result<usize>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let len = parts.iter().map(|p| p.len()).sum();
+    let mut total_size = write_length(w, prefix, len).await?;
+    for part in parts {
+        w.write_all(part).await?;
+    }
+    w.write_all(constants::CRLF).await?;
+    total_size += len + constants::CRLF.len();
+    Ok(total_size)
+}
+
+#[async_trait]
+impl Protocol for Resp3Piece {
+    fn prefix(&self) -> u8 {
+        match self {
+            Self::SimpleString { .. } => PREFIX_SIMPLE_STRING,
+            Self::Error { .. } => PREFIX_ERROR,
+            Self::Integer(_) => PREFIX_INTEGER,
+            Self::BulkString { .. } => PREFIX_BULK_STRING,
+            Self::Array(_) | Self::Pairs(_) => PREFIX_ARRAY,
+            Self::Null | Self::NullArray => PREFIX_NULL,
+            Self::Boolean(_) => PREFIX_BOOLEAN,
+            Self::Double(_) => PREFIX_DOUBLE,
+            Self::BigNumber(_) => PREFIX_BIG_NUMBER,
+            Self::BlobError { .. } => PREFIX_BLOB_ERROR,
+            Self::VerbatimString { .. } => PREFIX_VERBATIM_STRING,
+            Self::Map(_) => PREFIX_MAP,
+            Self::Set(_) => PREFIX_SET,
+            Self::Attribute { .. } => PREFIX_ATTRIBUTE,
+            Self::Push(_) => PREFIX_PUSH,
+        }
+    }
+
+    async fn marshal<W>(&self, w: &mut BufWriter<W>) -> Result<usize>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let prefix = self.prefix();
+        match self {
+            Self::SimpleString { data } => write_line(w, prefix, data).await,
+            Self::Error { typ, cause } => {
+                let mut line = typ.to_vec();
+                if !cause.is_empty() {
+                    line.push(b' ');
+                    line.extend_from_slice(cause);
+                }
+                write_line(w, prefix, &line).await
+            }
+            Self::Integer(i) => write_line(w, prefix, i.to_string().as_bytes()).await,
+            Self::BulkString { data } => write_blob(w, prefix, &[data]).await,
+            Self::Null | Self::NullArray => write_line(w, prefix, b"").await,
+            Self::Boolean(b) => write_line(w, prefix, if *b { b"t" } else { b"f" }).await,
+            Self::Double(d) => write_line(w, prefix, format_double(*d).as_bytes()).await,
+            Self::BigNumber(digits) => write_line(w, prefix, digits).await,
+            Self::BlobError { typ, cause } => {
+                if cause.is_empty() {
+                    write_blob(w, prefix, &[typ]).await
+                } else {
+                    write_blob(w, prefix, &[typ, b" ", cause]).await
+                }
+            }
+            Self::VerbatimString { format, data } => {
+                write_blob(w, prefix, &[format, b":", data]).await
+            }
+            Self::Array(items) | Self::Set(items) | Self::Push(items) => {
+                let mut total_size = write_length(w, prefix, items.len()).await?;
+                for each in items {
+                    total_size += each.marshal(w).await?;
+                }
+                Ok(total_size)
+            }
+            Self::Map(pairs) => {
+                let mut total_size = write_length(w, prefix, pairs.len()).await?;
+                for (key, value) in pairs {
+                    total_size += key.marshal(w).await?;
+                    total_size += value.marshal(w).await?;
+                }
+                Ok(total_size)
+            }
+            Self::Pairs(pairs) => {
+                let mut total_size = write_length(w, prefix, pairs.len()).await?;
+                for (first, second) in pairs {
+                    total_size += write_length(w, prefix, 2).await?;
+                    total_size += first.marshal(w).await?;
+                    total_size += second.marshal(w).await?;
+                }
+                Ok(total_size)
+            }
+            Self::Attribute { attrs, data } => {
+                let mut total_size = write_length(w, prefix, attrs.len()).await?;
+                for (key, value) in attrs {
+                    total_size += key.marshal(w).await?;
+                    total_size += value.marshal(w).await?;
+                }
+                total_size += data.marshal(w).await?;
+                Ok(total_size)
+            }
+        }
+    }
+
+    fn decode(buf: &mut BytesMut, limits: &Limits) -> Result<Option<Self>> {
+        let end = match Scanner::new(buf, true, limits).scan(0)? {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let mut r = FrameReader::new(buf.split_to(end).freeze());
+        Self::read(&mut r).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use tokio::io::{AsyncWriteExt, BufWriter};
+
+    use crate::protocol::{Limits, Protocol, RawPiece};
+
+    use super::Resp3Piece;
+
+    async fn encode(piece: &Resp3Piece) -> Vec<u8> {
+        let mut buffer = BufWriter::new(Vec::new());
+        let size = piece.marshal(&mut buffer).await.unwrap();
+        buffer.flush().await.unwrap();
+        let out = buffer.into_inner();
+        assert_eq!(size, out.len());
+        out
+    }
+
+    fn decode(input: &[u8]) -> Resp3Piece {
+        let mut buf = BytesMut::from(input);
+        // every proper prefix of a piece is incomplete.
+        for end in 0..input.len() {
+            let mut partial = BytesMut::from(&input[..end]);
+            assert_eq!(Resp3Piece::decode(&mut partial, &Limits::default()).unwrap(), None);
+        }
+        let piece = Resp3Piece::decode(&mut buf, &Limits::default()).unwrap().unwrap();
+        assert!(buf.is_empty());
+        piece
+    }
+
+    fn bulk(s: &'static str) -> Resp3Piece {
+        Resp3Piece::bulk(s.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let cases: Vec<(Resp3Piece, &[u8])> = vec![
+            (Resp3Piece::Null, b"_\r\n"),
+            (Resp3Piece::Boolean(true), b"#t\r\n"),
+            (Resp3Piece::Boolean(false), b"#f\r\n"),
+            (Resp3Piece::Double(1.5), b",1.5\r\n"),
+            (Resp3Piece::Double(f64::NEG_INFINITY), b",-inf\r\n"),
+            (Resp3Piece::Double(1e20), b",1e+20\r\n"),
+            (
+                Resp3Piece::BigNumber(Bytes::from_static(b"-3492890328409238509324850943850943825024385")),
+                b"(-3492890328409238509324850943850943825024385\r\n",
+            ),
+            (
+                Resp3Piece::BlobError {
+                    typ: Bytes::from_static(b"SYNTAX"),
+                    cause: Bytes::from_static(b"invalid\r\nsyntax"),
+                },
+                b"!22\r\nSYNTAX invalid\r\nsyntax\r\n",
+            ),
+            (
+                Resp3Piece::VerbatimString {
+                    format: Bytes::from_static(b"txt"),
+                    data: Bytes::from_static(b"Some string"),
+                },
+                b"=15\r\ntxt:Some string\r\n",
+            ),
+            (
+                Resp3Piece::Map(vec![
+                    (Resp3Piece::simple("first"), Resp3Piece::Integer(1)),
+                    (Resp3Piece::simple("second"), Resp3Piece::Integer(2)),
+                ]),
+                b"%2\r\n+first\r\n:1\r\n+second\r\n:2\r\n",
+            ),
+            (
+                Resp3Piece::Set(vec![bulk("a"), Resp3Piece::Integer(3)]),
+                b"~2\r\n$1\r\na\r\n:3\r\n",
+            ),
+            (
+                Resp3Piece::Push(vec![bulk("message"), bulk("ch")]),
+                b">2\r\n$7\r\nmessage\r\n$2\r\nch\r\n",
+            ),
+            (
+                Resp3Piece::Attribute {
+                    attrs: vec![(Resp3Piece::simple("ttl"), Resp3Piece::Integer(3600))],
+                    data: Box::new(Resp3Piece::Array(vec![Resp3Piece::Integer(2)])),
+                },
+                b"|1\r\n+ttl\r\n:3600\r\n*1\r\n:2\r\n",
+            ),
+        ];
+        for (piece, wire) in cases {
+            assert_eq!(encode(&piece).await, wire.to_vec());
+            assert_eq!(decode(wire), piece);
+        }
+    }
+
+    #[tokio::test]
+    async fn pairs() {
+        let pairs = vec![
+            (bulk("a"), Resp3Piece::Double(1.5)),
+            (bulk("b"), Resp3Piece::Double(2.0)),
+        ];
+        let wire = b"*2\r\n*2\r\n$1\r\na\r\n,1.5\r\n*2\r\n$1\r\nb\r\n,2\r\n";
+        assert_eq!(encode(&Resp3Piece::Pairs(pairs.clone())).await, wire.to_vec());
+        let nested = pairs.iter().map(|(a, b)| Resp3Piece::Array(vec![a.clone(), b.clone()]));
+        assert_eq!(decode(wire), Resp3Piece::Array(nested.collect()));
+        assert_eq!(
+            RawPiece::from(Resp3Piece::Pairs(pairs.clone())),
+            RawPiece::from(Resp3Piece::Map(pairs))
+        );
+    }
+
+    #[test]
+    fn downgrade() {
+        let reply = Resp3Piece::Map(vec![
+            (bulk("score"), Resp3Piece::Double(2.5)),
+            (bulk("ok"), Resp3Piece::Boolean(true)),
+            (bulk("none"), Resp3Piece::Null),
+            (bulk("list"), Resp3Piece::NullArray),
+        ]);
+        let raw = |s: &'static str| RawPiece::BulkString {
+            data: Bytes::from_static(s.as_bytes()),
+        };
+        assert_eq!(
+            RawPiece::from(reply),
+            RawPiece::Array(vec![
+                raw("score"),
+                raw("2.5"),
+                raw("ok"),
+                RawPiece::Integer(1),
+                raw("none"),
+                RawPiece::Null,
+                raw("list"),
+                RawPiece::NullArray,
+            ])
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! A radix tree mapping byte strings to values in order, like the rax of redis.
+//!
+//! Each node holds the bytes of the edge leading to it, and its children are sorted by
+//! the first byte of their edge, which no two children share. A node with no value and
+//! a single child is merged with it, so that runs of bytes shared by the keys below are
+//! stored once. Keys which mostly share their first bytes, like the big endian IDs of
+//! the entries of a stream, take little more than their distinct tails.
+//!
+//! Like the iterators of rax, [`Rax::seek`] finds the closest key on either side of a
+//! key, which is how the keys are walked in order.
+
+use std::{cmp::Ordering, fmt};
+
+/// Where [`Rax::seek`] looks from a key, like the operators of `raxSeek`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seek {
+    /// The lowest key greater than or equal to the key.
+    Ge,
+    /// The lowest key greater than the key.
+    Gt,
+    /// The greatest key lower than or equal to the key.
+    Le,
+    /// The greatest key lower than the key.
+    Lt,
+}
+
+#[derive(Debug, Clone)]
+struct Node<V> {
+    edge: Vec<u8>,
+    value: Option<V>,
+    children: Vec<Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new(edge: &[u8], value: Option<V>) -> Self {
+        Self { edge: edge.to_vec(), value, children: vec![] }
+    }
+
+    /// Index of the child whose edge starts with the byte, or where it would be.
+    fn child(&self, byte: u8) -> Result<usize, usize> {
+        self.children.binary_search_by_key(&byte, |child| child.edge[0])
+    }
+
+    /// The lowest key of the subtree, whose edge is pushed to `path` along the way.
+    /// Only the root may be an empty subtree.
+    fn first<'a>(&'a self, path: &mut Vec<u8>) -> Option<&'a V> {
+        path.extend_from_slice(&self.edge);
+        match &self.value {
+            Some(value) => Some(value),
+            None => self.children.first()?.first(path),
+        }
+    }
+
+    /// The greatest key of the subtree, whose edge is pushed to `path` along the way.
+    fn last<'a>(&'a self, path: &mut Vec<u8>) -> Option<&'a V> {
+        path.extend_from_slice(&self.edge);
+        match self.children.last() {
+            Some(child) => child.last(path),
+            None => self.value.as_ref(),
+        }
+    }
+
+    /// The lowest key of the subtree after `key`, relative to the node. The edge of
+    /// the node is already in `path`.
+    fn after<'a>(&'a self, key: &[u8], inclusive: bool, path: &mut Vec<u8>) -> Option<&'a V> {
+        let Some(&byte) = key.first() else {
+            return match &self.value {
+                Some(value) if inclusive => Some(value),
+                _ => self.children.first()?.first(path),
+            };
+        };
+        let start = self.children.partition_point(|child| child.edge[0] < byte);
+        for child in &self.children[start..] {
+            let n = child.edge.len().min(key.len());
+            let found = match child.edge[..n].cmp(&key[..n]) {
+                Ordering::Less => continue,
+                Ordering::Equal if child.edge.len() <= key.len() => {
+                    path.extend_from_slice(&child.edge);
+                    let found = child.after(&key[n..], inclusive, path);
+                    if found.is_none() {
+                        path.truncate(path.len() - n);
+                    }
+                    found
+                }
+                // The keys of the child extend `key`, or are past it.
+                _ => child.first(path),
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// The greatest key of the subtree before `key`, relative to the node. The edge of
+    /// the node is already in `path`.
+    fn before<'a>(&'a self, key: &[u8], inclusive: bool, path: &mut Vec<u8>) -> Option<&'a V> {
+        let Some(&byte) = key.first() else {
+            // The keys below extend the key of the node, so are greater.
+            return if inclusive { self.value.as_ref() } else { None };
+        };
+        let end = self.children.partition_point(|child| child.edge[0] <= byte);
+        for child in self.children[..end].iter().rev() {
+            let n = child.edge.len().min(key.len());
+            let found = match child.edge[..n].cmp(&key[..n]) {
+                Ordering::Greater => continue,
+                Ordering::Equal if child.edge.len() > key.len() => continue,
+                Ordering::Equal => {
+                    path.extend_from_slice(&child.edge);
+                    let found = child.before(&key[n..], inclusive, path);
+                    if found.is_none() {
+                        path.truncate(path.len() - n);
+                    }
+                    found
+                }
+                Ordering::Less => child.last(path),
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        // The key of the node is a prefix of `key`.
+        self.value.as_ref()
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let Some(&byte) = key.first() else {
+            return self.value.take();
+        };
+        let i = self.child(byte).ok()?;
+        let child = &mut self.children[i];
+        let rest = key.strip_prefix(&child.edge[..])?;
+        let value = child.remove(rest)?;
+        if child.value.is_none() {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(i);
+                }
+                1 => {
+                    let only = child.children.pop().unwrap();
+                    child.edge.extend_from_slice(&only.edge);
+                    child.value = only.value;
+                    child.children = only.children;
+                }
+                _ => {}
+            }
+        }
+        Some(value)
+    }
+}
+
+/// A map from byte strings to values, ordered by key.
+#[derive(Clone)]
+pub struct Rax<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+impl<V> Default for Rax<V> {
+    fn default() -> Self {
+        Self { root: Node::new(b"", None), len: 0 }
+    }
+}
+
+impl<V> Rax<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        let mut node = &self.root;
+        let mut key = key;
+        while let Some(&byte) = key.first() {
+            node = &node.children[node.child(byte).ok()?];
+            key = key.strip_prefix(&node.edge[..])?;
+        }
+        node.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        let mut node = &mut self.root;
+        let mut key = key;
+        while let Some(&byte) = key.first() {
+            let i = node.child(byte).ok()?;
+            node = &mut node.children[i];
+            key = key.strip_prefix(&node.edge[..])?;
+        }
+        node.value.as_mut()
+    }
+
+    /// Sets the value of the key, returning the value it had.
+    pub fn insert(&mut self, key: &[u8], value: V) -> Option<V> {
+        let mut node = &mut self.root;
+        let mut key = key;
+        while let Some(&byte) = key.first() {
+            let i = match node.child(byte) {
+                Ok(i) => i,
+                Err(i) => {
+                    node.children.insert(i, Node::new(key, Some(value)));
+                    self.len += 1;
+                    return None;
+                }
+            };
+            let child = &mut node.children[i];
+            let common = child.edge.iter().zip(key).take_while(|(a, b)| a == b).count();
+            if common < child.edge.len() {
+                // Split the child where the key leaves its edge.
+                let tail = Node {
+                    edge: child.edge.split_off(common),
+                    value: child.value.take(),
+                    children: std::mem::take(&mut child.children),
+                };
+                child.children.push(tail);
+            }
+            key = &key[common..];
+            node = child;
+        }
+        let old = node.value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Removes the key, returning its value.
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let value = self.root.remove(key)?;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// The closest key to `key` in the direction of `op`, along with its value.
+    pub fn seek(&self, op: Seek, key: &[u8]) -> Option<(Vec<u8>, &V)> {
+        let mut path = vec![];
+        let value = match op {
+            Seek::Ge | Seek::Gt => self.root.after(key, op == Seek::Ge, &mut path),
+            Seek::Le | Seek::Lt => self.root.before(key, op == Seek::Le, &mut path),
+        }?;
+        Some((path, value))
+    }
+
+    pub fn first(&self) -> Option<(Vec<u8>, &V)> {
+        self.seek(Seek::Ge, b"")
+    }
+
+    pub fn last(&self) -> Option<(Vec<u8>, &V)> {
+        let mut path = vec![];
+        let value = self.root.last(&mut path)?;
+        Some((path, value))
+    }
+
+    /// Iterates the keys in order, along with their values.
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, &V)> {
+        std::iter::successors(self.first(), move |(key, _)| self.seek(Seek::Gt, key))
+    }
+}
+
+impl<V: PartialEq> PartialEq for Rax<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<V: Eq> Eq for Rax<V> {}
+
+impl<V: fmt::Debug> fmt::Debug for Rax<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::util::random;
+
+    use super::{Rax, Seek};
+
+    #[test]
+    fn split_and_merged() {
+        let mut rax = Rax::new();
+        assert_eq!(rax.insert(b"romane", 1), None);
+        assert_eq!(rax.insert(b"romanus", 2), None);
+        assert_eq!(rax.insert(b"rom", 3), None);
+        assert_eq!(rax.insert(b"", 4), None);
+        assert_eq!(rax.insert(b"romanus", 5), Some(2));
+        assert_eq!(rax.len(), 4);
+        assert_eq!(rax.get(b"roman"), None);
+        assert_eq!(rax.get(b"romanus"), Some(&5));
+        assert_eq!(rax.remove(b"rom"), Some(3));
+        assert_eq!(rax.remove(b"rom"), None);
+        assert_eq!(rax.remove(b"romane"), Some(1));
+        assert_eq!(rax.root.children.len(), 1);
+        assert_eq!(rax.root.children[0].edge, b"romanus");
+        *rax.get_mut(b"").unwrap() += 1;
+        let entries: Vec<_> = rax.iter().collect();
+        assert_eq!(entries, [(b"".to_vec(), &5), (b"romanus".to_vec(), &5)]);
+    }
+
+    #[test]
+    fn seeks_like_a_btree() {
+        let mut rax = Rax::new();
+        let mut btree = BTreeMap::new();
+        let key = || (0..random() % 4).map(|_| b"abc"[random() as usize % 3]).collect::<Vec<_>>();
+        for i in 0..2000 {
+            let k = key();
+            if i % 3 == 2 {
+                assert_eq!(rax.remove(&k), btree.remove(&k));
+            } else {
+                assert_eq!(rax.insert(&k, i), btree.insert(k, i));
+            }
+            assert_eq!(rax.len(), btree.len());
+            let k = key();
+            let found = |(key, value): (&Vec<u8>, &i32)| (key.clone(), *value);
+            let seek = |op| rax.seek(op, &k).map(|(key, value)| (key, *value));
+            assert_eq!(seek(Seek::Ge), btree.range(k.clone()..).next().map(found));
+            assert_eq!(
+                seek(Seek::Gt),
+                btree.range(k.clone()..).find(|(key, _)| **key != k).map(found)
+            );
+            assert_eq!(seek(Seek::Le), btree.range(..=k.clone()).next_back().map(found));
+            assert_eq!(seek(Seek::Lt), btree.range(..k.clone()).next_back().map(found));
+        }
+        assert!(rax.iter().map(|(key, value)| (key, *value)).eq(btree.clone()));
+        assert_eq!(rax.last().map(|(key, _)| key), btree.keys().next_back().cloned());
+    }
+}
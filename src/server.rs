@@ -1,8 +1,7 @@
 use log::{debug, info, warn};
-use std::sync::Arc;
-use tokio::{io::AsyncWriteExt, sync::Mutex};
+use std::{sync::Arc, time::Duration};
+use tokio::{signal, sync::Mutex, time};
 
-use rax::RaxMap;
 use tokio::{
     net::{TcpListener, TcpStream},
     runtime::{self, Runtime},
@@ -10,7 +9,16 @@ use tokio::{
 
 use crate::client::Client;
 use crate::config::Config;
+use crate::db::Databases;
 use crate::error::Result;
+use crate::object::EncodingLimits;
+use crate::protocol::Limits;
+
+/// Version of redis whose behavior is followed, reported to clients by HELLO.
+pub const REDIS_VERSION: &str = "7.2.0";
+
+/// Share of each cron period the active expire cycle may spend, in percent.
+const ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC: u64 = 25;
 
 struct IdGen {
     id_slots: bitmaps::Bitmap<1024>,
@@ -27,11 +35,8 @@ impl IdGen {
         let id = self
             .id_slots
             .first_false_index()
-            .and_then(|id| Some(id as u64))?;
+            .map(|id| id as u64)?;
         self.id_slots.set(id as usize, true);
-        if id > 1 {
-            return None;
-        }
         Some(id)
     }
 
@@ -43,8 +48,10 @@ impl IdGen {
 pub struct Server {
     rt: Runtime,
     addr: String,
-    running: bool,
+    limits: Limits,
+    hz: u64,
     id_gen: Arc<Mutex<IdGen>>,
+    dbs: Arc<Mutex<Databases>>,
     // clients: RaxMap<u64, Client>,
     // streams: StreamMap<u64, Pin<Box<dyn Stream<Item = (Command, &mut Client)>>>>,
 }
@@ -60,12 +67,17 @@ impl Server {
             rt,
             id_gen,
             addr: conf.addr.clone(),
-            running: true,
+            limits: Limits::from(conf),
+            hz: conf.hz.clamp(1, 500),
+            dbs: Arc::new(Mutex::new(Databases::with_limits(
+                conf.databases,
+                EncodingLimits::from(conf),
+            ))),
             // clients: RaxMap::new(),
         })
     }
 
-    async fn on_client_created(&self, mut stream: TcpStream, id_gen: Arc<Mutex<IdGen>>) {
+    async fn on_client_created(&self, stream: TcpStream, id_gen: Arc<Mutex<IdGen>>) {
         let id = id_gen.lock().await.new_id();
         if id.is_none() {
             warn!("too many client");
@@ -74,32 +86,60 @@ impl Server {
             return;
         }
         let id = id.unwrap();
-        let mut client = Client::new(id, stream);
+        let dbs = self.dbs.clone();
+        let mut client = Client::new(id, stream, self.limits, dbs.clone());
         self.rt.spawn(async move {
-            while let Some(cmd) = client.read_command().await {
-                debug!("Got cmd by client({:?}): {:?}", id, cmd);
-                client.execute_command(cmd);
-            }
+            client.serve().await;
             debug!("Client {:?} exits", id);
+            // ids are recycled, a new client must not inherit a blocked command.
+            dbs.lock().await.unblock(id);
             id_gen.lock().await.recycle_id(id);
         });
     }
 
+    /// Runs the background tasks of the server, `hz` times per second: the active expire
+    /// cycle, removing expired keys which are not accessed anymore, and the rehashing
+    /// of the dicts of the keyspace, which may not be written to anymore.
+    fn spawn_cron(&self) {
+        let dbs = self.dbs.clone();
+        let period = 1000 / self.hz;
+        let budget = (period * ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC / 100).max(1);
+        self.rt.spawn(async move {
+            let mut interval = time::interval(Duration::from_millis(period));
+            interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let mut dbs = dbs.lock().await;
+                let removed = dbs.active_expire_cycle(budget);
+                dbs.incrementally_rehash();
+                if removed > 0 {
+                    debug!("active expire cycle removed {} keys", removed);
+                }
+            }
+        });
+    }
+
     async fn do_run(&self) -> Result<()> {
         info!("server starts");
+        self.spawn_cron();
         // let mut id_gen = Arc::new(Mutex::new(IdGen::new()));
         let listener = TcpListener::bind(self.addr.clone()).await?;
-        while self.running {
-            match listener.accept().await {
-                Ok((stream, addr)) => {
-                    println!("received on {:?}", addr);
-                    self.on_client_created(stream, self.id_gen.clone()).await;
-                }
-                Err(err) => {
-                    warn!("error on accepting new socket: {:?}", err);
-                }
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, addr)) => {
+                        println!("received on {:?}", addr);
+                        self.on_client_created(stream, self.id_gen.clone()).await;
+                    }
+                    Err(err) => {
+                        warn!("error on accepting new socket: {:?}", err);
+                    }
+                },
+                // the server stops accepting clients on SIGINT, and returns from run.
+                _ = signal::ctrl_c() => break,
             }
         }
+        info!("server stops");
         Ok(())
     }
 
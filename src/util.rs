@@ -0,0 +1,136 @@
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Milliseconds since the unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+thread_local! {
+    static RANDOM_STATE: Cell<u64> = Cell::new(now_ms() | 1);
+}
+
+/// A cheap pseudo random number (xorshift64*), good enough for sampling keys. It is not
+/// meant to be unpredictable.
+pub fn random() -> u64 {
+    RANDOM_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// Parses an integer as `string2ll` of redis does, which is stricter than
+/// `str::parse`: no sign other than a leading `-`, no leading zeros and no spaces.
+/// Only strings accepted here are considered integers by commands.
+pub fn parse_i64(src: &[u8]) -> Option<i64> {
+    let digits = match src {
+        [b'-', rest @ ..] => rest,
+        _ => src,
+    };
+    match digits {
+        [] => return None,
+        [b'0'] => return if digits.len() == src.len() { Some(0) } else { None },
+        [b'0', ..] => return None,
+        _ => {}
+    }
+    if !digits.iter().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    std::str::from_utf8(src).ok()?.parse().ok()
+}
+
+/// Parses a float as `string2ld` of redis does: no leading space, nothing after the
+/// number, and not a NaN. Infinities are accepted.
+pub fn parse_f64(src: &[u8]) -> Option<f64> {
+    match src.first() {
+        None => return None,
+        Some(c) if c.is_ascii_whitespace() => return None,
+        _ => {}
+    }
+    let value: f64 = std::str::from_utf8(src).ok()?.parse().ok()?;
+    if value.is_nan() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Significant decimal digits a f64 always holds, `DBL_DIG` of C.
+const F64_DIGITS: i32 = 15;
+
+/// Formats a float the way INCRBYFLOAT replies it. Redis prints its long double with
+/// `%.17Lf` and trims the trailing zeros, so that the binary noise of a sum, beyond the
+/// precision of a long double, is not shown. A f64 is less precise, so the value is
+/// also rounded to the 15 significant digits it holds: `0.1 + 0.2` gives `0.3`, and
+/// `1e-20` gives `0` as it has no digit within 17 decimals.
+///
+/// This differs from redis for values needing more than 15 significant digits, which
+/// a long double holds up to 18 of: `1 / 3` is `0.333333333333333` here, and
+/// `0.33333333333333333` for redis. Rust has no long double to match it, and printing
+/// 17 significant digits of a f64 would show its binary noise instead, such as
+/// `0.30000000000000004` for `0.1 + 0.2`.
+pub fn format_f64(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let exp = value.abs().log10().floor() as i32;
+    let decimals = (F64_DIGITS - 1 - exp).clamp(0, 17) as usize;
+    let mut s = format!("{:.*}", decimals, value);
+    if s.contains('.') {
+        let len = s.trim_end_matches('0').trim_end_matches('.').len();
+        s.truncate(len);
+    }
+    if s == "-0" {
+        // no negative zero.
+        s.remove(0);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_f64, parse_f64, parse_i64};
+
+    #[test]
+    fn parse_strict_integers() {
+        assert_eq!(parse_i64(b"0"), Some(0));
+        assert_eq!(parse_i64(b"-12"), Some(-12));
+        assert_eq!(parse_i64(b"9223372036854775807"), Some(i64::MAX));
+        assert_eq!(parse_i64(b"-9223372036854775808"), Some(i64::MIN));
+        for invalid in [&b""[..], b"-", b"+1", b"01", b"-0", b" 1", b"1 ", b"1.0", b"9223372036854775808"] {
+            assert_eq!(parse_i64(invalid), None);
+        }
+    }
+
+    #[test]
+    fn parse_and_format_floats() {
+        assert_eq!(parse_f64(b"10.5"), Some(10.5));
+        assert_eq!(parse_f64(b"5.0e3"), Some(5000.0));
+        assert_eq!(parse_f64(b"-inf"), Some(f64::NEG_INFINITY));
+        for invalid in [&b""[..], b" 1", b"1 ", b"nan", b"1,5", b"abc"] {
+            assert_eq!(parse_f64(invalid), None);
+        }
+        assert_eq!(format_f64(10.5 + 0.1), "10.6");
+        assert_eq!(format_f64(3.0), "3");
+        assert_eq!(format_f64(-0.0), "0");
+        assert_eq!(format_f64(5.0e3), "5000");
+        assert_eq!(format_f64(1e20), "100000000000000000000");
+        assert_eq!(format_f64(0.1 + 0.2), "0.3");
+        assert_eq!(format_f64(1e-20), "0");
+        assert_eq!(format_f64(-1e-20), "0");
+        assert_eq!(format_f64(1.5e-10), "0.00000000015");
+        assert_eq!(format_f64(0.12345678901234567), "0.123456789012346");
+        // redis replies 0.33333333333333333 with its long double.
+        assert_eq!(format_f64(1.0 / 3.0), "0.333333333333333");
+        assert_eq!(format_f64(-123456.75), "-123456.75");
+    }
+}